@@ -1,3 +1,23 @@
+#[path = "src/build_info/emit.rs"]
+mod build_info;
+
 fn main() {
+    // sdkconfig options read by `ble::preflight` and `ble::capabilities`; set by embuild when enabled.
+    for cfg in [
+        "esp_idf_bt_enabled",
+        "esp_idf_bt_bluedroid_enabled",
+        "esp_idf_btdm_ctrl_mode_btdm",
+        "esp_idf_btdm_ctrl_mode_br_edr_only",
+        "esp_idf_bt_ble_dynamic_env_memory",
+        "esp_idf_bt_ble_50_features_supported",
+        "esp_idf_bt_ble_smp_enable",
+    ] {
+        println!("cargo:rustc-check-cfg=cfg({cfg})");
+    }
+
+    build_info::emit();
+
+    // `embuild/espidf` is only enabled through esp-idf-sys, i.e. with the `esp` feature.
+    #[cfg(feature = "esp")]
     embuild::espidf::sysenv::output();
 }
@@ -0,0 +1,61 @@
+//! Mirrors characteristic writes to an MQTT topic.
+//!
+//! Network bring-up is left out; run this after Wi-Fi is connected. Set
+//! `MIRROR_MQTT_URL` at build time to point at your broker.
+
+use core::time::Duration;
+use std::time::Instant;
+
+use esp_gatt_rs_demo::ble::mirror::{MirrorMiddleware, MirrorSink, MirrorTarget};
+use esp_gatt_rs_demo::proto::Uuid;
+use esp_idf_svc::mqtt::client::{EspMqttClient, MqttClientConfiguration, QoS};
+
+const URL: &str = match option_env!("MIRROR_MQTT_URL") {
+    Some(url) => url,
+    None => "mqtt://192.168.1.10:1883",
+};
+const TOPIC: &str = "esp-gatt-rs-demo/mirror";
+
+struct MqttSink {
+    client: EspMqttClient<'static>,
+}
+
+impl MirrorSink for MqttSink {
+    fn send(&mut self, line: &str) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        // `enqueue` hands the message to the MQTT task instead of waiting for
+        // the broker, so the dispatch thread never blocks on the network.
+        self.client
+            .enqueue(TOPIC, QoS::AtMostOnce, false, line.as_bytes())?;
+        Ok(())
+    }
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    esp_idf_svc::sys::link_patches();
+    esp_idf_svc::log::EspLogger::initialize_default();
+
+    let client = EspMqttClient::new_cb(URL, &MqttClientConfiguration::default(), |_| {})?;
+    let mut mirror = MirrorMiddleware::new(MqttSink { client }, Duration::from_millis(100));
+    mirror.watch(
+        0x002A,
+        MirrorTarget {
+            uuid: Uuid::Uuid16(0xFF01),
+            sensitive: false,
+        },
+    );
+    mirror.watch(
+        0x002D,
+        MirrorTarget {
+            uuid: Uuid::Uuid16(0xFF02),
+            sensitive: true,
+        },
+    );
+
+    // In the firmware this is called from the write handler.
+    let peer = [0xC0, 0xFF, 0xEE, 0x00, 0x00, 0x01];
+    mirror.on_write(peer, 0x002A, b"ssid=lab", 0, Instant::now());
+    mirror.on_write(peer, 0x002D, b"hunter2", 150, Instant::now());
+
+    log::info!("mirror stats: {:?}", mirror.stats());
+    Ok(())
+}
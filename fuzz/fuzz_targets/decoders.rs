@@ -0,0 +1,48 @@
+//! Every fixed-layout `proto` decoder on the same input. Whatever decodes
+//! must encode back to a value that decodes to the same thing.
+
+#![no_main]
+
+use esp_gatt_rs_demo::ble::handles::HandleMap;
+use esp_gatt_rs_demo::proto::ack::{self, AckFrame};
+use esp_gatt_rs_demo::proto::prefs::Preferences;
+use esp_gatt_rs_demo::proto::{Alert, CccdFlags, Envelope, ProtocolVersion};
+use esp_gatt_rs_demo_fuzz as _;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Some(flags) = CccdFlags::decode(data) {
+        assert_eq!(CccdFlags::decode(&flags.encode()), Some(flags));
+    }
+
+    if let Ok(envelope) = Envelope::decode(data) {
+        assert_eq!(Envelope::decode(&envelope.encode()), Ok(envelope));
+    }
+
+    if let Ok(frame) = AckFrame::decode(data) {
+        assert_eq!(AckFrame::decode(&frame.encode()), Ok(frame));
+    }
+    if let Some((seq, payload)) = ack::decode_record(data) {
+        assert_eq!(
+            ack::decode_record(&ack::encode_record(seq, payload)),
+            Some((seq, payload))
+        );
+    }
+
+    if let Some(version) = ProtocolVersion::decode(data) {
+        assert_eq!(ProtocolVersion::decode(&version.encode()), Some(version));
+    }
+
+    if let Some(alert) = Alert::decode(data) {
+        let encoded = alert.encode(usize::MAX);
+        assert_eq!(Alert::decode(&encoded), Some(alert));
+    }
+
+    if let Ok(prefs) = Preferences::decode(data) {
+        assert_eq!(Preferences::decode(&prefs.encode()), Ok(prefs));
+    }
+
+    if let Some(map) = HandleMap::decode(data) {
+        assert_eq!(HandleMap::decode(&map.encode()), Some(map));
+    }
+});
@@ -0,0 +1,44 @@
+//! Prepare-write reassembly driven by arbitrary request sequences: the
+//! queue never holds more than its limit, however the chunks arrive.
+
+#![no_main]
+
+use esp_gatt_rs_demo::ble::prepare::PreparedWrites;
+use esp_gatt_rs_demo_fuzz as _;
+use libfuzzer_sys::fuzz_target;
+
+const MAX_BYTES: usize = 512;
+
+fuzz_target!(|data: &[u8]| {
+    let mut writes = PreparedWrites::new(MAX_BYTES);
+
+    // Each request: op, handle, offset (LE), chunk length, chunk.
+    let mut rest = data;
+    while let [op, handle, offset_lo, offset_hi, len, tail @ ..] = rest {
+        let len = usize::from(*len).min(tail.len());
+        let (chunk, tail) = tail.split_at(len);
+        rest = tail;
+
+        match op % 4 {
+            0 => {
+                let values = writes.execute(true);
+                assert!(values.iter().map(|(_, value)| value.len()).sum::<usize>() <= MAX_BYTES);
+            }
+            1 => assert!(writes.execute(false).is_empty()),
+            _ => {
+                let offset = u16::from_le_bytes([*offset_lo, *offset_hi]);
+                let refuse = *op == 0xFF;
+                let _ = writes.prepare(u16::from(*handle), offset, chunk, |_, _| {
+                    if refuse {
+                        Err(0x80)
+                    } else {
+                        Ok(())
+                    }
+                });
+            }
+        }
+    }
+
+    writes.execute(false);
+    assert!(writes.is_empty());
+});
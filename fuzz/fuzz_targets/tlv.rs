@@ -0,0 +1,31 @@
+//! TLV record sets: iteration terminates within the input, and the records
+//! read before the first error re-encode to the same bytes.
+
+#![no_main]
+
+use esp_gatt_rs_demo::proto::tlv::{self, Tlv};
+use esp_gatt_rs_demo_fuzz as _;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let mut encoded = Vec::new();
+    let mut valid_len = data.len();
+
+    for (count, record) in tlv::records(data).enumerate() {
+        // Every record takes at least two bytes.
+        assert!(count <= data.len() / 2);
+
+        match record {
+            Ok(Tlv::Value { tag, value }) => {
+                tlv::push(&mut encoded, tag, value).expect("decoded record re-encodes")
+            }
+            Ok(Tlv::Error { tag, status }) => tlv::push_error(&mut encoded, tag, status),
+            Err(offset) => {
+                assert!(offset < data.len());
+                valid_len = offset;
+            }
+        }
+    }
+
+    assert_eq!(encoded, &data[..valid_len]);
+});
@@ -0,0 +1,37 @@
+//! Shared setup of the fuzz targets.
+//!
+//! Every target links this crate, which installs [`CappedAlloc`]: a single
+//! allocation above [`MAX_ALLOC`] aborts the run, so a decoder trusting a
+//! length field from the input shows up as a crash instead of passing
+//! silently.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+
+/// Far above anything a decoder needs for the few-kilobyte inputs the
+/// fuzzer produces.
+pub const MAX_ALLOC: usize = 1 << 20;
+
+pub struct CappedAlloc;
+
+unsafe impl GlobalAlloc for CappedAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        assert!(
+            layout.size() <= MAX_ALLOC,
+            "allocation of {} bytes",
+            layout.size()
+        );
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        assert!(new_size <= MAX_ALLOC, "reallocation to {new_size} bytes");
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static ALLOC: CappedAlloc = CappedAlloc;
@@ -0,0 +1,102 @@
+//! Connection slot reservation for bonded peers.
+//!
+//! With few connection slots a random phone can lock out the bonded owner.
+//! [`SlotReservation`] keeps a number of slots free for bonded peers: once
+//! the unreserved slots are taken, unbonded connections are refused (the
+//! caller disconnects them right away in the `PeerConnected` handler), while
+//! bonded peers are admitted up to the controller maximum.
+
+use std::collections::HashMap;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum RefuseReason {
+    AtCapacity,
+    ReservedForBonded,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Admission {
+    Accept,
+    Refuse(RefuseReason),
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct RefusalStats {
+    pub at_capacity: u64,
+    pub reserved_for_bonded: u64,
+}
+
+#[derive(Debug)]
+pub struct SlotReservation {
+    max_connections: usize,
+    reserved: usize,
+    /// Admitted connections and whether each is bonded.
+    admitted: HashMap<u16, bool>,
+    refused: RefusalStats,
+}
+
+impl SlotReservation {
+    pub fn new(max_connections: usize) -> Self {
+        Self {
+            max_connections,
+            reserved: 0,
+            admitted: HashMap::new(),
+            refused: RefusalStats::default(),
+        }
+    }
+
+    /// Keeps `n` slots for bonded peers; capped at the maximum.
+    pub fn reserve_slots_for_bonded(&mut self, n: usize) {
+        self.reserved = n.min(self.max_connections);
+    }
+
+    pub fn refused(&self) -> RefusalStats {
+        self.refused
+    }
+
+    /// Whether an unbonded peer would be refused now; reflect this in the
+    /// advertising data so clients can tell.
+    pub fn is_busy(&self) -> bool {
+        self.check(false).is_some()
+    }
+
+    /// Decides on a new connection. Must be called synchronously when the
+    /// connection is reported.
+    pub fn admit(&mut self, conn_id: u16, bonded: bool) -> Admission {
+        match self.check(bonded) {
+            None => {
+                self.admitted.insert(conn_id, bonded);
+                Admission::Accept
+            }
+            Some(reason) => {
+                match reason {
+                    RefuseReason::AtCapacity => self.refused.at_capacity += 1,
+                    RefuseReason::ReservedForBonded => self.refused.reserved_for_bonded += 1,
+                }
+                Admission::Refuse(reason)
+            }
+        }
+    }
+
+    pub fn on_disconnect(&mut self, conn_id: u16) {
+        self.admitted.remove(&conn_id);
+    }
+
+    fn check(&self, bonded: bool) -> Option<RefuseReason> {
+        let total = self.admitted.len();
+        if total >= self.max_connections {
+            return Some(RefuseReason::AtCapacity);
+        }
+        if bonded {
+            return None;
+        }
+
+        let bonded_admitted = self.admitted.values().filter(|bonded| **bonded).count();
+        let still_reserved = self.reserved.saturating_sub(bonded_admitted);
+        if total + 1 + still_reserved > self.max_connections {
+            return Some(RefuseReason::ReservedForBonded);
+        }
+
+        None
+    }
+}
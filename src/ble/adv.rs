@@ -0,0 +1,30 @@
+//! Applying advertising payloads built by `proto::adv`.
+
+use esp_idf_svc::sys::{
+    esp, esp_ble_gap_config_adv_data_raw, esp_ble_gap_config_scan_rsp_data_raw, EspError,
+};
+
+use crate::proto::AdvPayload;
+
+impl AdvPayload {
+    /// Hands both payloads to Bluedroid via the raw configuration calls.
+    pub fn apply(&self) -> Result<(), EspError> {
+        // Bluedroid copies the buffers, the mutable pointer is only an FFI artefact.
+        esp!(unsafe {
+            esp_ble_gap_config_adv_data_raw(
+                self.adv_data.as_ptr() as *mut u8,
+                self.adv_data.len() as u32,
+            )
+        })?;
+
+        // Applied even when empty, so a previous scan response is cleared.
+        esp!(unsafe {
+            esp_ble_gap_config_scan_rsp_data_raw(
+                self.scan_rsp.as_ptr() as *mut u8,
+                self.scan_rsp.len() as u32,
+            )
+        })?;
+
+        Ok(())
+    }
+}
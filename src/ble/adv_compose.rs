@@ -0,0 +1,241 @@
+//! Advertising payload assembled from fragments of several services.
+//!
+//! Services register named [`AdFragment`]s (battery level in service data,
+//! an alert flag, ...) with [`AdvComposer::set_fragment`] instead of
+//! replacing the whole advertising configuration. The composer places them
+//! after the builder's own structures, highest priority first, in the
+//! advertising data if there is room, else in the scan response. Fragments
+//! that fit in neither are dropped and logged.
+//!
+//! Updates are debounced: the first change arms a deadline and every change
+//! until then is folded into one reconfiguration, returned by
+//! [`AdvComposer::poll`]. The caller applies it through the advertising
+//! lease (see [`super::adv_lease`]) so the restart stays guarded.
+
+use core::time::Duration;
+use std::collections::BTreeMap;
+use std::time::Instant;
+
+use crate::proto::adv::MAX_PAYLOAD_LEN;
+use crate::proto::{AdFragment, AdvError, AdvPayload, AdvPayloadBuilder};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Registered {
+    priority: u8,
+    fragment: AdFragment,
+}
+
+/// A payload to apply and the fragments left out of it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Composed {
+    pub payload: AdvPayload,
+    pub dropped: Vec<String>,
+}
+
+#[derive(Debug)]
+pub struct AdvComposer {
+    base: AdvPayloadBuilder,
+    debounce: Duration,
+    fragments: BTreeMap<String, Registered>,
+    /// Set by the first change after the last reconfiguration.
+    due_at: Option<Instant>,
+    applied: Option<AdvPayload>,
+}
+
+impl AdvComposer {
+    /// Composes on top of `base`, which holds flags, name and service
+    /// UUIDs.
+    pub fn new(base: AdvPayloadBuilder, debounce: Duration) -> Self {
+        Self {
+            base,
+            debounce,
+            fragments: BTreeMap::new(),
+            due_at: None,
+            applied: None,
+        }
+    }
+
+    /// Adds or replaces the fragment `name`. Higher `priority` is placed
+    /// first and dropped last.
+    pub fn set_fragment(
+        &mut self,
+        name: impl Into<String>,
+        priority: u8,
+        fragment: AdFragment,
+        now: Instant,
+    ) {
+        let registered = Registered { priority, fragment };
+        if self.fragments.insert(name.into(), registered.clone()) != Some(registered) {
+            self.changed(now);
+        }
+    }
+
+    pub fn remove_fragment(&mut self, name: &str, now: Instant) -> bool {
+        let removed = self.fragments.remove(name).is_some();
+        if removed {
+            self.changed(now);
+        }
+        removed
+    }
+
+    pub fn set_base(&mut self, base: AdvPayloadBuilder, now: Instant) {
+        self.base = base;
+        self.changed(now);
+    }
+
+    fn changed(&mut self, now: Instant) {
+        self.due_at.get_or_insert(now + self.debounce);
+    }
+
+    /// When [`poll`](Self::poll) has a reconfiguration ready.
+    pub fn next_deadline(&self) -> Option<Instant> {
+        self.due_at
+    }
+
+    /// The payload to apply once the debounce has passed, unless it equals
+    /// the one applied last.
+    pub fn poll(&mut self, now: Instant) -> Result<Option<Composed>, AdvError> {
+        if self.due_at.map_or(true, |due_at| now < due_at) {
+            return Ok(None);
+        }
+        self.due_at = None;
+
+        let composed = self.compose()?;
+        if self.applied.as_ref() == Some(&composed.payload) {
+            return Ok(None);
+        }
+
+        self.applied = Some(composed.payload.clone());
+        Ok(Some(composed))
+    }
+
+    /// Builds the payload now, without debouncing.
+    pub fn compose(&self) -> Result<Composed, AdvError> {
+        let mut payload = self.base.build()?;
+        let mut dropped = Vec::new();
+
+        let mut fragments: Vec<_> = self.fragments.iter().collect();
+        // Stable sort: equal priorities keep name order.
+        fragments.sort_by_key(|(_, registered)| core::cmp::Reverse(registered.priority));
+
+        for (name, registered) in fragments {
+            let len = registered.fragment.encoded_len();
+            let target = if payload.adv_data.len() + len <= MAX_PAYLOAD_LEN {
+                &mut payload.adv_data
+            } else if payload.scan_rsp.len() + len <= MAX_PAYLOAD_LEN {
+                &mut payload.scan_rsp
+            } else {
+                log::warn!(
+                    "advertising fragment {name} ({len} bytes, priority {}) does not fit, dropped",
+                    registered.priority
+                );
+                dropped.push(name.clone());
+                continue;
+            };
+
+            registered.fragment.push_to(target)?;
+        }
+
+        Ok(Composed { payload, dropped })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_clock::MockClock;
+
+    const DEBOUNCE: Duration = Duration::from_millis(100);
+
+    /// An AD structure of `len` bytes in total.
+    fn fragment(len: usize, fill: u8) -> AdFragment {
+        AdFragment::Raw {
+            ad_type: 0xFF,
+            data: vec![fill; len - 2],
+        }
+    }
+
+    fn composer() -> AdvComposer {
+        // Flags only: 3 bytes of advertising data.
+        AdvComposer::new(AdvPayloadBuilder::new(), DEBOUNCE)
+    }
+
+    #[test]
+    fn overflow_drops_the_lowest_priority_first() {
+        let clock = MockClock::new();
+        let mut composer = composer();
+        composer.set_fragment("high", 9, fragment(22, 1), clock.ms(0));
+        composer.set_fragment("mid", 5, fragment(22, 2), clock.ms(0));
+        composer.set_fragment("low", 1, fragment(12, 3), clock.ms(0));
+        composer.set_fragment("tiny", 0, fragment(4, 4), clock.ms(0));
+
+        let composed = composer.compose().unwrap();
+        // "low" fits in neither payload; "tiny" still fills the gap after it.
+        assert_eq!(composed.dropped, ["low"]);
+        assert_eq!(composed.payload.adv_data.len(), 3 + 22 + 4);
+        assert_eq!(composed.payload.adv_data[5], 1);
+        assert_eq!(composed.payload.adv_data[27], 4);
+        assert_eq!(composed.payload.scan_rsp.len(), 22);
+        assert_eq!(composed.payload.scan_rsp[2], 2);
+    }
+
+    #[test]
+    fn equal_priorities_place_in_name_order() {
+        let clock = MockClock::new();
+        let mut composer = composer();
+        composer.set_fragment("b", 1, fragment(14, 2), clock.ms(0));
+        composer.set_fragment("a", 1, fragment(14, 1), clock.ms(0));
+        composer.set_fragment("c", 1, fragment(14, 3), clock.ms(0));
+
+        let composed = composer.compose().unwrap();
+        assert!(composed.dropped.is_empty());
+        assert_eq!(composed.payload.adv_data[5], 1);
+        assert_eq!(composed.payload.adv_data[19], 2);
+        assert_eq!(composed.payload.scan_rsp[2], 3);
+    }
+
+    #[test]
+    fn changes_within_the_debounce_fold_into_one_update() {
+        let clock = MockClock::new();
+        let mut composer = composer();
+        assert_eq!(composer.next_deadline(), None);
+
+        composer.set_fragment("battery", 1, fragment(4, 80), clock.ms(0));
+        composer.set_fragment("battery", 1, fragment(4, 79), clock.ms(50));
+        composer.set_fragment("alert", 2, fragment(3, 1), clock.ms(90));
+        assert_eq!(composer.next_deadline(), Some(clock.ms(100)));
+
+        assert_eq!(composer.poll(clock.ms(99)), Ok(None));
+        let composed = composer.poll(clock.ms(100)).unwrap().unwrap();
+        assert_eq!(composed.payload, composer.compose().unwrap().payload);
+        assert_eq!(composed.payload.adv_data[5], 1);
+        assert_eq!(composed.payload.adv_data[8], 79);
+
+        assert_eq!(composer.next_deadline(), None);
+        assert_eq!(composer.poll(clock.ms(500)), Ok(None));
+    }
+
+    #[test]
+    fn unchanged_payloads_are_not_reapplied() {
+        let clock = MockClock::new();
+        let mut composer = composer();
+        composer.set_fragment("battery", 1, fragment(4, 80), clock.ms(0));
+        assert!(composer.poll(clock.ms(100)).unwrap().is_some());
+
+        // Setting the same fragment is not a change.
+        composer.set_fragment("battery", 1, fragment(4, 80), clock.ms(200));
+        assert_eq!(composer.next_deadline(), None);
+
+        // A change reverted within the debounce leaves nothing to apply.
+        composer.set_fragment("battery", 1, fragment(4, 79), clock.ms(300));
+        composer.set_fragment("battery", 1, fragment(4, 80), clock.ms(350));
+        assert_eq!(composer.poll(clock.ms(400)), Ok(None));
+        assert_eq!(composer.next_deadline(), None);
+
+        assert!(composer.remove_fragment("battery", clock.ms(500)));
+        assert!(!composer.remove_fragment("battery", clock.ms(500)));
+        assert_eq!(composer.next_deadline(), Some(clock.ms(600)));
+        let composed = composer.poll(clock.ms(600)).unwrap().unwrap();
+        assert_eq!(composed.payload.adv_data.len(), 3);
+    }
+}
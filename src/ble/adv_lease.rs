@@ -0,0 +1,160 @@
+//! Arbitration of advertising between firmware components.
+//!
+//! Whoever holds the highest-priority [`AdvLease`] owns advertising; the
+//! server's own policies (disconnect restart, schedules, rotation) hold a
+//! default lowest-priority lease that never expires. Acquiring, releasing or
+//! expiring a lease may change the owner, in which case a [`Handoff`] says
+//! which configuration to apply. Advertising is only (re)started once the
+//! `AdvertisingConfigured` event for that handoff arrives and
+//! [`AdvArbiter::on_configured`] confirms the lease still owns it, so a late
+//! event from a superseded configuration never starts the wrong one.
+
+use core::time::Duration;
+use std::time::Instant;
+
+/// Priority of the server's default lease.
+pub const DEFAULT_PRIORITY: u8 = 0;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct AdvLease(u32);
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Handoff<C> {
+    pub lease: AdvLease,
+    pub config: C,
+}
+
+#[derive(Debug)]
+struct Entry<C> {
+    lease: AdvLease,
+    priority: u8,
+    expires_at: Option<Instant>,
+    config: C,
+}
+
+#[derive(Debug)]
+pub struct AdvArbiter<C> {
+    /// In acquisition order; the owner is the first with the highest
+    /// priority.
+    entries: Vec<Entry<C>>,
+    owner: AdvLease,
+    /// Handoff applied but not yet confirmed by `AdvertisingConfigured`.
+    configuring: Option<AdvLease>,
+    next_id: u32,
+}
+
+impl<C: Clone> AdvArbiter<C> {
+    /// Starts with the default lease owning advertising with `config`.
+    pub fn new(config: C) -> Self {
+        let default = AdvLease(0);
+        Self {
+            entries: vec![Entry {
+                lease: default,
+                priority: DEFAULT_PRIORITY,
+                expires_at: None,
+                config,
+            }],
+            owner: default,
+            configuring: Some(default),
+            next_id: 1,
+        }
+    }
+
+    pub fn default_lease(&self) -> AdvLease {
+        AdvLease(0)
+    }
+
+    pub fn owner(&self) -> AdvLease {
+        self.owner
+    }
+
+    /// Leases waiting behind the owner.
+    pub fn queue_depth(&self) -> usize {
+        self.entries.len() - 1
+    }
+
+    pub fn acquire(
+        &mut self,
+        priority: u8,
+        max_duration: Duration,
+        config: C,
+        now: Instant,
+    ) -> (AdvLease, Option<Handoff<C>>) {
+        let lease = AdvLease(self.next_id);
+        self.next_id = self.next_id.wrapping_add(1).max(1);
+
+        self.entries.push(Entry {
+            lease,
+            priority,
+            expires_at: Some(now + max_duration),
+            config,
+        });
+        (lease, self.reelect())
+    }
+
+    /// Replaces the configuration of `lease`; a handoff if it owns
+    /// advertising.
+    pub fn update(&mut self, lease: AdvLease, config: C) -> Option<Handoff<C>> {
+        let entry = self.entries.iter_mut().find(|e| e.lease == lease)?;
+        entry.config = config;
+
+        if lease != self.owner {
+            return None;
+        }
+        self.configuring = Some(lease);
+        Some(Handoff {
+            lease,
+            config: entry.config.clone(),
+        })
+    }
+
+    /// Releases `lease`; the default lease cannot be released.
+    pub fn release(&mut self, lease: AdvLease) -> Option<Handoff<C>> {
+        if lease == self.default_lease() {
+            return None;
+        }
+
+        self.entries.retain(|entry| entry.lease != lease);
+        self.reelect()
+    }
+
+    /// Drops leases past their maximum duration.
+    pub fn expire(&mut self, now: Instant) -> Option<Handoff<C>> {
+        self.entries
+            .retain(|entry| entry.expires_at.map_or(true, |at| now < at));
+        self.reelect()
+    }
+
+    pub fn next_deadline(&self) -> Option<Instant> {
+        self.entries
+            .iter()
+            .filter_map(|entry| entry.expires_at)
+            .min()
+    }
+
+    /// Handles `AdvertisingConfigured` for the configuration applied for
+    /// `lease`. Returns whether to start advertising.
+    pub fn on_configured(&mut self, lease: AdvLease) -> bool {
+        if self.configuring == Some(lease) && self.owner == lease {
+            self.configuring = None;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn reelect(&mut self) -> Option<Handoff<C>> {
+        let top = self.entries.iter().map(|entry| entry.priority).max()?;
+        let entry = self.entries.iter().find(|entry| entry.priority == top)?;
+        if entry.lease == self.owner {
+            return None;
+        }
+
+        self.owner = entry.lease;
+        self.configuring = Some(entry.lease);
+        Some(Handoff {
+            lease: entry.lease,
+            config: entry.config.clone(),
+        })
+    }
+}
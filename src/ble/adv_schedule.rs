@@ -0,0 +1,89 @@
+//! Fast-then-slow advertising.
+//!
+//! After boot and after every disconnect the device advertises fast for a
+//! short window so phones find it quickly, then drops to a slow interval to
+//! save power. [`AdvScheduler`] tracks the window; the caller programs the
+//! interval it returns and calls [`AdvScheduler::poll`] when the deadline from
+//! [`AdvScheduler::next_deadline`] passes.
+
+use core::time::Duration;
+use std::time::Instant;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct AdvSchedule {
+    pub fast_interval: Duration,
+    pub fast_duration: Duration,
+    pub slow_interval: Duration,
+}
+
+impl Default for AdvSchedule {
+    fn default() -> Self {
+        Self {
+            fast_interval: Duration::from_millis(30),
+            fast_duration: Duration::from_secs(30),
+            slow_interval: Duration::from_secs(1),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum AdvPhase {
+    Fast,
+    Slow,
+}
+
+#[derive(Debug)]
+pub struct AdvScheduler {
+    schedule: AdvSchedule,
+    phase: AdvPhase,
+    fast_until: Option<Instant>,
+}
+
+impl AdvScheduler {
+    /// Starts in the slow phase; call [`start`](Self::start) when advertising
+    /// begins.
+    pub fn new(schedule: AdvSchedule) -> Self {
+        Self {
+            schedule,
+            phase: AdvPhase::Slow,
+            fast_until: None,
+        }
+    }
+
+    pub fn phase(&self) -> AdvPhase {
+        self.phase
+    }
+
+    pub fn current_interval(&self) -> Duration {
+        match self.phase {
+            AdvPhase::Fast => self.schedule.fast_interval,
+            AdvPhase::Slow => self.schedule.slow_interval,
+        }
+    }
+
+    /// (Re)opens the fast window, e.g. at boot or after a disconnect.
+    /// Returns the interval to program.
+    pub fn start(&mut self, now: Instant) -> Duration {
+        self.phase = AdvPhase::Fast;
+        self.fast_until = Some(now + self.schedule.fast_duration);
+        self.schedule.fast_interval
+    }
+
+    /// Returns the slow interval once the fast window has ended, `None` while
+    /// nothing changes.
+    pub fn poll(&mut self, now: Instant) -> Option<Duration> {
+        match self.fast_until {
+            Some(until) if now >= until => {
+                self.phase = AdvPhase::Slow;
+                self.fast_until = None;
+                Some(self.schedule.slow_interval)
+            }
+            _ => None,
+        }
+    }
+
+    /// When the fast window ends, if one is open.
+    pub fn next_deadline(&self) -> Option<Instant> {
+        self.fast_until
+    }
+}
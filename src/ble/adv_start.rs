@@ -0,0 +1,237 @@
+//! Guarded advertising start with retries.
+//!
+//! `esp_ble_gap_start_advertising` returning `ESP_OK` only means the request
+//! was queued; the start-complete GAP event carries the real outcome, and
+//! right after a disconnect the controller occasionally refuses. Every start
+//! (boot, disconnect restart, [`AdvScheduler`](super::adv_schedule) interval
+//! changes) goes through [`AdvStarter`], which retries a failed start with
+//! exponential backoff and gives up with a [`HealthAlert`] once the attempts
+//! are used up.
+
+use core::time::Duration;
+use std::time::Instant;
+
+use super::health::HealthAlert;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct AdvRetryPolicy {
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    /// Failed starts in a row before giving up.
+    pub max_attempts: u32,
+}
+
+impl Default for AdvRetryPolicy {
+    fn default() -> Self {
+        Self {
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(5),
+            max_attempts: 8,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum AdvertisingState {
+    Stopped,
+    /// Start requested, waiting for the start-complete event.
+    Starting,
+    Advertising,
+    /// The last start failed; retrying at `retry_at`.
+    Backoff {
+        retry_at: Instant,
+    },
+    /// Retries exhausted; only an explicit start tries again.
+    Failed,
+}
+
+#[derive(Debug)]
+pub struct AdvStarter {
+    policy: AdvRetryPolicy,
+    state: AdvertisingState,
+    failures: u32,
+}
+
+impl AdvStarter {
+    pub fn new(policy: AdvRetryPolicy) -> Self {
+        Self {
+            policy,
+            state: AdvertisingState::Stopped,
+            failures: 0,
+        }
+    }
+
+    pub fn advertising_state(&self) -> AdvertisingState {
+        self.state
+    }
+
+    /// Requests a start. Returns whether to call `start_advertising` now;
+    /// a start already pending or running is not issued twice. During
+    /// backoff the retry is only brought forward: the failures so far still
+    /// count towards the limit.
+    pub fn request_start(&mut self) -> bool {
+        match self.state {
+            AdvertisingState::Starting | AdvertisingState::Advertising => false,
+            AdvertisingState::Backoff { .. } => {
+                self.state = AdvertisingState::Starting;
+                true
+            }
+            AdvertisingState::Stopped | AdvertisingState::Failed => {
+                self.failures = 0;
+                self.state = AdvertisingState::Starting;
+                true
+            }
+        }
+    }
+
+    /// Handles the start-complete event. A start call that fails
+    /// synchronously is reported the same way with `success == false`.
+    pub fn on_start_complete(&mut self, success: bool, now: Instant) -> Option<HealthAlert> {
+        if success {
+            self.failures = 0;
+            self.state = AdvertisingState::Advertising;
+            return None;
+        }
+
+        self.failures += 1;
+        if self.failures >= self.policy.max_attempts {
+            self.state = AdvertisingState::Failed;
+            return Some(HealthAlert::AdvertisingFailed {
+                attempts: self.failures,
+            });
+        }
+
+        let backoff = self
+            .policy
+            .initial_backoff
+            .saturating_mul(1 << (self.failures - 1).min(16))
+            .min(self.policy.max_backoff);
+        self.state = AdvertisingState::Backoff {
+            retry_at: now + backoff,
+        };
+        None
+    }
+
+    /// Handles the stop-complete event; a failed stop leaves the state as is.
+    pub fn on_stop_complete(&mut self, success: bool) {
+        if success {
+            self.state = AdvertisingState::Stopped;
+        }
+    }
+
+    /// Returns whether a retry is due; the caller then calls
+    /// `start_advertising`.
+    pub fn poll(&mut self, now: Instant) -> bool {
+        match self.state {
+            AdvertisingState::Backoff { retry_at } if now >= retry_at => {
+                self.state = AdvertisingState::Starting;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn next_deadline(&self) -> Option<Instant> {
+        match self.state {
+            AdvertisingState::Backoff { retry_at } => Some(retry_at),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy() -> AdvRetryPolicy {
+        AdvRetryPolicy {
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_millis(300),
+            max_attempts: 3,
+        }
+    }
+
+    fn ms(at: Instant, ms: u64) -> Instant {
+        at + Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn start_is_not_issued_twice() {
+        let mut starter = AdvStarter::new(policy());
+        assert!(starter.request_start());
+        assert!(!starter.request_start());
+
+        assert_eq!(starter.on_start_complete(true, Instant::now()), None);
+        assert_eq!(starter.advertising_state(), AdvertisingState::Advertising);
+        assert!(!starter.request_start());
+    }
+
+    #[test]
+    fn failures_back_off_exponentially_then_give_up() {
+        let start = Instant::now();
+        let mut starter = AdvStarter::new(policy());
+        starter.request_start();
+
+        assert_eq!(starter.on_start_complete(false, start), None);
+        assert_eq!(starter.next_deadline(), Some(ms(start, 100)));
+        assert!(!starter.poll(ms(start, 99)));
+        assert!(starter.poll(ms(start, 100)));
+
+        assert_eq!(starter.on_start_complete(false, ms(start, 100)), None);
+        assert_eq!(starter.next_deadline(), Some(ms(start, 300)));
+        assert!(starter.poll(ms(start, 300)));
+
+        assert_eq!(
+            starter.on_start_complete(false, ms(start, 300)),
+            Some(HealthAlert::AdvertisingFailed { attempts: 3 })
+        );
+        assert_eq!(starter.advertising_state(), AdvertisingState::Failed);
+        assert_eq!(starter.next_deadline(), None);
+    }
+
+    #[test]
+    fn request_during_backoff_keeps_the_failure_count() {
+        let start = Instant::now();
+        let mut starter = AdvStarter::new(policy());
+        starter.request_start();
+        starter.on_start_complete(false, start);
+        starter.on_start_complete(false, start);
+
+        // A disconnect restart during backoff starts at once...
+        assert!(starter.request_start());
+        assert_eq!(starter.advertising_state(), AdvertisingState::Starting);
+        // ...but cannot extend the retries forever.
+        assert_eq!(
+            starter.on_start_complete(false, start),
+            Some(HealthAlert::AdvertisingFailed { attempts: 3 })
+        );
+    }
+
+    #[test]
+    fn request_after_failure_starts_over() {
+        let start = Instant::now();
+        let mut starter = AdvStarter::new(policy());
+        starter.request_start();
+        for _ in 0..3 {
+            starter.on_start_complete(false, start);
+        }
+        assert_eq!(starter.advertising_state(), AdvertisingState::Failed);
+
+        assert!(starter.request_start());
+        assert_eq!(starter.on_start_complete(false, start), None);
+        assert_eq!(starter.next_deadline(), Some(ms(start, 100)));
+    }
+
+    #[test]
+    fn stop_complete() {
+        let mut starter = AdvStarter::new(policy());
+        starter.request_start();
+        starter.on_start_complete(true, Instant::now());
+
+        starter.on_stop_complete(false);
+        assert_eq!(starter.advertising_state(), AdvertisingState::Advertising);
+        starter.on_stop_complete(true);
+        assert_eq!(starter.advertising_state(), AdvertisingState::Stopped);
+        assert!(starter.request_start());
+    }
+}
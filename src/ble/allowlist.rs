@@ -0,0 +1,164 @@
+//! Application-level allowlist of peer identity addresses.
+//!
+//! Phones connect with rotating resolvable private addresses, so the list
+//! holds identity addresses and is checked against
+//! [`Connection::identity_addr`], which becomes the bonded identity once
+//! pairing completes. A peer still on an unresolved RPA is handled as
+//! [`UnresolvedPolicy`] says.
+
+use std::collections::HashSet;
+
+use super::conn::Connection;
+use super::PeerAddr;
+
+/// Treatment of peers whose RPA has not been resolved through a bond yet.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum UnresolvedPolicy {
+    #[default]
+    Reject,
+    /// Let them in so they can pair; check again once bonded.
+    AllowUntilBonded,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum AllowDecision {
+    Allowed,
+    Denied,
+    /// Unresolved RPA let in under [`UnresolvedPolicy::AllowUntilBonded`].
+    Provisional,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Allowlist {
+    identities: HashSet<PeerAddr>,
+    unresolved: UnresolvedPolicy,
+}
+
+impl Allowlist {
+    pub fn new(unresolved: UnresolvedPolicy) -> Self {
+        Self {
+            identities: HashSet::new(),
+            unresolved,
+        }
+    }
+
+    pub fn add(&mut self, identity: PeerAddr) {
+        self.identities.insert(identity);
+    }
+
+    pub fn remove(&mut self, identity: &PeerAddr) -> bool {
+        self.identities.remove(identity)
+    }
+
+    pub fn contains(&self, identity: &PeerAddr) -> bool {
+        self.identities.contains(identity)
+    }
+
+    /// Checks a connection, again after each identity update.
+    pub fn check(&self, conn: &Connection) -> AllowDecision {
+        if self.identities.contains(&conn.identity_addr()) {
+            AllowDecision::Allowed
+        } else if conn.is_unresolved() && self.unresolved == UnresolvedPolicy::AllowUntilBonded {
+            AllowDecision::Provisional
+        } else {
+            AllowDecision::Denied
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use super::*;
+    use crate::ble::conn::{is_rpa, AddrType, ConnectionRegistry};
+
+    const OWNER: PeerAddr = [0xC0, 0x11, 0x22, 0x33, 0x44, 0x55];
+    const STRANGER: PeerAddr = [0xC0, 0x66, 0x77, 0x88, 0x99, 0xAA];
+    /// Top bits `01`: resolvable private.
+    const RPA: PeerAddr = [0x4A, 0x01, 0x02, 0x03, 0x04, 0x05];
+
+    fn connected(peer: PeerAddr, peer_type: AddrType) -> ConnectionRegistry {
+        let mut conns = ConnectionRegistry::new();
+        conns.on_connect(1, peer, peer_type, Instant::now());
+        conns
+    }
+
+    fn allowlist(unresolved: UnresolvedPolicy) -> Allowlist {
+        let mut allowlist = Allowlist::new(unresolved);
+        allowlist.add(OWNER);
+        allowlist
+    }
+
+    #[test]
+    fn rpa_detection() {
+        assert!(is_rpa(&RPA, AddrType::Random));
+        assert!(!is_rpa(&RPA, AddrType::Public));
+        // Static random addresses have the top bits `11`.
+        assert!(!is_rpa(&OWNER, AddrType::Random));
+    }
+
+    #[test]
+    fn bond_complete_resolves_rpa_to_allowed_identity() {
+        let allowlist = allowlist(UnresolvedPolicy::Reject);
+        let mut conns = connected(RPA, AddrType::Random);
+        assert_eq!(
+            allowlist.check(conns.get(1).unwrap()),
+            AllowDecision::Denied
+        );
+
+        conns.on_identity(1, OWNER);
+        let conn = conns.get(1).unwrap();
+        assert_eq!(conn.peer, RPA);
+        assert_eq!(conn.identity_addr(), OWNER);
+        assert!(!conn.is_unresolved());
+        assert_eq!(allowlist.check(conn), AllowDecision::Allowed);
+    }
+
+    #[test]
+    fn unresolved_peers_are_provisional_until_bonded() {
+        let allowlist = allowlist(UnresolvedPolicy::AllowUntilBonded);
+
+        let mut owner = connected(RPA, AddrType::Random);
+        assert_eq!(
+            allowlist.check(owner.get(1).unwrap()),
+            AllowDecision::Provisional
+        );
+        owner.on_identity(1, OWNER);
+        assert_eq!(
+            allowlist.check(owner.get(1).unwrap()),
+            AllowDecision::Allowed
+        );
+
+        let mut stranger = connected(RPA, AddrType::Random);
+        stranger.on_identity(1, STRANGER);
+        assert_eq!(
+            allowlist.check(stranger.get(1).unwrap()),
+            AllowDecision::Denied
+        );
+    }
+
+    #[test]
+    fn identity_addresses_are_checked_directly() {
+        let allowlist = allowlist(UnresolvedPolicy::AllowUntilBonded);
+
+        let owner = connected(OWNER, AddrType::Random);
+        assert_eq!(
+            allowlist.check(owner.get(1).unwrap()),
+            AllowDecision::Allowed
+        );
+        // Not an RPA, so never provisional.
+        let stranger = connected(STRANGER, AddrType::Public);
+        assert_eq!(
+            allowlist.check(stranger.get(1).unwrap()),
+            AllowDecision::Denied
+        );
+    }
+
+    #[test]
+    fn identity_of_unknown_connection_is_ignored() {
+        let mut conns = connected(RPA, AddrType::Random);
+        conns.on_identity(2, OWNER);
+        assert!(conns.get(1).unwrap().is_unresolved());
+    }
+}
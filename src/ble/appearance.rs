@@ -0,0 +1,13 @@
+//! GAP appearance characteristic.
+
+use esp_idf_svc::sys::{esp, esp_ble_gap_config_local_icon, EspError};
+
+use crate::proto::Appearance;
+
+/// Sets the value of the GAP appearance characteristic (0x2A01).
+///
+/// This is independent from the appearance field of the advertising payload;
+/// callers should keep both in sync so centrals see a consistent value.
+pub fn set_gap_appearance(appearance: Appearance) -> Result<(), EspError> {
+    esp!(unsafe { esp_ble_gap_config_local_icon(appearance.raw()) })
+}
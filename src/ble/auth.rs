@@ -0,0 +1,433 @@
+//! Capability checks and failed-token lockout.
+//!
+//! Services declare the capability each of their characteristics needs with
+//! [`Authorizer::require_capability`]; the write path then calls
+//! [`Authorizer::check`] with the token presented by the client. Failures
+//! are counted per peer address in a small LRU; after
+//! [`LockoutPolicy::max_failures`] the peer is locked out for a while and
+//! even a valid token is refused. Every refusal is answered with ATT
+//! "insufficient authorization". Tokens are added and revoked through
+//! [`Authorizer::add_token`] and [`Authorizer::revoke_token`], which need a
+//! token with [`Capabilities::MANAGE_TOKENS`].
+
+use core::fmt;
+use core::time::Duration;
+use std::collections::{HashMap, VecDeque};
+use std::time::Instant;
+
+use super::early_write::ATT_ERR_UNLIKELY;
+use super::PeerAddr;
+use crate::store::{Capabilities, StoreError, TokenStore};
+
+pub const ATT_ERR_INSUFFICIENT_AUTHORIZATION: u8 = 0x08;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct LockoutPolicy {
+    pub max_failures: u32,
+    pub lockout: Duration,
+    /// Peers tracked at once; the least recently failing one that is not
+    /// locked out is forgotten. Locked-out peers are kept until their
+    /// lockout ends, even past this limit.
+    pub max_peers: usize,
+}
+
+impl Default for LockoutPolicy {
+    fn default() -> Self {
+        Self {
+            max_failures: 3,
+            lockout: Duration::from_secs(60),
+            max_peers: 8,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum AuthError {
+    /// Unknown token, or one lacking the capability.
+    Denied,
+    LockedOut {
+        until: Instant,
+    },
+    /// The token store failed; not counted against the peer.
+    Store(StoreError),
+}
+
+impl fmt::Display for AuthError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Denied => f.write_str("token does not grant the capability"),
+            Self::LockedOut { .. } => f.write_str("too many failed tokens, locked out"),
+            Self::Store(err) => write!(f, "token lookup failed: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for AuthError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Store(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl AuthError {
+    pub const fn att_status(&self) -> u8 {
+        match self {
+            Self::Denied | Self::LockedOut { .. } => ATT_ERR_INSUFFICIENT_AUTHORIZATION,
+            Self::Store(_) => ATT_ERR_UNLIKELY,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Failures {
+    peer: PeerAddr,
+    count: u32,
+    locked_until: Option<Instant>,
+}
+
+#[derive(Debug)]
+pub struct FailureTracker {
+    policy: LockoutPolicy,
+    /// Most recently failing peer last.
+    peers: VecDeque<Failures>,
+}
+
+impl FailureTracker {
+    pub fn new(policy: LockoutPolicy) -> Self {
+        Self {
+            policy,
+            peers: VecDeque::new(),
+        }
+    }
+
+    /// When the lockout of `peer` ends, if it is locked out.
+    pub fn locked_until(&self, peer: &PeerAddr, now: Instant) -> Option<Instant> {
+        self.peers
+            .iter()
+            .find(|failures| failures.peer == *peer)
+            .and_then(|failures| failures.locked_until)
+            .filter(|&until| now < until)
+    }
+
+    /// Counts a failure; returns the lockout end if this one locked the
+    /// peer out.
+    pub fn record_failure(&mut self, peer: PeerAddr, now: Instant) -> Option<Instant> {
+        let mut failures = match self.peers.iter().position(|f| f.peer == peer) {
+            Some(index) => self.peers.remove(index).expect("index in range"),
+            None => Failures {
+                peer,
+                count: 0,
+                locked_until: None,
+            },
+        };
+
+        if failures.locked_until.is_some_and(|until| now >= until) {
+            failures.count = 0;
+            failures.locked_until = None;
+        }
+        failures.count += 1;
+        if failures.count >= self.policy.max_failures {
+            failures.locked_until = Some(now + self.policy.lockout);
+        }
+        let locked_until = failures.locked_until;
+
+        // Expired lockouts have nothing left to remember.
+        self.peers.retain(|failures| {
+            failures
+                .locked_until
+                .filter(|&until| now >= until)
+                .is_none()
+        });
+        if self.peers.len() >= self.policy.max_peers {
+            if let Some(index) = self
+                .peers
+                .iter()
+                .position(|failures| failures.locked_until.is_none())
+            {
+                self.peers.remove(index);
+            }
+        }
+        self.peers.push_back(failures);
+        locked_until
+    }
+
+    pub fn record_success(&mut self, peer: &PeerAddr) {
+        self.peers.retain(|failures| failures.peer != *peer);
+    }
+}
+
+#[derive(Debug)]
+pub struct Authorizer {
+    tokens: TokenStore,
+    failures: FailureTracker,
+    /// Capabilities required per characteristic handle.
+    required: HashMap<u16, Capabilities>,
+}
+
+impl Authorizer {
+    pub fn new(tokens: TokenStore, policy: LockoutPolicy) -> Self {
+        Self {
+            tokens,
+            failures: FailureTracker::new(policy),
+            required: HashMap::new(),
+        }
+    }
+
+    pub fn tokens(&self) -> &TokenStore {
+        &self.tokens
+    }
+
+    /// Guards `handle`: writes to it need a token granting `capability`.
+    /// Declaring a handle again adds to what it requires.
+    pub fn require_capability(&mut self, handle: u16, capability: Capabilities) {
+        let required = self.required.entry(handle).or_default();
+        *required = *required | capability;
+    }
+
+    /// What writes to `handle` require, `None` if it is not guarded.
+    pub fn required(&self, handle: u16) -> Option<Capabilities> {
+        self.required.get(&handle).copied()
+    }
+
+    /// The middleware check for a write to `handle`. Handles without a
+    /// requirement pass without looking at the token.
+    pub fn check(
+        &mut self,
+        peer: PeerAddr,
+        handle: u16,
+        token: &[u8],
+        now: Instant,
+    ) -> Result<(), AuthError> {
+        match self.required(handle) {
+            Some(required) => self.authorize(peer, token, required, now).map(drop),
+            None => Ok(()),
+        }
+    }
+
+    /// Admin command: adds `new_token` with `capabilities`, authorized by
+    /// `admin_token`.
+    pub fn add_token(
+        &mut self,
+        peer: PeerAddr,
+        admin_token: &[u8],
+        new_token: &[u8],
+        capabilities: Capabilities,
+        now: Instant,
+    ) -> Result<(), AuthError> {
+        self.authorize(peer, admin_token, Capabilities::MANAGE_TOKENS, now)?;
+        self.tokens
+            .add(new_token, capabilities)
+            .map_err(AuthError::Store)
+    }
+
+    /// Admin command: revokes `token`, authorized by `admin_token`. Returns
+    /// whether the token existed.
+    pub fn revoke_token(
+        &mut self,
+        peer: PeerAddr,
+        admin_token: &[u8],
+        token: &[u8],
+        now: Instant,
+    ) -> Result<bool, AuthError> {
+        self.authorize(peer, admin_token, Capabilities::MANAGE_TOKENS, now)?;
+        self.tokens.revoke(token).map_err(AuthError::Store)
+    }
+
+    /// Checks that `token` grants `required`, returning everything it
+    /// grants.
+    pub fn authorize(
+        &mut self,
+        peer: PeerAddr,
+        token: &[u8],
+        required: Capabilities,
+        now: Instant,
+    ) -> Result<Capabilities, AuthError> {
+        if let Some(until) = self.failures.locked_until(&peer, now) {
+            return Err(AuthError::LockedOut { until });
+        }
+
+        match self.tokens.verify(token).map_err(AuthError::Store)? {
+            Some(granted) if granted.contains(required) => {
+                self.failures.record_success(&peer);
+                Ok(granted)
+            }
+            _ => Err(match self.failures.record_failure(peer, now) {
+                Some(until) => AuthError::LockedOut { until },
+                None => AuthError::Denied,
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::store::MemStore;
+    use crate::test_clock::MockClock;
+
+    const PEER: PeerAddr = [1, 2, 3, 4, 5, 6];
+    const OTHER_PEER: PeerAddr = [6, 5, 4, 3, 2, 1];
+
+    const DIAGNOSTICS_HANDLE: u16 = 0x10;
+    const REBOOT_HANDLE: u16 = 0x12;
+    const OTA_HANDLE: u16 = 0x14;
+    const OPEN_HANDLE: u16 = 0x16;
+
+    fn policy() -> LockoutPolicy {
+        LockoutPolicy {
+            max_failures: 3,
+            lockout: Duration::from_secs(60),
+            max_peers: 2,
+        }
+    }
+
+    fn authorizer() -> Authorizer {
+        let tokens = TokenStore::new(Arc::new(MemStore::new()), "tokens");
+        tokens.add(b"diag", Capabilities::READ_DIAGNOSTICS).unwrap();
+        tokens.add(b"reboot", Capabilities::REBOOT).unwrap();
+        tokens.add(b"ota", Capabilities::OTA).unwrap();
+        tokens
+            .add(
+                b"admin",
+                Capabilities::READ_DIAGNOSTICS | Capabilities::REBOOT | Capabilities::MANAGE_TOKENS,
+            )
+            .unwrap();
+
+        let mut authorizer = Authorizer::new(tokens, policy());
+        authorizer.require_capability(DIAGNOSTICS_HANDLE, Capabilities::READ_DIAGNOSTICS);
+        authorizer.require_capability(REBOOT_HANDLE, Capabilities::REBOOT);
+        authorizer.require_capability(OTA_HANDLE, Capabilities::OTA);
+        authorizer
+    }
+
+    #[test]
+    fn capability_matrix() {
+        let handles = [DIAGNOSTICS_HANDLE, REBOOT_HANDLE, OTA_HANDLE, OPEN_HANDLE];
+        #[rustfmt::skip]
+        let matrix: [(&[u8], [bool; 4]); 5] = [
+            (b"diag",    [true,  false, false, true]),
+            (b"reboot",  [false, true,  false, true]),
+            (b"ota",     [false, false, true,  true]),
+            (b"admin",   [true,  true,  false, true]),
+            (b"unknown", [false, false, false, true]),
+        ];
+
+        let now = Instant::now();
+        for (token, allowed) in matrix {
+            for (handle, allowed) in handles.into_iter().zip(allowed) {
+                // A fresh authorizer, so refusals do not add up to a lockout.
+                let result = authorizer().check(PEER, handle, token, now);
+                assert_eq!(
+                    result.is_ok(),
+                    allowed,
+                    "token {:?} on handle {handle:#x}: {result:?}",
+                    String::from_utf8_lossy(token)
+                );
+                if let Err(err) = result {
+                    assert_eq!(err.att_status(), ATT_ERR_INSUFFICIENT_AUTHORIZATION);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn lockout_after_three_failures_until_it_expires() {
+        let clock = MockClock::new();
+        let mut authorizer = authorizer();
+
+        assert!(matches!(
+            authorizer.check(PEER, REBOOT_HANDLE, b"wrong", clock.secs(0)),
+            Err(AuthError::Denied)
+        ));
+        assert!(matches!(
+            authorizer.check(PEER, REBOOT_HANDLE, b"diag", clock.secs(1)),
+            Err(AuthError::Denied)
+        ));
+        match authorizer.check(PEER, REBOOT_HANDLE, b"wrong", clock.secs(2)) {
+            Err(AuthError::LockedOut { until }) => assert_eq!(until, clock.secs(62)),
+            other => panic!("expected a lockout, got {other:?}"),
+        }
+
+        // Even the right token is refused while locked out...
+        assert!(matches!(
+            authorizer.check(PEER, REBOOT_HANDLE, b"reboot", clock.secs(61)),
+            Err(AuthError::LockedOut { .. })
+        ));
+        // ...other peers are not affected...
+        authorizer
+            .check(OTHER_PEER, REBOOT_HANDLE, b"reboot", clock.secs(61))
+            .unwrap();
+        // ...and the lockout ends on time.
+        authorizer
+            .check(PEER, REBOOT_HANDLE, b"reboot", clock.secs(62))
+            .unwrap();
+    }
+
+    #[test]
+    fn failures_start_over_after_a_lockout_or_success() {
+        let clock = MockClock::new();
+        let mut authorizer = authorizer();
+
+        for at in 0..3 {
+            let _ = authorizer.check(PEER, REBOOT_HANDLE, b"wrong", clock.secs(at));
+        }
+        assert!(matches!(
+            authorizer.check(PEER, REBOOT_HANDLE, b"wrong", clock.secs(62)),
+            Err(AuthError::Denied)
+        ));
+
+        authorizer
+            .check(PEER, REBOOT_HANDLE, b"reboot", clock.secs(63))
+            .unwrap();
+        for at in 64..66 {
+            assert!(matches!(
+                authorizer.check(PEER, REBOOT_HANDLE, b"wrong", clock.secs(at)),
+                Err(AuthError::Denied)
+            ));
+        }
+    }
+
+    #[test]
+    fn locked_out_peers_are_not_evicted() {
+        let clock = MockClock::new();
+        let mut failures = FailureTracker::new(policy());
+
+        for _ in 0..3 {
+            failures.record_failure(PEER, clock.secs(0));
+        }
+        // More failing peers than the LRU holds.
+        for last in 0..5 {
+            failures.record_failure([0, 0, 0, 0, 0, last], clock.secs(1));
+        }
+
+        assert_eq!(
+            failures.locked_until(&PEER, clock.secs(1)),
+            Some(clock.secs(60))
+        );
+        assert_eq!(failures.locked_until(&PEER, clock.secs(60)), None);
+    }
+
+    #[test]
+    fn token_management_needs_the_capability() {
+        let now = Instant::now();
+        let mut authorizer = authorizer();
+
+        assert!(matches!(
+            authorizer.add_token(PEER, b"reboot", b"new", Capabilities::OTA, now),
+            Err(AuthError::Denied)
+        ));
+        authorizer
+            .add_token(PEER, b"admin", b"new", Capabilities::OTA, now)
+            .unwrap();
+        authorizer.check(PEER, OTA_HANDLE, b"new", now).unwrap();
+
+        assert!(authorizer
+            .revoke_token(PEER, b"admin", b"new", now)
+            .unwrap());
+        assert!(authorizer.check(PEER, OTA_HANDLE, b"new", now).is_err());
+    }
+}
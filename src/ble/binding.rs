@@ -0,0 +1,160 @@
+//! Detection of handlers registered for UUIDs that were never created.
+//!
+//! A typo in the UUID of a UUID-keyed handler registration means no handle
+//! ever maps to it and the handler silently never runs. Once the services
+//! have started, [`check_bindings`] compares the registered UUIDs with the
+//! created attributes in the [`HandleMap`] and reacts to each unbound one as
+//! [`StrictBinding`] says.
+
+use core::fmt;
+use std::sync::Arc;
+
+use super::handles::HandleMap;
+use crate::proto::Uuid;
+
+/// What to do about handlers whose UUID was never created.
+#[derive(Clone, Default)]
+pub enum StrictBinding {
+    /// Log a warning per unbound handler.
+    #[default]
+    Warn,
+    /// Fail startup with [`UnboundHandlers`].
+    Fail,
+    /// Call the hook once per unbound handler.
+    Hook(Arc<dyn Fn(Uuid) + Send + Sync>),
+}
+
+impl fmt::Debug for StrictBinding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Warn => f.write_str("Warn"),
+            Self::Fail => f.write_str("Fail"),
+            Self::Hook(_) => f.write_str("Hook(..)"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UnboundHandlers(pub Vec<Uuid>);
+
+impl fmt::Display for UnboundHandlers {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "handlers registered for UUIDs never created: {:?}",
+            self.0
+        )
+    }
+}
+
+impl std::error::Error for UnboundHandlers {}
+
+/// Registered UUIDs without a created attribute, in registration order.
+pub fn unbound(registered: &[Uuid], created: &HandleMap) -> Vec<Uuid> {
+    let mut unbound = Vec::new();
+    for uuid in registered {
+        let bound = created.entries().iter().any(|(created, _)| created == uuid);
+        if !bound && !unbound.contains(uuid) {
+            unbound.push(*uuid);
+        }
+    }
+    unbound
+}
+
+/// Applies `mode` to the unbound registrations. Unless it fails, returns
+/// them for the self-test report.
+pub fn check_bindings(
+    registered: &[Uuid],
+    created: &HandleMap,
+    mode: &StrictBinding,
+) -> Result<Vec<Uuid>, UnboundHandlers> {
+    let unbound = unbound(registered, created);
+
+    match mode {
+        StrictBinding::Warn => {
+            for uuid in &unbound {
+                log::warn!(
+                    "handler registered for {uuid:?}, which no service created; it will never run"
+                );
+            }
+        }
+        StrictBinding::Fail if !unbound.is_empty() => return Err(UnboundHandlers(unbound)),
+        StrictBinding::Fail => {}
+        StrictBinding::Hook(hook) => unbound.iter().for_each(|&uuid| hook(uuid)),
+    }
+
+    Ok(unbound)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use super::*;
+
+    const LEVEL: Uuid = Uuid::Uuid16(0x2A19);
+    /// A typo of `0x2A19`.
+    const TYPO: Uuid = Uuid::Uuid16(0x2A91);
+
+    type Handler = Box<dyn Fn(&[u8]) + Send>;
+
+    /// One handler for a created characteristic, one for a UUID no service
+    /// declares.
+    fn registered() -> Vec<(Uuid, Handler)> {
+        vec![
+            (LEVEL, Box::new(|_: &[u8]| {}) as Handler),
+            (TYPO, Box::new(|_: &[u8]| {})),
+        ]
+    }
+
+    fn created() -> HandleMap {
+        let mut map = HandleMap::new();
+        map.push(Uuid::Uuid16(0x180F), 40);
+        map.push(LEVEL, 42);
+        map
+    }
+
+    fn uuids(handlers: &[(Uuid, Handler)]) -> Vec<Uuid> {
+        handlers.iter().map(|(uuid, _)| *uuid).collect()
+    }
+
+    #[test]
+    fn strict_mode_names_the_missing_uuid() {
+        let registered = uuids(&registered());
+
+        let err = check_bindings(&registered, &created(), &StrictBinding::Fail).unwrap_err();
+        assert_eq!(err, UnboundHandlers(vec![TYPO]));
+        assert_eq!(
+            err.to_string(),
+            "handlers registered for UUIDs never created: [Uuid16(10897)]"
+        );
+    }
+
+    #[test]
+    fn all_bound_passes_strict_mode() {
+        assert_eq!(
+            check_bindings(&[LEVEL, LEVEL], &created(), &StrictBinding::Fail),
+            Ok(Vec::new())
+        );
+    }
+
+    #[test]
+    fn warn_and_hook_report_without_failing() {
+        let registered = uuids(&registered());
+        assert_eq!(
+            check_bindings(&registered, &created(), &StrictBinding::Warn),
+            Ok(vec![TYPO])
+        );
+
+        let heard = Arc::new(Mutex::new(Vec::new()));
+        let hook = {
+            let heard = heard.clone();
+            StrictBinding::Hook(Arc::new(move |uuid| heard.lock().unwrap().push(uuid)))
+        };
+        assert_eq!(
+            check_bindings(&[TYPO, LEVEL, TYPO], &created(), &hook),
+            Ok(vec![TYPO])
+        );
+        assert_eq!(*heard.lock().unwrap(), [TYPO]);
+    }
+}
@@ -0,0 +1,96 @@
+//! Attribute handle accounting.
+//!
+//! Every service needs one handle for its declaration, every characteristic
+//! two (declaration + value) and every descriptor one more. These functions
+//! are `const` so statically declared services can check their budget at
+//! compile time with [`assert_handle_budget!`](crate::assert_handle_budget).
+
+/// Shape of one characteristic as far as handle usage is concerned.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct CharShape {
+    pub cccd: bool,
+    pub user_description: bool,
+    /// Any further descriptors.
+    pub extra_descriptors: u16,
+}
+
+impl CharShape {
+    /// A characteristic without descriptors.
+    pub const PLAIN: Self = Self::new(false, false);
+    /// A notifying or indicating characteristic.
+    pub const WITH_CCCD: Self = Self::new(true, false);
+
+    pub const fn new(cccd: bool, user_description: bool) -> Self {
+        Self {
+            cccd,
+            user_description,
+            extra_descriptors: 0,
+        }
+    }
+
+    pub const fn handles(&self) -> u16 {
+        (2 + self.cccd as u16 + self.user_description as u16).saturating_add(self.extra_descriptors)
+    }
+}
+
+/// Handles needed by a service with the given characteristics.
+pub const fn service_handles(characteristics: &[CharShape]) -> u16 {
+    let mut handles = 1;
+    let mut i = 0;
+    while i < characteristics.len() {
+        handles = characteristics[i].handles().saturating_add(handles);
+        i += 1;
+    }
+
+    handles
+}
+
+/// Fails the build if `$shapes` needs more than `$num_handles` handles.
+///
+/// ```
+/// use esp_gatt_rs_demo::assert_handle_budget;
+/// use esp_gatt_rs_demo::ble::budget::CharShape;
+///
+/// const NUM_HANDLES: u16 = 8;
+///
+/// assert_handle_budget!(
+///     [CharShape::PLAIN, CharShape::WITH_CCCD, CharShape::PLAIN],
+///     NUM_HANDLES
+/// );
+/// ```
+#[macro_export]
+macro_rules! assert_handle_budget {
+    ($shapes:expr, $num_handles:expr) => {
+        const _: () = assert!(
+            $crate::ble::budget::service_handles(&$shapes) <= $num_handles,
+            "service needs more attribute handles than reserved"
+        );
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_declarations_values_and_descriptors() {
+        assert_eq!(service_handles(&[]), 1);
+        assert_eq!(CharShape::PLAIN.handles(), 2);
+        assert_eq!(CharShape::WITH_CCCD.handles(), 3);
+        assert_eq!(CharShape::new(true, true).handles(), 4);
+        assert_eq!(
+            service_handles(&[CharShape::PLAIN, CharShape::WITH_CCCD, CharShape::PLAIN]),
+            8
+        );
+    }
+
+    #[test]
+    fn saturates_instead_of_overflowing() {
+        let huge = CharShape {
+            extra_descriptors: u16::MAX,
+            ..CharShape::PLAIN
+        };
+        assert_eq!(huge.handles(), u16::MAX);
+        assert_eq!(service_handles(&[huge, huge]), u16::MAX);
+    }
+}
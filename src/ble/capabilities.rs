@@ -0,0 +1,179 @@
+//! Optional subsystems compiled into this build.
+//!
+//! Chips and sdkconfig baselines differ in what the stack offers: BLE 5
+//! extended advertising, SMP, and so on. [`capabilities`] reports what this
+//! build has, from the `esp_idf_*` cfgs and the crate features, so code can
+//! branch at runtime instead of repeating the cfgs. Optional APIs fail with
+//! [`Unsupported`] when their capability is missing, and
+//! [`super::preflight::check_capabilities`] turns a configuration that needs
+//! a missing capability into a fatal issue before anything starts.
+
+use core::fmt;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Capability {
+    Bluedroid,
+    /// BLE 5 extended advertising.
+    ExtendedAdvertising,
+    /// Pairing and bonding (SMP).
+    Security,
+    /// LE credit-based channels; no stack glue exists for them yet.
+    L2capCoc,
+    /// Deserializing a `ServerSpec` from JSON.
+    SerdeConfig,
+    /// Characteristic doc strings, see [`crate::doc_str!`].
+    RichDocs,
+}
+
+impl Capability {
+    pub const ALL: [Self; 6] = [
+        Self::Bluedroid,
+        Self::ExtendedAdvertising,
+        Self::Security,
+        Self::L2capCoc,
+        Self::SerdeConfig,
+        Self::RichDocs,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Bluedroid => "bluedroid",
+            Self::ExtendedAdvertising => "extended advertising",
+            Self::Security => "security",
+            Self::L2capCoc => "L2CAP CoC",
+            Self::SerdeConfig => "serde config",
+            Self::RichDocs => "rich docs",
+        }
+    }
+
+    /// What to change to get it.
+    pub fn fix(self) -> &'static str {
+        match self {
+            Self::Bluedroid => "CONFIG_BT_BLUEDROID_ENABLED=y",
+            Self::ExtendedAdvertising => "CONFIG_BT_BLE_50_FEATURES_SUPPORTED=y",
+            Self::Security => "CONFIG_BT_BLE_SMP_ENABLE=y",
+            Self::L2capCoc => "not available in this crate yet",
+            Self::SerdeConfig => "the `serde` crate feature",
+            Self::RichDocs => "the `rich-docs` crate feature",
+        }
+    }
+}
+
+impl fmt::Display for Capability {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// An optional API was called without its capability.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Unsupported(pub Capability);
+
+impl fmt::Display for Unsupported {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} is not supported by this build (needs {})",
+            self.0,
+            self.0.fix()
+        )
+    }
+}
+
+impl std::error::Error for Unsupported {}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Capabilities {
+    pub bluedroid: bool,
+    pub extended_advertising: bool,
+    pub security: bool,
+    pub l2cap_coc: bool,
+    pub serde_config: bool,
+    pub rich_docs: bool,
+}
+
+impl Capabilities {
+    pub const fn has(&self, capability: Capability) -> bool {
+        match capability {
+            Capability::Bluedroid => self.bluedroid,
+            Capability::ExtendedAdvertising => self.extended_advertising,
+            Capability::Security => self.security,
+            Capability::L2capCoc => self.l2cap_coc,
+            Capability::SerdeConfig => self.serde_config,
+            Capability::RichDocs => self.rich_docs,
+        }
+    }
+
+    /// The check at the top of every optional API.
+    pub fn require(&self, capability: Capability) -> Result<(), Unsupported> {
+        if self.has(capability) {
+            Ok(())
+        } else {
+            Err(Unsupported(capability))
+        }
+    }
+
+    /// The capabilities of `required` this build lacks.
+    pub fn missing(&self, required: &[Capability]) -> Vec<Capability> {
+        let mut missing = Vec::new();
+        for &capability in required {
+            if !self.has(capability) && !missing.contains(&capability) {
+                missing.push(capability);
+            }
+        }
+        missing
+    }
+}
+
+/// What this build was compiled with.
+pub const fn capabilities() -> Capabilities {
+    let esp = cfg!(feature = "esp");
+    let bluedroid = esp && cfg!(esp_idf_bt_bluedroid_enabled);
+
+    Capabilities {
+        bluedroid,
+        extended_advertising: bluedroid && cfg!(esp_idf_bt_ble_50_features_supported),
+        security: bluedroid && cfg!(esp_idf_bt_ble_smp_enable),
+        l2cap_coc: false,
+        serde_config: cfg!(feature = "serde"),
+        rich_docs: cfg!(feature = "rich-docs"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn this_build_follows_its_features() {
+        let built = capabilities();
+
+        assert_eq!(built.has(Capability::SerdeConfig), cfg!(feature = "serde"));
+        assert_eq!(built.has(Capability::RichDocs), cfg!(feature = "rich-docs"));
+        assert!(!built.has(Capability::L2capCoc));
+        if !cfg!(feature = "esp") {
+            assert!(!built.has(Capability::Bluedroid));
+        }
+        // Both live inside Bluedroid.
+        for capability in [Capability::ExtendedAdvertising, Capability::Security] {
+            assert!(!built.has(capability) || built.has(Capability::Bluedroid));
+        }
+    }
+
+    #[test]
+    fn require_and_missing_agree_with_has() {
+        let built = capabilities();
+
+        for capability in Capability::ALL {
+            assert_eq!(built.require(capability).is_ok(), built.has(capability));
+            assert_eq!(
+                built.missing(&[capability, capability]),
+                if built.has(capability) {
+                    Vec::new()
+                } else {
+                    vec![capability]
+                }
+            );
+        }
+    }
+}
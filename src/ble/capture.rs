@@ -0,0 +1,253 @@
+//! Recording live GATTS sessions and replaying them on the host.
+//!
+//! The dispatch path feeds every event to a [`SessionCapture`], which writes
+//! it to a sink (a flash partition, the serial port) in the
+//! [`crate::proto::capture`] format. Payload bytes are only written with
+//! [`CaptureOptions::include_payloads`], and never for handles marked
+//! sensitive; their records keep only the length.
+//!
+//! [`replay`] reads a capture back into a [`ScriptedStack`], with the
+//! original spacing or as fast as possible, to reproduce a field bug on the
+//! host.
+
+use std::collections::HashSet;
+use std::io::{self, Read, Write};
+use std::time::Duration;
+
+use crate::proto::capture::{CaptureError, CaptureReader, CaptureRecord, CaptureWriter};
+
+use super::event_ring::{EventKind, EventRecord};
+use super::scripted::{EventHandler, ScriptedStack};
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct CaptureOptions {
+    /// Off by default: payloads can hold user data.
+    pub include_payloads: bool,
+}
+
+#[derive(Debug)]
+pub struct SessionCapture<W> {
+    writer: CaptureWriter<W>,
+    options: CaptureOptions,
+    sensitive: HashSet<u16>,
+    last_ms: Option<u32>,
+    records: usize,
+}
+
+impl<W: Write> SessionCapture<W> {
+    /// Writes the capture header to `sink`.
+    pub fn start(sink: W, options: CaptureOptions) -> io::Result<Self> {
+        Ok(Self {
+            writer: CaptureWriter::new(sink)?,
+            options,
+            sensitive: HashSet::new(),
+            last_ms: None,
+            records: 0,
+        })
+    }
+
+    /// Payloads on `handle` are never captured, whatever the options.
+    pub fn mark_sensitive(&mut self, handle: u16) {
+        self.sensitive.insert(handle);
+    }
+
+    pub fn records(&self) -> usize {
+        self.records
+    }
+
+    /// Records one event. `payload` may be only the start of the value;
+    /// the record keeps `event.len` either way. It is truncated to
+    /// `event.len` bytes.
+    pub fn record(
+        &mut self,
+        event: &EventRecord,
+        payload: Option<&[u8]>,
+    ) -> Result<(), CaptureError> {
+        let delta_ms = self
+            .last_ms
+            .map_or(0, |last| event.timestamp_ms.wrapping_sub(last));
+        self.last_ms = Some(event.timestamp_ms);
+
+        let payload = payload
+            .filter(|_| self.options.include_payloads && !self.sensitive.contains(&event.handle))
+            .map(|payload| payload[..payload.len().min(usize::from(event.len))].to_vec());
+
+        self.writer.write(&CaptureRecord {
+            kind: event.kind as u8,
+            conn_id: event.conn_id,
+            handle: event.handle,
+            status: event.status,
+            delta_ms,
+            len: event.len,
+            payload,
+        })?;
+        self.records += 1;
+        Ok(())
+    }
+
+    /// Flushes the sink and hands it back.
+    pub fn stop(mut self) -> io::Result<W> {
+        self.writer.flush()?;
+        Ok(self.writer.into_inner())
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum ReplayTiming {
+    /// Sleeps between events as long as the capture says.
+    Original,
+    #[default]
+    AsFastAsPossible,
+}
+
+/// Delivers every event of the capture in `source` to `stack`, in order,
+/// and returns how many were delivered. Timestamps count from the start of
+/// the capture. Records of unknown kinds are skipped.
+pub fn replay<H: EventHandler>(
+    source: impl Read,
+    timing: ReplayTiming,
+    stack: &mut ScriptedStack<H>,
+) -> Result<usize, CaptureError> {
+    let mut reader = CaptureReader::new(source)?;
+    let mut timestamp_ms = 0u32;
+    let mut fed = 0;
+
+    while let Some(record) = reader.next_record()? {
+        timestamp_ms = timestamp_ms.wrapping_add(record.delta_ms);
+
+        let Some(kind) = EventKind::from_raw(record.kind) else {
+            log::warn!("replay: skipping record of unknown kind {}", record.kind);
+            continue;
+        };
+
+        if timing == ReplayTiming::Original && record.delta_ms > 0 {
+            std::thread::sleep(Duration::from_millis(record.delta_ms.into()));
+        }
+
+        stack.deliver(
+            EventRecord {
+                kind,
+                conn_id: record.conn_id,
+                handle: record.handle,
+                status: record.status,
+                len: record.len,
+                timestamp_ms,
+            },
+            record.payload,
+        );
+        fed += 1;
+    }
+    Ok(fed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ble::scripted::ScriptedEvent;
+
+    const SENSITIVE: u16 = 0x30;
+
+    /// Captures everything delivered to it, as the dispatch path would.
+    struct Recorder(SessionCapture<Vec<u8>>);
+
+    impl EventHandler for Recorder {
+        fn on_event(&mut self, event: &EventRecord, payload: Option<&[u8]>) {
+            self.0.record(event, payload).unwrap();
+        }
+    }
+
+    fn recorder(include_payloads: bool) -> Recorder {
+        let mut capture =
+            SessionCapture::start(Vec::new(), CaptureOptions { include_payloads }).unwrap();
+        capture.mark_sensitive(SENSITIVE);
+        Recorder(capture)
+    }
+
+    fn event(kind: EventKind, handle: u16, len: u16, timestamp_ms: u32) -> EventRecord {
+        EventRecord {
+            kind,
+            conn_id: 3,
+            handle,
+            status: 0,
+            len,
+            timestamp_ms,
+        }
+    }
+
+    /// A phone session: connect, MTU, a long write of which only the start
+    /// is passed on, a write to a sensitive handle, a read, disconnect.
+    fn script(stack: &mut ScriptedStack<Recorder>) {
+        stack.deliver(event(EventKind::Connect, 0, 0, 10_000), None);
+        stack.deliver(event(EventKind::Mtu, 0, 0, 10_040), None);
+        stack.deliver(
+            event(EventKind::Write, 0x2a, 512, 10_300),
+            Some(b"head".to_vec()),
+        );
+        stack.deliver(
+            event(EventKind::Write, SENSITIVE, 6, 10_310),
+            Some(b"secret".to_vec()),
+        );
+        stack.deliver(event(EventKind::Read, 0x2a, 0, 11_000), Some(Vec::new()));
+        stack.deliver(event(EventKind::Disconnect, 0, 0, 12_500), None);
+    }
+
+    fn capture(include_payloads: bool) -> Vec<u8> {
+        let mut stack = ScriptedStack::new(recorder(include_payloads));
+        script(&mut stack);
+        let Recorder(capture) = stack.into_handler();
+        assert_eq!(capture.records(), 6);
+        capture.stop().unwrap()
+    }
+
+    #[test]
+    fn capture_replay_round_trip_is_deterministic() {
+        let bytes = capture(true);
+
+        let mut replayed = ScriptedStack::new(recorder(true));
+        let fed = replay(
+            bytes.as_slice(),
+            ReplayTiming::AsFastAsPossible,
+            &mut replayed,
+        );
+        assert_eq!(fed.unwrap(), 6);
+
+        let transcript: Vec<_> = replayed
+            .transcript()
+            .iter()
+            .map(|scripted| (scripted.event.timestamp_ms, scripted.event.len))
+            .collect();
+        assert_eq!(
+            transcript,
+            [(0, 0), (40, 0), (300, 512), (310, 6), (1000, 0), (2500, 0)]
+        );
+        assert_eq!(
+            replayed.transcript()[2],
+            ScriptedEvent {
+                event: event(EventKind::Write, 0x2a, 512, 300),
+                payload: Some(b"head".to_vec()),
+            }
+        );
+        assert_eq!(replayed.transcript()[3].payload, None);
+
+        // Capturing the replay gives the same bytes back.
+        let Recorder(recaptured) = replayed.into_handler();
+        assert_eq!(recaptured.stop().unwrap(), bytes);
+    }
+
+    #[test]
+    fn payloads_are_opt_in() {
+        let mut replayed = ScriptedStack::new(recorder(false));
+        replay(
+            capture(false).as_slice(),
+            ReplayTiming::AsFastAsPossible,
+            &mut replayed,
+        )
+        .unwrap();
+
+        assert!(replayed
+            .transcript()
+            .iter()
+            .all(|scripted| scripted.payload.is_none()));
+        assert_eq!(replayed.transcript()[2].event.len, 512);
+    }
+}
@@ -0,0 +1,110 @@
+//! Interior mutability for services shared as `Arc<dyn ...>`.
+//!
+//! Handler methods take `&self`, so stateful services need a lock. Holding
+//! that lock across a gatts call risks deadlocking against the Bluedroid task
+//! calling back into the same service. [`ServiceCell`] only hands out the
+//! state inside a closure, and gatts call sites can check with
+//! [`debug_assert_no_cell_held`] that no cell is locked on the current thread.
+//!
+//! ```
+//! use esp_gatt_rs_demo::ble::cell::ServiceCell;
+//!
+//! struct CounterService {
+//!     writes: ServiceCell<u32>,
+//! }
+//!
+//! impl CounterService {
+//!     fn on_write(&self, _value: &[u8]) -> u32 {
+//!         let count = self.writes.with(|writes| {
+//!             *writes += 1;
+//!             *writes
+//!         });
+//!
+//!         // The lock is released here, before notifying the new count.
+//!         count
+//!     }
+//! }
+//!
+//! let service = CounterService { writes: ServiceCell::new(0) };
+//! service.on_write(b"a");
+//! assert_eq!(service.on_write(b"b"), 2);
+//! ```
+
+use core::cell::Cell;
+use std::sync::{Mutex, MutexGuard, PoisonError, TryLockError};
+
+thread_local! {
+    static HELD: Cell<usize> = const { Cell::new(0) };
+}
+
+#[derive(Debug, Default)]
+pub struct ServiceCell<S> {
+    state: Mutex<S>,
+}
+
+impl<S> ServiceCell<S> {
+    pub const fn new(state: S) -> Self {
+        Self {
+            state: Mutex::new(state),
+        }
+    }
+
+    /// Runs `f` with exclusive access to the state.
+    ///
+    /// A panic in an earlier critical section does not make the state
+    /// unusable: the poisoned lock is recovered.
+    pub fn with<R>(&self, f: impl FnOnce(&mut S) -> R) -> R {
+        let guard = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+
+        Self::run(guard, f)
+    }
+
+    /// Like [`with`](Self::with) but returns `None` instead of blocking when
+    /// the state is locked, e.g. from callback contexts that must not wait.
+    pub fn try_with<R>(&self, f: impl FnOnce(&mut S) -> R) -> Option<R> {
+        let guard = match self.state.try_lock() {
+            Ok(guard) => guard,
+            Err(TryLockError::Poisoned(poisoned)) => poisoned.into_inner(),
+            Err(TryLockError::WouldBlock) => return None,
+        };
+
+        Some(Self::run(guard, f))
+    }
+
+    pub fn into_inner(self) -> S {
+        self.state
+            .into_inner()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn run<R>(mut guard: MutexGuard<'_, S>, f: impl FnOnce(&mut S) -> R) -> R {
+        let _held = HeldMarker::enter();
+
+        f(&mut guard)
+    }
+}
+
+/// Debug-asserts that the current thread holds no [`ServiceCell`].
+///
+/// Call this before every gatts call (send_indicate, send_response, ...).
+pub fn debug_assert_no_cell_held(operation: &str) {
+    debug_assert!(
+        HELD.with(Cell::get) == 0,
+        "{operation} called while a ServiceCell is locked"
+    );
+}
+
+struct HeldMarker;
+
+impl HeldMarker {
+    fn enter() -> Self {
+        HELD.with(|held| held.set(held.get() + 1));
+        Self
+    }
+}
+
+impl Drop for HeldMarker {
+    fn drop(&mut self) {
+        HELD.with(|held| held.set(held.get() - 1));
+    }
+}
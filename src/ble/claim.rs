@@ -0,0 +1,434 @@
+//! Single-writer ownership of control characteristics.
+//!
+//! With two centrals connected, interleaved command/response flows on one
+//! control characteristic corrupt each other. A handle designated in
+//! [`WriteClaims`] is owned by the first connection writing to it; writes
+//! from every other connection are refused with [`ATT_ERR_CLAIMED`] until
+//! the owner releases the claim, disconnects, or stays idle past the
+//! handle's timeout. The owning service hears about each change through its
+//! [`ClaimListener`].
+
+use core::fmt;
+use core::time::Duration;
+use std::collections::BTreeMap;
+use std::time::Instant;
+
+/// ATT application error: another connection holds the claim.
+pub const ATT_ERR_CLAIMED: u8 = 0x85;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ReleaseReason {
+    /// The owner released it, e.g. with a release command.
+    Released,
+    Disconnected,
+    /// The owner did not write for longer than the timeout.
+    TimedOut,
+}
+
+/// Callbacks of the service owning a designated handle.
+pub trait ClaimListener: Send {
+    fn on_claim_acquired(&mut self, _handle: u16, _conn_id: u16) {}
+
+    fn on_claim_released(&mut self, _handle: u16, _conn_id: u16, _reason: ReleaseReason) {}
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+struct Claim {
+    owner: u16,
+    last_write: Instant,
+}
+
+struct Designated {
+    /// Idle time after which the claim lapses; never when `None`.
+    timeout: Option<Duration>,
+    listener: Option<Box<dyn ClaimListener>>,
+    claim: Option<Claim>,
+}
+
+#[derive(Default)]
+pub struct WriteClaims {
+    handles: BTreeMap<u16, Designated>,
+    rejected: u64,
+}
+
+impl fmt::Debug for WriteClaims {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WriteClaims")
+            .field("owners", &self.owners())
+            .field("rejected", &self.rejected)
+            .finish_non_exhaustive()
+    }
+}
+
+impl WriteClaims {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Puts `handle` under single-writer ownership.
+    pub fn designate(
+        &mut self,
+        handle: u16,
+        timeout: Option<Duration>,
+        listener: Option<Box<dyn ClaimListener>>,
+    ) {
+        self.handles.insert(
+            handle,
+            Designated {
+                timeout,
+                listener,
+                claim: None,
+            },
+        );
+    }
+
+    /// Checks a write and claims the handle for `conn_id` if it is free.
+    /// The `Err` is the ATT status.
+    pub fn check_write(&mut self, conn_id: u16, handle: u16, now: Instant) -> Result<(), u8> {
+        let Some(designated) = self.handles.get_mut(&handle) else {
+            return Ok(());
+        };
+
+        if let Some(claim) = designated.claim {
+            if claim.owner != conn_id && !designated.expired(now) {
+                self.rejected += 1;
+                return Err(ATT_ERR_CLAIMED);
+            }
+            if claim.owner != conn_id {
+                designated.release(handle, ReleaseReason::TimedOut);
+            }
+        }
+
+        match &mut designated.claim {
+            Some(claim) => claim.last_write = now,
+            None => {
+                designated.claim = Some(Claim {
+                    owner: conn_id,
+                    last_write: now,
+                });
+                log::info!("connection {conn_id} claimed handle {handle}");
+                if let Some(listener) = &mut designated.listener {
+                    listener.on_claim_acquired(handle, conn_id);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Explicit release by the owner; `false` if `conn_id` does not own it.
+    pub fn release(&mut self, conn_id: u16, handle: u16) -> bool {
+        match self.handles.get_mut(&handle) {
+            Some(designated) if designated.owner() == Some(conn_id) => {
+                designated.release(handle, ReleaseReason::Released);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn on_disconnect(&mut self, conn_id: u16) {
+        for (&handle, designated) in &mut self.handles {
+            if designated.owner() == Some(conn_id) {
+                designated.release(handle, ReleaseReason::Disconnected);
+            }
+        }
+    }
+
+    /// Releases the claims idle past their timeout; call when
+    /// [`next_deadline`](Self::next_deadline) passes.
+    pub fn sweep(&mut self, now: Instant) {
+        for (&handle, designated) in &mut self.handles {
+            if designated.expired(now) {
+                designated.release(handle, ReleaseReason::TimedOut);
+            }
+        }
+    }
+
+    /// When the next held claim times out.
+    pub fn next_deadline(&self) -> Option<Instant> {
+        self.handles
+            .values()
+            .filter_map(|designated| Some(designated.claim?.last_write + designated.timeout?))
+            .min()
+    }
+
+    pub fn owner(&self, handle: u16) -> Option<u16> {
+        self.handles.get(&handle)?.owner()
+    }
+
+    /// Claimed handles and their owners, by handle.
+    pub fn owners(&self) -> Vec<(u16, u16)> {
+        self.handles
+            .iter()
+            .filter_map(|(&handle, designated)| Some((handle, designated.owner()?)))
+            .collect()
+    }
+
+    /// Writes refused because another connection held the claim.
+    pub fn rejected(&self) -> u64 {
+        self.rejected
+    }
+
+    /// Logs every designated handle as `claim handle=<h> owner=<conn|->`.
+    pub fn dump(&self) {
+        for (&handle, designated) in &self.handles {
+            match designated.owner() {
+                Some(owner) => log::info!("claim handle={handle} owner={owner}"),
+                None => log::info!("claim handle={handle} owner=-"),
+            }
+        }
+    }
+}
+
+impl Designated {
+    fn owner(&self) -> Option<u16> {
+        self.claim.map(|claim| claim.owner)
+    }
+
+    fn expired(&self, now: Instant) -> bool {
+        match (self.claim, self.timeout) {
+            (Some(claim), Some(timeout)) => {
+                now.saturating_duration_since(claim.last_write) > timeout
+            }
+            _ => false,
+        }
+    }
+
+    fn release(&mut self, handle: u16, reason: ReleaseReason) {
+        let Some(claim) = self.claim.take() else {
+            return;
+        };
+
+        log::info!(
+            "connection {} lost claim on handle {handle}: {reason:?}",
+            claim.owner
+        );
+        if let Some(listener) = &mut self.listener {
+            listener.on_claim_released(handle, claim.owner, reason);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+
+    const CONTROL: u16 = 42;
+    const OTHER: u16 = 43;
+    const TIMEOUT: Duration = Duration::from_secs(30);
+
+    #[derive(Clone, Debug, PartialEq, Eq)]
+    enum Heard {
+        Acquired(u16, u16),
+        Released(u16, u16, ReleaseReason),
+    }
+
+    #[derive(Clone, Default)]
+    struct Recorder(Arc<Mutex<Vec<Heard>>>);
+
+    impl Recorder {
+        fn take(&self) -> Vec<Heard> {
+            core::mem::take(&mut self.0.lock().unwrap())
+        }
+    }
+
+    impl ClaimListener for Recorder {
+        fn on_claim_acquired(&mut self, handle: u16, conn_id: u16) {
+            self.0
+                .lock()
+                .unwrap()
+                .push(Heard::Acquired(handle, conn_id));
+        }
+
+        fn on_claim_released(&mut self, handle: u16, conn_id: u16, reason: ReleaseReason) {
+            self.0
+                .lock()
+                .unwrap()
+                .push(Heard::Released(handle, conn_id, reason));
+        }
+    }
+
+    fn claims(timeout: Option<Duration>) -> (WriteClaims, Recorder) {
+        let recorder = Recorder::default();
+        let mut claims = WriteClaims::new();
+        claims.designate(CONTROL, timeout, Some(Box::new(recorder.clone())));
+        (claims, recorder)
+    }
+
+    #[test]
+    fn first_writer_owns_the_handle() {
+        let (mut claims, recorder) = claims(Some(TIMEOUT));
+        let now = Instant::now();
+
+        assert_eq!(claims.check_write(1, CONTROL, now), Ok(()));
+        assert_eq!(claims.check_write(1, CONTROL, now), Ok(()));
+        assert_eq!(claims.check_write(2, CONTROL, now), Err(ATT_ERR_CLAIMED));
+        assert_eq!(claims.check_write(2, CONTROL, now), Err(0x85));
+
+        assert_eq!(claims.owner(CONTROL), Some(1));
+        assert_eq!(claims.owners(), [(CONTROL, 1)]);
+        assert_eq!(claims.rejected(), 2);
+        assert_eq!(recorder.take(), [Heard::Acquired(CONTROL, 1)]);
+    }
+
+    #[test]
+    fn undesignated_handles_are_not_claimed() {
+        let (mut claims, recorder) = claims(Some(TIMEOUT));
+        let now = Instant::now();
+
+        assert_eq!(claims.check_write(1, OTHER, now), Ok(()));
+        assert_eq!(claims.check_write(2, OTHER, now), Ok(()));
+        assert_eq!(claims.owner(OTHER), None);
+        assert!(claims.owners().is_empty());
+        assert_eq!(claims.rejected(), 0);
+        assert!(recorder.take().is_empty());
+    }
+
+    #[test]
+    fn release_only_by_the_owner() {
+        let (mut claims, recorder) = claims(None);
+        let now = Instant::now();
+        claims.check_write(1, CONTROL, now).unwrap();
+
+        assert!(!claims.release(2, CONTROL));
+        assert!(!claims.release(1, OTHER));
+        assert_eq!(claims.owner(CONTROL), Some(1));
+
+        assert!(claims.release(1, CONTROL));
+        assert!(!claims.release(1, CONTROL));
+        assert_eq!(claims.owner(CONTROL), None);
+
+        assert_eq!(claims.check_write(2, CONTROL, now), Ok(()));
+        assert_eq!(claims.owner(CONTROL), Some(2));
+        assert_eq!(
+            recorder.take(),
+            [
+                Heard::Acquired(CONTROL, 1),
+                Heard::Released(CONTROL, 1, ReleaseReason::Released),
+                Heard::Acquired(CONTROL, 2),
+            ]
+        );
+    }
+
+    #[test]
+    fn disconnect_releases_every_claim_of_the_connection() {
+        let (mut claims, recorder) = claims(None);
+        claims.designate(OTHER, None, None);
+        let now = Instant::now();
+        claims.check_write(1, CONTROL, now).unwrap();
+        claims.check_write(1, OTHER, now).unwrap();
+
+        claims.on_disconnect(2);
+        assert_eq!(claims.owners(), [(CONTROL, 1), (OTHER, 1)]);
+
+        claims.on_disconnect(1);
+        assert!(claims.owners().is_empty());
+        assert_eq!(
+            recorder.take(),
+            [
+                Heard::Acquired(CONTROL, 1),
+                Heard::Released(CONTROL, 1, ReleaseReason::Disconnected),
+            ]
+        );
+    }
+
+    #[test]
+    fn claim_expires_strictly_after_the_timeout() {
+        let (mut claims, recorder) = claims(Some(TIMEOUT));
+        let start = Instant::now();
+        claims.check_write(1, CONTROL, start).unwrap();
+
+        // Exactly at the timeout the owner still holds it.
+        assert_eq!(claims.next_deadline(), Some(start + TIMEOUT));
+        assert_eq!(
+            claims.check_write(2, CONTROL, start + TIMEOUT),
+            Err(ATT_ERR_CLAIMED)
+        );
+        claims.sweep(start + TIMEOUT);
+        assert_eq!(claims.owner(CONTROL), Some(1));
+
+        // Past it, another connection takes over.
+        let later = start + TIMEOUT + Duration::from_millis(1);
+        assert_eq!(claims.check_write(2, CONTROL, later), Ok(()));
+        assert_eq!(claims.owner(CONTROL), Some(2));
+        assert_eq!(claims.next_deadline(), Some(later + TIMEOUT));
+        assert_eq!(claims.rejected(), 1);
+        assert_eq!(
+            recorder.take(),
+            [
+                Heard::Acquired(CONTROL, 1),
+                Heard::Released(CONTROL, 1, ReleaseReason::TimedOut),
+                Heard::Acquired(CONTROL, 2),
+            ]
+        );
+    }
+
+    #[test]
+    fn owner_writes_extend_the_claim() {
+        let (mut claims, _) = claims(Some(TIMEOUT));
+        let start = Instant::now();
+        claims.check_write(1, CONTROL, start).unwrap();
+
+        let refreshed = start + TIMEOUT;
+        claims.check_write(1, CONTROL, refreshed).unwrap();
+        assert_eq!(claims.next_deadline(), Some(refreshed + TIMEOUT));
+
+        claims.sweep(start + TIMEOUT * 2);
+        assert_eq!(claims.owner(CONTROL), Some(1));
+        claims.sweep(refreshed + TIMEOUT + Duration::from_millis(1));
+        assert_eq!(claims.owner(CONTROL), None);
+        assert_eq!(claims.next_deadline(), None);
+    }
+
+    #[test]
+    fn sweep_releases_only_idle_claims() {
+        let (mut claims, recorder) = claims(Some(TIMEOUT));
+        claims.designate(OTHER, Some(TIMEOUT * 2), None);
+        let start = Instant::now();
+        claims.check_write(1, CONTROL, start).unwrap();
+        claims.check_write(2, OTHER, start).unwrap();
+
+        assert_eq!(claims.next_deadline(), Some(start + TIMEOUT));
+        claims.sweep(start + TIMEOUT + Duration::from_secs(1));
+
+        assert_eq!(claims.owners(), [(OTHER, 2)]);
+        assert_eq!(claims.next_deadline(), Some(start + TIMEOUT * 2));
+        assert_eq!(
+            recorder.take(),
+            [
+                Heard::Acquired(CONTROL, 1),
+                Heard::Released(CONTROL, 1, ReleaseReason::TimedOut),
+            ]
+        );
+    }
+
+    #[test]
+    fn claims_without_timeout_never_expire() {
+        let (mut claims, _) = claims(None);
+        let start = Instant::now();
+        claims.check_write(1, CONTROL, start).unwrap();
+
+        assert_eq!(claims.next_deadline(), None);
+        let much_later = start + Duration::from_secs(24 * 3600);
+        claims.sweep(much_later);
+        assert_eq!(
+            claims.check_write(2, CONTROL, much_later),
+            Err(ATT_ERR_CLAIMED)
+        );
+        assert_eq!(claims.owner(CONTROL), Some(1));
+    }
+
+    #[test]
+    fn redesignating_drops_the_claim() {
+        let (mut claims, recorder) = claims(None);
+        let now = Instant::now();
+        claims.check_write(1, CONTROL, now).unwrap();
+
+        claims.designate(CONTROL, None, None);
+        assert_eq!(claims.owner(CONTROL), None);
+        assert_eq!(claims.check_write(2, CONTROL, now), Ok(()));
+        assert_eq!(recorder.take(), [Heard::Acquired(CONTROL, 1)]);
+    }
+}
@@ -0,0 +1,82 @@
+//! Radio starvation detection under Wi-Fi coexistence.
+//!
+//! With Wi-Fi and BLE sharing the radio, heavy Wi-Fi traffic delays
+//! notifications. [`StarvationDetector`] is fed the time between enqueueing a
+//! notification and the controller accepting it, and reports when the rolling
+//! p95 crosses the threshold and when it falls back below it.
+
+use core::time::Duration;
+use std::collections::VecDeque;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum StarvationEvent {
+    Starved { p95: Duration },
+    Recovered { p95: Duration },
+}
+
+#[derive(Debug)]
+pub struct StarvationDetector {
+    samples: VecDeque<Duration>,
+    window: usize,
+    threshold: Duration,
+    starved: bool,
+}
+
+impl StarvationDetector {
+    /// `window` is the number of most recent samples the p95 is taken over.
+    pub fn new(window: usize, threshold: Duration) -> Self {
+        Self {
+            samples: VecDeque::with_capacity(window),
+            window: window.max(1),
+            threshold,
+            starved: false,
+        }
+    }
+
+    pub fn is_starved(&self) -> bool {
+        self.starved
+    }
+
+    /// 95th percentile of the current window, `None` before any sample.
+    pub fn p95(&self) -> Option<Duration> {
+        if self.samples.is_empty() {
+            return None;
+        }
+
+        let mut sorted: Vec<_> = self.samples.iter().copied().collect();
+        sorted.sort_unstable();
+        let rank = (sorted.len() * 95).div_ceil(100);
+        Some(sorted[rank.saturating_sub(1)])
+    }
+
+    /// Adds an enqueue-to-accept latency. Returns an event when the state
+    /// changes; nothing is reported until the window has filled.
+    pub fn record(&mut self, latency: Duration) -> Option<StarvationEvent> {
+        if self.samples.len() == self.window {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(latency);
+
+        if self.samples.len() < self.window {
+            return None;
+        }
+
+        let p95 = self.p95()?;
+        match (self.starved, p95 > self.threshold) {
+            (false, true) => {
+                self.starved = true;
+                Some(StarvationEvent::Starved { p95 })
+            }
+            (true, false) => {
+                self.starved = false;
+                Some(StarvationEvent::Recovered { p95 })
+            }
+            _ => None,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.samples.clear();
+        self.starved = false;
+    }
+}
@@ -0,0 +1,92 @@
+//! One read returning a snapshot of several values.
+//!
+//! A [`Composite`] calls its providers in order and joins their values
+//! into [`tlv`] records; a provider that fails contributes an error record
+//! instead of failing the read. A snapshot is taken at offset 0 and kept
+//! per connection, so the remaining blob reads of a value longer than the
+//! MTU continue the same snapshot.
+
+use std::collections::HashMap;
+use std::sync::{Mutex, PoisonError};
+
+use super::negotiation::ATT_ERR_INVALID_ATTRIBUTE_LEN;
+use super::long_read::read_blob;
+use crate::proto::tlv;
+
+/// Returns the current value, or an ATT status.
+pub type Provider = Box<dyn Fn() -> Result<Vec<u8>, u8> + Send + Sync>;
+
+pub struct Composite {
+    providers: Vec<(u8, Provider)>,
+    snapshots: Mutex<HashMap<u16, Vec<u8>>>,
+}
+
+impl core::fmt::Debug for Composite {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let tags: Vec<_> = self.providers.iter().map(|(tag, _)| *tag).collect();
+        f.debug_struct("Composite").field("tags", &tags).finish()
+    }
+}
+
+impl Composite {
+    pub fn new() -> Self {
+        Self {
+            providers: Vec::new(),
+            snapshots: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn with(
+        mut self,
+        tag: u8,
+        provider: impl Fn() -> Result<Vec<u8>, u8> + Send + Sync + 'static,
+    ) -> Self {
+        self.providers.push((tag, Box::new(provider)));
+        self
+    }
+
+    /// Calls every provider once and encodes the results.
+    pub fn snapshot(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+
+        for (tag, provider) in &self.providers {
+            match provider() {
+                Ok(value) => {
+                    if tlv::push(&mut buf, *tag, &value).is_none() {
+                        tlv::push_error(&mut buf, *tag, ATT_ERR_INVALID_ATTRIBUTE_LEN);
+                    }
+                }
+                Err(status) => tlv::push_error(&mut buf, *tag, status),
+            }
+        }
+
+        buf
+    }
+
+    /// Serves a read (blob) request of `conn_id`.
+    pub fn read(&self, conn_id: u16, offset: u16, mtu: u16) -> Result<Vec<u8>, u8> {
+        let mut snapshots = self
+            .snapshots
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        if offset == 0 {
+            snapshots.insert(conn_id, self.snapshot());
+        }
+
+        let snapshot = snapshots.entry(conn_id).or_insert_with(|| self.snapshot());
+        read_blob(snapshot, offset, mtu).map(<[u8]>::to_vec)
+    }
+
+    pub fn on_disconnect(&self, conn_id: u16) {
+        self.snapshots
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .remove(&conn_id);
+    }
+}
+
+impl Default for Composite {
+    fn default() -> Self {
+        Self::new()
+    }
+}
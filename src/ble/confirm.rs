@@ -0,0 +1,154 @@
+//! Two-phase confirmation of destructive commands.
+//!
+//! A gated command is not executed when written: [`ConfirmGate`] answers it
+//! with a random 4-byte challenge for the handler to indicate, and only a
+//! follow-up write echoing the challenge within the timeout confirms it. The
+//! pending challenge lives in the connection's
+//! [`Session`](super::session::Session), so a disconnect discards it.
+
+use core::time::Duration;
+use std::time::Instant;
+
+use super::session::Session;
+
+pub const CHALLENGE_LEN: usize = 4;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ConfirmOutcome {
+    /// Not a gated opcode; execute it directly.
+    Pass,
+    /// Indicate `challenge` to the client and wait for the echo.
+    Challenged {
+        challenge: [u8; CHALLENGE_LEN],
+    },
+    /// The echo matched; execute `opcode` now.
+    Confirmed {
+        opcode: u8,
+    },
+    Expired {
+        opcode: u8,
+    },
+    Mismatch {
+        opcode: u8,
+    },
+    /// An echo without a challenge outstanding.
+    NoPending,
+}
+
+#[derive(Debug)]
+struct Pending {
+    opcode: u8,
+    challenge: [u8; CHALLENGE_LEN],
+    expires_at: Instant,
+}
+
+pub struct ConfirmGate {
+    opcodes: Vec<u8>,
+    timeout: Duration,
+    rng: Box<dyn FnMut() -> u32 + Send>,
+}
+
+impl core::fmt::Debug for ConfirmGate {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("ConfirmGate")
+            .field("opcodes", &self.opcodes)
+            .field("timeout", &self.timeout)
+            .finish_non_exhaustive()
+    }
+}
+
+impl ConfirmGate {
+    /// Gates `opcodes`; `rng` supplies the challenges, normally
+    /// [`hardware_rng`].
+    pub fn new(
+        opcodes: &[u8],
+        timeout: Duration,
+        rng: impl FnMut() -> u32 + Send + 'static,
+    ) -> Self {
+        Self {
+            opcodes: opcodes.to_vec(),
+            timeout,
+            rng: Box::new(rng),
+        }
+    }
+
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    /// Handles a command write, replacing any challenge still pending.
+    pub fn on_command(
+        &mut self,
+        session: &mut Session,
+        opcode: u8,
+        now: Instant,
+    ) -> ConfirmOutcome {
+        if !self.opcodes.contains(&opcode) {
+            return ConfirmOutcome::Pass;
+        }
+
+        let challenge = (self.rng)().to_le_bytes();
+        // Kept past the deadline so a late echo is reported as expired; the
+        // session sweep drops it after that.
+        session.insert_with_ttl(
+            Pending {
+                opcode,
+                challenge,
+                expires_at: now + self.timeout,
+            },
+            self.timeout * 2,
+            now,
+        );
+        ConfirmOutcome::Challenged { challenge }
+    }
+
+    /// Handles the echo write. The challenge is used up whatever the
+    /// outcome.
+    pub fn on_confirm(
+        &mut self,
+        session: &mut Session,
+        echo: &[u8],
+        now: Instant,
+    ) -> ConfirmOutcome {
+        let Some(pending) = session.remove::<Pending>() else {
+            return ConfirmOutcome::NoPending;
+        };
+
+        if now >= pending.expires_at {
+            ConfirmOutcome::Expired {
+                opcode: pending.opcode,
+            }
+        } else if !constant_time_eq(echo, &pending.challenge) {
+            ConfirmOutcome::Mismatch {
+                opcode: pending.opcode,
+            }
+        } else {
+            ConfirmOutcome::Confirmed {
+                opcode: pending.opcode,
+            }
+        }
+    }
+
+    /// Drops a challenge whose deadline has passed, returning its opcode.
+    ///
+    /// Meant for a connection-scoped [`Scheduler`](super::scheduler::Scheduler)
+    /// task armed for [`timeout`](Self::timeout) after the challenge, so the
+    /// client can be told without waiting for its late echo.
+    pub fn expire(&self, session: &mut Session, now: Instant) -> Option<u8> {
+        let expired = session.get::<Pending>()?.expires_at <= now;
+        expired
+            .then(|| session.remove::<Pending>())
+            .flatten()
+            .map(|pending| pending.opcode)
+    }
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+/// Challenge source backed by the hardware RNG.
+#[cfg(feature = "esp")]
+pub fn hardware_rng() -> u32 {
+    unsafe { esp_idf_svc::sys::esp_random() }
+}
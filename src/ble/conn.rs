@@ -0,0 +1,131 @@
+//! Registry of live connections.
+
+use std::collections::HashMap;
+use std::time::Instant;
+
+use super::PeerAddr;
+
+/// ATT MTU before an exchange has completed.
+pub const DEFAULT_ATT_MTU: u16 = 23;
+
+/// Link security, from the GAP authentication and encryption events.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum SecurityLevel {
+    #[default]
+    None,
+    Encrypted,
+    /// Encrypted with MITM-protected keys.
+    EncryptedAuthenticated,
+}
+
+/// Address type reported with the connection.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub enum AddrType {
+    #[default]
+    Public,
+    Random,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Connection {
+    /// Address the peer connected with; may be a rotating RPA.
+    pub peer: PeerAddr,
+    pub peer_type: AddrType,
+    /// Identity address from the bond, once pairing has completed.
+    pub identity: Option<PeerAddr>,
+    pub connected_at: Instant,
+    /// Negotiated MTU; `None` until the client's MTU exchange.
+    pub mtu: Option<u16>,
+    pub security: SecurityLevel,
+}
+
+impl Connection {
+    /// Address to key peer lookups by: the identity address when known,
+    /// else the connection address.
+    pub fn identity_addr(&self) -> PeerAddr {
+        self.identity.unwrap_or(self.peer)
+    }
+
+    /// Whether the peer uses a resolvable private address whose identity is
+    /// not known yet.
+    pub fn is_unresolved(&self) -> bool {
+        self.identity.is_none() && is_rpa(&self.peer, self.peer_type)
+    }
+
+    pub fn mtu_exchanged(&self) -> bool {
+        self.mtu.is_some()
+    }
+
+    pub fn effective_mtu(&self) -> u16 {
+        self.mtu.unwrap_or(DEFAULT_ATT_MTU)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct ConnectionRegistry {
+    conns: HashMap<u16, Connection>,
+}
+
+impl ConnectionRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn on_connect(&mut self, conn_id: u16, peer: PeerAddr, peer_type: AddrType, now: Instant) {
+        let conn = Connection {
+            peer,
+            peer_type,
+            identity: None,
+            connected_at: now,
+            mtu: None,
+            security: SecurityLevel::None,
+        };
+        self.conns.insert(conn_id, conn);
+    }
+
+    pub fn on_mtu(&mut self, conn_id: u16, mtu: u16) {
+        if let Some(conn) = self.conns.get_mut(&conn_id) {
+            conn.mtu = Some(mtu);
+        }
+    }
+
+    pub fn on_security(&mut self, conn_id: u16, level: SecurityLevel) {
+        if let Some(conn) = self.conns.get_mut(&conn_id) {
+            conn.security = level;
+        }
+    }
+
+    /// Pairing completed; `identity` comes from the bond information.
+    pub fn on_identity(&mut self, conn_id: u16, identity: PeerAddr) {
+        if let Some(conn) = self.conns.get_mut(&conn_id) {
+            conn.identity = Some(identity);
+        }
+    }
+
+    pub fn on_disconnect(&mut self, conn_id: u16) -> Option<Connection> {
+        self.conns.remove(&conn_id)
+    }
+
+    pub fn get(&self, conn_id: u16) -> Option<&Connection> {
+        self.conns.get(&conn_id)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (u16, &Connection)> {
+        self.conns.iter().map(|(&conn_id, conn)| (conn_id, conn))
+    }
+
+    pub fn len(&self) -> usize {
+        self.conns.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.conns.is_empty()
+    }
+}
+
+/// Resolvable private addresses are random with the two most significant
+/// bits `01`.
+pub fn is_rpa(addr: &PeerAddr, addr_type: AddrType) -> bool {
+    addr_type == AddrType::Random && addr[0] >> 6 == 0b01
+}
@@ -0,0 +1,275 @@
+//! Application callback deciding on every new connection.
+//!
+//! Beyond static lists, firmware may accept connections only in pairing
+//! mode or as a cloud policy allows. The [`ConnectionAuthorizer`] runs the
+//! registered callback synchronously in the `PeerConnected` path; it can
+//! accept, reject, or accept read-only, in which case every write on the
+//! connection is answered with "insufficient authorization" until
+//! [`ConnectionAuthorizer::upgrade`] is called for it.
+//!
+//! The callback must return quickly: one taking longer than the time
+//! budget is logged and counted. One that panics counts as accepting, so a
+//! bug in it cannot lock everybody out (this relies on unwinding panics).
+
+use core::fmt;
+use core::time::Duration;
+use std::collections::HashSet;
+use std::panic::{self, AssertUnwindSafe};
+use std::time::Instant;
+
+use super::auth::ATT_ERR_INSUFFICIENT_AUTHORIZATION;
+use super::conn::AddrType;
+use super::PeerAddr;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ConnectionAttempt {
+    pub addr: PeerAddr,
+    pub addr_type: AddrType,
+    pub bonded: bool,
+    /// Connections open before this one.
+    pub connections: usize,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ConnAdmission {
+    Accept,
+    Reject {
+        reason: &'static str,
+    },
+    /// Reads are served, writes refused until upgraded.
+    AcceptReadOnly,
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct ConnAdmissionStats {
+    pub accepted: u64,
+    pub rejected: u64,
+    pub read_only: u64,
+    pub upgraded: u64,
+    /// Callback panics, counted as accepted too.
+    pub panicked: u64,
+    pub over_budget: u64,
+}
+
+pub type AuthorizerFn = Box<dyn Fn(&ConnectionAttempt) -> ConnAdmission + Send + Sync>;
+
+pub struct ConnectionAuthorizer {
+    authorizer: Option<AuthorizerFn>,
+    budget: Duration,
+    read_only: HashSet<u16>,
+    stats: ConnAdmissionStats,
+}
+
+impl fmt::Debug for ConnectionAuthorizer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ConnectionAuthorizer")
+            .field("budget", &self.budget)
+            .field("read_only", &self.read_only)
+            .field("stats", &self.stats)
+            .finish_non_exhaustive()
+    }
+}
+
+impl ConnectionAuthorizer {
+    /// Accepts everything until a callback is set.
+    pub fn new(budget: Duration) -> Self {
+        Self {
+            authorizer: None,
+            budget,
+            read_only: HashSet::new(),
+            stats: ConnAdmissionStats::default(),
+        }
+    }
+
+    pub fn set_authorizer(&mut self, authorizer: AuthorizerFn) {
+        self.authorizer = Some(authorizer);
+    }
+
+    pub fn stats(&self) -> ConnAdmissionStats {
+        self.stats
+    }
+
+    /// Decides on connection `conn_id`; the caller disconnects on reject.
+    pub fn authorize(&mut self, conn_id: u16, attempt: &ConnectionAttempt) -> ConnAdmission {
+        let admission = match &self.authorizer {
+            None => ConnAdmission::Accept,
+            Some(authorizer) => {
+                let started = Instant::now();
+                let result = panic::catch_unwind(AssertUnwindSafe(|| authorizer(attempt)));
+
+                let elapsed = started.elapsed();
+                if elapsed > self.budget {
+                    self.stats.over_budget += 1;
+                    log::warn!(
+                        "connection authorizer took {elapsed:?}, budget {:?}",
+                        self.budget
+                    );
+                }
+
+                result.unwrap_or_else(|_| {
+                    self.stats.panicked += 1;
+                    log::error!("connection authorizer panicked, accepting {conn_id}");
+                    ConnAdmission::Accept
+                })
+            }
+        };
+
+        match admission {
+            ConnAdmission::Accept => self.stats.accepted += 1,
+            ConnAdmission::Reject { .. } => self.stats.rejected += 1,
+            ConnAdmission::AcceptReadOnly => {
+                self.stats.read_only += 1;
+                self.read_only.insert(conn_id);
+            }
+        }
+        admission
+    }
+
+    /// Lifts read-only mode; returns whether the connection was read-only.
+    pub fn upgrade(&mut self, conn_id: u16) -> bool {
+        let upgraded = self.read_only.remove(&conn_id);
+        if upgraded {
+            self.stats.upgraded += 1;
+        }
+        upgraded
+    }
+
+    pub fn is_read_only(&self, conn_id: u16) -> bool {
+        self.read_only.contains(&conn_id)
+    }
+
+    /// Checks a write; the `Err` is the ATT status.
+    pub fn check_write(&self, conn_id: u16) -> Result<(), u8> {
+        if self.is_read_only(conn_id) {
+            Err(ATT_ERR_INSUFFICIENT_AUTHORIZATION)
+        } else {
+            Ok(())
+        }
+    }
+
+    pub fn on_disconnect(&mut self, conn_id: u16) {
+        self.read_only.remove(&conn_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn attempt(bonded: bool, connections: usize) -> ConnectionAttempt {
+        ConnectionAttempt {
+            addr: [1, 2, 3, 4, 5, 6],
+            addr_type: AddrType::Public,
+            bonded,
+            connections,
+        }
+    }
+
+    /// Bonded peers get in, one unbonded peer at a time read-only, and
+    /// nobody else.
+    fn authorizer() -> ConnectionAuthorizer {
+        let mut authorizer = ConnectionAuthorizer::new(Duration::from_secs(1));
+        authorizer.set_authorizer(Box::new(|attempt| {
+            if attempt.bonded {
+                ConnAdmission::Accept
+            } else if attempt.connections == 0 {
+                ConnAdmission::AcceptReadOnly
+            } else {
+                ConnAdmission::Reject {
+                    reason: "not in pairing mode",
+                }
+            }
+        }));
+        authorizer
+    }
+
+    #[test]
+    fn three_admission_outcomes() {
+        let mut authorizer = authorizer();
+
+        assert_eq!(
+            authorizer.authorize(1, &attempt(true, 0)),
+            ConnAdmission::Accept
+        );
+        assert_eq!(authorizer.check_write(1), Ok(()));
+
+        assert_eq!(
+            authorizer.authorize(2, &attempt(false, 0)),
+            ConnAdmission::AcceptReadOnly
+        );
+        assert_eq!(
+            authorizer.check_write(2),
+            Err(ATT_ERR_INSUFFICIENT_AUTHORIZATION)
+        );
+
+        assert_eq!(
+            authorizer.authorize(3, &attempt(false, 2)),
+            ConnAdmission::Reject {
+                reason: "not in pairing mode"
+            }
+        );
+
+        assert_eq!(
+            authorizer.stats(),
+            ConnAdmissionStats {
+                accepted: 1,
+                rejected: 1,
+                read_only: 1,
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn upgrade_lifts_read_only() {
+        let mut authorizer = authorizer();
+        authorizer.authorize(2, &attempt(false, 0));
+
+        assert!(authorizer.upgrade(2));
+        assert_eq!(authorizer.check_write(2), Ok(()));
+        assert!(!authorizer.upgrade(2));
+        assert_eq!(authorizer.stats().upgraded, 1);
+    }
+
+    #[test]
+    fn disconnect_forgets_read_only() {
+        let mut authorizer = authorizer();
+        authorizer.authorize(2, &attempt(false, 0));
+        authorizer.on_disconnect(2);
+        assert!(!authorizer.is_read_only(2));
+    }
+
+    #[test]
+    fn accepts_without_a_callback() {
+        let mut authorizer = ConnectionAuthorizer::new(Duration::from_secs(1));
+        assert_eq!(
+            authorizer.authorize(1, &attempt(false, 5)),
+            ConnAdmission::Accept
+        );
+    }
+
+    #[test]
+    fn panicking_callback_accepts() {
+        let mut authorizer = ConnectionAuthorizer::new(Duration::from_secs(1));
+        authorizer.set_authorizer(Box::new(|_| panic!("policy bug")));
+
+        assert_eq!(
+            authorizer.authorize(1, &attempt(false, 0)),
+            ConnAdmission::Accept
+        );
+        assert_eq!(authorizer.stats().panicked, 1);
+        assert_eq!(authorizer.stats().accepted, 1);
+    }
+
+    #[test]
+    fn slow_callback_is_counted() {
+        let mut authorizer = ConnectionAuthorizer::new(Duration::ZERO);
+        authorizer.set_authorizer(Box::new(|_| {
+            std::thread::sleep(Duration::from_millis(1));
+            ConnAdmission::Accept
+        }));
+
+        authorizer.authorize(1, &attempt(false, 0));
+        assert_eq!(authorizer.stats().over_budget, 1);
+    }
+}
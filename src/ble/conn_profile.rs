@@ -0,0 +1,143 @@
+//! Automatic low-latency connection parameters for real-time services.
+//!
+//! When a real-time service (e.g. HID) becomes active on a connection,
+//! [`ProfilePolicy`] asks for a 7.5–15 ms connection interval with no slave
+//! latency, and reverts to the power-friendly profile once the service has
+//! been idle for a while. A profile is held for a minimum time before it may
+//! change again, so bursty input does not make the parameters flap.
+
+use core::time::Duration;
+use std::collections::HashMap;
+use std::time::Instant;
+
+/// Connection parameters in controller units.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ConnParams {
+    /// Connection interval bounds, in 1.25 ms units.
+    pub min_interval: u16,
+    pub max_interval: u16,
+    pub latency: u16,
+    /// Supervision timeout, in 10 ms units.
+    pub timeout: u16,
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum ConnProfile {
+    #[default]
+    Balanced,
+    LowLatency,
+}
+
+impl ConnProfile {
+    pub const fn params(self) -> ConnParams {
+        match self {
+            Self::Balanced => ConnParams {
+                min_interval: 24,
+                max_interval: 40,
+                latency: 4,
+                timeout: 400,
+            },
+            Self::LowLatency => ConnParams {
+                min_interval: 6,
+                max_interval: 12,
+                latency: 0,
+                timeout: 400,
+            },
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+struct ConnState {
+    profile: ConnProfile,
+    last_activity: Instant,
+    /// `None` until the first switch.
+    switched_at: Option<Instant>,
+}
+
+#[derive(Debug)]
+pub struct ProfilePolicy {
+    idle_after: Duration,
+    min_hold: Duration,
+    conns: HashMap<u16, ConnState>,
+}
+
+impl ProfilePolicy {
+    /// Reverts after `idle_after` without real-time activity; a profile is
+    /// kept at least `min_hold`.
+    pub fn new(idle_after: Duration, min_hold: Duration) -> Self {
+        Self {
+            idle_after,
+            min_hold,
+            conns: HashMap::new(),
+        }
+    }
+
+    pub fn profile(&self, conn_id: u16) -> ConnProfile {
+        self.conns
+            .get(&conn_id)
+            .map_or(ConnProfile::Balanced, |state| state.profile)
+    }
+
+    /// Real-time traffic or a new subscriber on `conn_id`. Returns the
+    /// parameters to request when the profile changes.
+    pub fn on_activity(&mut self, conn_id: u16, now: Instant) -> Option<ConnParams> {
+        let state = self.conns.entry(conn_id).or_insert(ConnState {
+            profile: ConnProfile::Balanced,
+            last_activity: now,
+            switched_at: None,
+        });
+        state.last_activity = now;
+
+        if state.profile == ConnProfile::LowLatency || !held(state, self.min_hold, now) {
+            return None;
+        }
+
+        state.profile = ConnProfile::LowLatency;
+        state.switched_at = Some(now);
+        Some(ConnProfile::LowLatency.params())
+    }
+
+    /// Connections to revert, with the parameters to request.
+    pub fn poll(&mut self, now: Instant) -> Vec<(u16, ConnParams)> {
+        let mut reverted = Vec::new();
+
+        for (&conn_id, state) in &mut self.conns {
+            let idle = now.saturating_duration_since(state.last_activity) >= self.idle_after;
+
+            if state.profile == ConnProfile::LowLatency && idle && held(state, self.min_hold, now) {
+                state.profile = ConnProfile::Balanced;
+                state.switched_at = Some(now);
+                reverted.push((conn_id, ConnProfile::Balanced.params()));
+            }
+        }
+
+        reverted
+    }
+
+    /// Earliest time [`poll`](Self::poll) may revert a connection, for
+    /// arming a [`Scheduler`](super::scheduler::Scheduler) task.
+    pub fn next_deadline(&self) -> Option<Instant> {
+        self.conns
+            .values()
+            .filter(|state| state.profile == ConnProfile::LowLatency)
+            .map(|state| {
+                let idle_at = state.last_activity + self.idle_after;
+                state
+                    .switched_at
+                    .map_or(idle_at, |at| idle_at.max(at + self.min_hold))
+            })
+            .min()
+    }
+
+    pub fn on_disconnect(&mut self, conn_id: u16) {
+        self.conns.remove(&conn_id);
+    }
+}
+
+/// Whether the current profile has been kept for at least `min_hold`.
+fn held(state: &ConnState, min_hold: Duration, now: Instant) -> bool {
+    state
+        .switched_at
+        .map_or(true, |at| now.saturating_duration_since(at) >= min_hold)
+}
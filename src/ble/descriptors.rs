@@ -0,0 +1,151 @@
+//! Routing of descriptor writes.
+//!
+//! Descriptor handles recorded from `DescriptorAdded` are kept in a
+//! [`DescriptorTable`] so their writes do not reach `on_write` as unknown
+//! handles. CCCD writes are parsed and belong to the subscription tracker;
+//! writes to any other descriptor are delivered with the characteristic and
+//! descriptor UUIDs.
+
+use std::collections::HashMap;
+
+use crate::proto::assigned::descriptors::CLIENT_CHAR_CONFIG;
+use crate::proto::{CccdFlags, Uuid};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct DescriptorEntry {
+    pub characteristic: Uuid,
+    /// Value handle of the owning characteristic.
+    pub char_handle: u16,
+    pub descriptor: Uuid,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DescriptorWrite<'a> {
+    /// Not a descriptor; use the regular write path.
+    NotDescriptor,
+    /// A CCCD write; `flags` is `None` when the value is malformed.
+    Cccd {
+        char_handle: u16,
+        flags: Option<CccdFlags>,
+    },
+    Other {
+        characteristic: Uuid,
+        descriptor: Uuid,
+        value: &'a [u8],
+    },
+}
+
+#[derive(Debug, Default)]
+pub struct DescriptorTable {
+    by_handle: HashMap<u16, DescriptorEntry>,
+}
+
+impl DescriptorTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn on_descriptor_added(&mut self, handle: u16, entry: DescriptorEntry) {
+        self.by_handle.insert(handle, entry);
+    }
+
+    pub fn get(&self, handle: u16) -> Option<&DescriptorEntry> {
+        self.by_handle.get(&handle)
+    }
+
+    /// Handle of the CCCD of the characteristic with value handle
+    /// `char_handle`.
+    pub fn cccd_handle(&self, char_handle: u16) -> Option<u16> {
+        self.by_handle
+            .iter()
+            .find(|(_, entry)| {
+                entry.char_handle == char_handle && entry.descriptor == CLIENT_CHAR_CONFIG
+            })
+            .map(|(&handle, _)| handle)
+    }
+
+    pub fn route_write<'a>(&self, handle: u16, value: &'a [u8]) -> DescriptorWrite<'a> {
+        match self.by_handle.get(&handle) {
+            None => DescriptorWrite::NotDescriptor,
+            Some(entry) if entry.descriptor == CLIENT_CHAR_CONFIG => DescriptorWrite::Cccd {
+                char_handle: entry.char_handle,
+                flags: CccdFlags::decode(value),
+            },
+            Some(entry) => DescriptorWrite::Other {
+                characteristic: entry.characteristic,
+                descriptor: entry.descriptor,
+                value,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proto::assigned::characteristics::BATTERY_LEVEL;
+    use crate::proto::assigned::descriptors::CHAR_USER_DESCRIPTION;
+
+    const CHAR_HANDLE: u16 = 42;
+    const CCCD_HANDLE: u16 = 43;
+    const DESCRIPTION_HANDLE: u16 = 44;
+
+    fn table() -> DescriptorTable {
+        let mut table = DescriptorTable::new();
+        for (handle, descriptor) in [
+            (CCCD_HANDLE, CLIENT_CHAR_CONFIG),
+            (DESCRIPTION_HANDLE, CHAR_USER_DESCRIPTION),
+        ] {
+            table.on_descriptor_added(
+                handle,
+                DescriptorEntry {
+                    characteristic: BATTERY_LEVEL,
+                    char_handle: CHAR_HANDLE,
+                    descriptor,
+                },
+            );
+        }
+        table
+    }
+
+    #[test]
+    fn cccd_write_goes_to_subscriptions() {
+        assert_eq!(
+            table().route_write(CCCD_HANDLE, &[0x01, 0x00]),
+            DescriptorWrite::Cccd {
+                char_handle: CHAR_HANDLE,
+                flags: Some(CccdFlags::NOTIFY),
+            }
+        );
+        assert_eq!(
+            table().route_write(CCCD_HANDLE, &[0x01]),
+            DescriptorWrite::Cccd {
+                char_handle: CHAR_HANDLE,
+                flags: None,
+            }
+        );
+    }
+
+    #[test]
+    fn user_description_write_goes_to_descriptor_callback() {
+        assert_eq!(
+            table().route_write(DESCRIPTION_HANDLE, b"Kitchen"),
+            DescriptorWrite::Other {
+                characteristic: BATTERY_LEVEL,
+                descriptor: CHAR_USER_DESCRIPTION,
+                value: b"Kitchen",
+            }
+        );
+    }
+
+    #[test]
+    fn other_handles_take_the_regular_write_path() {
+        let table = table();
+        assert_eq!(
+            table.route_write(CHAR_HANDLE, &[0x01, 0x00]),
+            DescriptorWrite::NotDescriptor
+        );
+        assert_eq!(table.cccd_handle(CHAR_HANDLE), Some(CCCD_HANDLE));
+        assert_eq!(table.cccd_handle(DESCRIPTION_HANDLE), None);
+    }
+}
@@ -0,0 +1,249 @@
+//! Writes that arrive while their service is still being created.
+//!
+//! A fast client can write to a characteristic after `start_service` but
+//! before the routing tables know its handle. While a service is in
+//! [`ServiceState::AddingCharacteristics`], writes inside its handle range are
+//! held in an [`EarlyWriteQueue`] (bounded, with a TTL) and handed back for
+//! normal dispatch once it is started. If it fails or the TTL passes, they
+//! are returned with the ATT error to respond with.
+
+use core::time::Duration;
+use std::collections::{HashMap, VecDeque};
+use std::ops::RangeInclusive;
+use std::time::Instant;
+
+use crate::proto::{SmallPayload, Uuid};
+
+use super::lifecycle::{ServiceLifecycle, ServiceState};
+
+pub const ATT_ERR_UNLIKELY: u8 = 0x0E;
+pub const ATT_ERR_INSUFFICIENT_RESOURCES: u8 = 0x11;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EarlyWrite {
+    pub conn_id: u16,
+    pub trans_id: u32,
+    pub handle: u16,
+    pub offset: u16,
+    pub value: SmallPayload,
+    pub need_rsp: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Admission {
+    Queued,
+    /// Not in a service under creation; dispatch as usual.
+    Dispatch(EarlyWrite),
+    /// Queue full; respond with the ATT error.
+    Rejected(EarlyWrite, u8),
+}
+
+#[derive(Debug)]
+pub struct EarlyWriteQueue {
+    ranges: HashMap<Uuid, RangeInclusive<u16>>,
+    queued: VecDeque<(Uuid, Instant, EarlyWrite)>,
+    capacity: usize,
+    ttl: Duration,
+}
+
+impl EarlyWriteQueue {
+    pub fn new(capacity: usize, ttl: Duration) -> Self {
+        Self {
+            ranges: HashMap::new(),
+            queued: VecDeque::new(),
+            capacity,
+            ttl,
+        }
+    }
+
+    /// Records the handle range reported by `ServiceCreated`.
+    pub fn on_service_created(&mut self, uuid: Uuid, start_handle: u16, num_handles: u16) {
+        let end = start_handle.saturating_add(num_handles.saturating_sub(1));
+        self.ranges.insert(uuid, start_handle..=end);
+    }
+
+    pub fn offer(
+        &mut self,
+        lifecycle: &ServiceLifecycle,
+        write: EarlyWrite,
+        now: Instant,
+    ) -> Admission {
+        let pending = self.ranges.iter().find(|(uuid, range)| {
+            range.contains(&write.handle)
+                && lifecycle.service_state(uuid) == Some(&ServiceState::AddingCharacteristics)
+        });
+
+        let Some((&uuid, _)) = pending else {
+            return Admission::Dispatch(write);
+        };
+        if self.queued.len() >= self.capacity {
+            return Admission::Rejected(write, ATT_ERR_INSUFFICIENT_RESOURCES);
+        }
+
+        self.queued.push_back((uuid, now, write));
+        Admission::Queued
+    }
+
+    /// Writes to replay through normal dispatch, in arrival order.
+    pub fn on_started(&mut self, uuid: &Uuid) -> Vec<EarlyWrite> {
+        self.take(uuid)
+    }
+
+    /// Writes to fail because the service will not start.
+    pub fn on_failed(&mut self, uuid: &Uuid) -> Vec<(EarlyWrite, u8)> {
+        self.ranges.remove(uuid);
+        self.take(uuid)
+            .into_iter()
+            .map(|write| (write, ATT_ERR_UNLIKELY))
+            .collect()
+    }
+
+    /// Writes held longer than the TTL.
+    pub fn expire(&mut self, now: Instant) -> Vec<(EarlyWrite, u8)> {
+        let mut expired = Vec::new();
+
+        self.queued.retain(|(_, queued_at, write)| {
+            let keep = now < *queued_at + self.ttl;
+            if !keep {
+                expired.push((write.clone(), ATT_ERR_UNLIKELY));
+            }
+            keep
+        });
+
+        expired
+    }
+
+    pub fn on_disconnect(&mut self, conn_id: u16) {
+        self.queued.retain(|(_, _, write)| write.conn_id != conn_id);
+    }
+
+    pub fn len(&self) -> usize {
+        self.queued.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queued.is_empty()
+    }
+
+    fn take(&mut self, uuid: &Uuid) -> Vec<EarlyWrite> {
+        let (taken, kept) = self
+            .queued
+            .drain(..)
+            .partition(|(service, _, _)| service == uuid);
+        self.queued = kept;
+
+        taken.into_iter().map(|(_, _, write)| write).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ble::lifecycle::LifecycleEvent;
+
+    const SERVICE: Uuid = Uuid::Uuid16(0x180F);
+    const OTHER: Uuid = Uuid::Uuid16(0x180A);
+
+    fn write(conn_id: u16, handle: u16, value: &[u8]) -> EarlyWrite {
+        EarlyWrite {
+            conn_id,
+            trans_id: u32::from(handle),
+            handle,
+            offset: 0,
+            value: SmallPayload::from(value),
+            need_rsp: true,
+        }
+    }
+
+    fn creating(lifecycle: &mut ServiceLifecycle, queue: &mut EarlyWriteQueue, uuid: Uuid) {
+        lifecycle.declare(uuid);
+        lifecycle.apply(uuid, LifecycleEvent::CreateRequested);
+        lifecycle.apply(uuid, LifecycleEvent::Created);
+        queue.on_service_created(uuid, 40, 8);
+    }
+
+    #[test]
+    fn writes_racing_characteristic_added_are_replayed_in_order() {
+        let now = Instant::now();
+        let mut lifecycle = ServiceLifecycle::new();
+        let mut queue = EarlyWriteQueue::new(4, Duration::from_secs(1));
+        creating(&mut lifecycle, &mut queue, SERVICE);
+
+        // CharacteristicAdded for 42, a write to 44 before its own event, ...
+        lifecycle.apply(SERVICE, LifecycleEvent::AttributeAdded);
+        assert_eq!(
+            queue.offer(&lifecycle, write(1, 44, b"early"), now),
+            Admission::Queued
+        );
+        lifecycle.apply(SERVICE, LifecycleEvent::AttributeAdded);
+        assert_eq!(
+            queue.offer(&lifecycle, write(2, 42, b"second"), now),
+            Admission::Queued
+        );
+        // ... a write outside the range is dispatched at once ...
+        assert_eq!(
+            queue.offer(&lifecycle, write(1, 60, b"x"), now),
+            Admission::Dispatch(write(1, 60, b"x"))
+        );
+        // ... and after ServiceStarted writes go straight through again.
+        lifecycle.apply(SERVICE, LifecycleEvent::Started);
+        assert_eq!(
+            queue.on_started(&SERVICE),
+            vec![write(1, 44, b"early"), write(2, 42, b"second")]
+        );
+        assert_eq!(
+            queue.offer(&lifecycle, write(1, 44, b"late"), now),
+            Admission::Dispatch(write(1, 44, b"late"))
+        );
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn full_queue_rejects() {
+        let now = Instant::now();
+        let mut lifecycle = ServiceLifecycle::new();
+        let mut queue = EarlyWriteQueue::new(1, Duration::from_secs(1));
+        creating(&mut lifecycle, &mut queue, SERVICE);
+
+        assert_eq!(
+            queue.offer(&lifecycle, write(1, 41, b"a"), now),
+            Admission::Queued
+        );
+        assert_eq!(
+            queue.offer(&lifecycle, write(1, 42, b"b"), now),
+            Admission::Rejected(write(1, 42, b"b"), ATT_ERR_INSUFFICIENT_RESOURCES)
+        );
+    }
+
+    #[test]
+    fn failure_ttl_and_disconnect_release_writes() {
+        let now = Instant::now();
+        let mut lifecycle = ServiceLifecycle::new();
+        let mut queue = EarlyWriteQueue::new(8, Duration::from_millis(100));
+        creating(&mut lifecycle, &mut queue, SERVICE);
+        lifecycle.declare(OTHER);
+        lifecycle.apply(OTHER, LifecycleEvent::CreateRequested);
+        lifecycle.apply(OTHER, LifecycleEvent::Created);
+        queue.on_service_created(OTHER, 60, 4);
+
+        queue.offer(&lifecycle, write(1, 41, b"a"), now);
+        queue.offer(&lifecycle, write(2, 61, b"b"), now);
+        queue.offer(
+            &lifecycle,
+            write(3, 62, b"c"),
+            now + Duration::from_millis(50),
+        );
+        queue.on_disconnect(3);
+
+        assert_eq!(
+            queue.on_failed(&SERVICE),
+            vec![(write(1, 41, b"a"), ATT_ERR_UNLIKELY)]
+        );
+        assert!(queue.expire(now + Duration::from_millis(99)).is_empty());
+        assert_eq!(
+            queue.expire(now + Duration::from_millis(100)),
+            vec![(write(2, 61, b"b"), ATT_ERR_UNLIKELY)]
+        );
+        assert!(queue.is_empty());
+    }
+}
@@ -0,0 +1,113 @@
+//! Bluetooth errors that keep the original status.
+//!
+//! Collapsing every non-OK [`BtStatus`]/[`GattStatus`] into `ESP_FAIL`
+//! loses which failure it was. [`check_bt_status`] and
+//! [`check_gatt_status`] keep the status and the name of the failing
+//! operation, so errors read like
+//! `add_characteristic(service=0x0028) failed: GattStatus::InsufficientResource`
+//! and callers can match on the status, e.g. with [`BtError::is_no_mem`].
+
+use core::fmt;
+
+use esp_idf_svc::bt::ble::gatt::GattStatus;
+use esp_idf_svc::bt::BtStatus;
+use esp_idf_svc::sys::EspError;
+
+use super::capabilities::{Capability, Unsupported};
+
+#[derive(Debug)]
+pub enum BtError {
+    Esp(EspError),
+    BtStatus(BtStatus),
+    GattStatus(GattStatus),
+    /// The API needs a capability this build lacks.
+    Unsupported(Capability),
+}
+
+impl BtError {
+    /// Whether the stack ran out of memory or resources; worth retrying
+    /// after freeing some.
+    pub fn is_no_mem(&self) -> bool {
+        matches!(
+            self,
+            Self::BtStatus(BtStatus::NoMem)
+                | Self::GattStatus(GattStatus::NoResources | GattStatus::InsufficientResource)
+        )
+    }
+}
+
+impl fmt::Display for BtError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Esp(err) => write!(f, "{err}"),
+            Self::BtStatus(status) => write!(f, "BtStatus::{status:?}"),
+            Self::GattStatus(status) => write!(f, "GattStatus::{status:?}"),
+            Self::Unsupported(capability) => write!(f, "{}", Unsupported(*capability)),
+        }
+    }
+}
+
+impl std::error::Error for BtError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Esp(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<EspError> for BtError {
+    fn from(err: EspError) -> Self {
+        Self::Esp(err)
+    }
+}
+
+impl From<Unsupported> for BtError {
+    fn from(Unsupported(capability): Unsupported) -> Self {
+        Self::Unsupported(capability)
+    }
+}
+
+/// A [`BtError`] with the operation that failed.
+#[derive(Debug)]
+pub struct OpError {
+    pub op: String,
+    pub error: BtError,
+}
+
+impl OpError {
+    pub fn new(op: impl Into<String>, error: impl Into<BtError>) -> Self {
+        Self {
+            op: op.into(),
+            error: error.into(),
+        }
+    }
+}
+
+impl fmt::Display for OpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} failed: {}", self.op, self.error)
+    }
+}
+
+impl std::error::Error for OpError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+/// `op` is only formatted on failure.
+pub fn check_bt_status(status: BtStatus, op: impl FnOnce() -> String) -> Result<(), OpError> {
+    match status {
+        BtStatus::Success => Ok(()),
+        status => Err(OpError::new(op(), BtError::BtStatus(status))),
+    }
+}
+
+/// `op` is only formatted on failure.
+pub fn check_gatt_status(status: GattStatus, op: impl FnOnce() -> String) -> Result<(), OpError> {
+    match status {
+        GattStatus::Ok => Ok(()),
+        status => Err(OpError::new(op(), BtError::GattStatus(status))),
+    }
+}
@@ -0,0 +1,315 @@
+//! Post-mortem ring of recent BLE events.
+//!
+//! The dispatch path records a compact [`EventRecord`] per event into a
+//! fixed-size [`EventRing`] without taking a lock. On a panic or fatal BT
+//! error the ring is dumped to the log, one parseable line per record, so a
+//! field crash comes with the peer activity that led up to it. Payloads never
+//! enter the ring, only their lengths.
+//!
+//! The ring is sized at compile time and can live in a `static`:
+//!
+//! ```
+//! use esp_gatt_rs_demo::ble::event_ring::{EventKind, EventRecord, EventRing};
+//!
+//! static EVENTS: EventRing<64> = EventRing::new();
+//!
+//! EVENTS.record(EventRecord {
+//!     kind: EventKind::Write,
+//!     conn_id: 0,
+//!     handle: 42,
+//!     status: 0,
+//!     len: 20,
+//!     timestamp_ms: 1234,
+//! });
+//! assert_eq!(EVENTS.snapshot().len(), 1);
+//! ```
+
+use core::fmt;
+use core::sync::atomic::{fence, AtomicU32, Ordering};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum EventKind {
+    Connect = 1,
+    Disconnect,
+    Mtu,
+    Read,
+    Write,
+    ExecWrite,
+    Notify,
+    Indicate,
+    Confirm,
+    Error,
+}
+
+impl EventKind {
+    const ALL: [Self; 10] = [
+        Self::Connect,
+        Self::Disconnect,
+        Self::Mtu,
+        Self::Read,
+        Self::Write,
+        Self::ExecWrite,
+        Self::Notify,
+        Self::Indicate,
+        Self::Confirm,
+        Self::Error,
+    ];
+
+    pub fn from_raw(raw: u8) -> Option<Self> {
+        Self::ALL.into_iter().find(|kind| *kind as u8 == raw)
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Connect => "connect",
+            Self::Disconnect => "disconnect",
+            Self::Mtu => "mtu",
+            Self::Read => "read",
+            Self::Write => "write",
+            Self::ExecWrite => "exec_write",
+            Self::Notify => "notify",
+            Self::Indicate => "indicate",
+            Self::Confirm => "confirm",
+            Self::Error => "error",
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct EventRecord {
+    pub kind: EventKind,
+    pub conn_id: u16,
+    pub handle: u16,
+    /// GATT or ESP status, truncated to a byte.
+    pub status: u8,
+    /// Payload length; the payload itself is never recorded.
+    pub len: u16,
+    pub timestamp_ms: u32,
+}
+
+impl EventRecord {
+    fn pack(&self) -> [u32; 3] {
+        [
+            self.timestamp_ms,
+            u32::from(self.kind as u8) | u32::from(self.status) << 8 | u32::from(self.len) << 16,
+            u32::from(self.conn_id) | u32::from(self.handle) << 16,
+        ]
+    }
+
+    fn unpack([timestamp_ms, b, c]: [u32; 3]) -> Option<Self> {
+        Some(Self {
+            kind: EventKind::from_raw(b as u8)?,
+            conn_id: c as u16,
+            handle: (c >> 16) as u16,
+            status: (b >> 8) as u8,
+            len: (b >> 16) as u16,
+            timestamp_ms,
+        })
+    }
+}
+
+impl fmt::Display for EventRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "t={} kind={} conn={} handle={} status=0x{:02x} len={}",
+            self.timestamp_ms,
+            self.kind.name(),
+            self.conn_id,
+            self.handle,
+            self.status,
+            self.len
+        )
+    }
+}
+
+/// Marks a slot whose record is being written.
+const WRITING: u32 = u32::MAX;
+
+/// 32-bit words only; Xtensa targets have no 64-bit atomics.
+struct Slot {
+    /// Sequence number + 1 of the record held, 0 when empty.
+    seq: AtomicU32,
+    words: [AtomicU32; 3],
+}
+
+#[allow(clippy::declare_interior_mutable_const)]
+const EMPTY_SLOT: Slot = Slot {
+    seq: AtomicU32::new(0),
+    words: [AtomicU32::new(0), AtomicU32::new(0), AtomicU32::new(0)],
+};
+
+/// Lock-free ring of the last `N` events.
+pub struct EventRing<const N: usize> {
+    next: AtomicU32,
+    slots: [Slot; N],
+}
+
+impl<const N: usize> fmt::Debug for EventRing<N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EventRing")
+            .field("capacity", &N)
+            .field("recorded", &self.next.load(Ordering::Relaxed))
+            .finish_non_exhaustive()
+    }
+}
+
+impl<const N: usize> Default for EventRing<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> EventRing<N> {
+    pub const fn new() -> Self {
+        Self {
+            next: AtomicU32::new(0),
+            slots: [EMPTY_SLOT; N],
+        }
+    }
+
+    /// Records an event, overwriting the oldest one when full.
+    pub fn record(&self, record: EventRecord) {
+        if N == 0 {
+            return;
+        }
+
+        let seq = self.next.fetch_add(1, Ordering::Relaxed);
+        let slot = &self.slots[seq as usize % N];
+
+        slot.seq.store(WRITING, Ordering::Relaxed);
+        fence(Ordering::Release);
+        for (word, value) in slot.words.iter().zip(record.pack()) {
+            word.store(value, Ordering::Relaxed);
+        }
+        // Sequence numbers wrap within 1..WRITING.
+        slot.seq.store(seq % (WRITING - 1) + 1, Ordering::Release);
+    }
+
+    /// Records held, oldest first, with their sequence numbers. Slots being
+    /// written concurrently are skipped.
+    pub fn snapshot(&self) -> Vec<(u32, EventRecord)> {
+        let mut records: Vec<_> = self.slots.iter().filter_map(Self::read_slot).collect();
+        let newest = self.next.load(Ordering::Acquire);
+        records.sort_by_key(|(seq, _)| seq.wrapping_sub(newest));
+        records
+    }
+
+    /// Logs every held record as `evt seq=<n> t=<ms> kind=<kind> ...`.
+    pub fn dump(&self) {
+        let records = self.snapshot();
+
+        log::error!("event ring dump: {} records", records.len());
+        for (seq, record) in records {
+            log::error!("evt seq={seq} {record}");
+        }
+    }
+
+    fn read_slot(slot: &Slot) -> Option<(u32, EventRecord)> {
+        let before = slot.seq.load(Ordering::Acquire);
+        if before == 0 || before == WRITING {
+            return None;
+        }
+
+        let words = [0, 1, 2].map(|i| slot.words[i].load(Ordering::Relaxed));
+        fence(Ordering::Acquire);
+        if slot.seq.load(Ordering::Relaxed) != before {
+            return None;
+        }
+
+        EventRecord::unpack(words).map(|record| (before - 1, record))
+    }
+}
+
+/// Dumps `ring` from the panic hook, before the previously installed hook
+/// runs.
+pub fn install_panic_dump<const N: usize>(ring: &'static EventRing<N>) {
+    let previous = std::panic::take_hook();
+
+    std::panic::set_hook(Box::new(move |info| {
+        ring.dump();
+        previous(info);
+    }));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write(i: u16) -> EventRecord {
+        EventRecord {
+            kind: EventKind::Write,
+            conn_id: i % 3,
+            handle: 40 + i,
+            status: 0,
+            len: i,
+            timestamp_ms: 1000 + u32::from(i),
+        }
+    }
+
+    #[test]
+    fn keeps_the_last_n_oldest_first() {
+        let ring = EventRing::<4>::new();
+        for i in 0..10 {
+            ring.record(write(i));
+        }
+
+        let snapshot = ring.snapshot();
+        assert_eq!(
+            snapshot,
+            (6..10)
+                .map(|i| (u32::from(i), write(i)))
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn partly_filled_ring_holds_what_was_recorded() {
+        let ring = EventRing::<4>::new();
+        assert!(ring.snapshot().is_empty());
+
+        ring.record(write(0));
+        ring.record(write(1));
+        assert_eq!(ring.snapshot(), [(0, write(0)), (1, write(1))]);
+    }
+
+    #[test]
+    fn dump_line_format() {
+        let ring = EventRing::<2>::new();
+        ring.record(EventRecord {
+            kind: EventKind::ExecWrite,
+            conn_id: 1,
+            handle: 42,
+            status: 0x85,
+            len: 512,
+            timestamp_ms: 1234,
+        });
+
+        let lines: Vec<_> = ring
+            .snapshot()
+            .into_iter()
+            .map(|(seq, record)| format!("evt seq={seq} {record}"))
+            .collect();
+        assert_eq!(
+            lines,
+            ["evt seq=0 t=1234 kind=exec_write conn=1 handle=42 status=0x85 len=512"]
+        );
+    }
+
+    #[test]
+    fn records_survive_packing() {
+        for kind in EventKind::ALL {
+            let record = EventRecord {
+                kind,
+                conn_id: u16::MAX,
+                handle: 0xABCD,
+                status: 0xFF,
+                len: u16::MAX,
+                timestamp_ms: u32::MAX - 1,
+            };
+            assert_eq!(EventRecord::unpack(record.pack()), Some(record));
+        }
+        assert_eq!(EventRecord::unpack([0, 0, 0]), None);
+    }
+}
@@ -0,0 +1,186 @@
+//! `std::io::Read + Write` over a characteristic pair.
+//!
+//! [`GattStream::open`] binds one connection's rx characteristic (written by
+//! the client) and tx characteristic (notified to it). The GATTS event side
+//! keeps the returned [`StreamFeed`] and hands it the rx writes; the stream
+//! serves them from a bounded buffer through `Read`. `Write` cuts data into
+//! notifications of at most MTU - 3 bytes and queues them in the
+//! [`Outbox`], waiting for space when it is full; `flush` waits until the
+//! queue drained. After [`StreamFeed::close`] (on disconnect) reads return
+//! EOF once the buffer is empty and writes fail with `BrokenPipe`.
+
+use core::time::Duration;
+use std::collections::VecDeque;
+use std::io;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError};
+
+use super::outbox::{Outbox, SendError};
+use super::wait::LinkState;
+
+/// Notification header: opcode and handle.
+const NOTIFY_OVERHEAD: u16 = 3;
+
+#[derive(Debug, Default)]
+struct Rx {
+    buf: VecDeque<u8>,
+    closed: bool,
+}
+
+#[derive(Debug)]
+struct Shared {
+    rx: Mutex<Rx>,
+    readable: Condvar,
+    capacity: usize,
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, Rx> {
+        self.rx.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// Event side of a [`GattStream`].
+#[derive(Debug, Clone)]
+pub struct StreamFeed {
+    shared: Arc<Shared>,
+    rx_handle: u16,
+}
+
+impl StreamFeed {
+    pub fn rx_handle(&self) -> u16 {
+        self.rx_handle
+    }
+
+    /// Buffers a write to the rx characteristic. Returns `false` if it did
+    /// not fit and was dropped.
+    pub fn on_write(&self, data: &[u8]) -> bool {
+        let mut rx = self.shared.lock();
+        if rx.closed || rx.buf.len() + data.len() > self.shared.capacity {
+            return false;
+        }
+
+        rx.buf.extend(data);
+        self.shared.readable.notify_all();
+        true
+    }
+
+    /// Signals EOF to the reader.
+    pub fn close(&self) {
+        self.shared.lock().closed = true;
+        self.shared.readable.notify_all();
+    }
+}
+
+#[derive(Debug)]
+pub struct GattStream {
+    conn_id: u16,
+    tx_handle: u16,
+    shared: Arc<Shared>,
+    link: Arc<LinkState>,
+    outbox: Arc<Outbox>,
+    send_timeout: Duration,
+}
+
+impl GattStream {
+    /// `rx_capacity` bounds the unread incoming bytes; `send_timeout` is how
+    /// long a write or flush waits for queue space.
+    pub fn open(
+        conn_id: u16,
+        rx_handle: u16,
+        tx_handle: u16,
+        link: Arc<LinkState>,
+        outbox: Arc<Outbox>,
+        rx_capacity: usize,
+        send_timeout: Duration,
+    ) -> (Self, StreamFeed) {
+        let shared = Arc::new(Shared {
+            rx: Mutex::new(Rx::default()),
+            readable: Condvar::new(),
+            capacity: rx_capacity,
+        });
+
+        let stream = Self {
+            conn_id,
+            tx_handle,
+            shared: shared.clone(),
+            link,
+            outbox,
+            send_timeout,
+        };
+        (stream, StreamFeed { shared, rx_handle })
+    }
+
+    pub fn conn_id(&self) -> u16 {
+        self.conn_id
+    }
+
+    fn chunk_len(&self) -> usize {
+        let mtu = self
+            .link
+            .read(|conns, _| conns.get(self.conn_id).map(|conn| conn.effective_mtu()));
+        usize::from(mtu.unwrap_or_default().saturating_sub(NOTIFY_OVERHEAD))
+    }
+}
+
+impl io::Read for GattStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        let rx = self.shared.lock();
+        let mut rx = self
+            .shared
+            .readable
+            .wait_while(rx, |rx| rx.buf.is_empty() && !rx.closed)
+            .unwrap_or_else(PoisonError::into_inner);
+
+        let len = buf.len().min(rx.buf.len());
+        for (dst, src) in buf.iter_mut().zip(rx.buf.drain(..len)) {
+            *dst = src;
+        }
+        Ok(len)
+    }
+}
+
+impl io::Write for GattStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.shared.lock().closed {
+            return Err(io::ErrorKind::BrokenPipe.into());
+        }
+
+        let chunk_len = self.chunk_len();
+        if chunk_len == 0 {
+            return Err(io::ErrorKind::BrokenPipe.into());
+        }
+
+        let chunk = &buf[..buf.len().min(chunk_len)];
+        self.outbox
+            .notify(
+                &self.link,
+                self.conn_id,
+                self.tx_handle,
+                chunk,
+                self.send_timeout,
+            )
+            .map_err(to_io)?;
+        Ok(chunk.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.outbox
+            .wait_for_drain(self.conn_id, self.send_timeout)
+            .map_err(to_io)
+    }
+}
+
+fn to_io(err: SendError) -> io::Error {
+    let kind = match err {
+        SendError::Disconnected => io::ErrorKind::BrokenPipe,
+        SendError::NotSubscribed => io::ErrorKind::NotConnected,
+        SendError::InsufficientSecurity { .. } => io::ErrorKind::PermissionDenied,
+        SendError::QueueFull { .. } => io::ErrorKind::WouldBlock,
+        SendError::Timeout => io::ErrorKind::TimedOut,
+    };
+    io::Error::new(kind, err)
+}
@@ -0,0 +1,205 @@
+//! Attribute handle stability across firmware updates.
+//!
+//! Clients that cache handles break silently when an update reorders
+//! attribute creation. After startup the server records the handle it got
+//! for each attribute UUID in a [`HandleMap`]; [`HandleMap::check_persisted`]
+//! compares it against the map stored by the previous boot and stores the new
+//! one. Duplicate UUIDs (the same characteristic in two services) are matched
+//! by occurrence, in creation order.
+
+use core::fmt;
+
+use crate::proto::Uuid;
+use crate::store::{KvStore, StoreError};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Mismatch {
+    Moved {
+        uuid: Uuid,
+        expected: u16,
+        actual: u16,
+    },
+    Missing {
+        uuid: Uuid,
+        expected: u16,
+    },
+    Unexpected {
+        uuid: Uuid,
+        actual: u16,
+    },
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Moved {
+                uuid,
+                expected,
+                actual,
+            } => write!(f, "{uuid:?} moved from handle {expected} to {actual}"),
+            Self::Missing { uuid, expected } => {
+                write!(f, "{uuid:?} (handle {expected}) no longer exists")
+            }
+            Self::Unexpected { uuid, actual } => write!(f, "{uuid:?} added at handle {actual}"),
+        }
+    }
+}
+
+/// Attribute UUIDs and their handles, in creation order.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct HandleMap {
+    entries: Vec<(Uuid, u16)>,
+}
+
+impl HandleMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, uuid: Uuid, handle: u16) {
+        self.entries.push((uuid, handle));
+    }
+
+    pub fn entries(&self) -> &[(Uuid, u16)] {
+        &self.entries
+    }
+
+    /// Compares against `expected`, reporting every difference.
+    pub fn expect(&self, expected: &[(Uuid, u16)]) -> Result<(), Vec<Mismatch>> {
+        let mut unmatched: Vec<Option<&(Uuid, u16)>> = self.entries.iter().map(Some).collect();
+        let mut mismatches = Vec::new();
+
+        for &(uuid, expected) in expected {
+            let found = unmatched
+                .iter_mut()
+                .find(|entry| entry.is_some_and(|(actual_uuid, _)| *actual_uuid == uuid))
+                .and_then(Option::take);
+
+            match found {
+                Some(&(_, actual)) if actual == expected => {}
+                Some(&(_, actual)) => mismatches.push(Mismatch::Moved {
+                    uuid,
+                    expected,
+                    actual,
+                }),
+                None => mismatches.push(Mismatch::Missing { uuid, expected }),
+            }
+        }
+
+        mismatches.extend(
+            unmatched
+                .into_iter()
+                .flatten()
+                .map(|&(uuid, actual)| Mismatch::Unexpected { uuid, actual }),
+        );
+
+        if mismatches.is_empty() {
+            Ok(())
+        } else {
+            Err(mismatches)
+        }
+    }
+
+    /// Per entry: UUID width (2, 4 or 16), UUID little endian, handle u16 LE.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+
+        for &(uuid, handle) in &self.entries {
+            buf.push(uuid.width() as u8);
+            uuid.write_le(&mut buf);
+            buf.extend_from_slice(&handle.to_le_bytes());
+        }
+
+        buf
+    }
+
+    pub fn decode(mut buf: &[u8]) -> Option<Self> {
+        let mut map = Self::new();
+
+        while let Some((&width, rest)) = buf.split_first() {
+            let width = usize::from(width);
+            if rest.len() < width + 2 {
+                return None;
+            }
+            let (uuid, rest) = rest.split_at(width);
+            let (handle, rest) = rest.split_at(2);
+
+            let uuid = match width {
+                2 => Uuid::Uuid16(u16::from_le_bytes(uuid.try_into().ok()?)),
+                4 => Uuid::Uuid32(u32::from_le_bytes(uuid.try_into().ok()?)),
+                16 => Uuid::Uuid128(u128::from_le_bytes(uuid.try_into().ok()?)),
+                _ => return None,
+            };
+            map.push(uuid, u16::from_le_bytes([handle[0], handle[1]]));
+            buf = rest;
+        }
+
+        Some(map)
+    }
+
+    /// Compares with the map stored under `namespace`/`key`, logs any
+    /// difference and stores this map. Returns whether handles changed; a
+    /// first boot or unreadable stored map counts as unchanged.
+    pub fn check_persisted(
+        &self,
+        store: &dyn KvStore,
+        namespace: &str,
+        key: &str,
+    ) -> Result<bool, StoreError> {
+        let previous = store.get(namespace, key)?.and_then(|raw| {
+            let map = Self::decode(&raw);
+            if map.is_none() {
+                log::warn!("ignoring unreadable handle map {namespace}/{key}");
+            }
+            map
+        });
+
+        let changed = match previous.map(|previous| self.expect(&previous.entries)) {
+            Some(Err(mismatches)) => {
+                for mismatch in &mismatches {
+                    log::warn!("attribute handles changed: {mismatch}");
+                }
+                true
+            }
+            _ => false,
+        };
+
+        store.set(namespace, key, &self.encode())?;
+        Ok(changed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::*;
+
+    fn uuid() -> impl Strategy<Value = Uuid> {
+        prop_oneof![
+            any::<u16>().prop_map(Uuid::Uuid16),
+            any::<u32>().prop_map(Uuid::Uuid32),
+            any::<u128>().prop_map(Uuid::Uuid128),
+        ]
+    }
+
+    proptest! {
+        #[test]
+        fn decode_never_panics(buf in prop::collection::vec(any::<u8>(), 0..64)) {
+            if let Some(map) = HandleMap::decode(&buf) {
+                prop_assert_eq!(map.encode(), buf.clone());
+            }
+        }
+
+        #[test]
+        fn encode_decode_round_trips(
+            entries in prop::collection::vec((uuid(), any::<u16>()), 0..8),
+        ) {
+            let mut map = HandleMap::new();
+            for (uuid, handle) in entries {
+                map.push(uuid, handle);
+            }
+            prop_assert_eq!(HandleMap::decode(&map.encode()), Some(map));
+        }
+    }
+}
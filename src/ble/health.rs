@@ -0,0 +1,195 @@
+//! Stack margin monitoring of the Bluetooth tasks.
+//!
+//! A BTC task stack overflow shows up as a corrupted backtrace long after
+//! the fact. [`HealthMonitor`] is fed the stack high-watermarks of the
+//! Bluedroid and controller tasks from a periodic timer and raises a
+//! [`HealthAlert`] when one gets close to its limit. An alert is raised once
+//! per episode and re-armed when the margin has recovered.
+
+use core::fmt;
+use core::time::Duration;
+use std::collections::{HashMap, HashSet};
+
+use super::PeerAddr;
+
+/// Tasks watched by default; the controller task name differs per chip, a
+/// task that does not exist is simply skipped.
+pub const DEFAULT_WATCHES: [TaskWatch; 3] = [
+    TaskWatch::new("BTC_TASK", 1024),
+    TaskWatch::new("BTU_TASK", 1024),
+    TaskWatch::new("btController", 512),
+];
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct TaskWatch {
+    pub task: &'static str,
+    /// Alert when fewer bytes than this have never been used.
+    pub min_free_bytes: u32,
+}
+
+impl TaskWatch {
+    pub const fn new(task: &'static str, min_free_bytes: u32) -> Self {
+        Self {
+            task,
+            min_free_bytes,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum HealthAlert {
+    LowStack {
+        task: &'static str,
+        free_bytes: u32,
+        threshold: u32,
+    },
+    /// Advertising could not be started after `attempts` tries.
+    AdvertisingFailed { attempts: u32 },
+    /// `peer` reconnected too often and is ignored for `penalty`.
+    ReconnectStorm {
+        peer: PeerAddr,
+        attempts: u32,
+        penalty: Duration,
+    },
+}
+
+/// Payload-free [`HealthAlert`] discriminant, for fixed-size event records.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[repr(u8)]
+pub enum HealthKind {
+    LowStack,
+    AdvertisingFailed,
+    ReconnectStorm,
+}
+
+impl HealthAlert {
+    pub const fn kind(&self) -> HealthKind {
+        match self {
+            Self::LowStack { .. } => HealthKind::LowStack,
+            Self::AdvertisingFailed { .. } => HealthKind::AdvertisingFailed,
+            Self::ReconnectStorm { .. } => HealthKind::ReconnectStorm,
+        }
+    }
+}
+
+impl fmt::Display for HealthAlert {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::LowStack {
+                task,
+                free_bytes,
+                threshold,
+            } => write!(
+                f,
+                "{task} stack down to {free_bytes} free bytes (threshold {threshold})"
+            ),
+            Self::AdvertisingFailed { attempts } => {
+                write!(f, "advertising failed to start after {attempts} attempts")
+            }
+            Self::ReconnectStorm {
+                peer,
+                attempts,
+                penalty,
+            } => write!(
+                f,
+                "{peer:02x?} connected {attempts} times in a row, ignored for {penalty:?}"
+            ),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct HealthMonitor {
+    watches: Vec<TaskWatch>,
+    /// Lowest free stack seen per task.
+    low_water: HashMap<&'static str, u32>,
+    alerting: HashSet<&'static str>,
+    alerts_raised: u64,
+}
+
+impl Default for HealthMonitor {
+    fn default() -> Self {
+        Self::new(DEFAULT_WATCHES.to_vec())
+    }
+}
+
+impl HealthMonitor {
+    pub fn new(watches: Vec<TaskWatch>) -> Self {
+        Self {
+            watches,
+            low_water: HashMap::new(),
+            alerting: HashSet::new(),
+            alerts_raised: 0,
+        }
+    }
+
+    pub fn alerts_raised(&self) -> u64 {
+        self.alerts_raised
+    }
+
+    /// Lowest free stack observed for `task`.
+    pub fn low_water(&self, task: &str) -> Option<u32> {
+        self.low_water.get(task).copied()
+    }
+
+    /// Takes one sample; `free_stack` returns a task's high-watermark in
+    /// bytes, or `None` if the task does not exist.
+    pub fn evaluate(&mut self, free_stack: impl Fn(&str) -> Option<u32>) -> Vec<HealthAlert> {
+        let mut alerts = Vec::new();
+
+        for watch in &self.watches {
+            let Some(free_bytes) = free_stack(watch.task) else {
+                continue;
+            };
+
+            let low = self.low_water.entry(watch.task).or_insert(free_bytes);
+            *low = (*low).min(free_bytes);
+
+            if free_bytes < watch.min_free_bytes {
+                if self.alerting.insert(watch.task) {
+                    self.alerts_raised += 1;
+                    alerts.push(HealthAlert::LowStack {
+                        task: watch.task,
+                        free_bytes,
+                        threshold: watch.min_free_bytes,
+                    });
+                }
+            } else {
+                self.alerting.remove(watch.task);
+            }
+        }
+
+        alerts
+    }
+}
+
+/// Stack high-watermark of the FreeRTOS task named `task`, in bytes.
+#[cfg(feature = "esp")]
+pub fn task_stack_free(task: &str) -> Option<u32> {
+    use esp_idf_svc::sys;
+
+    let name = std::ffi::CString::new(task).ok()?;
+    let handle = unsafe { sys::xTaskGetHandle(name.as_ptr()) };
+    if handle.is_null() {
+        return None;
+    }
+
+    Some(unsafe { sys::uxTaskGetStackHighWaterMark(handle) } as u32)
+}
+
+/// Logs the configured BTC stack size and the current margins of `watches`.
+#[cfg(feature = "esp")]
+pub fn log_startup(watches: &[TaskWatch]) {
+    #[cfg(esp_idf_bt_bluedroid_enabled)]
+    log::info!(
+        "BTC task stack: {} bytes configured",
+        esp_idf_svc::sys::CONFIG_BT_BTC_TASK_STACK_SIZE
+    );
+
+    for watch in watches {
+        match task_stack_free(watch.task) {
+            Some(free) => log::info!("{} stack: {free} bytes never used", watch.task),
+            None => log::info!("{} not running", watch.task),
+        }
+    }
+}
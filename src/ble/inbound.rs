@@ -0,0 +1,180 @@
+//! Fair draining of inbound events across connections.
+//!
+//! With one FIFO, a central flooding write-without-response starves every
+//! other connection. [`InboundQueue`] keeps a sub-queue per connection and
+//! serves them round-robin, at most `quantum` events per turn. Events with a
+//! response deadline (writes needing a response, reads) go to a separate
+//! queue that is always served first, so they do not run into the ATT
+//! timeout behind someone else's backlog.
+
+use std::collections::{HashMap, VecDeque};
+
+#[derive(Debug)]
+pub struct InboundQueue<T> {
+    urgent: VecDeque<(u16, T)>,
+    queues: HashMap<u16, VecDeque<T>>,
+    /// Connections with queued events, the one being served first.
+    ring: VecDeque<u16>,
+    quantum: usize,
+    served: usize,
+}
+
+impl<T> InboundQueue<T> {
+    /// `quantum` is the number of events one connection gets per turn.
+    pub fn new(quantum: usize) -> Self {
+        Self {
+            urgent: VecDeque::new(),
+            queues: HashMap::new(),
+            ring: VecDeque::new(),
+            quantum: quantum.max(1),
+            served: 0,
+        }
+    }
+
+    pub fn push(&mut self, conn_id: u16, event: T, urgent: bool) {
+        if urgent {
+            self.urgent.push_back((conn_id, event));
+            return;
+        }
+
+        let queue = self.queues.entry(conn_id).or_default();
+        if queue.is_empty() {
+            self.ring.push_back(conn_id);
+        }
+        queue.push_back(event);
+    }
+
+    pub fn pop(&mut self) -> Option<(u16, T)> {
+        if let Some(urgent) = self.urgent.pop_front() {
+            return Some(urgent);
+        }
+
+        let conn_id = *self.ring.front()?;
+        let queue = self.queues.get_mut(&conn_id)?;
+        let event = queue.pop_front()?;
+        self.served += 1;
+
+        if queue.is_empty() {
+            self.queues.remove(&conn_id);
+            self.ring.pop_front();
+            self.served = 0;
+        } else if self.served >= self.quantum {
+            self.ring.rotate_left(1);
+            self.served = 0;
+        }
+
+        Some((conn_id, event))
+    }
+
+    /// Events queued for `conn_id`, urgent ones included.
+    pub fn depth(&self, conn_id: u16) -> usize {
+        let urgent = self.urgent.iter().filter(|(id, _)| *id == conn_id).count();
+        urgent + self.queues.get(&conn_id).map_or(0, VecDeque::len)
+    }
+
+    pub fn len(&self) -> usize {
+        self.urgent.len() + self.queues.values().map(VecDeque::len).sum::<usize>()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.urgent.is_empty() && self.ring.is_empty()
+    }
+
+    /// Drops the events of `conn_id`, returning how many there were.
+    pub fn on_disconnect(&mut self, conn_id: u16) -> usize {
+        let before = self.len();
+
+        self.urgent.retain(|(id, _)| *id != conn_id);
+        self.queues.remove(&conn_id);
+        if self.ring.front() == Some(&conn_id) {
+            self.served = 0;
+        }
+        self.ring.retain(|&id| id != conn_id);
+
+        before - self.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FLOODER: u16 = 1;
+    const QUIET: u16 = 2;
+    const QUANTUM: usize = 4;
+
+    #[test]
+    fn flood_does_not_starve_other_connections() {
+        let mut queue = InboundQueue::new(QUANTUM);
+        for i in 0..1000 {
+            queue.push(FLOODER, i, false);
+        }
+        queue.push(QUIET, 0, false);
+        queue.push(QUIET, 1, false);
+
+        // Each quiet event is served within one turn of the flooder.
+        let order: Vec<_> = core::iter::from_fn(|| queue.pop()).take(10).collect();
+        assert_eq!(
+            order,
+            [
+                (FLOODER, 0),
+                (FLOODER, 1),
+                (FLOODER, 2),
+                (FLOODER, 3),
+                (QUIET, 0),
+                (QUIET, 1),
+                (FLOODER, 4),
+                (FLOODER, 5),
+                (FLOODER, 6),
+                (FLOODER, 7),
+            ]
+        );
+        assert_eq!(queue.depth(FLOODER), 992);
+        assert_eq!(queue.depth(QUIET), 0);
+    }
+
+    #[test]
+    fn late_arrival_waits_at_most_one_turn() {
+        let mut queue = InboundQueue::new(QUANTUM);
+        for i in 0..100 {
+            queue.push(FLOODER, i, false);
+        }
+        queue.pop();
+
+        queue.push(QUIET, 0, false);
+        let turns = core::iter::from_fn(|| queue.pop())
+            .position(|(conn_id, _)| conn_id == QUIET)
+            .unwrap();
+        assert!(turns < QUANTUM, "served after {turns} flooder events");
+    }
+
+    #[test]
+    fn deadline_bound_events_bypass_fairness() {
+        let mut queue = InboundQueue::new(QUANTUM);
+        for i in 0..100 {
+            queue.push(FLOODER, i, false);
+        }
+        queue.pop();
+
+        queue.push(QUIET, 10, true);
+        queue.push(FLOODER, 11, true);
+        assert_eq!(queue.pop(), Some((QUIET, 10)));
+        assert_eq!(queue.pop(), Some((FLOODER, 11)));
+        assert_eq!(queue.pop(), Some((FLOODER, 1)));
+    }
+
+    #[test]
+    fn disconnect_drops_every_queued_event() {
+        let mut queue = InboundQueue::new(QUANTUM);
+        queue.push(FLOODER, 0, false);
+        queue.push(FLOODER, 1, true);
+        queue.push(QUIET, 2, false);
+        assert_eq!(queue.depth(FLOODER), 2);
+        assert_eq!(queue.len(), 3);
+
+        assert_eq!(queue.on_disconnect(FLOODER), 2);
+        assert_eq!(queue.pop(), Some((QUIET, 2)));
+        assert_eq!(queue.pop(), None);
+        assert!(queue.is_empty());
+    }
+}
@@ -0,0 +1,214 @@
+//! Credit accounting for LE connection-oriented channels.
+//!
+//! On an LE credit-based channel every K-frame costs the sender one credit,
+//! and the receiver hands out more with LE Flow Control Credit packets. An
+//! SDU is split into K-frames of at most MPS bytes, the first one starting
+//! with the 2-byte SDU length. [`CreditFlow`] keeps both directions' counts
+//! for one channel and says when to top the peer up; the stack glue sends
+//! and receives the frames.
+
+use core::fmt;
+
+/// Credits a side may hold at once.
+pub const MAX_CREDITS: u16 = u16::MAX;
+/// Length prefix on the first K-frame of an SDU.
+pub const SDU_LEN_PREFIX: usize = 2;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum CreditError {
+    /// The peer granted credits past [`MAX_CREDITS`]; the channel must be
+    /// disconnected.
+    Overflow { held: u16, granted: u16 },
+    /// The peer sent a K-frame without holding a credit for it.
+    NoRxCredit,
+}
+
+impl fmt::Display for CreditError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Overflow { held, granted } => {
+                write!(f, "peer granted {granted} credits on top of {held}")
+            }
+            Self::NoRxCredit => write!(f, "peer sent a K-frame without credit"),
+        }
+    }
+}
+
+impl std::error::Error for CreditError {}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct CreditFlow {
+    /// K-frames we may still send.
+    tx_credits: u16,
+    /// K-frames the peer may still send.
+    rx_credits: u16,
+    /// Credits granted up front, and topped back up to.
+    rx_window: u16,
+    /// Top up once the peer holds this many or fewer.
+    rx_low_water: u16,
+}
+
+impl CreditFlow {
+    /// `peer_initial` comes from the peer's connection request or response;
+    /// `rx_window` is what we grant in ours.
+    pub fn new(peer_initial: u16, rx_window: u16) -> Self {
+        Self {
+            tx_credits: peer_initial,
+            rx_credits: rx_window,
+            rx_window,
+            rx_low_water: rx_window / 4,
+        }
+    }
+
+    pub fn tx_credits(&self) -> u16 {
+        self.tx_credits
+    }
+
+    pub fn rx_credits(&self) -> u16 {
+        self.rx_credits
+    }
+
+    /// Whether `frames` K-frames can go out now.
+    pub fn can_send(&self, frames: usize) -> bool {
+        usize::from(self.tx_credits) >= frames
+    }
+
+    /// Spends one credit per K-frame; `false`, spending nothing, if there
+    /// are not enough.
+    pub fn on_send(&mut self, frames: usize) -> bool {
+        if !self.can_send(frames) {
+            return false;
+        }
+        self.tx_credits -= frames as u16;
+        true
+    }
+
+    /// Credits received from the peer.
+    pub fn on_credits(&mut self, granted: u16) -> Result<(), CreditError> {
+        self.tx_credits = self
+            .tx_credits
+            .checked_add(granted)
+            .ok_or(CreditError::Overflow {
+                held: self.tx_credits,
+                granted,
+            })?;
+        Ok(())
+    }
+
+    /// Accounts for one K-frame received. Returns the credits to grant the
+    /// peer now, if it has run low.
+    pub fn on_receive(&mut self) -> Result<Option<u16>, CreditError> {
+        self.rx_credits = self
+            .rx_credits
+            .checked_sub(1)
+            .ok_or(CreditError::NoRxCredit)?;
+
+        if self.rx_credits > self.rx_low_water {
+            return Ok(None);
+        }
+        let grant = self.rx_window - self.rx_credits;
+        self.rx_credits = self.rx_window;
+        Ok(Some(grant))
+    }
+}
+
+/// Number of K-frames needed for an SDU of `sdu_len` bytes.
+pub fn frames_for_sdu(sdu_len: usize, mps: u16) -> usize {
+    let mps = usize::from(mps.max(1));
+    (sdu_len + SDU_LEN_PREFIX).div_ceil(mps)
+}
+
+/// Splits `sdu` into K-frame payloads of at most `mps` bytes, the first one
+/// prefixed with the SDU length. `None` if the SDU is longer than `u16::MAX`
+/// or `mps` cannot hold the prefix.
+pub fn segment_sdu(sdu: &[u8], mps: u16) -> Option<Vec<Vec<u8>>> {
+    let sdu_len = u16::try_from(sdu.len()).ok()?;
+    let mps = usize::from(mps);
+    if mps <= SDU_LEN_PREFIX {
+        return None;
+    }
+
+    let (first, rest) = sdu.split_at(sdu.len().min(mps - SDU_LEN_PREFIX));
+    let mut head = Vec::with_capacity(SDU_LEN_PREFIX + first.len());
+    head.extend_from_slice(&sdu_len.to_le_bytes());
+    head.extend_from_slice(first);
+
+    let mut frames = vec![head];
+    frames.extend(rest.chunks(mps).map(<[u8]>::to_vec));
+    Some(frames)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sending_spends_credits() {
+        let mut flow = CreditFlow::new(3, 8);
+        assert!(flow.on_send(2));
+        assert_eq!(flow.tx_credits(), 1);
+
+        // Not enough for two: nothing is spent.
+        assert!(!flow.can_send(2));
+        assert!(!flow.on_send(2));
+        assert_eq!(flow.tx_credits(), 1);
+
+        flow.on_credits(4).unwrap();
+        assert!(flow.on_send(5));
+        assert_eq!(flow.tx_credits(), 0);
+    }
+
+    #[test]
+    fn credit_overflow_is_an_error() {
+        let mut flow = CreditFlow::new(MAX_CREDITS - 1, 8);
+        flow.on_credits(1).unwrap();
+        assert_eq!(
+            flow.on_credits(1),
+            Err(CreditError::Overflow {
+                held: MAX_CREDITS,
+                granted: 1
+            })
+        );
+        assert_eq!(flow.tx_credits(), MAX_CREDITS);
+    }
+
+    #[test]
+    fn peer_is_topped_up_at_low_water() {
+        let mut flow = CreditFlow::new(0, 8);
+
+        // Low water is 8 / 4 = 2.
+        for _ in 0..5 {
+            assert_eq!(flow.on_receive(), Ok(None));
+        }
+        assert_eq!(flow.rx_credits(), 3);
+        assert_eq!(flow.on_receive(), Ok(Some(6)));
+        assert_eq!(flow.rx_credits(), 8);
+    }
+
+    #[test]
+    fn frame_without_credit_is_an_error() {
+        let mut flow = CreditFlow::new(0, 0);
+        assert_eq!(flow.on_receive(), Err(CreditError::NoRxCredit));
+    }
+
+    #[test]
+    fn segmentation_matches_the_frame_count() {
+        let sdu: Vec<u8> = (0..=255).collect();
+        for mps in [3, 23, 64, 254, 255, 258, 512] {
+            let frames = segment_sdu(&sdu, mps).unwrap();
+            assert_eq!(frames.len(), frames_for_sdu(sdu.len(), mps), "mps {mps}");
+            assert!(frames.iter().all(|frame| frame.len() <= usize::from(mps)));
+
+            assert_eq!(frames[0][..SDU_LEN_PREFIX], 256u16.to_le_bytes());
+            let reassembled: Vec<u8> = frames.concat()[SDU_LEN_PREFIX..].to_vec();
+            assert_eq!(reassembled, sdu);
+        }
+    }
+
+    #[test]
+    fn unsegmentable_sdus() {
+        assert_eq!(segment_sdu(b"abc", 2), None);
+        assert_eq!(segment_sdu(&vec![0; 65_536], 64), None);
+        assert_eq!(segment_sdu(b"", 23), Some(vec![vec![0, 0]]));
+    }
+}
@@ -0,0 +1,188 @@
+//! Per-service lifecycle state machine driven by GATTS events.
+
+use core::fmt;
+use std::collections::HashMap;
+
+use log::warn;
+
+use crate::proto::Uuid;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ServiceState {
+    Declared,
+    Creating,
+    AddingCharacteristics,
+    Started,
+    Stopping,
+    Stopped,
+    Deleted,
+    Failed(String),
+}
+
+/// Inputs of the state machine, one per relevant GATTS event or request.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum LifecycleEvent {
+    /// `create_service` was issued.
+    CreateRequested,
+    /// `ServiceCreated`.
+    Created,
+    /// `CharacteristicAdded` or `DescriptorAdded`.
+    AttributeAdded,
+    /// `ServiceStarted`.
+    Started,
+    /// `stop_service` was issued.
+    StopRequested,
+    /// `ServiceStopped`.
+    Stopped,
+    /// `ServiceDeleted`.
+    Deleted,
+    /// A GATTS event reported a failure status.
+    Failed(String),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct IllegalTransition {
+    pub state: ServiceState,
+    pub event: LifecycleEvent,
+}
+
+impl fmt::Display for IllegalTransition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?} is not valid in state {:?}", self.event, self.state)
+    }
+}
+
+impl std::error::Error for IllegalTransition {}
+
+impl ServiceState {
+    pub fn next(&self, event: &LifecycleEvent) -> Result<ServiceState, IllegalTransition> {
+        use LifecycleEvent as E;
+        use ServiceState as S;
+
+        let next = match (self, event) {
+            (S::Deleted, E::Failed(_)) => None,
+            (_, E::Failed(reason)) => Some(S::Failed(reason.clone())),
+            (S::Declared | S::Deleted | S::Failed(_), E::CreateRequested) => Some(S::Creating),
+            (S::Creating, E::Created) => Some(S::AddingCharacteristics),
+            (S::AddingCharacteristics, E::AttributeAdded) => Some(S::AddingCharacteristics),
+            (S::AddingCharacteristics | S::Stopped, E::Started) => Some(S::Started),
+            (S::Started, E::StopRequested) => Some(S::Stopping),
+            (S::Stopping, E::Stopped) => Some(S::Stopped),
+            (S::Started | S::Stopping | S::Stopped | S::Failed(_), E::Deleted) => Some(S::Deleted),
+            _ => None,
+        };
+
+        next.ok_or_else(|| IllegalTransition {
+            state: self.clone(),
+            event: event.clone(),
+        })
+    }
+}
+
+/// Lifecycle state of every registered service.
+#[derive(Debug, Default)]
+pub struct ServiceLifecycle {
+    states: HashMap<Uuid, ServiceState>,
+}
+
+impl ServiceLifecycle {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn declare(&mut self, uuid: Uuid) {
+        self.states.insert(uuid, ServiceState::Declared);
+    }
+
+    /// Applies an event. Illegal transitions and unknown services are logged
+    /// with the current state and otherwise ignored.
+    pub fn apply(&mut self, uuid: Uuid, event: LifecycleEvent) -> Option<&ServiceState> {
+        let Some(state) = self.states.get_mut(&uuid) else {
+            warn!("Lifecycle event {event:?} for undeclared service {uuid:?}");
+            return None;
+        };
+
+        match state.next(&event) {
+            Ok(next) => *state = next,
+            Err(err) => warn!("Service {uuid:?}: {err}"),
+        }
+
+        Some(state)
+    }
+
+    pub fn service_state(&self, uuid: &Uuid) -> Option<&ServiceState> {
+        self.states.get(uuid)
+    }
+
+    pub fn states(&self) -> impl Iterator<Item = (&Uuid, &ServiceState)> {
+        self.states.iter()
+    }
+
+    /// Completion condition of startup: every declared service is `Started`.
+    pub fn all_started(&self) -> bool {
+        self.states
+            .values()
+            .all(|state| *state == ServiceState::Started)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const UUID: Uuid = Uuid::Uuid16(0x180F);
+
+    fn drive(lifecycle: &mut ServiceLifecycle, events: &[LifecycleEvent]) -> ServiceState {
+        let mut state = None;
+        for event in events {
+            state = lifecycle.apply(UUID, event.clone()).cloned();
+        }
+        state.unwrap()
+    }
+
+    #[test]
+    fn stop_reaches_stopped_and_restarts() {
+        use LifecycleEvent as E;
+
+        let mut lifecycle = ServiceLifecycle::new();
+        lifecycle.declare(UUID);
+
+        let state = drive(
+            &mut lifecycle,
+            &[
+                E::CreateRequested,
+                E::Created,
+                E::AttributeAdded,
+                E::Started,
+            ],
+        );
+        assert_eq!(state, ServiceState::Started);
+        assert!(lifecycle.all_started());
+
+        assert_eq!(
+            drive(&mut lifecycle, &[E::StopRequested]),
+            ServiceState::Stopping
+        );
+        assert_eq!(drive(&mut lifecycle, &[E::Stopped]), ServiceState::Stopped);
+        assert!(!lifecycle.all_started());
+
+        assert_eq!(drive(&mut lifecycle, &[E::Started]), ServiceState::Started);
+        assert_eq!(
+            drive(&mut lifecycle, &[E::StopRequested, E::Stopped, E::Deleted]),
+            ServiceState::Deleted
+        );
+    }
+
+    #[test]
+    fn illegal_transitions_are_ignored() {
+        use LifecycleEvent as E;
+
+        assert!(ServiceState::Stopped.next(&E::AttributeAdded).is_err());
+        assert!(ServiceState::Stopping.next(&E::Started).is_err());
+        assert!(ServiceState::Deleted.next(&E::Stopped).is_err());
+
+        let mut lifecycle = ServiceLifecycle::new();
+        lifecycle.declare(UUID);
+        assert_eq!(drive(&mut lifecycle, &[E::Stopped]), ServiceState::Declared);
+    }
+}
@@ -0,0 +1,29 @@
+//! Long reads: values longer than the MTU, read in offset (blob) reads.
+
+pub const ATT_ERR_INVALID_OFFSET: u8 = 0x07;
+
+/// The part of `value` to answer a read (blob) request at `offset` with,
+/// given the connection's `mtu`.
+pub fn read_blob(value: &[u8], offset: u16, mtu: u16) -> Result<&[u8], u8> {
+    let start = usize::from(offset);
+    if start > value.len() {
+        return Err(ATT_ERR_INVALID_OFFSET);
+    }
+
+    let end = value.len().min(start + usize::from(mtu.saturating_sub(1)));
+    Ok(&value[start..end])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn read_blob_splits_by_mtu() {
+        let value: Vec<u8> = (0..50).collect();
+        assert_eq!(read_blob(&value, 0, 23).unwrap(), &value[..22]);
+        assert_eq!(read_blob(&value, 44, 23).unwrap(), &value[44..]);
+        assert_eq!(read_blob(&value, 50, 23).unwrap(), &[] as &[u8]);
+        assert_eq!(read_blob(&value, 51, 23), Err(ATT_ERR_INVALID_OFFSET));
+    }
+}
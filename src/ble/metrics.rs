@@ -0,0 +1,153 @@
+//! Periodic metrics export.
+//!
+//! Components register named gauges with [`Metrics`]; a sink installed with
+//! [`Metrics::set_sink`] receives a snapshot of all of them, plus the change
+//! since the previous report, every interval. The sink runs on the caller's
+//! (worker) thread, a panic in it is caught and counted, and its execution
+//! time is recorded.
+
+use core::fmt;
+use core::time::Duration;
+use std::collections::BTreeMap;
+use std::panic::{self, AssertUnwindSafe};
+use std::time::Instant;
+
+type Gauge = Box<dyn Fn() -> i64 + Send + Sync>;
+type Sink = Box<dyn Fn(&MetricsSnapshot, &MetricsSnapshot) + Send + Sync>;
+
+/// Gauge values by name.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MetricsSnapshot {
+    pub values: BTreeMap<&'static str, i64>,
+}
+
+impl MetricsSnapshot {
+    pub fn get(&self, name: &str) -> Option<i64> {
+        self.values.get(name).copied()
+    }
+
+    /// Change of every value since `previous`; new names count from zero.
+    pub fn delta(&self, previous: &Self) -> Self {
+        let values = self
+            .values
+            .iter()
+            .map(|(&name, &value)| {
+                let before = previous.get(name).unwrap_or(0);
+                (name, value.wrapping_sub(before))
+            })
+            .collect();
+
+        Self { values }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct SinkStats {
+    pub reports: u64,
+    pub panics: u64,
+    pub last_duration: Duration,
+    pub max_duration: Duration,
+}
+
+#[derive(Default)]
+pub struct Metrics {
+    gauges: BTreeMap<&'static str, Gauge>,
+    sink: Option<(Sink, Duration)>,
+    last: MetricsSnapshot,
+    next_report: Option<Instant>,
+    stats: SinkStats,
+}
+
+impl fmt::Debug for Metrics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Metrics")
+            .field("gauges", &self.gauges.keys().collect::<Vec<_>>())
+            .field("next_report", &self.next_report)
+            .field("stats", &self.stats)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a gauge, replacing any previous one with the same name.
+    pub fn register_gauge(
+        &mut self,
+        name: &'static str,
+        gauge: impl Fn() -> i64 + Send + Sync + 'static,
+    ) {
+        self.gauges.insert(name, Box::new(gauge));
+    }
+
+    pub fn unregister_gauge(&mut self, name: &str) -> bool {
+        self.gauges.remove(name).is_some()
+    }
+
+    /// Installs a sink called with `(snapshot, delta)` every `interval`,
+    /// starting one interval after `now`.
+    pub fn set_sink(
+        &mut self,
+        sink: impl Fn(&MetricsSnapshot, &MetricsSnapshot) + Send + Sync + 'static,
+        interval: Duration,
+        now: Instant,
+    ) {
+        self.sink = Some((Box::new(sink), interval));
+        self.next_report = Some(now + interval);
+    }
+
+    pub fn clear_sink(&mut self) {
+        self.sink = None;
+        self.next_report = None;
+    }
+
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        let values = self
+            .gauges
+            .iter()
+            .map(|(&name, gauge)| (name, gauge()))
+            .collect();
+
+        MetricsSnapshot { values }
+    }
+
+    pub fn sink_stats(&self) -> SinkStats {
+        self.stats
+    }
+
+    pub fn next_report(&self) -> Option<Instant> {
+        self.next_report
+    }
+
+    /// Reports to the sink if the interval has elapsed. Returns whether it
+    /// ran.
+    pub fn poll(&mut self, now: Instant) -> bool {
+        let Some((sink, interval)) = &self.sink else {
+            return false;
+        };
+        if self.next_report.is_some_and(|due| now < due) {
+            return false;
+        }
+
+        let snapshot = self.snapshot();
+        let delta = snapshot.delta(&self.last);
+
+        let started = Instant::now();
+        let outcome = panic::catch_unwind(AssertUnwindSafe(|| sink(&snapshot, &delta)));
+        let elapsed = started.elapsed();
+
+        self.stats.reports += 1;
+        self.stats.last_duration = elapsed;
+        self.stats.max_duration = self.stats.max_duration.max(elapsed);
+        if outcome.is_err() {
+            self.stats.panics += 1;
+            log::warn!("metrics sink panicked");
+        }
+
+        self.next_report = Some(now + *interval);
+        self.last = snapshot;
+        true
+    }
+}
@@ -0,0 +1,294 @@
+//! Mirroring of characteristic writes to a debug transport.
+//!
+//! For remote debugging, [`MirrorMiddleware`] formats every write to an
+//! allowlisted handle as one JSON line and hands it to a [`MirrorSink`]: the
+//! serial console ([`ConsoleSink`]) or anything else, e.g. an MQTT topic
+//! (see `examples/mirror_mqtt.rs`). Values of sensitive characteristics are
+//! replaced by their length.
+//!
+//! At most one record per `min_interval` is sent; writes in between are
+//! counted and reported in the next record's `skipped` field. A failing
+//! sink is counted and logged at debug level, never reported back to the
+//! write path. Sinks are called on the dispatch thread and must not block.
+
+use core::fmt::{self, Write as _};
+use core::time::Duration;
+use std::collections::HashMap;
+use std::time::Instant;
+
+use super::PeerAddr;
+use crate::proto::Uuid;
+
+pub trait MirrorSink: Send {
+    /// Sends one JSON record, without a trailing newline.
+    fn send(&mut self, line: &str) -> Result<(), Box<dyn std::error::Error + Send + Sync>>;
+}
+
+/// Prints records to stdout, i.e. the serial console.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct ConsoleSink;
+
+impl MirrorSink for ConsoleSink {
+    fn send(&mut self, line: &str) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        println!("{line}");
+        Ok(())
+    }
+}
+
+/// How writes to one handle are mirrored.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct MirrorTarget {
+    pub uuid: Uuid,
+    /// Mirror only the value length.
+    pub sensitive: bool,
+}
+
+/// One mirrored write; `Display` formats it as a JSON object.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MirrorRecord<'a> {
+    /// Milliseconds since boot.
+    pub timestamp_ms: u64,
+    pub peer: PeerAddr,
+    pub target: MirrorTarget,
+    pub value: &'a [u8],
+    /// Writes left out since the previous record.
+    pub skipped: u32,
+}
+
+impl fmt::Display for MirrorRecord<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{{\"ts_ms\":{},\"peer\":\"", self.timestamp_ms)?;
+        // Most significant byte first, as addresses are usually written.
+        for (i, byte) in self.peer.iter().enumerate() {
+            let sep = if i == 0 { "" } else { ":" };
+            write!(f, "{sep}{byte:02x}")?;
+        }
+
+        write!(
+            f,
+            "\",\"uuid\":\"{}\",\"len\":{}",
+            self.target.uuid,
+            self.value.len()
+        )?;
+        if !self.target.sensitive {
+            f.write_str(",\"hex\":\"")?;
+            for byte in self.value {
+                write!(f, "{byte:02x}")?;
+            }
+            f.write_char('"')?;
+        }
+
+        write!(f, ",\"skipped\":{}}}", self.skipped)
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct MirrorStats {
+    pub sent: u64,
+    /// Writes left out by the rate limit.
+    pub skipped: u64,
+    pub sink_errors: u64,
+}
+
+pub struct MirrorMiddleware {
+    targets: HashMap<u16, MirrorTarget>,
+    sink: Box<dyn MirrorSink>,
+    min_interval: Duration,
+    last_sent: Option<Instant>,
+    /// Skipped since the last record.
+    pending_skipped: u32,
+    stats: MirrorStats,
+}
+
+impl fmt::Debug for MirrorMiddleware {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MirrorMiddleware")
+            .field("targets", &self.targets)
+            .field("min_interval", &self.min_interval)
+            .field("stats", &self.stats)
+            .finish_non_exhaustive()
+    }
+}
+
+impl MirrorMiddleware {
+    /// Sends at most one record per `min_interval`.
+    pub fn new(sink: impl MirrorSink + 'static, min_interval: Duration) -> Self {
+        Self {
+            targets: HashMap::new(),
+            sink: Box::new(sink),
+            min_interval,
+            last_sent: None,
+            pending_skipped: 0,
+            stats: MirrorStats::default(),
+        }
+    }
+
+    /// Adds `handle` to the allowlist.
+    pub fn watch(&mut self, handle: u16, target: MirrorTarget) {
+        self.targets.insert(handle, target);
+    }
+
+    pub fn unwatch(&mut self, handle: u16) -> bool {
+        self.targets.remove(&handle).is_some()
+    }
+
+    pub fn stats(&self) -> MirrorStats {
+        self.stats
+    }
+
+    /// Mirrors a write if `handle` is watched and the rate limit allows.
+    pub fn on_write(
+        &mut self,
+        peer: PeerAddr,
+        handle: u16,
+        value: &[u8],
+        timestamp_ms: u64,
+        now: Instant,
+    ) {
+        let Some(&target) = self.targets.get(&handle) else {
+            return;
+        };
+
+        let limited = self
+            .last_sent
+            .is_some_and(|at| now.saturating_duration_since(at) < self.min_interval);
+        if limited {
+            self.pending_skipped = self.pending_skipped.saturating_add(1);
+            self.stats.skipped += 1;
+            return;
+        }
+
+        let record = MirrorRecord {
+            timestamp_ms,
+            peer,
+            target,
+            value,
+            skipped: self.pending_skipped,
+        };
+        self.last_sent = Some(now);
+        self.pending_skipped = 0;
+
+        match self.sink.send(&record.to_string()) {
+            Ok(()) => self.stats.sent += 1,
+            Err(err) => {
+                self.stats.sink_errors += 1;
+                log::debug!("mirror sink failed: {err}");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use crate::test_clock::MockClock;
+
+    const PEER: PeerAddr = [0xC0, 0xFF, 0xEE, 0x00, 0x12, 0x34];
+    const COMMAND: u16 = 42;
+    const PASSWORD: u16 = 44;
+
+    #[derive(Clone, Default)]
+    struct Lines(Arc<Mutex<Vec<String>>>);
+
+    impl Lines {
+        fn take(&self) -> Vec<String> {
+            core::mem::take(&mut self.0.lock().unwrap())
+        }
+    }
+
+    impl MirrorSink for Lines {
+        fn send(&mut self, line: &str) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+            self.0.lock().unwrap().push(line.to_owned());
+            Ok(())
+        }
+    }
+
+    struct Failing;
+
+    impl MirrorSink for Failing {
+        fn send(&mut self, _: &str) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+            Err("broker gone".into())
+        }
+    }
+
+    fn middleware(sink: impl MirrorSink + 'static) -> MirrorMiddleware {
+        let mut middleware = MirrorMiddleware::new(sink, Duration::from_millis(100));
+        middleware.watch(
+            COMMAND,
+            MirrorTarget {
+                uuid: Uuid::Uuid16(0xFFF1),
+                sensitive: false,
+            },
+        );
+        middleware.watch(
+            PASSWORD,
+            MirrorTarget {
+                uuid: Uuid::Uuid16(0xFFF2),
+                sensitive: true,
+            },
+        );
+        middleware
+    }
+
+    #[test]
+    fn record_format() {
+        let lines = Lines::default();
+        let clock = MockClock::new();
+        let mut middleware = middleware(lines.clone());
+
+        middleware.on_write(PEER, COMMAND, &[0x01, 0xAB], 1234, clock.ms(0));
+        middleware.on_write(PEER, PASSWORD, b"hunter2", 1500, clock.ms(500));
+
+        assert_eq!(
+            lines.take(),
+            [
+                r#"{"ts_ms":1234,"peer":"c0:ff:ee:00:12:34","uuid":"fff1","len":2,"hex":"01ab","skipped":0}"#,
+                r#"{"ts_ms":1500,"peer":"c0:ff:ee:00:12:34","uuid":"fff2","len":7,"skipped":0}"#,
+            ]
+        );
+    }
+
+    #[test]
+    fn write_flood_is_rate_limited() {
+        let lines = Lines::default();
+        let clock = MockClock::new();
+        let mut middleware = middleware(lines.clone());
+
+        // 50 writes 10 ms apart: one record per 100 ms.
+        for i in 0..50 {
+            middleware.on_write(PEER, COMMAND, &[i as u8], i * 10, clock.ms(i * 10));
+        }
+        // Unwatched handles are neither sent nor counted.
+        middleware.on_write(PEER, COMMAND + 1, &[0], 500, clock.ms(500));
+
+        let lines = lines.take();
+        assert_eq!(lines.len(), 5);
+        assert!(lines[0].ends_with(r#""hex":"00","skipped":0}"#));
+        assert!(lines[1].ends_with(r#""hex":"0a","skipped":9}"#));
+        assert_eq!(
+            middleware.stats(),
+            MirrorStats {
+                sent: 5,
+                skipped: 45,
+                sink_errors: 0,
+            }
+        );
+    }
+
+    #[test]
+    fn sink_errors_are_counted() {
+        let clock = MockClock::new();
+        let mut middleware = middleware(Failing);
+
+        middleware.on_write(PEER, COMMAND, &[1], 0, clock.ms(0));
+        middleware.on_write(PEER, COMMAND, &[1], 200, clock.ms(200));
+        assert_eq!(middleware.stats().sink_errors, 2);
+        assert_eq!(middleware.stats().sent, 0);
+
+        assert!(middleware.unwatch(COMMAND));
+        middleware.on_write(PEER, COMMAND, &[1], 400, clock.ms(400));
+        assert_eq!(middleware.stats().sink_errors, 2);
+    }
+}
@@ -0,0 +1,79 @@
+//! Bluetooth LE server-side building blocks.
+//!
+//! Byte-level logic lives in [`crate::proto`]. Submodules calling into
+//! `esp_idf_svc` are only built with the `esp` feature; the rest is plain
+//! state-keeping that the GATTS event handling drives.
+//!
+//! Components with deadlines never read the clock themselves: they take the
+//! current time as a `now: Instant` argument and report their next deadline,
+//! so the caller decides which clock drives them and tests can step time.
+
+pub mod admission;
+#[cfg(feature = "esp")]
+pub mod adv;
+pub mod adv_compose;
+pub mod adv_lease;
+pub mod adv_schedule;
+pub mod adv_start;
+pub mod allowlist;
+#[cfg(feature = "esp")]
+pub mod appearance;
+pub mod auth;
+pub mod binding;
+pub mod budget;
+pub mod capabilities;
+pub mod capture;
+pub mod cell;
+pub mod claim;
+pub mod coex;
+pub mod composite;
+pub mod confirm;
+pub mod conn;
+pub mod conn_auth;
+pub mod conn_profile;
+pub mod descriptors;
+pub mod early_write;
+#[cfg(all(feature = "experimental", esp_idf_bt_bluedroid_enabled))]
+pub mod error;
+pub mod event_ring;
+pub mod gatt_stream;
+pub mod handles;
+pub mod health;
+pub mod inbound;
+pub mod l2cap;
+pub mod lifecycle;
+pub mod long_read;
+pub mod metrics;
+pub mod mirror;
+pub mod mutation;
+pub mod negotiation;
+pub mod nudge;
+pub mod observed;
+pub mod order;
+pub mod outbox;
+pub mod power;
+pub mod pre_mtu;
+pub mod preflight;
+pub mod prepare;
+pub mod protocol_descriptor;
+pub mod readiness;
+pub mod report;
+pub mod resume;
+pub mod routes;
+pub mod scheduler;
+pub mod scripted;
+pub mod session;
+pub mod spec;
+pub mod storm;
+pub mod stream;
+pub mod subscription;
+pub mod swap;
+pub mod sysloop;
+pub mod throttle;
+pub mod timing;
+pub mod transform;
+pub mod txn;
+pub mod wait;
+
+/// Bluetooth device address, in the byte order used by `BdAddr`.
+pub type PeerAddr = [u8; 6];
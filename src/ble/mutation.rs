@@ -0,0 +1,345 @@
+//! Batched runtime changes to the GATT table.
+//!
+//! Adding services one at a time means one Service Changed indication each,
+//! and some Android versions re-run discovery for every one of them.
+//! [`mutate_services`] applies a whole [`MutationBatch`] and returns a single
+//! [`AffectedRange`] covering everything it touched, to be indicated once to
+//! every subscribed peer after the batch commits.
+//!
+//! If a step fails, the steps already applied are undone in reverse order:
+//! created services are deleted and removed ones added back.
+
+use core::fmt;
+
+use crate::proto::Uuid;
+
+use super::spec::ServiceSpec;
+
+/// Inclusive attribute handle range, as carried by Service Changed.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct AffectedRange {
+    pub start: u16,
+    pub end: u16,
+}
+
+impl AffectedRange {
+    pub const fn new(start: u16, end: u16) -> Self {
+        Self { start, end }
+    }
+
+    /// The smallest range covering both.
+    pub fn merge(self, other: Self) -> Self {
+        Self {
+            start: self.start.min(other.start),
+            end: self.end.max(other.end),
+        }
+    }
+
+    /// The Service Changed value: start and end handle, little-endian.
+    pub fn encode(&self) -> [u8; 4] {
+        let mut value = [0; 4];
+        value[..2].copy_from_slice(&self.start.to_le_bytes());
+        value[2..].copy_from_slice(&self.end.to_le_bytes());
+        value
+    }
+}
+
+/// What [`mutate_services`] drives: the stack plus the routing tables.
+pub trait ServiceTable {
+    type Error;
+
+    /// Creates and starts a service, returning the handles it occupies.
+    fn add(&mut self, spec: &ServiceSpec) -> Result<AffectedRange, Self::Error>;
+
+    /// Deletes a service, returning its spec and the handles it occupied.
+    fn remove(&mut self, uuid: &Uuid) -> Result<(ServiceSpec, AffectedRange), Self::Error>;
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Mutation {
+    Add(ServiceSpec),
+    Remove(Uuid),
+}
+
+/// Changes to apply together, in order.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MutationBatch {
+    mutations: Vec<Mutation>,
+}
+
+impl MutationBatch {
+    pub fn add(&mut self, spec: ServiceSpec) -> &mut Self {
+        self.mutations.push(Mutation::Add(spec));
+        self
+    }
+
+    pub fn remove(&mut self, uuid: Uuid) -> &mut Self {
+        self.mutations.push(Mutation::Remove(uuid));
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.mutations.is_empty()
+    }
+}
+
+#[derive(Debug)]
+pub struct MutationError<E> {
+    /// Index of the failed step in the batch.
+    pub step: usize,
+    pub error: E,
+    /// Undo steps that failed too; the table may be inconsistent if nonzero.
+    pub rollback_failures: usize,
+}
+
+impl<E: fmt::Display> fmt::Display for MutationError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "service mutation step {} failed: {}",
+            self.step, self.error
+        )?;
+        if self.rollback_failures > 0 {
+            write!(f, " ({} rollback steps failed)", self.rollback_failures)?;
+        }
+        Ok(())
+    }
+}
+
+impl<E: fmt::Debug + fmt::Display> std::error::Error for MutationError<E> {}
+
+/// Undo record of one applied step.
+enum Applied {
+    Added(Uuid),
+    Removed(ServiceSpec),
+}
+
+/// Applies the batch built by `build` to `table`. Returns the merged range
+/// to announce with Service Changed, `None` for an empty batch.
+pub fn mutate_services<T: ServiceTable>(
+    table: &mut T,
+    build: impl FnOnce(&mut MutationBatch),
+) -> Result<Option<AffectedRange>, MutationError<T::Error>> {
+    let mut batch = MutationBatch::default();
+    build(&mut batch);
+
+    let mut applied = Vec::with_capacity(batch.mutations.len());
+    let mut affected: Option<AffectedRange> = None;
+
+    for (step, mutation) in batch.mutations.into_iter().enumerate() {
+        let result = match mutation {
+            Mutation::Add(spec) => table
+                .add(&spec)
+                .inspect(|_| applied.push(Applied::Added(spec.uuid))),
+            Mutation::Remove(uuid) => table.remove(&uuid).map(|(spec, range)| {
+                applied.push(Applied::Removed(spec));
+                range
+            }),
+        };
+
+        match result {
+            Ok(range) => affected = Some(affected.map_or(range, |merged| merged.merge(range))),
+            Err(error) => {
+                let rollback_failures = roll_back(table, applied);
+                return Err(MutationError {
+                    step,
+                    error,
+                    rollback_failures,
+                });
+            }
+        }
+    }
+
+    Ok(affected)
+}
+
+fn roll_back<T: ServiceTable>(table: &mut T, applied: Vec<Applied>) -> usize {
+    let mut failures = 0;
+
+    for undo in applied.into_iter().rev() {
+        let ok = match &undo {
+            Applied::Added(uuid) => table.remove(uuid).is_ok(),
+            Applied::Removed(spec) => table.add(spec).is_ok(),
+        };
+        if !ok {
+            failures += 1;
+            match undo {
+                Applied::Added(uuid) => log::error!("rollback: could not remove {uuid:?}"),
+                Applied::Removed(spec) => {
+                    log::error!("rollback: could not restore {:?}", spec.uuid)
+                }
+            }
+        }
+    }
+
+    failures
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::*;
+
+    const BATTERY: Uuid = Uuid::Uuid16(0x180F);
+    const DEVICE_INFO: Uuid = Uuid::Uuid16(0x180A);
+    const CUSTOM: Uuid = Uuid::Uuid16(0xFFF0);
+
+    fn service(uuid: Uuid, num_handles: u16) -> ServiceSpec {
+        ServiceSpec {
+            uuid,
+            num_handles: Some(num_handles),
+            characteristics: Vec::new(),
+            doc: "",
+            protocol_version: None,
+        }
+    }
+
+    /// Hands out handles upwards like the stack, and fails adding `fail_add`.
+    #[derive(Default)]
+    struct MockStack {
+        services: BTreeMap<u16, (ServiceSpec, AffectedRange)>,
+        next_handle: u16,
+        fail_add: Option<Uuid>,
+        /// Every successful call, in order.
+        calls: Vec<String>,
+    }
+
+    impl MockStack {
+        fn with(services: &[ServiceSpec]) -> Self {
+            let mut stack = Self {
+                next_handle: 40,
+                ..Self::default()
+            };
+            for spec in services {
+                stack.add(spec).unwrap();
+            }
+            stack.calls.clear();
+            stack
+        }
+
+        fn uuids(&self) -> Vec<Uuid> {
+            self.services.values().map(|(spec, _)| spec.uuid).collect()
+        }
+    }
+
+    impl ServiceTable for MockStack {
+        type Error = &'static str;
+
+        fn add(&mut self, spec: &ServiceSpec) -> Result<AffectedRange, Self::Error> {
+            if self.fail_add == Some(spec.uuid) {
+                return Err("no room");
+            }
+
+            let start = self.next_handle;
+            let range = AffectedRange::new(start, start + spec.num_handles() - 1);
+            self.next_handle += spec.num_handles();
+            self.services.insert(start, (spec.clone(), range));
+            self.calls.push(format!("add {}", spec.uuid));
+            Ok(range)
+        }
+
+        fn remove(&mut self, uuid: &Uuid) -> Result<(ServiceSpec, AffectedRange), Self::Error> {
+            let start = self
+                .services
+                .iter()
+                .find(|(_, (spec, _))| spec.uuid == *uuid)
+                .map(|(&start, _)| start)
+                .ok_or("unknown service")?;
+            self.calls.push(format!("remove {uuid}"));
+            Ok(self.services.remove(&start).unwrap())
+        }
+    }
+
+    #[test]
+    fn merge_covers_both_ranges() {
+        let a = AffectedRange::new(40, 45);
+        assert_eq!(
+            a.merge(AffectedRange::new(50, 60)),
+            AffectedRange::new(40, 60)
+        );
+        assert_eq!(
+            AffectedRange::new(50, 60).merge(a),
+            AffectedRange::new(40, 60)
+        );
+        assert_eq!(a.merge(AffectedRange::new(42, 43)), a);
+        assert_eq!(
+            AffectedRange::new(0x0102, 0xFFFF).encode(),
+            [0x02, 0x01, 0xFF, 0xFF]
+        );
+    }
+
+    #[test]
+    fn batch_reports_one_merged_range() {
+        let mut stack = MockStack::with(&[service(BATTERY, 4), service(DEVICE_INFO, 6)]);
+
+        let affected = mutate_services(&mut stack, |batch| {
+            batch
+                .remove(BATTERY)
+                .add(service(CUSTOM, 8))
+                .add(service(BATTERY, 4));
+        });
+
+        // Battery was 40..=43; custom lands at 50..=57 and battery again at
+        // 58..=61.
+        assert_eq!(affected.unwrap(), Some(AffectedRange::new(40, 61)));
+        assert_eq!(stack.uuids(), [DEVICE_INFO, CUSTOM, BATTERY]);
+    }
+
+    #[test]
+    fn empty_batch_announces_nothing() {
+        let mut stack = MockStack::with(&[service(BATTERY, 4)]);
+
+        assert_eq!(mutate_services(&mut stack, |_| {}).unwrap(), None);
+        assert!(stack.calls.is_empty());
+    }
+
+    #[test]
+    fn failed_step_rolls_back_in_reverse() {
+        let mut stack = MockStack::with(&[service(BATTERY, 4), service(DEVICE_INFO, 6)]);
+        stack.fail_add = Some(CUSTOM);
+
+        let err = mutate_services(&mut stack, |batch| {
+            batch
+                .remove(DEVICE_INFO)
+                .add(service(Uuid::Uuid16(0xFFF1), 3))
+                .add(service(CUSTOM, 8));
+        })
+        .unwrap_err();
+
+        assert_eq!(err.step, 2);
+        assert_eq!(err.error, "no room");
+        assert_eq!(err.rollback_failures, 0);
+        assert_eq!(err.to_string(), "service mutation step 2 failed: no room");
+        assert_eq!(
+            stack.calls,
+            ["remove 180a", "add fff1", "remove fff1", "add 180a",]
+        );
+        let mut uuids = stack.uuids();
+        uuids.sort();
+        assert_eq!(uuids, [DEVICE_INFO, BATTERY]);
+    }
+
+    #[test]
+    fn failed_rollback_is_counted() {
+        let mut stack = MockStack::with(&[service(BATTERY, 4)]);
+
+        let err = mutate_services(&mut stack, |batch| {
+            batch.remove(BATTERY).remove(CUSTOM);
+        })
+        .unwrap_err();
+        assert_eq!(err.step, 1);
+        assert_eq!(err.rollback_failures, 0);
+        assert_eq!(stack.uuids(), [BATTERY]);
+
+        // Now the removed service cannot be restored either.
+        stack.fail_add = Some(BATTERY);
+        let err = mutate_services(&mut stack, |batch| {
+            batch.remove(BATTERY).remove(CUSTOM);
+        })
+        .unwrap_err();
+        assert_eq!(err.rollback_failures, 1);
+        assert!(err.to_string().ends_with("(1 rollback steps failed)"));
+        assert!(stack.uuids().is_empty());
+    }
+}
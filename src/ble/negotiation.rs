@@ -0,0 +1,106 @@
+//! Per-connection protocol negotiation.
+//!
+//! A service declared [`with_protocol_version`](super::spec::ServiceSpec::with_protocol_version)
+//! serves its [`ProtocolVersion`] for reading. When the client writes its
+//! own, [`on_client_version`] stores the [`Negotiated`] result in the
+//! connection's [`Session`]; handlers branch on it through
+//! [`peer_capabilities`], and [`require_features`] refuses commands needing
+//! a feature the peer did not claim.
+
+use super::session::Session;
+use crate::proto::{Negotiated, ProtocolVersion};
+
+pub const ATT_ERR_REQUEST_NOT_SUPPORTED: u8 = 0x06;
+pub const ATT_ERR_INVALID_ATTRIBUTE_LEN: u8 = 0x0D;
+/// ATT application error for a client with another major version.
+pub const ATT_ERR_INCOMPATIBLE_VERSION: u8 = 0x84;
+
+/// Handles the client's write of its version; the `Err` is the ATT status
+/// to answer with. A failed negotiation clears an earlier result.
+pub fn on_client_version(
+    session: &mut Session,
+    local: &ProtocolVersion,
+    value: &[u8],
+) -> Result<Negotiated, u8> {
+    session.remove::<Negotiated>();
+
+    let peer = ProtocolVersion::decode(value).ok_or(ATT_ERR_INVALID_ATTRIBUTE_LEN)?;
+    let negotiated = local
+        .negotiate(&peer)
+        .map_err(|_| ATT_ERR_INCOMPATIBLE_VERSION)?;
+
+    session.insert(negotiated);
+    Ok(negotiated)
+}
+
+/// The negotiated result, `None` until the client wrote its version.
+pub fn peer_capabilities(session: &Session) -> Option<&Negotiated> {
+    session.get::<Negotiated>()
+}
+
+/// Refuses a command needing `features` unless the peer negotiated them.
+pub fn require_features(session: &Session, features: u32) -> Result<(), u8> {
+    match peer_capabilities(session) {
+        Some(negotiated) if negotiated.supports(features) => Ok(()),
+        _ => Err(ATT_ERR_REQUEST_NOT_SUPPORTED),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const COMPRESSION: u32 = 0x01;
+    const BATCHING: u32 = 0x02;
+
+    const LOCAL: ProtocolVersion = ProtocolVersion::new(1, 3, COMPRESSION | BATCHING);
+
+    #[test]
+    fn features_the_peer_did_not_claim_are_refused() {
+        let mut session = Session::new();
+        assert_eq!(
+            require_features(&session, COMPRESSION),
+            Err(ATT_ERR_REQUEST_NOT_SUPPORTED)
+        );
+
+        let peer = ProtocolVersion::new(1, 2, COMPRESSION);
+        let negotiated = on_client_version(&mut session, &LOCAL, &peer.encode()).unwrap();
+        assert_eq!(peer_capabilities(&session), Some(&negotiated));
+        assert_eq!(negotiated.minor, 2);
+
+        assert_eq!(require_features(&session, COMPRESSION), Ok(()));
+        assert_eq!(
+            require_features(&session, BATCHING),
+            Err(ATT_ERR_REQUEST_NOT_SUPPORTED)
+        );
+        assert_eq!(
+            require_features(&session, COMPRESSION | BATCHING),
+            Err(ATT_ERR_REQUEST_NOT_SUPPORTED)
+        );
+    }
+
+    #[test]
+    fn bad_writes_clear_the_earlier_result() {
+        let mut session = Session::new();
+        let peer = ProtocolVersion::new(1, 3, COMPRESSION | BATCHING);
+        on_client_version(&mut session, &LOCAL, &peer.encode()).unwrap();
+
+        assert_eq!(
+            on_client_version(&mut session, &LOCAL, &[1, 3]),
+            Err(ATT_ERR_INVALID_ATTRIBUTE_LEN)
+        );
+        assert_eq!(peer_capabilities(&session), None);
+
+        on_client_version(&mut session, &LOCAL, &peer.encode()).unwrap();
+        let other_major = ProtocolVersion::new(2, 0, COMPRESSION);
+        assert_eq!(
+            on_client_version(&mut session, &LOCAL, &other_major.encode()),
+            Err(ATT_ERR_INCOMPATIBLE_VERSION)
+        );
+        assert_eq!(peer_capabilities(&session), None);
+        assert_eq!(
+            require_features(&session, 0),
+            Err(ATT_ERR_REQUEST_NOT_SUPPORTED)
+        );
+    }
+}
@@ -0,0 +1,315 @@
+//! Hinting idle centrals that they should subscribe.
+//!
+//! Some centrals connect and never enable notifications. With a
+//! [`NudgeConfig`] set, [`Nudger::on_connect`] posts a task to the shared
+//! [`Scheduler`]; if it comes due before the connection subscribes to the
+//! designated characteristic, [`Nudger::on_due`] raises the hint: an
+//! advertising fragment through the [`AdvComposer`], a nonzero
+//! [`Nudger::hint`] byte for the readable hint characteristic, and the
+//! `on_idle_unsubscribed` callback. A subscription cancels the pending task
+//! and, once no connection is left idle, lowers the hint again.
+//!
+//! Without a config, the default, every call is a no-op.
+
+use core::fmt;
+use core::time::Duration;
+use std::collections::{HashMap, HashSet};
+use std::time::Instant;
+
+use crate::proto::AdFragment;
+
+use super::adv_compose::AdvComposer;
+use super::scheduler::{ScheduleToken, Scheduler};
+
+/// Name of the fragment registered with the [`AdvComposer`].
+pub const NUDGE_FRAGMENT: &str = "nudge";
+
+/// Value of the hint characteristic while a connection is nudged.
+pub const HINT_DATA_AVAILABLE: u8 = 1;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NudgeConfig {
+    /// Idle time after connecting before the nudge.
+    pub after: Duration,
+    /// The characteristic a subscription to which counts.
+    pub handle: u16,
+    /// Advertised while any connection is nudged, if set.
+    pub adv_fragment: Option<(u8, AdFragment)>,
+}
+
+pub type IdleCallback = Box<dyn FnMut(u16) + Send>;
+
+#[derive(Default)]
+pub struct Nudger {
+    config: Option<NudgeConfig>,
+    on_idle_unsubscribed: Option<IdleCallback>,
+    pending: HashMap<u16, ScheduleToken>,
+    nudged: HashSet<u16>,
+}
+
+impl fmt::Debug for Nudger {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Nudger")
+            .field("config", &self.config)
+            .field("pending", &self.pending.keys())
+            .field("nudged", &self.nudged)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Nudger {
+    /// Disabled until [`set_config`](Self::set_config).
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set_config(&mut self, config: Option<NudgeConfig>) {
+        self.config = config;
+    }
+
+    pub fn set_on_idle_unsubscribed(&mut self, callback: IdleCallback) {
+        self.on_idle_unsubscribed = Some(callback);
+    }
+
+    /// Schedules the nudge for a new connection. `task` is what the
+    /// dispatch worker hands back to [`on_due`](Self::on_due) for it.
+    pub fn on_connect<T>(
+        &mut self,
+        conn_id: u16,
+        scheduler: &mut Scheduler<T>,
+        task: T,
+        now: Instant,
+    ) {
+        let Some(config) = &self.config else {
+            return;
+        };
+
+        let token = scheduler.schedule_for(conn_id, config.after, task, now);
+        if let Some(previous) = self.pending.insert(conn_id, token) {
+            scheduler.cancel(&previous);
+        }
+    }
+
+    /// Raises the hint for `conn_id` unless it subscribed in the meantime.
+    pub fn on_due(&mut self, conn_id: u16, composer: &mut AdvComposer, now: Instant) {
+        if self.pending.remove(&conn_id).is_none() {
+            return;
+        }
+        let Some(config) = &self.config else {
+            return;
+        };
+
+        if self.nudged.is_empty() {
+            if let Some((priority, fragment)) = &config.adv_fragment {
+                composer.set_fragment(NUDGE_FRAGMENT, *priority, fragment.clone(), now);
+            }
+        }
+        self.nudged.insert(conn_id);
+
+        log::info!("connection {conn_id} idle without subscribing, nudging");
+        if let Some(callback) = &mut self.on_idle_unsubscribed {
+            callback(conn_id);
+        }
+    }
+
+    /// Called on every CCCD write that enables notifications or
+    /// indications.
+    pub fn on_subscribed<T>(
+        &mut self,
+        conn_id: u16,
+        handle: u16,
+        scheduler: &mut Scheduler<T>,
+        composer: &mut AdvComposer,
+        now: Instant,
+    ) {
+        if self.config.as_ref().map(|config| config.handle) != Some(handle) {
+            return;
+        }
+        self.forget(conn_id, scheduler, composer, now);
+    }
+
+    pub fn on_disconnect<T>(
+        &mut self,
+        conn_id: u16,
+        scheduler: &mut Scheduler<T>,
+        composer: &mut AdvComposer,
+        now: Instant,
+    ) {
+        self.forget(conn_id, scheduler, composer, now);
+    }
+
+    /// The hint characteristic's value.
+    pub fn hint(&self) -> u8 {
+        if self.nudged.is_empty() {
+            0
+        } else {
+            HINT_DATA_AVAILABLE
+        }
+    }
+
+    pub fn is_nudged(&self, conn_id: u16) -> bool {
+        self.nudged.contains(&conn_id)
+    }
+
+    fn forget<T>(
+        &mut self,
+        conn_id: u16,
+        scheduler: &mut Scheduler<T>,
+        composer: &mut AdvComposer,
+        now: Instant,
+    ) {
+        if let Some(token) = self.pending.remove(&conn_id) {
+            scheduler.cancel(&token);
+        }
+        if self.nudged.remove(&conn_id) && self.nudged.is_empty() {
+            composer.remove_fragment(NUDGE_FRAGMENT, now);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use crate::proto::AdvPayloadBuilder;
+    use crate::test_clock::MockClock;
+
+    const STREAM: u16 = 42;
+
+    struct Harness {
+        clock: MockClock,
+        nudger: Nudger,
+        scheduler: Scheduler<u16>,
+        composer: AdvComposer,
+        idle: Arc<Mutex<Vec<u16>>>,
+    }
+
+    impl Harness {
+        fn new() -> Self {
+            let mut nudger = Nudger::new();
+            nudger.set_config(Some(NudgeConfig {
+                after: Duration::from_secs(10),
+                handle: STREAM,
+                adv_fragment: Some((
+                    1,
+                    AdFragment::Raw {
+                        ad_type: 0xFF,
+                        data: vec![0xAB],
+                    },
+                )),
+            }));
+            let idle = Arc::new(Mutex::new(Vec::new()));
+            let heard = idle.clone();
+            nudger.set_on_idle_unsubscribed(Box::new(move |conn_id| {
+                heard.lock().unwrap().push(conn_id);
+            }));
+
+            Self {
+                clock: MockClock::new(),
+                nudger,
+                scheduler: Scheduler::new(),
+                composer: AdvComposer::new(AdvPayloadBuilder::new(), Duration::ZERO),
+                idle,
+            }
+        }
+
+        fn connect(&mut self, conn_id: u16, secs: u64) {
+            let now = self.clock.secs(secs);
+            self.nudger
+                .on_connect(conn_id, &mut self.scheduler, conn_id, now);
+        }
+
+        /// Runs the tasks due at `secs`, as the dispatch worker would.
+        fn run(&mut self, secs: u64) {
+            let now = self.clock.secs(secs);
+            for conn_id in self.scheduler.poll(now) {
+                self.nudger.on_due(conn_id, &mut self.composer, now);
+            }
+        }
+
+        fn subscribe(&mut self, conn_id: u16, handle: u16, secs: u64) {
+            let now = self.clock.secs(secs);
+            self.nudger.on_subscribed(
+                conn_id,
+                handle,
+                &mut self.scheduler,
+                &mut self.composer,
+                now,
+            );
+        }
+
+        fn advertised(&self) -> bool {
+            self.composer
+                .compose()
+                .unwrap()
+                .payload
+                .adv_data
+                .ends_with(&[2, 0xFF, 0xAB])
+        }
+    }
+
+    #[test]
+    fn subscribing_before_the_timeout_cancels_the_nudge() {
+        let mut harness = Harness::new();
+        harness.connect(1, 0);
+
+        harness.subscribe(1, STREAM, 5);
+        assert!(harness.scheduler.is_empty());
+
+        harness.run(10);
+        assert!(!harness.nudger.is_nudged(1));
+        assert_eq!(harness.nudger.hint(), 0);
+        assert!(!harness.advertised());
+        assert!(harness.idle.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn other_subscriptions_do_not_count() {
+        let mut harness = Harness::new();
+        harness.connect(1, 0);
+
+        harness.subscribe(1, STREAM + 1, 5);
+        harness.run(9);
+        assert!(!harness.nudger.is_nudged(1));
+        harness.run(10);
+        assert!(harness.nudger.is_nudged(1));
+    }
+
+    #[test]
+    fn subscribing_after_the_timeout_lowers_the_hint() {
+        let mut harness = Harness::new();
+        harness.connect(1, 0);
+        harness.connect(2, 5);
+
+        harness.run(10);
+        assert!(harness.nudger.is_nudged(1));
+        assert_eq!(harness.nudger.hint(), HINT_DATA_AVAILABLE);
+        assert!(harness.advertised());
+        assert_eq!(*harness.idle.lock().unwrap(), [1]);
+
+        harness.run(15);
+        assert_eq!(*harness.idle.lock().unwrap(), [1, 2]);
+
+        // The hint stays up while any connection is still idle.
+        harness.subscribe(1, STREAM, 20);
+        assert!(!harness.nudger.is_nudged(1));
+        assert_eq!(harness.nudger.hint(), HINT_DATA_AVAILABLE);
+        assert!(harness.advertised());
+
+        harness.subscribe(2, STREAM, 21);
+        assert_eq!(harness.nudger.hint(), 0);
+        assert!(!harness.advertised());
+    }
+
+    #[test]
+    fn disabled_without_config() {
+        let mut harness = Harness::new();
+        harness.nudger.set_config(None);
+
+        harness.connect(1, 0);
+        assert!(harness.scheduler.is_empty());
+        harness.run(60);
+        assert_eq!(harness.nudger.hint(), 0);
+    }
+}
@@ -0,0 +1,239 @@
+//! Firmware-owned values notified on change, debounced.
+//!
+//! An [`ObservedValue`] holds a value such as a door or relay state that
+//! clients read and subscribe to. [`ObservedValue::set`] records a new
+//! value; only a real change, as judged by the comparator, schedules a
+//! notification, and notifications are at least `min_interval` apart. A
+//! burst of changes within the interval collapses into one notification of
+//! the latest value, and a change that is reverted before it is sent is not
+//! sent at all.
+//!
+//! `set` never blocks: when the state is locked by a reader it parks the
+//! value, with the time it was set, in a pending slot that the next
+//! [`get`](ObservedValue::get), [`poll`](ObservedValue::poll) or
+//! [`next_deadline`](ObservedValue::next_deadline) folds in. The owner
+//! polls at `next_deadline`, e.g. from a
+//! [`Scheduler`](super::scheduler::Scheduler) task.
+
+use core::fmt;
+use core::time::Duration;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, MutexGuard, PoisonError, TryLockError};
+use std::time::Instant;
+
+type Comparator<T> = Box<dyn Fn(&T, &T) -> bool + Send + Sync>;
+
+#[derive(Debug)]
+struct State<T> {
+    current: T,
+    notified: T,
+    last_sent: Option<Instant>,
+    due_at: Option<Instant>,
+}
+
+pub struct ObservedValue<T> {
+    state: Mutex<State<T>>,
+    pending: Mutex<Option<(T, Instant)>>,
+    has_pending: AtomicBool,
+    min_interval: Duration,
+    same: Comparator<T>,
+}
+
+impl<T: fmt::Debug> fmt::Debug for ObservedValue<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ObservedValue")
+            .field("state", &self.state)
+            .field("min_interval", &self.min_interval)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<T: Clone + PartialEq + Send + 'static> ObservedValue<T> {
+    /// Starts at `initial`, which counts as already notified.
+    pub fn new(initial: T, min_interval: Duration) -> Self {
+        Self::with_comparator(initial, min_interval, |a: &T, b: &T| a == b)
+    }
+}
+
+impl<T: Clone + Send> ObservedValue<T> {
+    /// Like [`new`](Self::new), but `same` decides whether two values count
+    /// as equal, e.g. to ignore sensor jitter.
+    pub fn with_comparator(
+        initial: T,
+        min_interval: Duration,
+        same: impl Fn(&T, &T) -> bool + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            state: Mutex::new(State {
+                current: initial.clone(),
+                notified: initial,
+                last_sent: None,
+                due_at: None,
+            }),
+            pending: Mutex::new(None),
+            has_pending: AtomicBool::new(false),
+            min_interval,
+            same: Box::new(same),
+        }
+    }
+
+    /// Records a new value without blocking. Returns `false` only if both
+    /// the state and the pending slot were busy and the value was dropped.
+    pub fn set(&self, value: T, now: Instant) -> bool {
+        match self.state.try_lock() {
+            Ok(mut state) => {
+                self.apply(&mut state, value, now);
+                true
+            }
+            Err(TryLockError::Poisoned(poisoned)) => {
+                self.apply(&mut poisoned.into_inner(), value, now);
+                true
+            }
+            Err(TryLockError::WouldBlock) => match self.pending.try_lock() {
+                Ok(mut pending) => {
+                    *pending = Some((value, now));
+                    self.has_pending.store(true, Ordering::Release);
+                    true
+                }
+                Err(_) => false,
+            },
+        }
+    }
+
+    pub fn get(&self) -> T {
+        self.state().current.clone()
+    }
+
+    /// The value to notify, once its debounce has passed.
+    pub fn poll(&self, now: Instant) -> Option<T> {
+        let mut state = self.state();
+        if state.due_at.map_or(true, |due_at| now < due_at) {
+            return None;
+        }
+        state.due_at = None;
+
+        if (self.same)(&state.current, &state.notified) {
+            return None;
+        }
+        state.notified = state.current.clone();
+        state.last_sent = Some(now);
+        Some(state.current.clone())
+    }
+
+    pub fn next_deadline(&self) -> Option<Instant> {
+        self.state().due_at
+    }
+
+    /// Locks the state with any pending value folded in.
+    fn state(&self) -> MutexGuard<'_, State<T>> {
+        let mut state = self.lock();
+        if self.has_pending.swap(false, Ordering::Acquire) {
+            let pending = self
+                .pending
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .take();
+            if let Some((value, set_at)) = pending {
+                self.apply(&mut state, value, set_at);
+            }
+        }
+        state
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State<T>> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn apply(&self, state: &mut State<T>, value: T, now: Instant) {
+        if (self.same)(&state.current, &value) {
+            return;
+        }
+        state.current = value;
+
+        let earliest = state
+            .last_sent
+            .map_or(now, |sent| (sent + self.min_interval).max(now));
+        state.due_at.get_or_insert(earliest);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INTERVAL: Duration = Duration::from_millis(100);
+
+    fn ms(start: Instant, ms: u64) -> Instant {
+        start + Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn first_change_is_due_at_once() {
+        let start = Instant::now();
+        let door = ObservedValue::new(false, INTERVAL);
+        assert_eq!(door.next_deadline(), None);
+
+        assert!(door.set(true, start));
+        assert!(door.get());
+        assert_eq!(door.next_deadline(), Some(start));
+        assert_eq!(door.poll(start), Some(true));
+        assert_eq!(door.next_deadline(), None);
+        assert_eq!(door.poll(start), None);
+    }
+
+    #[test]
+    fn burst_collapses_into_one_notification_of_the_latest_value() {
+        let start = Instant::now();
+        let relay = ObservedValue::new(0u8, INTERVAL);
+        relay.set(1, start);
+        assert_eq!(relay.poll(start), Some(1));
+
+        for (at, value) in [(10, 2), (20, 3), (30, 4)] {
+            relay.set(value, ms(start, at));
+        }
+        assert_eq!(relay.next_deadline(), Some(ms(start, 100)));
+        assert_eq!(relay.poll(ms(start, 99)), None);
+        assert_eq!(relay.poll(ms(start, 100)), Some(4));
+        assert_eq!(relay.poll(ms(start, 200)), None);
+    }
+
+    #[test]
+    fn reverted_change_is_not_sent() {
+        let start = Instant::now();
+        let relay = ObservedValue::new(0u8, INTERVAL);
+        relay.set(1, start);
+        relay.poll(start);
+
+        relay.set(2, ms(start, 10));
+        relay.set(1, ms(start, 20));
+        assert_eq!(relay.poll(ms(start, 100)), None);
+        assert_eq!(relay.next_deadline(), None);
+    }
+
+    #[test]
+    fn comparator_suppresses_jitter() {
+        let start = Instant::now();
+        let temperature =
+            ObservedValue::with_comparator(20.0f32, INTERVAL, |a, b| (a - b).abs() < 0.5);
+
+        temperature.set(20.3, start);
+        assert_eq!(temperature.next_deadline(), None);
+        assert_eq!(temperature.get(), 20.0);
+
+        temperature.set(21.0, start);
+        assert_eq!(temperature.poll(start), Some(21.0));
+    }
+
+    #[test]
+    fn value_parked_while_locked_is_folded_into_the_deadline() {
+        let start = Instant::now();
+        let door = ObservedValue::new(false, INTERVAL);
+
+        let reader = door.lock();
+        assert!(door.set(true, ms(start, 5)));
+        drop(reader);
+
+        assert_eq!(door.next_deadline(), Some(ms(start, 5)));
+        assert_eq!(door.poll(ms(start, 5)), Some(true));
+    }
+}
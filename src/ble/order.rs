@@ -0,0 +1,292 @@
+//! Service creation ordering and readiness gates.
+//!
+//! Services can declare that they must be created after other services and
+//! only once a predicate holds (e.g. NVS is mounted). [`creation_order`]
+//! derives a deterministic creation sequence from the declared dependencies;
+//! the creation queue polls [`ReadinessGate::check`] until a gate opens or
+//! its wait times out.
+
+use core::fmt;
+use core::hash::Hash;
+use core::time::Duration;
+use std::collections::HashSet;
+use std::time::Instant;
+
+/// A service and the services it must be created after.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Dependencies<K> {
+    pub service: K,
+    pub after: Vec<K>,
+}
+
+impl<K> Dependencies<K> {
+    pub fn new(service: K) -> Self {
+        Self {
+            service,
+            after: Vec::new(),
+        }
+    }
+
+    pub fn after(mut self, service: K) -> Self {
+        self.after.push(service);
+        self
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum OrderError<K> {
+    /// `service` depends on `missing`, which is not declared.
+    UnknownDependency { service: K, missing: K },
+    /// The listed services depend on each other. Services merely waiting
+    /// behind the cycle are not listed.
+    Cycle(Vec<K>),
+}
+
+impl<K: fmt::Debug> fmt::Display for OrderError<K> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnknownDependency { service, missing } => {
+                write!(f, "{service:?} depends on undeclared service {missing:?}")
+            }
+            Self::Cycle(services) => write!(f, "dependency cycle between {services:?}"),
+        }
+    }
+}
+
+impl<K: fmt::Debug> std::error::Error for OrderError<K> {}
+
+/// Orders services so each comes after its dependencies.
+///
+/// Among services whose dependencies are satisfied, declaration order wins,
+/// so the result is deterministic.
+pub fn creation_order<K>(services: &[Dependencies<K>]) -> Result<Vec<K>, OrderError<K>>
+where
+    K: Clone + Eq + Hash,
+{
+    let declared: HashSet<_> = services.iter().map(|deps| &deps.service).collect();
+    for deps in services {
+        if let Some(missing) = deps.after.iter().find(|dep| !declared.contains(dep)) {
+            return Err(OrderError::UnknownDependency {
+                service: deps.service.clone(),
+                missing: missing.clone(),
+            });
+        }
+    }
+
+    let mut placed = HashSet::new();
+    let mut order = Vec::with_capacity(services.len());
+    let mut pending: Vec<_> = services.iter().collect();
+
+    while !pending.is_empty() {
+        let Some(next) = pending
+            .iter()
+            .position(|deps| deps.after.iter().all(|dep| placed.contains(dep)))
+        else {
+            return Err(OrderError::Cycle(
+                pending
+                    .iter()
+                    .filter(|deps| on_cycle(&pending, &deps.service))
+                    .map(|deps| deps.service.clone())
+                    .collect(),
+            ));
+        };
+
+        let deps = pending.remove(next);
+        placed.insert(&deps.service);
+        order.push(deps.service.clone());
+    }
+
+    Ok(order)
+}
+
+/// Whether `service` can reach itself through the dependencies of the
+/// services in `pending`.
+fn on_cycle<K: Eq + Hash>(pending: &[&Dependencies<K>], service: &K) -> bool {
+    let after = |service: &K| {
+        pending
+            .iter()
+            .find(|deps| deps.service == *service)
+            .map_or(&[][..], |deps| &deps.after[..])
+    };
+
+    let mut seen = HashSet::new();
+    let mut stack: Vec<&K> = after(service).iter().collect();
+    while let Some(next) = stack.pop() {
+        if next == service {
+            return true;
+        }
+        if seen.insert(next) {
+            stack.extend(after(next));
+        }
+    }
+
+    false
+}
+
+/// Named predicate a service waits on before its creation begins.
+pub struct ReadinessGate {
+    pub name: &'static str,
+    predicate: Box<dyn Fn() -> bool + Send + Sync>,
+}
+
+impl fmt::Debug for ReadinessGate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ReadinessGate")
+            .field("name", &self.name)
+            .finish_non_exhaustive()
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct GateTimeout {
+    pub gate: &'static str,
+    pub waited: Duration,
+}
+
+impl fmt::Display for GateTimeout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "gate {:?} still closed after {:?}",
+            self.gate, self.waited
+        )
+    }
+}
+
+impl std::error::Error for GateTimeout {}
+
+impl ReadinessGate {
+    pub fn new(name: &'static str, predicate: impl Fn() -> bool + Send + Sync + 'static) -> Self {
+        Self {
+            name,
+            predicate: Box::new(predicate),
+        }
+    }
+
+    pub fn is_open(&self) -> bool {
+        (self.predicate)()
+    }
+
+    /// Checks the gate for a wait that began at `since`: `Ok(true)` once it
+    /// is open, `Ok(false)` while it is closed with time left, and
+    /// [`GateTimeout`] once `timeout` has passed at `now`.
+    pub fn check(
+        &self,
+        since: Instant,
+        timeout: Duration,
+        now: Instant,
+    ) -> Result<bool, GateTimeout> {
+        if self.is_open() {
+            return Ok(true);
+        }
+
+        let waited = now.saturating_duration_since(since);
+        if waited >= timeout {
+            return Err(GateTimeout {
+                gate: self.name,
+                waited,
+            });
+        }
+        Ok(false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_clock::MockClock;
+
+    #[test]
+    fn orders_three_services_deterministically() {
+        let services = [
+            Dependencies::new("ota").after("dis"),
+            Dependencies::new("wifi"),
+            Dependencies::new("dis"),
+        ];
+        assert_eq!(creation_order(&services), Ok(vec!["wifi", "dis", "ota"]));
+
+        let services = [
+            Dependencies::new("wifi").after("ota"),
+            Dependencies::new("ota").after("dis"),
+            Dependencies::new("dis"),
+        ];
+        assert_eq!(creation_order(&services), Ok(vec!["dis", "ota", "wifi"]));
+    }
+
+    #[test]
+    fn independent_services_keep_declaration_order() {
+        let services = [
+            Dependencies::new("dis"),
+            Dependencies::new("ota"),
+            Dependencies::new("wifi"),
+        ];
+        assert_eq!(creation_order(&services), Ok(vec!["dis", "ota", "wifi"]));
+    }
+
+    #[test]
+    fn undeclared_dependency_is_reported() {
+        let services = [Dependencies::new("ota").after("dis")];
+        assert_eq!(
+            creation_order(&services),
+            Err(OrderError::UnknownDependency {
+                service: "ota",
+                missing: "dis",
+            })
+        );
+    }
+
+    #[test]
+    fn cycle_lists_only_its_members() {
+        let services = [
+            Dependencies::new("dis"),
+            Dependencies::new("ota").after("wifi"),
+            Dependencies::new("wifi").after("ota").after("dis"),
+            Dependencies::new("logs").after("wifi"),
+        ];
+        assert_eq!(
+            creation_order(&services),
+            Err(OrderError::Cycle(vec!["ota", "wifi"]))
+        );
+    }
+
+    #[test]
+    fn self_dependency_is_a_cycle() {
+        let services = [
+            Dependencies::new("dis"),
+            Dependencies::new("ota").after("ota"),
+        ];
+        assert_eq!(
+            creation_order(&services),
+            Err(OrderError::Cycle(vec!["ota"]))
+        );
+    }
+
+    #[test]
+    fn gate_check_times_out_or_opens() {
+        let clock = MockClock::new();
+        let closed = ReadinessGate::new("nvs", || false);
+        let timeout = Duration::from_secs(5);
+
+        assert_eq!(
+            closed.check(clock.secs(0), timeout, clock.secs(0)),
+            Ok(false)
+        );
+        assert_eq!(
+            closed.check(clock.secs(0), timeout, clock.ms(4999)),
+            Ok(false)
+        );
+        assert_eq!(
+            closed.check(clock.secs(0), timeout, clock.secs(6)),
+            Err(GateTimeout {
+                gate: "nvs",
+                waited: Duration::from_secs(6),
+            })
+        );
+
+        let open = ReadinessGate::new("nvs", || true);
+        assert_eq!(
+            open.check(clock.secs(0), Duration::ZERO, clock.secs(9)),
+            Ok(true)
+        );
+    }
+}
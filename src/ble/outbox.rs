@@ -0,0 +1,489 @@
+//! Bounded outgoing queues per connection.
+//!
+//! Producers such as the log streamer need to know when a connection's
+//! queue is full so they can drop records themselves.
+//! [`Outbox::try_notify`] never blocks and says why a value was not queued;
+//! [`Outbox::wait_for_space`] blocks until the drain makes room, on the
+//! condvar the drain signals. [`Outbox::notify`] is those two combined.
+//!
+//! A handle can require a minimum link [`SecurityLevel`]; values for a less
+//! secure connection are refused or held until [`Outbox::on_security`]
+//! sees the link upgraded.
+
+use core::fmt;
+use core::time::Duration;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Condvar, Mutex, MutexGuard, PoisonError};
+use std::time::Instant;
+
+use super::conn::SecurityLevel;
+use super::subscription::{Delivery, SendMode};
+use super::wait::LinkState;
+use crate::proto::SmallPayload;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SendError {
+    QueueFull {
+        depth: usize,
+        capacity: usize,
+    },
+    NotSubscribed,
+    Disconnected,
+    InsufficientSecurity {
+        required: SecurityLevel,
+        actual: SecurityLevel,
+    },
+    /// The queue did not drain far enough within the wait.
+    Timeout,
+}
+
+impl fmt::Display for SendError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::QueueFull { depth, capacity } => {
+                write!(f, "outgoing queue full ({depth}/{capacity})")
+            }
+            Self::NotSubscribed => f.write_str("client is not subscribed"),
+            Self::Disconnected => f.write_str("connection is gone"),
+            Self::InsufficientSecurity { required, actual } => {
+                write!(f, "link security {actual:?} is below {required:?}")
+            }
+            Self::Timeout => f.write_str("timed out waiting for queue space"),
+        }
+    }
+}
+
+impl std::error::Error for SendError {}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Outgoing {
+    pub handle: u16,
+    pub value: SmallPayload,
+    pub delivery: Delivery,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum OnInsecure {
+    Refuse,
+    /// Queue the value, but send it only once the link is secure enough.
+    Hold,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SendSecurity {
+    pub min: SecurityLevel,
+    pub on_insecure: OnInsecure,
+}
+
+#[derive(Debug, Default)]
+struct Queue {
+    ready: VecDeque<Outgoing>,
+    held: VecDeque<Outgoing>,
+}
+
+impl Queue {
+    fn len(&self) -> usize {
+        self.ready.len() + self.held.len()
+    }
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    queues: HashMap<u16, Queue>,
+    security: HashMap<u16, SendSecurity>,
+}
+
+#[derive(Debug)]
+pub struct Outbox {
+    inner: Mutex<Inner>,
+    drained: Condvar,
+    capacity: usize,
+}
+
+impl Outbox {
+    /// `capacity` is per connection.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            inner: Mutex::new(Inner::default()),
+            drained: Condvar::new(),
+            capacity,
+        }
+    }
+
+    pub fn queue_capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Queued values, held ones included; `None` for an unknown connection.
+    pub fn queue_len(&self, conn_id: u16) -> Option<usize> {
+        self.lock().queues.get(&conn_id).map(Queue::len)
+    }
+
+    pub fn set_send_security(&self, handle: u16, security: SendSecurity) {
+        self.lock().security.insert(handle, security);
+    }
+
+    pub fn on_connect(&self, conn_id: u16) {
+        self.lock().queues.entry(conn_id).or_default();
+    }
+
+    /// Drops the queue and wakes its waiters, who get
+    /// [`SendError::Disconnected`].
+    pub fn on_disconnect(&self, conn_id: u16) {
+        self.lock().queues.remove(&conn_id);
+        self.drained.notify_all();
+    }
+
+    /// Releases held values the link of `conn_id` is now secure enough for;
+    /// call after updating its level in `link`. Returns how many.
+    pub fn on_security(&self, link: &LinkState, conn_id: u16) -> usize {
+        let Some(level) = link.read(|conns, _| conns.get(conn_id).map(|conn| conn.security)) else {
+            return 0;
+        };
+
+        let mut inner = self.lock();
+        let inner = &mut *inner;
+        let Some(queue) = inner.queues.get_mut(&conn_id) else {
+            return 0;
+        };
+
+        let before = queue.held.len();
+        let security = &inner.security;
+        let (release, keep) = core::mem::take(&mut queue.held)
+            .into_iter()
+            .partition::<VecDeque<_>, _>(|outgoing| {
+                security
+                    .get(&outgoing.handle)
+                    .map_or(true, |required| level >= required.min)
+            });
+        queue.held = keep;
+        queue.ready.extend(release);
+        before - queue.held.len()
+    }
+
+    /// Queues a notification without blocking.
+    pub fn try_notify(
+        &self,
+        link: &LinkState,
+        conn_id: u16,
+        handle: u16,
+        value: &[u8],
+    ) -> Result<(), SendError> {
+        let (delivery, level) = link.read(|conns, subs| {
+            let conn = conns.get(conn_id).ok_or(SendError::Disconnected)?;
+            let delivery = subs
+                .delivery(conn_id, handle, SendMode::Notify)
+                .ok_or(SendError::NotSubscribed)?;
+            Ok((delivery, conn.security))
+        })?;
+
+        let mut inner = self.lock();
+        let inner = &mut *inner;
+        let hold = match inner.security.get(&handle) {
+            Some(required) if level < required.min => match required.on_insecure {
+                OnInsecure::Refuse => {
+                    return Err(SendError::InsufficientSecurity {
+                        required: required.min,
+                        actual: level,
+                    })
+                }
+                OnInsecure::Hold => true,
+            },
+            _ => false,
+        };
+
+        let queue = inner
+            .queues
+            .get_mut(&conn_id)
+            .ok_or(SendError::Disconnected)?;
+        if queue.len() >= self.capacity {
+            return Err(SendError::QueueFull {
+                depth: queue.len(),
+                capacity: self.capacity,
+            });
+        }
+
+        let outgoing = Outgoing {
+            handle,
+            value: value.into(),
+            delivery,
+        };
+        if hold {
+            queue.held.push_back(outgoing);
+        } else {
+            queue.ready.push_back(outgoing);
+        }
+        Ok(())
+    }
+
+    /// Waits up to `timeout` until the queue of `conn_id` has room.
+    pub fn wait_for_space(&self, conn_id: u16, timeout: Duration) -> Result<(), SendError> {
+        self.wait_while(conn_id, timeout, |queue| queue.len() >= self.capacity)
+    }
+
+    /// Waits up to `timeout` until everything sendable queued for `conn_id`
+    /// was taken by the drain; held values do not count.
+    pub fn wait_for_drain(&self, conn_id: u16, timeout: Duration) -> Result<(), SendError> {
+        self.wait_while(conn_id, timeout, |queue| !queue.ready.is_empty())
+    }
+
+    /// Queues a notification, waiting up to `timeout` for room.
+    pub fn notify(
+        &self,
+        link: &LinkState,
+        conn_id: u16,
+        handle: u16,
+        value: &[u8],
+        timeout: Duration,
+    ) -> Result<(), SendError> {
+        let deadline = Instant::now() + timeout;
+
+        loop {
+            match self.try_notify(link, conn_id, handle, value) {
+                Err(SendError::QueueFull { .. }) => {
+                    let left = deadline.saturating_duration_since(Instant::now());
+                    self.wait_for_space(conn_id, left)?;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Takes the next value to send to `conn_id` and wakes waiters.
+    pub fn pop(&self, conn_id: u16) -> Option<Outgoing> {
+        let outgoing = self.lock().queues.get_mut(&conn_id)?.ready.pop_front();
+        if outgoing.is_some() {
+            self.drained.notify_all();
+        }
+        outgoing
+    }
+
+    fn wait_while(
+        &self,
+        conn_id: u16,
+        timeout: Duration,
+        busy: impl Fn(&Queue) -> bool,
+    ) -> Result<(), SendError> {
+        let (inner, _) = self
+            .drained
+            .wait_timeout_while(self.lock(), timeout, |inner| {
+                inner.queues.get(&conn_id).is_some_and(&busy)
+            })
+            .unwrap_or_else(PoisonError::into_inner);
+
+        match inner.queues.get(&conn_id) {
+            None => Err(SendError::Disconnected),
+            Some(queue) if busy(queue) => Err(SendError::Timeout),
+            Some(_) => Ok(()),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Inner> {
+        self.inner.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::super::conn::AddrType;
+    use super::*;
+    use crate::proto::CccdFlags;
+
+    const LEVEL: u16 = 42;
+
+    fn connected(conn_ids: &[u16]) -> (LinkState, Outbox) {
+        let link = LinkState::new();
+        let outbox = Outbox::new(2);
+        for &conn_id in conn_ids {
+            link.update(|conns, subs| {
+                conns.on_connect(
+                    conn_id,
+                    [conn_id as u8; 6],
+                    AddrType::Public,
+                    Instant::now(),
+                );
+                subs.on_cccd_write(conn_id, LEVEL, CccdFlags::NOTIFY)
+                    .unwrap();
+            });
+            outbox.on_connect(conn_id);
+        }
+        (link, outbox)
+    }
+
+    #[test]
+    fn full_queue_reports_depth_and_capacity() {
+        let (link, outbox) = connected(&[1]);
+
+        outbox.try_notify(&link, 1, LEVEL, b"a").unwrap();
+        outbox.try_notify(&link, 1, LEVEL, b"b").unwrap();
+        assert_eq!(
+            outbox.try_notify(&link, 1, LEVEL, b"c"),
+            Err(SendError::QueueFull {
+                depth: 2,
+                capacity: 2
+            })
+        );
+        assert_eq!(outbox.queue_len(1), Some(2));
+
+        assert_eq!(outbox.pop(1).unwrap().value, b"a");
+        assert_eq!(outbox.try_notify(&link, 1, LEVEL, b"c"), Ok(()));
+    }
+
+    #[test]
+    fn unknown_connection_or_subscription_is_refused() {
+        let (link, outbox) = connected(&[1]);
+
+        assert_eq!(
+            outbox.try_notify(&link, 2, LEVEL, b"a"),
+            Err(SendError::Disconnected)
+        );
+        assert_eq!(
+            outbox.try_notify(&link, 1, LEVEL + 1, b"a"),
+            Err(SendError::NotSubscribed)
+        );
+    }
+
+    #[test]
+    fn disconnect_wakes_a_blocked_waiter() {
+        let (link, outbox) = connected(&[1]);
+        outbox.try_notify(&link, 1, LEVEL, b"a").unwrap();
+        outbox.try_notify(&link, 1, LEVEL, b"b").unwrap();
+
+        thread::scope(|scope| {
+            let waiter = scope.spawn(|| outbox.wait_for_space(1, Duration::from_secs(10)));
+            thread::sleep(Duration::from_millis(20));
+            outbox.on_disconnect(1);
+
+            assert_eq!(waiter.join().unwrap(), Err(SendError::Disconnected));
+        });
+        assert_eq!(outbox.queue_len(1), None);
+    }
+
+    #[test]
+    fn drain_wakes_a_blocked_waiter() {
+        let (link, outbox) = connected(&[1]);
+        outbox.try_notify(&link, 1, LEVEL, b"a").unwrap();
+        outbox.try_notify(&link, 1, LEVEL, b"b").unwrap();
+
+        thread::scope(|scope| {
+            let sender =
+                scope.spawn(|| outbox.notify(&link, 1, LEVEL, b"c", Duration::from_secs(10)));
+            thread::sleep(Duration::from_millis(20));
+            outbox.pop(1);
+
+            assert_eq!(sender.join().unwrap(), Ok(()));
+        });
+        assert_eq!(outbox.queue_len(1), Some(2));
+    }
+
+    #[test]
+    fn notify_times_out_when_nothing_drains() {
+        let (link, outbox) = connected(&[1]);
+        outbox.try_notify(&link, 1, LEVEL, b"a").unwrap();
+        outbox.try_notify(&link, 1, LEVEL, b"b").unwrap();
+
+        assert_eq!(
+            outbox.notify(&link, 1, LEVEL, b"c", Duration::from_millis(20)),
+            Err(SendError::Timeout)
+        );
+        assert_eq!(
+            outbox.wait_for_drain(1, Duration::from_millis(1)),
+            Err(SendError::Timeout)
+        );
+    }
+
+    fn secure(outbox: &Outbox, on_insecure: OnInsecure) {
+        outbox.set_send_security(
+            LEVEL,
+            SendSecurity {
+                min: SecurityLevel::Encrypted,
+                on_insecure,
+            },
+        );
+    }
+
+    fn encrypt(link: &LinkState, conn_id: u16) {
+        link.update(|conns, _| conns.on_security(conn_id, SecurityLevel::Encrypted));
+    }
+
+    #[test]
+    fn send_after_encryption_goes_out_directly() {
+        let (link, outbox) = connected(&[1]);
+        secure(&outbox, OnInsecure::Hold);
+
+        encrypt(&link, 1);
+        assert_eq!(outbox.on_security(&link, 1), 0);
+        outbox.try_notify(&link, 1, LEVEL, b"a").unwrap();
+
+        assert_eq!(outbox.pop(1).unwrap().value, b"a");
+    }
+
+    #[test]
+    fn send_before_encryption_is_held_until_it() {
+        let (link, outbox) = connected(&[1]);
+        secure(&outbox, OnInsecure::Hold);
+
+        outbox.try_notify(&link, 1, LEVEL, b"a").unwrap();
+        assert_eq!(outbox.queue_len(1), Some(1));
+        assert_eq!(outbox.pop(1), None);
+        // Held values are not waiting for the drain.
+        assert_eq!(outbox.wait_for_drain(1, Duration::ZERO), Ok(()));
+
+        // Security events for the link before the upgrade release nothing.
+        assert_eq!(outbox.on_security(&link, 1), 0);
+        encrypt(&link, 1);
+        assert_eq!(outbox.on_security(&link, 1), 1);
+
+        let outgoing = outbox.pop(1).unwrap();
+        assert_eq!(outgoing.value, b"a");
+        assert_eq!(outgoing.delivery, Delivery::Notify);
+        assert_eq!(outbox.queue_len(1), Some(0));
+    }
+
+    #[test]
+    fn insecure_send_is_refused() {
+        let (link, outbox) = connected(&[1]);
+        secure(&outbox, OnInsecure::Refuse);
+
+        assert_eq!(
+            outbox.try_notify(&link, 1, LEVEL, b"a"),
+            Err(SendError::InsufficientSecurity {
+                required: SecurityLevel::Encrypted,
+                actual: SecurityLevel::None,
+            })
+        );
+        assert_eq!(outbox.queue_len(1), Some(0));
+
+        encrypt(&link, 1);
+        assert_eq!(outbox.try_notify(&link, 1, LEVEL, b"a"), Ok(()));
+    }
+
+    #[test]
+    fn held_values_count_against_capacity() {
+        let (link, outbox) = connected(&[1]);
+        secure(&outbox, OnInsecure::Hold);
+
+        outbox.try_notify(&link, 1, LEVEL, b"a").unwrap();
+        outbox.try_notify(&link, 1, LEVEL, b"b").unwrap();
+        assert_eq!(
+            outbox.try_notify(&link, 1, LEVEL, b"c"),
+            Err(SendError::QueueFull {
+                depth: 2,
+                capacity: 2
+            })
+        );
+        assert_eq!(
+            outbox.wait_for_space(1, Duration::from_millis(1)),
+            Err(SendError::Timeout)
+        );
+
+        encrypt(&link, 1);
+        assert_eq!(outbox.on_security(&link, 1), 2);
+        assert_eq!(outbox.pop(1).unwrap().value, b"a");
+        assert_eq!(outbox.pop(1).unwrap().value, b"b");
+    }
+}
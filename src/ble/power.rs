@@ -0,0 +1,128 @@
+//! Battery-aware degradation of BLE activity.
+//!
+//! A [`PowerGovernor`] is fed the battery level (the same provider the
+//! battery service reads) from a periodic timer. When the level drops below
+//! the policy threshold it switches to the degraded settings, and back once
+//! the level has recovered past the hysteresis threshold. Each switch yields
+//! the individual actions to apply, so the caller can emit one event per step.
+
+use core::time::Duration;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct PowerSettings {
+    pub adv_interval_ms: u16,
+    /// Preferred connection interval range, in milliseconds.
+    pub conn_interval_ms: (u16, u16),
+    /// Minimum spacing between notifications of periodic producers.
+    pub notify_min_interval: Duration,
+    pub accept_connections: bool,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct PowerPolicy {
+    /// Battery percentage below which the degraded settings apply.
+    pub degrade_below: u8,
+    /// Battery percentage at or above which normal settings are restored.
+    pub recover_at: u8,
+    pub normal: PowerSettings,
+    pub degraded: PowerSettings,
+}
+
+impl Default for PowerPolicy {
+    fn default() -> Self {
+        Self {
+            degrade_below: 20,
+            recover_at: 30,
+            normal: PowerSettings {
+                adv_interval_ms: 100,
+                conn_interval_ms: (30, 50),
+                notify_min_interval: Duration::ZERO,
+                accept_connections: true,
+            },
+            degraded: PowerSettings {
+                adv_interval_ms: 1000,
+                conn_interval_ms: (200, 400),
+                notify_min_interval: Duration::from_secs(1),
+                accept_connections: true,
+            },
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PowerState {
+    Normal,
+    Degraded,
+}
+
+/// One step of a switch between normal and degraded settings.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PowerAction {
+    SetAdvInterval(u16),
+    RequestConnInterval { min_ms: u16, max_ms: u16 },
+    SetNotifyMinInterval(Duration),
+    AcceptConnections(bool),
+}
+
+#[derive(Debug)]
+pub struct PowerGovernor {
+    policy: PowerPolicy,
+    state: PowerState,
+}
+
+impl PowerGovernor {
+    pub fn new(policy: PowerPolicy) -> Self {
+        Self {
+            policy,
+            state: PowerState::Normal,
+        }
+    }
+
+    pub fn state(&self) -> PowerState {
+        self.state
+    }
+
+    pub fn settings(&self) -> &PowerSettings {
+        match self.state {
+            PowerState::Normal => &self.policy.normal,
+            PowerState::Degraded => &self.policy.degraded,
+        }
+    }
+
+    /// Evaluates a new battery reading. Returns the actions to apply, empty
+    /// when the state does not change.
+    pub fn evaluate(&mut self, battery_level: u8) -> Vec<PowerAction> {
+        let next = match self.state {
+            PowerState::Normal if battery_level < self.policy.degrade_below => PowerState::Degraded,
+            PowerState::Degraded if battery_level >= self.policy.recover_at => PowerState::Normal,
+            state => state,
+        };
+
+        if next == self.state {
+            return Vec::new();
+        }
+
+        let from = *self.settings();
+        self.state = next;
+        let to = *self.settings();
+
+        let mut actions = Vec::new();
+        if from.adv_interval_ms != to.adv_interval_ms {
+            actions.push(PowerAction::SetAdvInterval(to.adv_interval_ms));
+        }
+        if from.conn_interval_ms != to.conn_interval_ms {
+            actions.push(PowerAction::RequestConnInterval {
+                min_ms: to.conn_interval_ms.0,
+                max_ms: to.conn_interval_ms.1,
+            });
+        }
+        if from.notify_min_interval != to.notify_min_interval {
+            actions.push(PowerAction::SetNotifyMinInterval(to.notify_min_interval));
+        }
+        if from.accept_connections != to.accept_connections {
+            actions.push(PowerAction::AcceptConnections(to.accept_connections));
+        }
+
+        actions
+    }
+}
@@ -0,0 +1,223 @@
+//! Requests arriving before the MTU exchange.
+//!
+//! Some centrals read right after connecting, before exchanging the MTU, and
+//! a long value is then cut to 22 bytes. Per characteristic a
+//! [`PreMtuPolicy`] chooses what happens in that window: serve it truncated,
+//! hold the response until the MTU arrives (or a timeout passes), or reject
+//! it so the client retries.
+
+use core::time::Duration;
+use std::collections::HashMap;
+use std::time::Instant;
+
+use super::conn::ConnectionRegistry;
+
+/// ATT application error asking the client to retry after the MTU exchange.
+pub const ATT_ERR_MTU_PENDING: u8 = 0x83;
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum PreMtuPolicy {
+    #[default]
+    ServeTruncated,
+    Defer {
+        timeout: Duration,
+    },
+    Reject {
+        code: u8,
+    },
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct PendingRequest {
+    pub conn_id: u16,
+    pub trans_id: u32,
+    pub handle: u16,
+    pub offset: u16,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PreMtuAction {
+    /// Respond now with the current MTU.
+    Serve,
+    /// Held; returned later by [`PreMtuGate::on_mtu`] or
+    /// [`PreMtuGate::expire`].
+    Deferred,
+    Reject(u8),
+}
+
+#[derive(Debug, Default)]
+pub struct PreMtuGate {
+    policies: HashMap<u16, PreMtuPolicy>,
+    pending: Vec<(Instant, PendingRequest)>,
+}
+
+impl PreMtuGate {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set_policy(&mut self, handle: u16, policy: PreMtuPolicy) {
+        self.policies.insert(handle, policy);
+    }
+
+    pub fn on_request(
+        &mut self,
+        conns: &ConnectionRegistry,
+        request: PendingRequest,
+        now: Instant,
+    ) -> PreMtuAction {
+        let exchanged = conns
+            .get(request.conn_id)
+            .map_or(true, |conn| conn.mtu_exchanged());
+        if exchanged {
+            return PreMtuAction::Serve;
+        }
+
+        match self
+            .policies
+            .get(&request.handle)
+            .copied()
+            .unwrap_or_default()
+        {
+            PreMtuPolicy::ServeTruncated => PreMtuAction::Serve,
+            PreMtuPolicy::Defer { timeout } => {
+                self.pending.push((now + timeout, request));
+                PreMtuAction::Deferred
+            }
+            PreMtuPolicy::Reject { code } => PreMtuAction::Reject(code),
+        }
+    }
+
+    /// Requests of `conn_id` to serve now that its MTU is known.
+    pub fn on_mtu(&mut self, conn_id: u16) -> Vec<PendingRequest> {
+        self.take(|request, _| request.conn_id == conn_id)
+    }
+
+    /// Requests whose timeout passed; serve them with the default MTU.
+    pub fn expire(&mut self, now: Instant) -> Vec<PendingRequest> {
+        self.take(|_, deadline| now >= deadline)
+    }
+
+    pub fn next_deadline(&self) -> Option<Instant> {
+        self.pending.iter().map(|(deadline, _)| *deadline).min()
+    }
+
+    pub fn on_disconnect(&mut self, conn_id: u16) {
+        self.pending
+            .retain(|(_, request)| request.conn_id != conn_id);
+    }
+
+    fn take(
+        &mut self,
+        mut due: impl FnMut(&PendingRequest, Instant) -> bool,
+    ) -> Vec<PendingRequest> {
+        let mut taken = Vec::new();
+
+        self.pending.retain(|(deadline, request)| {
+            let take = due(request, *deadline);
+            if take {
+                taken.push(*request);
+            }
+            !take
+        });
+
+        taken
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::conn::AddrType;
+    use super::*;
+
+    const LONG: u16 = 42;
+    const TIMEOUT: Duration = Duration::from_secs(2);
+
+    fn read(conn_id: u16, trans_id: u32) -> PendingRequest {
+        PendingRequest {
+            conn_id,
+            trans_id,
+            handle: LONG,
+            offset: 0,
+        }
+    }
+
+    fn setup(policy: PreMtuPolicy) -> (PreMtuGate, ConnectionRegistry, Instant) {
+        let now = Instant::now();
+        let mut conns = ConnectionRegistry::new();
+        conns.on_connect(1, [1; 6], AddrType::Public, now);
+        conns.on_connect(2, [2; 6], AddrType::Public, now);
+
+        let mut gate = PreMtuGate::new();
+        gate.set_policy(LONG, policy);
+        (gate, conns, now)
+    }
+
+    #[test]
+    fn read_before_mtu_is_deferred_until_the_exchange() {
+        let (mut gate, mut conns, now) = setup(PreMtuPolicy::Defer { timeout: TIMEOUT });
+
+        assert_eq!(
+            gate.on_request(&conns, read(1, 7), now),
+            PreMtuAction::Deferred
+        );
+        assert_eq!(gate.next_deadline(), Some(now + TIMEOUT));
+        assert!(gate.on_mtu(2).is_empty());
+
+        conns.on_mtu(1, 247);
+        assert_eq!(gate.on_mtu(1), [read(1, 7)]);
+        assert_eq!(gate.next_deadline(), None);
+        assert!(gate.expire(now + TIMEOUT).is_empty());
+    }
+
+    #[test]
+    fn mtu_before_read_is_served() {
+        let (mut gate, mut conns, now) = setup(PreMtuPolicy::Defer { timeout: TIMEOUT });
+
+        conns.on_mtu(1, 247);
+        assert_eq!(
+            gate.on_request(&conns, read(1, 7), now),
+            PreMtuAction::Serve
+        );
+        assert_eq!(gate.next_deadline(), None);
+        assert!(gate.on_mtu(1).is_empty());
+    }
+
+    #[test]
+    fn deferred_read_is_served_after_the_timeout() {
+        let (mut gate, conns, now) = setup(PreMtuPolicy::Defer { timeout: TIMEOUT });
+        gate.on_request(&conns, read(1, 7), now);
+        gate.on_request(&conns, read(2, 8), now + Duration::from_secs(1));
+
+        assert!(gate
+            .expire(now + TIMEOUT - Duration::from_millis(1))
+            .is_empty());
+        assert_eq!(gate.expire(now + TIMEOUT), [read(1, 7)]);
+        assert_eq!(
+            gate.next_deadline(),
+            Some(now + Duration::from_secs(1) + TIMEOUT)
+        );
+
+        gate.on_disconnect(2);
+        assert_eq!(gate.next_deadline(), None);
+    }
+
+    #[test]
+    fn other_policies_before_the_exchange() {
+        let (mut gate, conns, now) = setup(PreMtuPolicy::Reject {
+            code: ATT_ERR_MTU_PENDING,
+        });
+        assert_eq!(
+            gate.on_request(&conns, read(1, 7), now),
+            PreMtuAction::Reject(0x83)
+        );
+
+        // Handles without a policy are served truncated.
+        let other = PendingRequest {
+            handle: LONG + 1,
+            ..read(1, 8)
+        };
+        assert_eq!(gate.on_request(&conns, other, now), PreMtuAction::Serve);
+        assert_eq!(gate.next_deadline(), None);
+    }
+}
@@ -0,0 +1,382 @@
+//! Startup checks of the linked sdkconfig.
+//!
+//! A missing `CONFIG_BT_ENABLED` or Bluedroid option usually shows up as a
+//! crash or reboot loop somewhere inside driver init. [`preflight`] inspects
+//! the configuration the firmware was built with before the driver is
+//! created and reports each problem with the option that fixes it. Only
+//! [`Severity::Fatal`] issues fail the check.
+
+use core::fmt;
+
+use super::capabilities::{capabilities, Capabilities, Capability};
+
+/// BTC task stack below which Rust GATT callbacks tend to overflow, in bytes.
+pub const RECOMMENDED_BTC_TASK_STACK: u32 = 8000;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Warning,
+    Fatal,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ControllerMode {
+    BleOnly,
+    /// BLE and BR/EDR (ESP32 only).
+    Dual,
+    BrEdrOnly,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PreflightIssue {
+    BtDisabled,
+    /// NimBLE or no host stack is selected instead of Bluedroid.
+    BluedroidDisabled,
+    BrEdrOnlyController,
+    /// Dual-mode controller; works, but reserves BR/EDR memory.
+    ControllerNotBleOnly,
+    BtcTaskStackTooSmall {
+        configured: u32,
+    },
+    DynamicEnvMemoryOff,
+    /// The controller was already initialised by someone else.
+    ControllerNotIdle,
+    /// The configuration needs a capability this build lacks.
+    Unsupported(Capability),
+}
+
+impl PreflightIssue {
+    pub fn severity(&self) -> Severity {
+        match self {
+            Self::BtDisabled
+            | Self::BluedroidDisabled
+            | Self::BrEdrOnlyController
+            | Self::ControllerNotIdle
+            | Self::Unsupported(_) => Severity::Fatal,
+            Self::ControllerNotBleOnly
+            | Self::BtcTaskStackTooSmall { .. }
+            | Self::DynamicEnvMemoryOff => Severity::Warning,
+        }
+    }
+
+    /// The sdkconfig setting that resolves the issue.
+    pub fn fix(&self) -> &'static str {
+        match self {
+            Self::BtDisabled => "CONFIG_BT_ENABLED=y",
+            Self::BluedroidDisabled => "CONFIG_BT_BLUEDROID_ENABLED=y",
+            Self::BrEdrOnlyController | Self::ControllerNotBleOnly => {
+                "CONFIG_BTDM_CTRL_MODE_BLE_ONLY=y"
+            }
+            Self::BtcTaskStackTooSmall { .. } => "CONFIG_BT_BTC_TASK_STACK_SIZE>=8000",
+            Self::DynamicEnvMemoryOff => "CONFIG_BT_BLE_DYNAMIC_ENV_MEMORY=y",
+            Self::ControllerNotIdle => "do not initialise the BT controller before the server",
+            Self::Unsupported(capability) => capability.fix(),
+        }
+    }
+}
+
+impl fmt::Display for PreflightIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let severity = match self.severity() {
+            Severity::Warning => "warning",
+            Severity::Fatal => "fatal",
+        };
+
+        write!(f, "{severity}: ")?;
+        match self {
+            Self::BtDisabled => write!(f, "Bluetooth is disabled")?,
+            Self::BluedroidDisabled => write!(f, "Bluedroid host stack is not enabled")?,
+            Self::BrEdrOnlyController => write!(f, "controller is in BR/EDR-only mode")?,
+            Self::ControllerNotBleOnly => write!(f, "controller is not in BLE-only mode")?,
+            Self::BtcTaskStackTooSmall { configured } => write!(
+                f,
+                "BTC task stack is {configured} bytes, {RECOMMENDED_BTC_TASK_STACK} recommended"
+            )?,
+            Self::DynamicEnvMemoryOff => write!(f, "BLE dynamic env memory is off")?,
+            Self::ControllerNotIdle => write!(f, "BT controller is already initialised")?,
+            Self::Unsupported(capability) => {
+                write!(f, "{capability} is required but not compiled in")?
+            }
+        }
+        write!(f, " (set {})", self.fix())
+    }
+}
+
+/// A check that found at least one fatal issue. Holds every issue found.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PreflightError(pub Vec<PreflightIssue>);
+
+impl fmt::Display for PreflightError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "sdkconfig preflight failed")?;
+        for issue in &self.0 {
+            write!(f, "\n  {issue}")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for PreflightError {}
+
+/// The BT-related configuration the firmware was built with.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct LinkedConfig {
+    pub bt_enabled: bool,
+    pub bluedroid_enabled: bool,
+    pub controller_mode: ControllerMode,
+    pub btc_task_stack: Option<u32>,
+    pub dynamic_env_memory: bool,
+    pub controller_idle: bool,
+}
+
+impl LinkedConfig {
+    /// Reads the configuration from the `esp_idf_*` cfgs and the controller.
+    #[cfg(feature = "esp")]
+    pub fn probe() -> Self {
+        let controller_mode = if cfg!(esp_idf_btdm_ctrl_mode_br_edr_only) {
+            ControllerMode::BrEdrOnly
+        } else if cfg!(esp_idf_btdm_ctrl_mode_btdm) {
+            ControllerMode::Dual
+        } else {
+            ControllerMode::BleOnly
+        };
+
+        #[cfg(esp_idf_bt_bluedroid_enabled)]
+        let btc_task_stack = Some(esp_idf_svc::sys::CONFIG_BT_BTC_TASK_STACK_SIZE);
+        #[cfg(not(esp_idf_bt_bluedroid_enabled))]
+        let btc_task_stack = None;
+
+        #[cfg(esp_idf_bt_enabled)]
+        let controller_idle = unsafe { esp_idf_svc::sys::esp_bt_controller_get_status() }
+            == esp_idf_svc::sys::esp_bt_controller_status_t_ESP_BT_CONTROLLER_STATUS_IDLE;
+        #[cfg(not(esp_idf_bt_enabled))]
+        let controller_idle = true;
+
+        Self {
+            bt_enabled: cfg!(esp_idf_bt_enabled),
+            bluedroid_enabled: cfg!(esp_idf_bt_bluedroid_enabled),
+            controller_mode,
+            btc_task_stack,
+            dynamic_env_memory: cfg!(esp_idf_bt_ble_dynamic_env_memory),
+            controller_idle,
+        }
+    }
+
+    /// Every issue with this configuration, fatal ones first.
+    pub fn issues(&self) -> Vec<PreflightIssue> {
+        if !self.bt_enabled {
+            return vec![PreflightIssue::BtDisabled];
+        }
+
+        let mut issues = Vec::new();
+        if !self.bluedroid_enabled {
+            issues.push(PreflightIssue::BluedroidDisabled);
+        }
+        match self.controller_mode {
+            ControllerMode::BleOnly => {}
+            ControllerMode::Dual => issues.push(PreflightIssue::ControllerNotBleOnly),
+            ControllerMode::BrEdrOnly => issues.push(PreflightIssue::BrEdrOnlyController),
+        }
+        if !self.controller_idle {
+            issues.push(PreflightIssue::ControllerNotIdle);
+        }
+        if let Some(configured) = self.btc_task_stack {
+            if configured < RECOMMENDED_BTC_TASK_STACK {
+                issues.push(PreflightIssue::BtcTaskStackTooSmall { configured });
+            }
+        }
+        if self.bluedroid_enabled && !self.dynamic_env_memory {
+            issues.push(PreflightIssue::DynamicEnvMemoryOff);
+        }
+
+        issues.sort_by_key(|issue| core::cmp::Reverse(issue.severity()));
+        issues
+    }
+
+    /// Returns the warnings, or every issue if any of them is fatal.
+    pub fn check(&self) -> Result<Vec<PreflightIssue>, PreflightError> {
+        self.check_for(&[], &capabilities())
+    }
+
+    /// [`check`](Self::check), plus one fatal issue per capability in
+    /// `required` that `capabilities` lacks.
+    pub fn check_for(
+        &self,
+        required: &[Capability],
+        capabilities: &Capabilities,
+    ) -> Result<Vec<PreflightIssue>, PreflightError> {
+        let mut issues = self.issues();
+        issues.extend(check_capabilities(required, capabilities));
+        issues.sort_by_key(|issue| core::cmp::Reverse(issue.severity()));
+
+        if issues
+            .iter()
+            .any(|issue| issue.severity() == Severity::Fatal)
+        {
+            Err(PreflightError(issues))
+        } else {
+            Ok(issues)
+        }
+    }
+}
+
+/// Checks the linked configuration and that this build has every capability
+/// in `required`, usually [`ServerSpec::required_capabilities`]; call before
+/// creating the BT driver.
+///
+/// [`ServerSpec::required_capabilities`]: super::spec::ServerSpec::required_capabilities
+#[cfg(feature = "esp")]
+pub fn preflight(required: &[Capability]) -> Result<Vec<PreflightIssue>, PreflightError> {
+    LinkedConfig::probe().check_for(required, &capabilities())
+}
+
+/// One fatal issue per capability in `required` that `capabilities` lacks,
+/// e.g. for [`ServerSpec::required_capabilities`](super::spec::ServerSpec::required_capabilities).
+pub fn check_capabilities(
+    required: &[Capability],
+    capabilities: &Capabilities,
+) -> Vec<PreflightIssue> {
+    capabilities
+        .missing(required)
+        .into_iter()
+        .map(PreflightIssue::Unsupported)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::spec::{AdvertisingSpec, SecurityMode, ServerSpec};
+    use super::*;
+
+    fn healthy() -> LinkedConfig {
+        LinkedConfig {
+            bt_enabled: true,
+            bluedroid_enabled: true,
+            controller_mode: ControllerMode::BleOnly,
+            btc_task_stack: Some(RECOMMENDED_BTC_TASK_STACK),
+            dynamic_env_memory: true,
+            controller_idle: true,
+        }
+    }
+
+    /// Bit `i` of `mask` stands for `Capability::ALL[i]`.
+    fn subset(mask: u32) -> Vec<Capability> {
+        Capability::ALL
+            .into_iter()
+            .enumerate()
+            .filter_map(|(bit, capability)| (mask & 1 << bit != 0).then_some(capability))
+            .collect()
+    }
+
+    fn build(mask: u32) -> Capabilities {
+        let has = |bit: u32| mask & 1 << bit != 0;
+        Capabilities {
+            bluedroid: has(0),
+            extended_advertising: has(1),
+            security: has(2),
+            l2cap_coc: has(3),
+            serde_config: has(4),
+            rich_docs: has(5),
+        }
+    }
+
+    fn server(security: SecurityMode) -> ServerSpec {
+        ServerSpec {
+            device_name: "sensor".into(),
+            advertising: AdvertisingSpec::default(),
+            security,
+            services: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn every_missing_capability_is_fatal() {
+        let combinations = 1 << Capability::ALL.len();
+
+        for built in 0..combinations {
+            let capabilities = build(built);
+            assert_eq!(subset(built), {
+                let mut present = Capability::ALL.to_vec();
+                present.retain(|&capability| capabilities.has(capability));
+                present
+            });
+
+            for needed in 0..combinations {
+                let required = subset(needed);
+                let expected: Vec<_> = subset(needed & !built)
+                    .into_iter()
+                    .map(PreflightIssue::Unsupported)
+                    .collect();
+
+                assert_eq!(check_capabilities(&required, &capabilities), expected);
+                let result = healthy().check_for(&required, &capabilities);
+                if expected.is_empty() {
+                    assert_eq!(result, Ok(Vec::new()));
+                } else {
+                    assert_eq!(result, Err(PreflightError(expected)));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn spec_requirements_against_every_build() {
+        for security in [
+            SecurityMode::None,
+            SecurityMode::Bonded,
+            SecurityMode::Authenticated,
+        ] {
+            let required = server(security).required_capabilities();
+
+            for built in 0..1 << Capability::ALL.len() {
+                let capabilities = build(built);
+                let ok = capabilities.has(Capability::Bluedroid)
+                    && (security == SecurityMode::None || capabilities.has(Capability::Security));
+                assert_eq!(
+                    healthy().check_for(&required, &capabilities).is_ok(),
+                    ok,
+                    "{security:?} on {capabilities:?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn this_build_is_checked_against_its_own_capabilities() {
+        let spec = server(SecurityMode::Bonded);
+        let required = spec.required_capabilities();
+        let expected: Vec<_> = required
+            .iter()
+            .filter(|&&capability| !capabilities().has(capability))
+            .map(|&capability| PreflightIssue::Unsupported(capability))
+            .collect();
+
+        match healthy().check_for(&required, &capabilities()) {
+            Ok(warnings) => {
+                assert!(expected.is_empty());
+                assert!(warnings.is_empty());
+            }
+            Err(PreflightError(issues)) => assert_eq!(issues, expected),
+        }
+    }
+
+    #[test]
+    fn missing_capabilities_sort_before_warnings() {
+        let config = LinkedConfig {
+            controller_mode: ControllerMode::Dual,
+            ..healthy()
+        };
+
+        assert_eq!(
+            config.check_for(&[Capability::Security, Capability::Security], &build(0)),
+            Err(PreflightError(vec![
+                PreflightIssue::Unsupported(Capability::Security),
+                PreflightIssue::ControllerNotBleOnly,
+            ]))
+        );
+        assert_eq!(
+            config.check_for(&[], &build(0)),
+            Ok(vec![PreflightIssue::ControllerNotBleOnly])
+        );
+    }
+}
@@ -0,0 +1,237 @@
+//! Prepare/execute (long) writes with per-chunk validation.
+//!
+//! [`PreparedWrites`] holds one connection's queued writes in that
+//! connection's [`Session`], through [`on_prepare_write`] and
+//! [`on_execute_write`], so a disconnect discards them. A chunk at an offset
+//! inside the queued value overwrites those bytes in place. Each prepared
+//! chunk is shown to a validator with its offset, so a transfer can be
+//! refused at the first bad chunk instead of at execute time. A refused
+//! transfer is poisoned: later chunks for the same handle get the same error,
+//! and execute drops it without delivering anything.
+
+use std::collections::BTreeMap;
+
+use super::long_read::ATT_ERR_INVALID_OFFSET;
+use super::session::Session;
+
+pub const ATT_ERR_PREPARE_QUEUE_FULL: u8 = 0x09;
+
+#[derive(Debug, Default)]
+struct Transfer {
+    value: Vec<u8>,
+    poisoned: Option<u8>,
+}
+
+#[derive(Debug)]
+pub struct PreparedWrites {
+    transfers: BTreeMap<u16, Transfer>,
+    max_bytes: usize,
+}
+
+impl PreparedWrites {
+    /// `max_bytes` bounds the data queued over all handles.
+    pub fn new(max_bytes: usize) -> Self {
+        Self {
+            transfers: BTreeMap::new(),
+            max_bytes,
+        }
+    }
+
+    /// Queues a prepare-write chunk. `validate` sees the chunk and its offset
+    /// and may refuse it with an ATT status; the `Err` is the status to
+    /// answer the prepare-write with.
+    pub fn prepare(
+        &mut self,
+        handle: u16,
+        offset: u16,
+        chunk: &[u8],
+        validate: impl FnOnce(u16, &[u8]) -> Result<(), u8>,
+    ) -> Result<(), u8> {
+        let queued: usize = self.transfers.values().map(|t| t.value.len()).sum();
+        let current = self.transfers.get(&handle);
+
+        if let Some(code) = current.and_then(|transfer| transfer.poisoned) {
+            return Err(code);
+        }
+
+        // Checked before the transfer exists, so refused chunks for new
+        // handles do not leave entries behind.
+        let len = current.map_or(0, |transfer| transfer.value.len());
+        let start = usize::from(offset);
+        if start > len {
+            return Err(ATT_ERR_INVALID_OFFSET);
+        }
+        let end = start + chunk.len();
+        if queued - len + end.max(len) > self.max_bytes {
+            return Err(ATT_ERR_PREPARE_QUEUE_FULL);
+        }
+
+        let transfer = self.transfers.entry(handle).or_default();
+        if let Err(code) = validate(offset, chunk) {
+            transfer.poisoned = Some(code);
+            return Err(code);
+        }
+
+        // Overwrite what the chunk covers, keep any bytes beyond it.
+        let overlap = chunk.len().min(len - start);
+        transfer.value[start..start + overlap].copy_from_slice(&chunk[..overlap]);
+        transfer.value.extend_from_slice(&chunk[overlap..]);
+        Ok(())
+    }
+
+    pub fn is_poisoned(&self, handle: u16) -> bool {
+        self.transfers
+            .get(&handle)
+            .is_some_and(|transfer| transfer.poisoned.is_some())
+    }
+
+    /// Handles an execute-write request. On commit, returns the complete
+    /// values of all transfers that were not poisoned; on cancel, nothing.
+    /// Either way the queue is cleared.
+    pub fn execute(&mut self, commit: bool) -> Vec<(u16, Vec<u8>)> {
+        let transfers = core::mem::take(&mut self.transfers);
+        if !commit {
+            return Vec::new();
+        }
+
+        transfers
+            .into_iter()
+            .filter(|(_, transfer)| transfer.poisoned.is_none())
+            .map(|(handle, transfer)| (handle, transfer.value))
+            .collect()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.transfers.is_empty()
+    }
+}
+
+/// Handles a prepare-write request against the connection's queue, created
+/// with `max_bytes` on the first chunk; see [`PreparedWrites::prepare`].
+pub fn on_prepare_write(
+    session: &mut Session,
+    max_bytes: usize,
+    handle: u16,
+    offset: u16,
+    chunk: &[u8],
+    validate: impl FnOnce(u16, &[u8]) -> Result<(), u8>,
+) -> Result<(), u8> {
+    session
+        .get_or_insert_with(|| PreparedWrites::new(max_bytes))
+        .prepare(handle, offset, chunk, validate)
+}
+
+/// Handles an execute-write request and drops the connection's queue; see
+/// [`PreparedWrites::execute`].
+pub fn on_execute_write(session: &mut Session, commit: bool) -> Vec<(u16, Vec<u8>)> {
+    session
+        .remove::<PreparedWrites>()
+        .map_or_else(Vec::new, |mut writes| writes.execute(commit))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HANDLE: u16 = 0x2a;
+
+    fn accept(_: u16, _: &[u8]) -> Result<(), u8> {
+        Ok(())
+    }
+
+    /// Refuses any chunk at offset 0 that does not start with the magic.
+    fn check_magic(offset: u16, chunk: &[u8]) -> Result<(), u8> {
+        if offset == 0 && !chunk.starts_with(b"MG") {
+            return Err(0x80);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn chunks_assemble_in_order() {
+        let mut writes = PreparedWrites::new(64);
+        writes.prepare(HANDLE, 0, b"MG01", check_magic).unwrap();
+        writes.prepare(HANDLE, 4, b"2345", check_magic).unwrap();
+
+        assert_eq!(writes.execute(true), vec![(HANDLE, b"MG012345".to_vec())]);
+        assert!(writes.is_empty());
+    }
+
+    #[test]
+    fn overlapping_chunk_overwrites_in_place() {
+        let mut writes = PreparedWrites::new(64);
+        writes.prepare(HANDLE, 0, b"abcdefgh", accept).unwrap();
+        writes.prepare(HANDLE, 2, b"XY", accept).unwrap();
+        writes.prepare(HANDLE, 6, b"ZZZZ", accept).unwrap();
+
+        assert_eq!(writes.execute(true), vec![(HANDLE, b"abXYefZZZZ".to_vec())]);
+    }
+
+    #[test]
+    fn offset_past_the_end_is_refused() {
+        let mut writes = PreparedWrites::new(64);
+        assert_eq!(
+            writes.prepare(HANDLE, 1, b"x", accept),
+            Err(ATT_ERR_INVALID_OFFSET)
+        );
+        assert!(writes.is_empty());
+    }
+
+    #[test]
+    fn queue_limit_counts_only_growth() {
+        let mut writes = PreparedWrites::new(8);
+        writes.prepare(HANDLE, 0, b"abcdefgh", accept).unwrap();
+        writes.prepare(HANDLE, 0, b"ABCD", accept).unwrap();
+        assert_eq!(
+            writes.prepare(HANDLE, 8, b"i", accept),
+            Err(ATT_ERR_PREPARE_QUEUE_FULL)
+        );
+        assert_eq!(writes.execute(true), vec![(HANDLE, b"ABCDefgh".to_vec())]);
+    }
+
+    #[test]
+    fn poisoned_then_cancel() {
+        let mut session = Session::new();
+        assert_eq!(
+            on_prepare_write(&mut session, 64, HANDLE, 0, b"XX01", check_magic),
+            Err(0x80)
+        );
+        // Later chunks are refused with the same status, valid or not.
+        assert_eq!(
+            on_prepare_write(&mut session, 64, HANDLE, 4, b"2345", accept),
+            Err(0x80)
+        );
+        assert!(session.get::<PreparedWrites>().unwrap().is_poisoned(HANDLE));
+
+        assert!(on_execute_write(&mut session, false).is_empty());
+        assert!(!session.contains::<PreparedWrites>());
+
+        // The next transfer starts clean.
+        on_prepare_write(&mut session, 64, HANDLE, 0, b"MG01", check_magic).unwrap();
+        assert_eq!(
+            on_execute_write(&mut session, true),
+            vec![(HANDLE, b"MG01".to_vec())]
+        );
+    }
+
+    #[test]
+    fn poisoned_then_execute() {
+        let mut session = Session::new();
+        on_prepare_write(&mut session, 64, HANDLE + 1, 0, b"ok", accept).unwrap();
+        assert_eq!(
+            on_prepare_write(&mut session, 64, HANDLE, 0, b"XX01", check_magic),
+            Err(0x80)
+        );
+        assert_eq!(
+            on_prepare_write(&mut session, 64, HANDLE, 0, b"MG01", check_magic),
+            Err(0x80)
+        );
+
+        // Only the clean transfer is delivered.
+        assert_eq!(
+            on_execute_write(&mut session, true),
+            vec![(HANDLE + 1, b"ok".to_vec())]
+        );
+        assert!(!session.contains::<PreparedWrites>());
+    }
+}
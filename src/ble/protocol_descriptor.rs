@@ -0,0 +1,316 @@
+//! Self-description of the custom services for generic GATT browsers.
+//!
+//! The protocol descriptor service has one read-only characteristic with a
+//! CBOR description of the other services:
+//!
+//! ```text
+//! { "v": 1, "truncated": bool,
+//!   "services": { service uuid: { characteristic uuid:
+//!       { "dir": "rwn", "enc": encoding hint, "desc": user description } } } }
+//! ```
+//!
+//! and optionally a second one with the same as plain text. Both are
+//! generated from the [`ServiceSpec`]s the server declares; regenerate
+//! them whenever a service is added or removed. Values longer than the MTU
+//! are served with [`read_blob`](super::long_read::read_blob).
+//!
+//! Both are kept within a byte budget. When the description does not fit,
+//! descriptions are shortened, then dropped, then trailing services are
+//! left out, and `truncated` is set.
+
+use core::fmt::Write as _;
+
+use crate::proto::{cbor, truncate_utf8, Uuid};
+
+use super::spec::{CharPerm, CharProp, CharacteristicSpec, ServiceSpec};
+
+pub const DESCRIPTOR_SERVICE_UUID: Uuid = Uuid::Uuid128(0x5e1f_0100_7a3c_4b6e_9d2a_61c8_f04b_7e93);
+/// CBOR description.
+pub const DESCRIPTOR_UUID: Uuid = Uuid::Uuid128(0x5e1f_0101_7a3c_4b6e_9d2a_61c8_f04b_7e93);
+/// Plain-text summary.
+pub const SUMMARY_UUID: Uuid = Uuid::Uuid128(0x5e1f_0102_7a3c_4b6e_9d2a_61c8_f04b_7e93);
+
+/// Budget per value; a long read of 2 KB takes about 100 ms at the
+/// default MTU.
+pub const DEFAULT_BUDGET: usize = 2048;
+
+const FORMAT_VERSION: u64 = 1;
+/// Description lengths tried, in bytes, before services are dropped.
+const DESCRIPTION_LIMITS: [usize; 3] = [usize::MAX, 32, 0];
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ProtocolDescriptor {
+    pub cbor: Vec<u8>,
+    pub summary: String,
+    /// Something was shortened or left out to fit the budget.
+    pub truncated: bool,
+}
+
+impl ProtocolDescriptor {
+    pub fn generate(services: &[ServiceSpec], budget: usize) -> Self {
+        let (cbor, cbor_truncated) = fit_cbor(services, budget);
+
+        let summary = summary(services);
+        let fitted = truncate_utf8(&summary, budget);
+        let summary_truncated = fitted.len() < summary.len();
+
+        Self {
+            cbor,
+            summary: fitted.to_owned(),
+            truncated: cbor_truncated || summary_truncated,
+        }
+    }
+
+    /// The service serving the description, with the summary
+    /// characteristic if `with_summary`.
+    pub fn service_spec(&self, with_summary: bool) -> ServiceSpec {
+        let read_only = |uuid, len: usize| CharacteristicSpec {
+            uuid,
+            props: vec![CharProp::Read],
+            perms: vec![CharPerm::Read],
+            max_len: u16::try_from(len).unwrap_or(u16::MAX),
+            cccd: false,
+            user_description: None,
+            doc: "",
+            encoding: None,
+            handler: None,
+            max_subscribers: None,
+            send_security: None,
+        };
+
+        let mut characteristics = vec![read_only(DESCRIPTOR_UUID, self.cbor.len())];
+        if with_summary {
+            characteristics.push(read_only(SUMMARY_UUID, self.summary.len()));
+        }
+
+        ServiceSpec {
+            uuid: DESCRIPTOR_SERVICE_UUID,
+            num_handles: None,
+            characteristics,
+            doc: "",
+            protocol_version: None,
+        }
+    }
+}
+
+fn fit_cbor(services: &[ServiceSpec], budget: usize) -> (Vec<u8>, bool) {
+    for kept in (0..=services.len()).rev() {
+        for (attempt, &limit) in DESCRIPTION_LIMITS.iter().enumerate() {
+            let truncated = kept < services.len() || attempt > 0;
+            let cbor = encode(&services[..kept], limit, truncated);
+            if cbor.len() <= budget {
+                return (cbor, truncated);
+            }
+        }
+    }
+
+    // Not even the empty description fits.
+    (Vec::new(), true)
+}
+
+fn encode(services: &[ServiceSpec], description_limit: usize, truncated: bool) -> Vec<u8> {
+    let mut buf = Vec::new();
+    cbor::map(&mut buf, 3);
+    cbor::text(&mut buf, "v");
+    cbor::uint(&mut buf, FORMAT_VERSION);
+    cbor::text(&mut buf, "truncated");
+    cbor::bool(&mut buf, truncated);
+
+    cbor::text(&mut buf, "services");
+    cbor::map(&mut buf, services.len());
+    for service in services {
+        cbor::text(&mut buf, &service.uuid.to_string());
+        cbor::map(&mut buf, service.characteristics.len());
+
+        for characteristic in &service.characteristics {
+            let description = characteristic
+                .description()
+                .map(|description| truncate_utf8(description, description_limit))
+                .filter(|description| !description.is_empty());
+            let encoding = characteristic.encoding.as_deref();
+
+            cbor::text(&mut buf, &characteristic.uuid.to_string());
+            cbor::map(
+                &mut buf,
+                1 + usize::from(encoding.is_some()) + usize::from(description.is_some()),
+            );
+            cbor::text(&mut buf, "dir");
+            cbor::text(&mut buf, &direction(characteristic));
+            if let Some(encoding) = encoding {
+                cbor::text(&mut buf, "enc");
+                cbor::text(&mut buf, encoding);
+            }
+            if let Some(description) = description {
+                cbor::text(&mut buf, "desc");
+                cbor::text(&mut buf, description);
+            }
+        }
+    }
+
+    buf
+}
+
+/// `r` readable, `w` writable, `n` notified or indicated.
+fn direction(characteristic: &CharacteristicSpec) -> String {
+    let has = |props: &[CharProp]| props.iter().any(|p| characteristic.props.contains(p));
+
+    [
+        ('r', has(&[CharProp::Read])),
+        ('w', has(&[CharProp::Write, CharProp::WriteNoResponse])),
+        ('n', has(&[CharProp::Notify, CharProp::Indicate])),
+    ]
+    .into_iter()
+    .filter_map(|(c, set)| set.then_some(c))
+    .collect()
+}
+
+fn summary(services: &[ServiceSpec]) -> String {
+    let mut text = String::new();
+
+    for service in services {
+        let _ = write!(text, "service {}", service.uuid);
+        if !service.doc.is_empty() {
+            let _ = write!(text, ": {}", service.doc);
+        }
+        text.push('\n');
+        for characteristic in &service.characteristics {
+            let _ = write!(
+                text,
+                "  {} [{}]",
+                characteristic.uuid,
+                direction(characteristic)
+            );
+            if let Some(encoding) = &characteristic.encoding {
+                let _ = write!(text, " {encoding}");
+            }
+            if let Some(description) = characteristic.description() {
+                let _ = write!(text, ": {description}");
+            }
+            text.push('\n');
+        }
+    }
+
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `services` services of four characteristics, each described by
+    /// `description_len` bytes.
+    fn services(services: u16, description_len: usize) -> Vec<ServiceSpec> {
+        (0..services)
+            .map(|s| ServiceSpec {
+                uuid: Uuid::Uuid128(
+                    0x5e1f_1000_7a3c_4b6e_9d2a_61c8_f04b_7e93 + (u128::from(s) << 96),
+                ),
+                num_handles: None,
+                characteristics: (0..4)
+                    .map(|c| CharacteristicSpec {
+                        uuid: Uuid::Uuid128(
+                            0x5e1f_2000_7a3c_4b6e_9d2a_61c8_f04b_7e93
+                                + (u128::from(s * 4 + c) << 96),
+                        ),
+                        props: vec![CharProp::Read, CharProp::Notify],
+                        perms: vec![CharPerm::Read],
+                        max_len: 20,
+                        cccd: true,
+                        user_description: Some("d".repeat(description_len)),
+                        doc: "",
+                        encoding: None,
+                        handler: None,
+                        max_subscribers: None,
+                        send_security: None,
+                    })
+                    .collect(),
+                doc: "",
+                protocol_version: None,
+            })
+            .collect()
+    }
+
+    fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+        haystack
+            .windows(needle.len())
+            .any(|window| window == needle)
+    }
+
+    fn truncated_flag(cbor: &[u8]) -> bool {
+        let mut key = Vec::new();
+        cbor::text(&mut key, "truncated");
+        let at = cbor
+            .windows(key.len())
+            .position(|window| window == key)
+            .unwrap();
+        cbor[at + key.len()] == 0xF5
+    }
+
+    #[test]
+    fn small_description_is_complete() {
+        let services = services(1, 40);
+        let descriptor = ProtocolDescriptor::generate(&services, DEFAULT_BUDGET);
+
+        assert!(!descriptor.truncated);
+        assert!(!truncated_flag(&descriptor.cbor));
+        assert!(contains(&descriptor.cbor, "d".repeat(40).as_bytes()));
+        assert_eq!(descriptor.summary, summary(&services));
+    }
+
+    #[test]
+    fn descriptions_are_shortened_first_around_2_kb() {
+        let services = services(4, 80);
+        assert!(encode(&services, usize::MAX, false).len() > DEFAULT_BUDGET);
+
+        let descriptor = ProtocolDescriptor::generate(&services, DEFAULT_BUDGET);
+        assert!(descriptor.cbor.len() <= DEFAULT_BUDGET);
+        assert!(descriptor.truncated);
+        assert!(truncated_flag(&descriptor.cbor));
+        // Every service is still there, with 32-byte descriptions.
+        for service in &services {
+            assert!(contains(
+                &descriptor.cbor,
+                service.uuid.to_string().as_bytes()
+            ));
+        }
+        assert!(contains(&descriptor.cbor, "d".repeat(32).as_bytes()));
+        assert!(!contains(&descriptor.cbor, "d".repeat(33).as_bytes()));
+    }
+
+    #[test]
+    fn trailing_services_are_dropped_when_shortening_is_not_enough() {
+        let services = services(12, 80);
+        let descriptor = ProtocolDescriptor::generate(&services, DEFAULT_BUDGET);
+
+        assert!(descriptor.cbor.len() <= DEFAULT_BUDGET);
+        assert!(descriptor.truncated);
+        let first = services[0].uuid.to_string();
+        let last = services[11].uuid.to_string();
+        assert!(contains(&descriptor.cbor, first.as_bytes()));
+        assert!(!contains(&descriptor.cbor, last.as_bytes()));
+
+        assert!(descriptor.summary.len() <= DEFAULT_BUDGET);
+        assert!(summary(&services).starts_with(&descriptor.summary));
+    }
+
+    #[test]
+    fn service_spec_sizes_follow_the_values() {
+        let descriptor = ProtocolDescriptor::generate(&services(2, 10), DEFAULT_BUDGET);
+        let spec = descriptor.service_spec(true);
+
+        assert_eq!(spec.uuid, DESCRIPTOR_SERVICE_UUID);
+        let sizes: Vec<_> = spec
+            .characteristics
+            .iter()
+            .map(|characteristic| (characteristic.uuid, usize::from(characteristic.max_len)))
+            .collect();
+        assert_eq!(
+            sizes,
+            [
+                (DESCRIPTOR_UUID, descriptor.cbor.len()),
+                (SUMMARY_UUID, descriptor.summary.len()),
+            ]
+        );
+        assert_eq!(descriptor.service_spec(false).characteristics.len(), 1);
+    }
+}
@@ -0,0 +1,247 @@
+//! Holding advertising back until the GATT table is complete.
+//!
+//! A phone that connects while services are still being created discovers
+//! and caches a partial table. [`AdvGate`] only lets advertising start once
+//! the advertising data is configured and, in the default
+//! [`GateMode::AfterServicesStarted`], every declared service has reached
+//! `Started` in the [`ServiceLifecycle`]. A failed service is handled as
+//! [`OnServiceFailure`] says.
+//!
+//! The gate only covers startup: services added after advertising has
+//! begun are announced with Service Changed instead.
+
+use crate::proto::Uuid;
+
+use super::lifecycle::{ServiceLifecycle, ServiceState};
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum GateMode {
+    /// Advertise as soon as the advertising data is configured.
+    Immediate,
+    #[default]
+    AfterServicesStarted,
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum OnServiceFailure {
+    /// Advertise with the services that did start.
+    AdvertiseAnyway,
+    /// Do not advertise; report the failed services.
+    #[default]
+    Hold,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum GateAction {
+    /// Nothing to do yet.
+    Wait,
+    StartAdvertising,
+    /// Held by [`OnServiceFailure::Hold`]; `failed` is sorted.
+    Held {
+        failed: Vec<Uuid>,
+    },
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+enum Phase {
+    #[default]
+    Waiting,
+    Open,
+    Held,
+}
+
+#[derive(Debug, Default)]
+pub struct AdvGate {
+    mode: GateMode,
+    on_failure: OnServiceFailure,
+    adv_configured: bool,
+    /// Once open or held, later events do nothing.
+    phase: Phase,
+}
+
+impl AdvGate {
+    pub fn new(mode: GateMode, on_failure: OnServiceFailure) -> Self {
+        Self {
+            mode,
+            on_failure,
+            ..Self::default()
+        }
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.phase == Phase::Open
+    }
+
+    /// `AdvertisingConfigured` arrived.
+    pub fn on_adv_configured(&mut self, lifecycle: &ServiceLifecycle) -> GateAction {
+        self.adv_configured = true;
+        self.evaluate(lifecycle)
+    }
+
+    /// Call after every lifecycle event applied during startup.
+    pub fn on_lifecycle(&mut self, lifecycle: &ServiceLifecycle) -> GateAction {
+        self.evaluate(lifecycle)
+    }
+
+    fn evaluate(&mut self, lifecycle: &ServiceLifecycle) -> GateAction {
+        if self.phase != Phase::Waiting || !self.adv_configured {
+            return GateAction::Wait;
+        }
+
+        if self.mode == GateMode::Immediate {
+            self.phase = Phase::Open;
+            return GateAction::StartAdvertising;
+        }
+
+        let mut failed = Vec::new();
+        for (uuid, state) in lifecycle.states() {
+            match state {
+                ServiceState::Started => {}
+                ServiceState::Failed(_) => failed.push(*uuid),
+                _ => return GateAction::Wait,
+            }
+        }
+
+        failed.sort_unstable();
+        if !failed.is_empty() && self.on_failure == OnServiceFailure::Hold {
+            self.phase = Phase::Held;
+            return GateAction::Held { failed };
+        }
+
+        for uuid in &failed {
+            log::warn!("advertising without failed service {uuid}");
+        }
+        self.phase = Phase::Open;
+        GateAction::StartAdvertising
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::lifecycle::LifecycleEvent;
+    use super::*;
+
+    const BATTERY: Uuid = Uuid::Uuid16(0x180F);
+    const DEVICE_INFO: Uuid = Uuid::Uuid16(0x180A);
+    const CUSTOM: Uuid = Uuid::Uuid128(0x0000_1524_1212_efde_1523_785f_eabc_d123);
+
+    fn lifecycle(services: &[Uuid]) -> ServiceLifecycle {
+        let mut lifecycle = ServiceLifecycle::new();
+        for &uuid in services {
+            lifecycle.declare(uuid);
+        }
+        lifecycle
+    }
+
+    /// Creates a service up to, but not including, `ServiceStarted`.
+    fn create(lifecycle: &mut ServiceLifecycle, gate: &mut AdvGate, uuid: Uuid) -> Vec<GateAction> {
+        [
+            LifecycleEvent::CreateRequested,
+            LifecycleEvent::Created,
+            LifecycleEvent::AttributeAdded,
+            LifecycleEvent::AttributeAdded,
+        ]
+        .into_iter()
+        .map(|event| {
+            lifecycle.apply(uuid, event);
+            gate.on_lifecycle(lifecycle)
+        })
+        .collect()
+    }
+
+    fn start(lifecycle: &mut ServiceLifecycle, gate: &mut AdvGate, uuid: Uuid) -> GateAction {
+        lifecycle.apply(uuid, LifecycleEvent::Started);
+        gate.on_lifecycle(lifecycle)
+    }
+
+    #[test]
+    fn advertising_waits_for_the_last_service_started() {
+        let services = [BATTERY, DEVICE_INFO, CUSTOM];
+        let mut lifecycle = lifecycle(&services);
+        let mut gate = AdvGate::default();
+
+        // Advertising data usually comes back before the services do.
+        assert_eq!(gate.on_adv_configured(&lifecycle), GateAction::Wait);
+
+        let mut actions = Vec::new();
+        for uuid in services {
+            actions.extend(create(&mut lifecycle, &mut gate, uuid));
+        }
+        actions.push(start(&mut lifecycle, &mut gate, BATTERY));
+        actions.push(start(&mut lifecycle, &mut gate, DEVICE_INFO));
+        assert!(actions.iter().all(|action| *action == GateAction::Wait));
+        assert!(!gate.is_open());
+
+        assert_eq!(
+            start(&mut lifecycle, &mut gate, CUSTOM),
+            GateAction::StartAdvertising
+        );
+        assert!(gate.is_open());
+        // Opens once.
+        assert_eq!(gate.on_lifecycle(&lifecycle), GateAction::Wait);
+    }
+
+    #[test]
+    fn services_started_before_adv_data_open_on_configured() {
+        let mut lifecycle = lifecycle(&[BATTERY]);
+        let mut gate = AdvGate::default();
+
+        create(&mut lifecycle, &mut gate, BATTERY);
+        assert_eq!(start(&mut lifecycle, &mut gate, BATTERY), GateAction::Wait);
+        assert_eq!(
+            gate.on_adv_configured(&lifecycle),
+            GateAction::StartAdvertising
+        );
+    }
+
+    #[test]
+    fn immediate_mode_ignores_services() {
+        let lifecycle = lifecycle(&[BATTERY]);
+        let mut gate = AdvGate::new(GateMode::Immediate, OnServiceFailure::Hold);
+
+        assert_eq!(
+            gate.on_adv_configured(&lifecycle),
+            GateAction::StartAdvertising
+        );
+    }
+
+    #[test]
+    fn failed_services_hold_in_sorted_order() {
+        let services = [CUSTOM, DEVICE_INFO, BATTERY];
+        let mut lifecycle = lifecycle(&services);
+        let mut gate = AdvGate::default();
+        gate.on_adv_configured(&lifecycle);
+
+        create(&mut lifecycle, &mut gate, DEVICE_INFO);
+        start(&mut lifecycle, &mut gate, DEVICE_INFO);
+        lifecycle.apply(CUSTOM, LifecycleEvent::Failed("no handles".into()));
+        assert_eq!(gate.on_lifecycle(&lifecycle), GateAction::Wait);
+        lifecycle.apply(BATTERY, LifecycleEvent::Failed("no handles".into()));
+
+        assert_eq!(
+            gate.on_lifecycle(&lifecycle),
+            GateAction::Held {
+                failed: vec![BATTERY, CUSTOM]
+            }
+        );
+        assert!(!gate.is_open());
+        assert_eq!(gate.on_lifecycle(&lifecycle), GateAction::Wait);
+    }
+
+    #[test]
+    fn failed_services_can_be_advertised_without() {
+        let mut lifecycle = lifecycle(&[BATTERY, CUSTOM]);
+        let mut gate = AdvGate::new(
+            GateMode::AfterServicesStarted,
+            OnServiceFailure::AdvertiseAnyway,
+        );
+        gate.on_adv_configured(&lifecycle);
+
+        create(&mut lifecycle, &mut gate, BATTERY);
+        lifecycle.apply(CUSTOM, LifecycleEvent::Failed("no handles".into()));
+        assert_eq!(
+            start(&mut lifecycle, &mut gate, BATTERY),
+            GateAction::StartAdvertising
+        );
+    }
+}
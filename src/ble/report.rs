@@ -0,0 +1,204 @@
+//! Capability report for test fixtures and the startup banner.
+//!
+//! A manufacturing fixture talking to the device over serial needs to know
+//! what the BLE layer is configured to do before BLE itself is verified.
+//! [`CapabilityReport`] is derived from the [`ServerSpec`] the server is
+//! started with, so it cannot drift from what is actually declared. With
+//! the `serde` feature it serializes to JSON; [`CapabilityReport::print_banner`]
+//! logs a compact human-readable form.
+
+#[cfg(feature = "serde")]
+use serde::Serialize;
+
+use crate::proto::Uuid;
+
+use super::preflight::PreflightIssue;
+use super::spec::{CharPerm, CharProp, SecurityMode, ServerSpec};
+
+/// Settings the server takes besides the [`ServerSpec`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct RuntimeConfig {
+    pub app_id: u16,
+    pub max_connections: u8,
+    pub local_mtu: u16,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct CharacteristicReport {
+    pub uuid: Uuid,
+    pub props: Vec<CharProp>,
+    pub perms: Vec<CharPerm>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct ServiceReport {
+    pub uuid: Uuid,
+    pub characteristics: Vec<CharacteristicReport>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct CapabilityReport {
+    pub crate_version: &'static str,
+    pub device_name: String,
+    pub app_id: u16,
+    pub security: SecurityMode,
+    pub max_connections: u8,
+    pub local_mtu: u16,
+    /// Cargo features this firmware was built with.
+    pub features: Vec<&'static str>,
+    pub services: Vec<ServiceReport>,
+    /// Preflight findings, as displayed.
+    pub preflight: Vec<String>,
+}
+
+impl CapabilityReport {
+    pub fn new(spec: &ServerSpec, runtime: RuntimeConfig, preflight: &[PreflightIssue]) -> Self {
+        let services = spec
+            .services
+            .iter()
+            .map(|service| ServiceReport {
+                uuid: service.uuid,
+                characteristics: service
+                    .characteristics
+                    .iter()
+                    .map(|characteristic| CharacteristicReport {
+                        uuid: characteristic.uuid,
+                        props: characteristic.props.clone(),
+                        perms: characteristic.perms.clone(),
+                    })
+                    .collect(),
+            })
+            .collect();
+
+        Self {
+            crate_version: env!("CARGO_PKG_VERSION"),
+            device_name: spec.device_name.clone(),
+            app_id: runtime.app_id,
+            security: spec.security,
+            max_connections: runtime.max_connections,
+            local_mtu: runtime.local_mtu,
+            features: enabled_features(),
+            services,
+            preflight: preflight.iter().map(ToString::to_string).collect(),
+        }
+    }
+
+    pub fn print_banner(&self) {
+        log::info!(
+            "BLE {} v{}: app {}, {:?} security, {} connections, MTU {}, features [{}]",
+            self.device_name,
+            self.crate_version,
+            self.app_id,
+            self.security,
+            self.max_connections,
+            self.local_mtu,
+            self.features.join(", ")
+        );
+        for service in &self.services {
+            log::info!(
+                "  service {:?}: {} characteristics",
+                service.uuid,
+                service.characteristics.len()
+            );
+        }
+        for finding in &self.preflight {
+            log::info!("  preflight: {finding}");
+        }
+    }
+}
+
+fn enabled_features() -> Vec<&'static str> {
+    [
+        ("esp", cfg!(feature = "esp")),
+        ("experimental", cfg!(feature = "experimental")),
+        ("resource-ledger", cfg!(feature = "resource-ledger")),
+        ("rich-docs", cfg!(feature = "rich-docs")),
+        ("serde", cfg!(feature = "serde")),
+    ]
+    .into_iter()
+    .filter_map(|(name, enabled)| enabled.then_some(name))
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn features_follow_the_build() {
+        let features = enabled_features();
+        assert_eq!(features.contains(&"esp"), cfg!(feature = "esp"));
+        assert_eq!(features.contains(&"rich-docs"), cfg!(feature = "rich-docs"));
+        assert_eq!(features.contains(&"serde"), cfg!(feature = "serde"));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn json_shape() {
+        use super::super::spec::{AdvertisingSpec, CharacteristicSpec, ServiceSpec};
+
+        let spec = ServerSpec {
+            device_name: "sensor".into(),
+            advertising: AdvertisingSpec::default(),
+            security: SecurityMode::Bonded,
+            services: vec![ServiceSpec {
+                uuid: Uuid::Uuid16(0x180F),
+                num_handles: None,
+                characteristics: vec![CharacteristicSpec {
+                    uuid: Uuid::Uuid16(0x2A19),
+                    props: crate::props![Read, Notify],
+                    perms: crate::perms![ReadEncrypted],
+                    max_len: 1,
+                    cccd: true,
+                    user_description: None,
+                    doc: "",
+                    encoding: None,
+                    handler: None,
+                    max_subscribers: None,
+                    send_security: None,
+                }],
+                doc: "",
+                protocol_version: None,
+            }],
+        };
+        let runtime = RuntimeConfig {
+            app_id: 7,
+            max_connections: 3,
+            local_mtu: 247,
+        };
+
+        let mut report =
+            CapabilityReport::new(&spec, runtime, &[PreflightIssue::DynamicEnvMemoryOff]);
+        // Depend on the build, not on the spec.
+        report.crate_version = "0.1.0";
+        report.features = vec!["esp", "serde"];
+
+        let expected = r#"{
+            "crate_version": "0.1.0",
+            "device_name": "sensor",
+            "app_id": 7,
+            "security": "Bonded",
+            "max_connections": 3,
+            "local_mtu": 247,
+            "features": ["esp", "serde"],
+            "services": [{
+                "uuid": { "Uuid16": 6159 },
+                "characteristics": [{
+                    "uuid": { "Uuid16": 10777 },
+                    "props": ["Read", "Notify"],
+                    "perms": ["ReadEncrypted"]
+                }]
+            }],
+            "preflight": [
+                "warning: BLE dynamic env memory is off (set CONFIG_BT_BLE_DYNAMIC_ENV_MEMORY=y)"
+            ]
+        }"#;
+        assert_eq!(
+            serde_json::to_value(&report).unwrap(),
+            serde_json::from_str::<serde_json::Value>(expected).unwrap()
+        );
+    }
+}
@@ -0,0 +1,249 @@
+//! Deciding whether a returning non-bonded peer resumes its subscriptions.
+//!
+//! Some phone stacks rewrite the CCCD after reconnecting and some do not, so
+//! a streaming service cannot tell whether to wait for one. A characteristic
+//! with a [`ResumePolicy`] gets a [`ResumeDecision`] when a peer seen before
+//! (by address) reconnects: `resumed` follows the CCCD if one is written
+//! before the timeout, otherwise the policy's fallback applies. Peers seen
+//! for the first time subscribe the usual way and get no decision.
+
+use core::time::Duration;
+use std::collections::{HashMap, VecDeque};
+use std::time::Instant;
+
+use super::PeerAddr;
+use crate::proto::CccdFlags;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Fallback {
+    Resume,
+    StayIdle,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ResumePolicy {
+    WaitForCccd {
+        timeout: Duration,
+        on_timeout: Fallback,
+    },
+}
+
+/// Passed on to the owning service's `on_resume_decision`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ResumeDecision {
+    pub conn_id: u16,
+    pub handle: u16,
+    pub resumed: bool,
+}
+
+#[derive(Debug)]
+pub struct ResumeTracker {
+    policies: HashMap<u16, ResumePolicy>,
+    /// Recently connected peers, most recent last.
+    seen: VecDeque<PeerAddr>,
+    max_seen: usize,
+    /// Decisions still waiting for a CCCD write, by `(conn_id, handle)`.
+    pending: HashMap<(u16, u16), (Instant, Fallback)>,
+}
+
+impl ResumeTracker {
+    /// Remembers up to `max_seen` peer addresses.
+    pub fn new(max_seen: usize) -> Self {
+        Self {
+            policies: HashMap::new(),
+            seen: VecDeque::new(),
+            max_seen,
+            pending: HashMap::new(),
+        }
+    }
+
+    pub fn set_policy(&mut self, handle: u16, policy: ResumePolicy) {
+        self.policies.insert(handle, policy);
+    }
+
+    /// Starts the wait for every characteristic with a policy if `peer`
+    /// has been connected before.
+    pub fn on_connect(&mut self, conn_id: u16, peer: PeerAddr, now: Instant) {
+        let returning = match self.seen.iter().position(|seen| *seen == peer) {
+            Some(index) => {
+                self.seen.remove(index);
+                true
+            }
+            None => false,
+        };
+        if self.seen.len() >= self.max_seen {
+            self.seen.pop_front();
+        }
+        self.seen.push_back(peer);
+
+        if !returning {
+            return;
+        }
+        for (&handle, policy) in &self.policies {
+            let ResumePolicy::WaitForCccd {
+                timeout,
+                on_timeout,
+            } = *policy;
+            self.pending
+                .insert((conn_id, handle), (now + timeout, on_timeout));
+        }
+    }
+
+    /// A CCCD write settles a pending decision.
+    pub fn on_cccd_write(
+        &mut self,
+        conn_id: u16,
+        handle: u16,
+        flags: CccdFlags,
+    ) -> Option<ResumeDecision> {
+        self.pending.remove(&(conn_id, handle))?;
+
+        Some(ResumeDecision {
+            conn_id,
+            handle,
+            resumed: !flags.is_empty(),
+        })
+    }
+
+    /// Applies the fallback of every wait that timed out.
+    pub fn poll(&mut self, now: Instant) -> Vec<ResumeDecision> {
+        let mut decisions = Vec::new();
+
+        self.pending
+            .retain(|&(conn_id, handle), &mut (deadline, fallback)| {
+                if now < deadline {
+                    return true;
+                }
+                decisions.push(ResumeDecision {
+                    conn_id,
+                    handle,
+                    resumed: fallback == Fallback::Resume,
+                });
+                false
+            });
+
+        decisions.sort_unstable_by_key(|decision| (decision.conn_id, decision.handle));
+        decisions
+    }
+
+    pub fn next_deadline(&self) -> Option<Instant> {
+        self.pending.values().map(|&(deadline, _)| deadline).min()
+    }
+
+    pub fn on_disconnect(&mut self, conn_id: u16) {
+        self.pending.retain(|&(conn, _), _| conn != conn_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_clock::MockClock;
+
+    const STREAM: u16 = 42;
+    const PHONE: PeerAddr = [1, 2, 3, 4, 5, 6];
+
+    fn tracker(on_timeout: Fallback) -> ResumeTracker {
+        let mut tracker = ResumeTracker::new(4);
+        tracker.set_policy(
+            STREAM,
+            ResumePolicy::WaitForCccd {
+                timeout: Duration::from_secs(5),
+                on_timeout,
+            },
+        );
+        tracker
+    }
+
+    fn decision(conn_id: u16, resumed: bool) -> ResumeDecision {
+        ResumeDecision {
+            conn_id,
+            handle: STREAM,
+            resumed,
+        }
+    }
+
+    #[test]
+    fn first_connection_gets_no_decision() {
+        let clock = MockClock::new();
+        let mut tracker = tracker(Fallback::Resume);
+
+        tracker.on_connect(1, PHONE, clock.secs(0));
+        assert_eq!(tracker.next_deadline(), None);
+        assert_eq!(tracker.on_cccd_write(1, STREAM, CccdFlags::NOTIFY), None);
+        assert!(tracker.poll(clock.secs(60)).is_empty());
+    }
+
+    #[test]
+    fn cccd_write_before_the_timeout_decides() {
+        let clock = MockClock::new();
+        let mut tracker = tracker(Fallback::StayIdle);
+        tracker.on_connect(1, PHONE, clock.secs(0));
+        tracker.on_disconnect(1);
+
+        tracker.on_connect(2, PHONE, clock.secs(10));
+        assert_eq!(tracker.next_deadline(), Some(clock.secs(15)));
+        assert!(tracker.poll(clock.secs(14)).is_empty());
+
+        assert_eq!(
+            tracker.on_cccd_write(2, STREAM, CccdFlags::NOTIFY),
+            Some(decision(2, true))
+        );
+        assert_eq!(tracker.next_deadline(), None);
+        assert!(tracker.poll(clock.secs(15)).is_empty());
+        // Only the first write after reconnecting is a decision.
+        assert_eq!(tracker.on_cccd_write(2, STREAM, CccdFlags::NONE), None);
+    }
+
+    #[test]
+    fn cccd_write_disabling_does_not_resume() {
+        let clock = MockClock::new();
+        let mut tracker = tracker(Fallback::Resume);
+        tracker.on_connect(1, PHONE, clock.secs(0));
+        tracker.on_connect(2, PHONE, clock.secs(10));
+
+        assert_eq!(
+            tracker.on_cccd_write(2, STREAM, CccdFlags::NONE),
+            Some(decision(2, false))
+        );
+    }
+
+    #[test]
+    fn timeout_applies_the_fallback() {
+        for (on_timeout, resumed) in [(Fallback::Resume, true), (Fallback::StayIdle, false)] {
+            let clock = MockClock::new();
+            let mut tracker = tracker(on_timeout);
+            tracker.on_connect(1, PHONE, clock.secs(0));
+            tracker.on_connect(2, PHONE, clock.secs(10));
+
+            assert_eq!(tracker.poll(clock.secs(15)), [decision(2, resumed)]);
+            assert_eq!(tracker.next_deadline(), None);
+            assert_eq!(tracker.on_cccd_write(2, STREAM, CccdFlags::NOTIFY), None);
+        }
+    }
+
+    #[test]
+    fn disconnect_cancels_the_wait() {
+        let clock = MockClock::new();
+        let mut tracker = tracker(Fallback::Resume);
+        tracker.on_connect(1, PHONE, clock.secs(0));
+        tracker.on_connect(2, PHONE, clock.secs(10));
+
+        tracker.on_disconnect(2);
+        assert_eq!(tracker.next_deadline(), None);
+        assert!(tracker.poll(clock.secs(60)).is_empty());
+    }
+
+    #[test]
+    fn forgotten_peers_are_new_again() {
+        let clock = MockClock::new();
+        let mut tracker = tracker(Fallback::Resume);
+        tracker.on_connect(1, PHONE, clock.secs(0));
+        for (conn_id, other) in (2..6).zip(10u8..) {
+            tracker.on_connect(conn_id, [other; 6], clock.secs(1));
+        }
+
+        tracker.on_connect(6, PHONE, clock.secs(2));
+        assert_eq!(tracker.next_deadline(), None);
+    }
+}
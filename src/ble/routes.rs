@@ -0,0 +1,183 @@
+//! Characteristic handles learned from the GATTS creation events.
+//!
+//! Services declare their characteristic UUIDs up front with
+//! [`CharRoutes::declare`]. As `ServiceCreated` and `CharacteristicAdded`
+//! arrive, the handles the stack assigned are filled in, so handlers can
+//! look up their own handles with [`CharRoutes::handle_for_char`] and write
+//! routing can map a handle back with [`CharRoutes::char_for_handle`]
+//! instead of every handler caching handles itself. A UUID declared twice
+//! in one service is matched in declaration order.
+
+use core::fmt;
+use std::collections::HashMap;
+
+use crate::proto::Uuid;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct CharHandle {
+    pub service_handle: u16,
+    pub attr_handle: u16,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum RouteError {
+    /// `ServiceCreated` for a service that was not declared.
+    UnknownService(Uuid),
+    /// `CharacteristicAdded` on a service handle no `ServiceCreated` named.
+    UnknownServiceHandle(u16),
+    /// A characteristic the service did not declare, or declared fewer
+    /// times than it was added.
+    UndeclaredCharacteristic { service: Uuid, characteristic: Uuid },
+}
+
+impl fmt::Display for RouteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnknownService(uuid) => write!(f, "service {uuid:?} was not declared"),
+            Self::UnknownServiceHandle(handle) => {
+                write!(f, "no service was created with handle {handle}")
+            }
+            Self::UndeclaredCharacteristic {
+                service,
+                characteristic,
+            } => write!(
+                f,
+                "characteristic {characteristic:?} was not declared in service {service:?}"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for RouteError {}
+
+#[derive(Clone, Debug, Default)]
+struct ServiceRoutes {
+    handle: Option<u16>,
+    /// Declared characteristics, in order, with their handles once added.
+    chars: Vec<(Uuid, Option<CharHandle>)>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct CharRoutes {
+    services: HashMap<Uuid, ServiceRoutes>,
+    by_service_handle: HashMap<u16, Uuid>,
+    by_attr_handle: HashMap<u16, (Uuid, Uuid)>,
+}
+
+impl CharRoutes {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Declares a service's characteristics, replacing an earlier
+    /// declaration and its handles.
+    pub fn declare(&mut self, service: Uuid, chars: impl IntoIterator<Item = Uuid>) {
+        self.forget(&service);
+        self.services.insert(
+            service,
+            ServiceRoutes {
+                handle: None,
+                chars: chars.into_iter().map(|uuid| (uuid, None)).collect(),
+            },
+        );
+    }
+
+    /// Drops a service and every handle learned for it, e.g. on
+    /// `ServiceDeleted`.
+    pub fn forget(&mut self, service: &Uuid) {
+        let Some(routes) = self.services.remove(service) else {
+            return;
+        };
+
+        if let Some(handle) = routes.handle {
+            self.by_service_handle.remove(&handle);
+        }
+        for char_handle in routes.chars.iter().filter_map(|(_, handle)| *handle) {
+            self.by_attr_handle.remove(&char_handle.attr_handle);
+        }
+    }
+
+    /// `ServiceCreated`.
+    pub fn on_service_created(
+        &mut self,
+        service: Uuid,
+        service_handle: u16,
+    ) -> Result<(), RouteError> {
+        let routes = self
+            .services
+            .get_mut(&service)
+            .ok_or(RouteError::UnknownService(service))?;
+
+        if let Some(previous) = routes.handle.replace(service_handle) {
+            self.by_service_handle.remove(&previous);
+        }
+        self.by_service_handle.insert(service_handle, service);
+        Ok(())
+    }
+
+    /// `CharacteristicAdded`; fills the first declared entry for
+    /// `char_uuid` that has no handle yet.
+    pub fn on_characteristic_added(
+        &mut self,
+        service_handle: u16,
+        char_uuid: Uuid,
+        attr_handle: u16,
+    ) -> Result<(), RouteError> {
+        let service = *self
+            .by_service_handle
+            .get(&service_handle)
+            .ok_or(RouteError::UnknownServiceHandle(service_handle))?;
+        let routes = self
+            .services
+            .get_mut(&service)
+            .ok_or(RouteError::UnknownService(service))?;
+
+        let slot = routes
+            .chars
+            .iter_mut()
+            .find(|(uuid, handle)| *uuid == char_uuid && handle.is_none())
+            .ok_or(RouteError::UndeclaredCharacteristic {
+                service,
+                characteristic: char_uuid,
+            })?;
+
+        slot.1 = Some(CharHandle {
+            service_handle,
+            attr_handle,
+        });
+        self.by_attr_handle
+            .insert(attr_handle, (service, char_uuid));
+        Ok(())
+    }
+
+    /// The value handle of the first `char_uuid` declared in `service`.
+    pub fn handle_for_char(&self, service: &Uuid, char_uuid: &Uuid) -> Option<u16> {
+        self.services
+            .get(service)?
+            .chars
+            .iter()
+            .find(|(uuid, _)| uuid == char_uuid)
+            .and_then(|(_, handle)| *handle)
+            .map(|handle| handle.attr_handle)
+    }
+
+    /// The service and characteristic UUIDs owning `attr_handle`.
+    pub fn char_for_handle(&self, attr_handle: u16) -> Option<(Uuid, Uuid)> {
+        self.by_attr_handle.get(&attr_handle).copied()
+    }
+
+    /// Characteristics of `service` still waiting for `CharacteristicAdded`.
+    pub fn pending(&self, service: &Uuid) -> Vec<Uuid> {
+        self.services
+            .get(service)
+            .map(|routes| {
+                routes
+                    .chars
+                    .iter()
+                    .filter(|(_, handle)| handle.is_none())
+                    .map(|(uuid, _)| *uuid)
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+}
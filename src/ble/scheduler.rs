@@ -0,0 +1,159 @@
+//! Delayed work for handlers, run on the dispatch worker.
+//!
+//! Handlers that need "do X in 500 ms" post a task to the shared
+//! [`Scheduler`] instead of creating their own timer. The dispatch worker
+//! arms one timer for [`Scheduler::next_deadline`] and runs whatever
+//! [`Scheduler::poll`] returns, so tasks execute on the same thread as every
+//! other callback.
+//!
+//! A task scheduled for a connection can be dropped automatically when that
+//! connection goes away; see [`Scheduler::schedule_for`].
+
+use core::time::Duration;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+
+/// Cancels a scheduled task; cloning shares the handle.
+///
+/// Cancelling is only a flag, so it is cheap and may be done from any
+/// thread. The task is dropped unrun when it comes due.
+#[derive(Clone, Debug)]
+pub struct ScheduleToken {
+    id: u64,
+    cancelled: Arc<AtomicBool>,
+}
+
+impl ScheduleToken {
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+}
+
+#[derive(Debug)]
+struct Scheduled<T> {
+    task: T,
+    conn_id: Option<u16>,
+    cancelled: Arc<AtomicBool>,
+}
+
+/// Tasks of type `T` ordered by deadline, ties in scheduling order.
+#[derive(Debug)]
+pub struct Scheduler<T> {
+    queue: BTreeMap<(Instant, u64), Scheduled<T>>,
+    /// Deadline of each queued id, for cancellation by id.
+    deadlines: HashMap<u64, Instant>,
+    next_id: u64,
+}
+
+impl<T> Default for Scheduler<T> {
+    fn default() -> Self {
+        Self {
+            queue: BTreeMap::new(),
+            deadlines: HashMap::new(),
+            next_id: 0,
+        }
+    }
+}
+
+impl<T> Scheduler<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Runs `task` once `delay` has passed.
+    pub fn schedule(&mut self, delay: Duration, task: T, now: Instant) -> ScheduleToken {
+        self.insert(now + delay, task, None)
+    }
+
+    /// Like [`schedule`](Self::schedule), but the task is dropped unrun if
+    /// `conn_id` disconnects first.
+    pub fn schedule_for(
+        &mut self,
+        conn_id: u16,
+        delay: Duration,
+        task: T,
+        now: Instant,
+    ) -> ScheduleToken {
+        self.insert(now + delay, task, Some(conn_id))
+    }
+
+    fn insert(&mut self, deadline: Instant, task: T, conn_id: Option<u16>) -> ScheduleToken {
+        let id = self.next_id;
+        self.next_id += 1;
+
+        let cancelled = Arc::new(AtomicBool::new(false));
+        self.queue.insert(
+            (deadline, id),
+            Scheduled {
+                task,
+                conn_id,
+                cancelled: cancelled.clone(),
+            },
+        );
+        self.deadlines.insert(id, deadline);
+        ScheduleToken { id, cancelled }
+    }
+
+    /// Removes a task before it runs, returning it if it was still queued.
+    pub fn cancel(&mut self, token: &ScheduleToken) -> Option<T> {
+        token.cancel();
+        let deadline = self.deadlines.remove(&token.id)?;
+        self.queue
+            .remove(&(deadline, token.id))
+            .map(|scheduled| scheduled.task)
+    }
+
+    /// Drops the tasks scheduled for `conn_id`, returning how many.
+    pub fn on_disconnect(&mut self, conn_id: u16) -> usize {
+        let before = self.queue.len();
+        self.queue
+            .retain(|_, scheduled| scheduled.conn_id != Some(conn_id));
+        self.deadlines
+            .retain(|&id, &mut deadline| self.queue.contains_key(&(deadline, id)));
+        before - self.queue.len()
+    }
+
+    /// Takes the tasks due at `now`, in deadline order. Cancelled tasks are
+    /// dropped here.
+    pub fn poll(&mut self, now: Instant) -> Vec<T> {
+        let mut due = Vec::new();
+
+        while let Some(entry) = self.queue.first_entry() {
+            let &(deadline, id) = entry.key();
+            if deadline > now {
+                break;
+            }
+
+            let scheduled = entry.remove();
+            self.deadlines.remove(&id);
+            if !scheduled.cancelled.load(Ordering::Relaxed) {
+                due.push(scheduled.task);
+            }
+        }
+
+        due
+    }
+
+    /// When the worker's timer should fire next.
+    pub fn next_deadline(&self) -> Option<Instant> {
+        self.queue.keys().next().map(|&(deadline, _)| deadline)
+    }
+
+    pub fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+}
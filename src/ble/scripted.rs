@@ -0,0 +1,63 @@
+//! A stand-in for the Bluedroid GATTS event source.
+//!
+//! [`ScriptedStack`] delivers events to an [`EventHandler`] the way the
+//! stack's callback would, one at a time and in order, and keeps a
+//! transcript of what it delivered. Sessions are scripted by hand with
+//! [`ScriptedStack::deliver`] or replayed from a capture with
+//! [`super::capture::replay`].
+
+use core::fmt;
+
+use super::event_ring::EventRecord;
+
+/// The receiving end of GATTS events: the server's dispatch.
+pub trait EventHandler {
+    fn on_event(&mut self, event: &EventRecord, payload: Option<&[u8]>);
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ScriptedEvent {
+    pub event: EventRecord,
+    pub payload: Option<Vec<u8>>,
+}
+
+pub struct ScriptedStack<H> {
+    handler: H,
+    transcript: Vec<ScriptedEvent>,
+}
+
+impl<H> fmt::Debug for ScriptedStack<H> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ScriptedStack")
+            .field("delivered", &self.transcript.len())
+            .finish_non_exhaustive()
+    }
+}
+
+impl<H: EventHandler> ScriptedStack<H> {
+    pub fn new(handler: H) -> Self {
+        Self {
+            handler,
+            transcript: Vec::new(),
+        }
+    }
+
+    /// Hands one event to the handler.
+    pub fn deliver(&mut self, event: EventRecord, payload: Option<Vec<u8>>) {
+        self.handler.on_event(&event, payload.as_deref());
+        self.transcript.push(ScriptedEvent { event, payload });
+    }
+
+    /// Every event delivered so far, in order.
+    pub fn transcript(&self) -> &[ScriptedEvent] {
+        &self.transcript
+    }
+
+    pub fn handler(&self) -> &H {
+        &self.handler
+    }
+
+    pub fn into_handler(self) -> H {
+        self.handler
+    }
+}
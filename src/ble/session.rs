@@ -0,0 +1,289 @@
+//! Per-connection session storage.
+//!
+//! Features that need per-connection state (prepare-write buffers, staged
+//! transactions, handshake progress, ...) store it as typed entries in the
+//! connection's [`Session`] instead of keeping their own maps. The
+//! [`SessionRegistry`] creates a session on connect and drops it wholesale on
+//! disconnect, so there is exactly one cleanup path. Entries can carry a TTL,
+//! expired entries are removed by [`SessionRegistry::sweep`] from one timer.
+
+use core::any::{Any, TypeId};
+use core::time::Duration;
+use std::collections::HashMap;
+use std::time::Instant;
+
+use crate::ledger::{self, ResourceKind, Tracked};
+
+#[derive(Debug)]
+struct Entry {
+    value: Box<dyn Any + Send>,
+    expires_at: Option<Instant>,
+}
+
+/// Typed extension storage of one connection, at most one value per type.
+#[derive(Debug)]
+pub struct Session {
+    entries: HashMap<TypeId, Entry>,
+    _tracked: Tracked,
+}
+
+impl Default for Session {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Session {
+    pub fn new() -> Self {
+        Self {
+            entries: HashMap::new(),
+            _tracked: ledger::track(ResourceKind::Session, "session"),
+        }
+    }
+
+    /// Stores `value`, returning the previous value of that type.
+    pub fn insert<T: Any + Send>(&mut self, value: T) -> Option<T> {
+        self.insert_entry(value, None)
+    }
+
+    /// Stores `value` until `now + ttl`.
+    pub fn insert_with_ttl<T: Any + Send>(
+        &mut self,
+        value: T,
+        ttl: Duration,
+        now: Instant,
+    ) -> Option<T> {
+        self.insert_entry(value, Some(now + ttl))
+    }
+
+    pub fn get<T: Any + Send>(&self) -> Option<&T> {
+        self.entries
+            .get(&TypeId::of::<T>())
+            .and_then(|entry| entry.value.downcast_ref())
+    }
+
+    pub fn get_mut<T: Any + Send>(&mut self) -> Option<&mut T> {
+        self.entries
+            .get_mut(&TypeId::of::<T>())
+            .and_then(|entry| entry.value.downcast_mut())
+    }
+
+    pub fn get_or_insert_with<T: Any + Send>(&mut self, f: impl FnOnce() -> T) -> &mut T {
+        self.entries
+            .entry(TypeId::of::<T>())
+            .or_insert_with(|| Entry {
+                value: Box::new(f()),
+                expires_at: None,
+            })
+            .value
+            .downcast_mut()
+            .expect("session entry stored under its own TypeId")
+    }
+
+    pub fn remove<T: Any + Send>(&mut self) -> Option<T> {
+        self.entries
+            .remove(&TypeId::of::<T>())
+            .and_then(|entry| entry.value.downcast().ok())
+            .map(|value| *value)
+    }
+
+    pub fn contains<T: Any + Send>(&self) -> bool {
+        self.entries.contains_key(&TypeId::of::<T>())
+    }
+
+    /// Removes expired entries, returning how many were dropped.
+    pub fn sweep(&mut self, now: Instant) -> usize {
+        let before = self.entries.len();
+        self.entries
+            .retain(|_, entry| entry.expires_at.map_or(true, |at| at > now));
+
+        before - self.entries.len()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    fn insert_entry<T: Any + Send>(&mut self, value: T, expires_at: Option<Instant>) -> Option<T> {
+        let entry = Entry {
+            value: Box::new(value),
+            expires_at,
+        };
+
+        self.entries
+            .insert(TypeId::of::<T>(), entry)
+            .and_then(|old| old.value.downcast().ok())
+            .map(|old| *old)
+    }
+}
+
+/// Sessions of all live connections, keyed by connection id.
+#[derive(Debug, Default)]
+pub struct SessionRegistry {
+    sessions: HashMap<u16, Session>,
+}
+
+impl SessionRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Starts a fresh session; a stale one under the same id is dropped.
+    pub fn on_connect(&mut self, conn_id: u16) -> &mut Session {
+        self.sessions.insert(conn_id, Session::new());
+        self.sessions
+            .get_mut(&conn_id)
+            .expect("session just inserted")
+    }
+
+    /// Drops the session and everything stored in it.
+    pub fn on_disconnect(&mut self, conn_id: u16) -> Option<Session> {
+        self.sessions.remove(&conn_id)
+    }
+
+    pub fn get(&self, conn_id: u16) -> Option<&Session> {
+        self.sessions.get(&conn_id)
+    }
+
+    pub fn get_mut(&mut self, conn_id: u16) -> Option<&mut Session> {
+        self.sessions.get_mut(&conn_id)
+    }
+
+    /// Sweeps expired entries in every session.
+    pub fn sweep(&mut self, now: Instant) -> usize {
+        self.sessions
+            .values_mut()
+            .map(|session| session.sweep(now))
+            .sum()
+    }
+
+    /// Earliest expiry over all sessions, to arm the sweep timer.
+    pub fn next_expiry(&self) -> Option<Instant> {
+        self.sessions
+            .values()
+            .flat_map(|session| session.entries.values())
+            .filter_map(|entry| entry.expires_at)
+            .min()
+    }
+
+    pub fn len(&self) -> usize {
+        self.sessions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sessions.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::ble::confirm::{ConfirmGate, ConfirmOutcome};
+    use crate::ble::negotiation;
+    use crate::ble::prepare::{self, PreparedWrites};
+    use crate::proto::{Negotiated, ProtocolVersion};
+
+    /// Counts live instances, to see that dropped sessions free their entries.
+    struct LiveCounter(Arc<AtomicUsize>);
+
+    impl LiveCounter {
+        fn new(live: &Arc<AtomicUsize>) -> Self {
+            live.fetch_add(1, Ordering::SeqCst);
+            Self(live.clone())
+        }
+    }
+
+    impl Drop for LiveCounter {
+        fn drop(&mut self) {
+            self.0.fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn typed_entries() {
+        let mut session = Session::new();
+        assert_eq!(session.insert(5_u32), None);
+        assert_eq!(session.insert(7_u32), Some(5));
+        session.insert("name");
+
+        assert_eq!(session.get::<u32>(), Some(&7));
+        *session.get_or_insert_with(|| 0_u8) += 1;
+        assert_eq!(session.get::<u8>(), Some(&1));
+        assert_eq!(session.len(), 3);
+        assert_eq!(session.remove::<&str>(), Some("name"));
+        assert!(!session.contains::<&str>());
+    }
+
+    #[test]
+    fn sweep_drops_expired_entries() {
+        let start = Instant::now();
+        let mut registry = SessionRegistry::new();
+        let session = registry.on_connect(1);
+        session.insert(1_u8);
+        session.insert_with_ttl(2_u16, Duration::from_secs(5), start);
+
+        assert_eq!(registry.next_expiry(), Some(start + Duration::from_secs(5)));
+        assert_eq!(registry.sweep(start + Duration::from_secs(4)), 0);
+        assert_eq!(registry.sweep(start + Duration::from_secs(5)), 1);
+        assert_eq!(registry.next_expiry(), None);
+        assert!(registry.get(1).unwrap().contains::<u8>());
+    }
+
+    #[test]
+    fn reconnect_starts_fresh() {
+        let mut registry = SessionRegistry::new();
+        registry.on_connect(1).insert(1_u8);
+        assert!(registry.on_connect(1).is_empty());
+    }
+
+    #[test]
+    fn hundred_connect_disconnect_cycles_leak_nothing() {
+        let live = Arc::new(AtomicUsize::new(0));
+        let version = ProtocolVersion::new(1, 0, 0b11);
+        let mut gate = ConfirmGate::new(&[0xFF], Duration::from_secs(1), || 0x1234_5678);
+        let mut registry = SessionRegistry::new();
+        let now = Instant::now();
+
+        for i in 0..100_u16 {
+            let conn_id = i % 4;
+            let session = registry.on_connect(conn_id);
+
+            session.insert(LiveCounter::new(&live));
+            negotiation::on_client_version(session, &version, &version.encode()).unwrap();
+            prepare::on_prepare_write(session, 64, 0x2A, 0, b"half", |_, _| Ok(())).unwrap();
+            assert!(matches!(
+                gate.on_command(session, 0xFF, now),
+                ConfirmOutcome::Challenged { .. }
+            ));
+            assert!(session.contains::<Negotiated>());
+            assert!(session.contains::<PreparedWrites>());
+
+            assert!(registry.on_disconnect(conn_id).is_some());
+        }
+
+        assert!(registry.is_empty());
+        assert_eq!(registry.next_expiry(), None);
+        assert_eq!(live.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn execute_consumes_the_prepared_queue() {
+        let mut session = Session::new();
+        prepare::on_prepare_write(&mut session, 64, 0x2A, 0, b"ab", |_, _| Ok(())).unwrap();
+        prepare::on_prepare_write(&mut session, 64, 0x2A, 2, b"cd", |_, _| Ok(())).unwrap();
+
+        assert_eq!(
+            prepare::on_execute_write(&mut session, true),
+            vec![(0x2A, b"abcd".to_vec())]
+        );
+        assert!(session.is_empty());
+        assert!(prepare::on_execute_write(&mut session, true).is_empty());
+    }
+}
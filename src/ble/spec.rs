@@ -0,0 +1,891 @@
+//! Declarative server layout.
+//!
+//! A [`ServerSpec`] describes the device name, advertising, security and the
+//! GATT services. With the `serde` feature it can be deserialized from the
+//! JSON configuration blob stored in NVS. [`ServerSpec::validate`] reports
+//! every problem it finds before anything is handed to the stack.
+
+use core::fmt;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::proto::version::{
+    ProtocolVersion, CLIENT_VERSION_UUID, SERVER_VERSION_UUID, VERSION_LEN,
+};
+use crate::proto::Uuid;
+
+use super::budget::{self, CharShape};
+use super::capabilities::Capability;
+use super::conn::SecurityLevel;
+use super::outbox::{OnInsecure, SendSecurity};
+
+pub const ATT_ERR_READ_NOT_PERMITTED: u8 = 0x02;
+pub const ATT_ERR_WRITE_NOT_PERMITTED: u8 = 0x03;
+pub const ATT_ERR_INSUFFICIENT_ENCRYPTION: u8 = 0x0F;
+
+/// GAP device name limit, in UTF-8 bytes.
+pub const MAX_DEVICE_NAME_LEN: usize = 248;
+/// Attribute value limit, in bytes.
+pub const MAX_ATTRIBUTE_LEN: usize = 512;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ServerSpec {
+    pub device_name: String,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub advertising: AdvertisingSpec,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub security: SecurityMode,
+    pub services: Vec<ServiceSpec>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct AdvertisingSpec {
+    /// Advertise the UUIDs of all declared services.
+    pub include_service_uuids: bool,
+    pub appearance: Option<u16>,
+    /// Advertising interval in milliseconds; the stack default when `None`.
+    pub interval_ms: Option<u16>,
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum SecurityMode {
+    #[default]
+    None,
+    /// Just Works pairing with bonding.
+    Bonded,
+    /// MITM-protected pairing with bonding.
+    Authenticated,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ServiceSpec {
+    pub uuid: Uuid,
+    /// Handle count passed to `create_service`; computed when `None`.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub num_handles: Option<u16>,
+    pub characteristics: Vec<CharacteristicSpec>,
+    /// Built with [`doc_str!`]; empty unless the `rich-docs` feature is on.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub doc: &'static str,
+    /// Version served on the characteristics added by
+    /// [`ServiceSpec::with_protocol_version`].
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub protocol_version: Option<ProtocolVersion>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct CharacteristicSpec {
+    pub uuid: Uuid,
+    pub props: Vec<CharProp>,
+    pub perms: Vec<CharPerm>,
+    pub max_len: u16,
+    /// Create a CCCD (0x2902) for this characteristic.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub cccd: bool,
+    /// Value of a user description descriptor (0x2901), if any.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub user_description: Option<String>,
+    /// Built with [`doc_str!`]; stands in for `user_description` when that
+    /// is unset, see [`CharacteristicSpec::description`].
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub doc: &'static str,
+    /// How the value is encoded, e.g. `"u8 percent"`; published by the
+    /// protocol descriptor service.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub encoding: Option<String>,
+    /// Name of the handler binding serving this characteristic.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub handler: Option<String>,
+    /// Limit of concurrently subscribed connections; unlimited when `None`.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub max_subscribers: Option<u8>,
+    /// Link security required to notify or indicate; derived from the read
+    /// permission when `None`, see [`CharacteristicSpec::send_security`].
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub send_security: Option<SendSecurity>,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum CharProp {
+    Read,
+    Write,
+    WriteNoResponse,
+    Notify,
+    Indicate,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum CharPerm {
+    Read,
+    Write,
+    ReadEncrypted,
+    WriteEncrypted,
+}
+
+/// Builds the `props` list of a [`CharacteristicSpec`].
+///
+/// ```
+/// use esp_gatt_rs_demo::ble::spec::{CharPerm, CharProp};
+/// use esp_gatt_rs_demo::{perms, props};
+///
+/// assert_eq!(props![Read, Notify], vec![CharProp::Read, CharProp::Notify]);
+/// assert_eq!(perms![ReadEncrypted], vec![CharPerm::ReadEncrypted]);
+/// ```
+#[macro_export]
+macro_rules! props {
+    ($($prop:ident),* $(,)?) => {
+        ::std::vec![$($crate::ble::spec::CharProp::$prop),*]
+    };
+}
+
+/// Builds the `perms` list of a [`CharacteristicSpec`], see [`props!`].
+#[macro_export]
+macro_rules! perms {
+    ($($perm:ident),* $(,)?) => {
+        ::std::vec![$($crate::ble::spec::CharPerm::$perm),*]
+    };
+}
+
+/// A documentation string that is only kept with the `rich-docs` feature.
+///
+/// ```
+/// use esp_gatt_rs_demo::doc_str;
+///
+/// let doc: &'static str = doc_str!("Battery level in percent");
+/// assert!(doc.is_empty() || doc == "Battery level in percent");
+/// ```
+#[cfg(feature = "rich-docs")]
+#[macro_export]
+macro_rules! doc_str {
+    ($doc:expr) => {
+        $doc
+    };
+}
+
+/// A documentation string that is only kept with the `rich-docs` feature.
+///
+/// ```
+/// use esp_gatt_rs_demo::doc_str;
+///
+/// let doc: &'static str = doc_str!("Battery level in percent");
+/// assert!(doc.is_empty() || doc == "Battery level in percent");
+/// ```
+#[cfg(not(feature = "rich-docs"))]
+#[macro_export]
+macro_rules! doc_str {
+    ($doc:expr) => {
+        ""
+    };
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SpecError {
+    DuplicateService(Uuid),
+    DuplicateCharacteristic {
+        service: Uuid,
+        characteristic: Uuid,
+    },
+    UnknownHandler {
+        characteristic: Uuid,
+        name: String,
+    },
+    OverBudget {
+        service: Uuid,
+        required: u16,
+        available: u16,
+    },
+    ZeroMaxLen {
+        service: Uuid,
+        characteristic: Uuid,
+    },
+    /// Notify or Indicate declared without a CCCD to enable it.
+    MissingCccd {
+        service: Uuid,
+        characteristic: Uuid,
+    },
+    /// A CCCD on a characteristic that cannot notify or indicate.
+    UnusedCccd {
+        service: Uuid,
+        characteristic: Uuid,
+    },
+    /// A property the permissions do not allow.
+    MissingPermission {
+        service: Uuid,
+        characteristic: Uuid,
+        prop: CharProp,
+    },
+    /// A permission no property makes use of.
+    UnusedPermission {
+        service: Uuid,
+        characteristic: Uuid,
+        perm: CharPerm,
+    },
+    /// Empty, or longer than [`MAX_DEVICE_NAME_LEN`] bytes.
+    InvalidDeviceName {
+        len: usize,
+    },
+    /// A user description longer than [`MAX_ATTRIBUTE_LEN`] bytes.
+    DescriptionTooLong {
+        service: Uuid,
+        characteristic: Uuid,
+        len: usize,
+    },
+}
+
+impl fmt::Display for SpecError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::DuplicateService(uuid) => write!(f, "service {uuid:?} declared twice"),
+            Self::DuplicateCharacteristic {
+                service,
+                characteristic,
+            } => write!(
+                f,
+                "characteristic {characteristic:?} declared twice in service {service:?}"
+            ),
+            Self::UnknownHandler {
+                characteristic,
+                name,
+            } => write!(
+                f,
+                "characteristic {characteristic:?} is bound to unknown handler {name:?}"
+            ),
+            Self::OverBudget {
+                service,
+                required,
+                available,
+            } => write!(
+                f,
+                "service {service:?} needs {required} handles but only {available} are reserved"
+            ),
+            Self::ZeroMaxLen {
+                service,
+                characteristic,
+            } => write!(
+                f,
+                "characteristic {characteristic:?} in service {service:?} has max_len 0"
+            ),
+            Self::MissingCccd {
+                service,
+                characteristic,
+            } => write!(
+                f,
+                "characteristic {characteristic:?} in service {service:?} notifies or indicates but has no CCCD"
+            ),
+            Self::UnusedCccd {
+                service,
+                characteristic,
+            } => write!(
+                f,
+                "characteristic {characteristic:?} in service {service:?} has a CCCD but neither notifies nor indicates"
+            ),
+            Self::MissingPermission {
+                service,
+                characteristic,
+                prop,
+            } => write!(
+                f,
+                "characteristic {characteristic:?} in service {service:?} declares {prop:?} without a permission allowing it"
+            ),
+            Self::UnusedPermission {
+                service,
+                characteristic,
+                perm,
+            } => write!(
+                f,
+                "characteristic {characteristic:?} in service {service:?} grants {perm:?} but no property uses it"
+            ),
+            Self::InvalidDeviceName { len } => write!(
+                f,
+                "device name is {len} bytes, must be 1 to {MAX_DEVICE_NAME_LEN}"
+            ),
+            Self::DescriptionTooLong {
+                service,
+                characteristic,
+                len,
+            } => write!(
+                f,
+                "user description of characteristic {characteristic:?} in service {service:?} is {len} bytes, at most {MAX_ATTRIBUTE_LEN} fit"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for SpecError {}
+
+impl CharacteristicSpec {
+    pub fn shape(&self) -> CharShape {
+        CharShape::new(self.cccd, self.description().is_some())
+    }
+
+    /// The 0x2901 value: the user description, else the doc string. `None`
+    /// when both are empty, so no empty descriptor is created.
+    pub fn description(&self) -> Option<&str> {
+        self.user_description
+            .as_deref()
+            .or(Some(self.doc))
+            .filter(|description| !description.is_empty())
+    }
+
+    /// Security to enforce on sends. Unless set explicitly, a value only
+    /// readable over an encrypted link is not pushed over a weaker one
+    /// either, so reads and sends agree.
+    pub fn send_security(&self) -> Option<SendSecurity> {
+        self.send_security.or_else(|| {
+            self.perms
+                .contains(&CharPerm::ReadEncrypted)
+                .then_some(SendSecurity {
+                    min: SecurityLevel::Encrypted,
+                    on_insecure: OnInsecure::Refuse,
+                })
+        })
+    }
+
+    /// Checks an inbound read or write against the permissions and the
+    /// link's tracked security; the `Err` is the ATT status.
+    pub fn check_access(&self, write: bool, level: SecurityLevel) -> Result<(), u8> {
+        let (plain, encrypted, denied) = if write {
+            (
+                CharPerm::Write,
+                CharPerm::WriteEncrypted,
+                ATT_ERR_WRITE_NOT_PERMITTED,
+            )
+        } else {
+            (
+                CharPerm::Read,
+                CharPerm::ReadEncrypted,
+                ATT_ERR_READ_NOT_PERMITTED,
+            )
+        };
+
+        if self.perms.contains(&plain) {
+            Ok(())
+        } else if self.perms.contains(&encrypted) {
+            if level >= SecurityLevel::Encrypted {
+                Ok(())
+            } else {
+                Err(ATT_ERR_INSUFFICIENT_ENCRYPTION)
+            }
+        } else {
+            Err(denied)
+        }
+    }
+}
+
+const VERSION_ENCODING: &str = "major u8, minor u8, features u32 LE";
+
+impl ServiceSpec {
+    /// Adds the characteristics of a [`ProtocolVersion`] declaration: the
+    /// service's version for the client to read and the one the client
+    /// writes back, see [`super::negotiation`].
+    pub fn with_protocol_version(mut self, version: ProtocolVersion) -> Self {
+        self.protocol_version = Some(version);
+        self.characteristics.push(CharacteristicSpec {
+            uuid: SERVER_VERSION_UUID,
+            props: vec![CharProp::Read],
+            perms: vec![CharPerm::Read],
+            max_len: VERSION_LEN as u16,
+            cccd: false,
+            user_description: None,
+            doc: crate::doc_str!("Protocol version"),
+            encoding: Some(VERSION_ENCODING.into()),
+            handler: None,
+            max_subscribers: None,
+            send_security: None,
+        });
+        self.characteristics.push(CharacteristicSpec {
+            uuid: CLIENT_VERSION_UUID,
+            props: vec![CharProp::Write],
+            perms: vec![CharPerm::Write],
+            max_len: VERSION_LEN as u16,
+            cccd: false,
+            user_description: None,
+            doc: crate::doc_str!("Protocol version"),
+            encoding: Some(VERSION_ENCODING.into()),
+            handler: None,
+            max_subscribers: None,
+            send_security: None,
+        });
+        self
+    }
+
+    /// [`budget::service_handles`] over the characteristics' shapes, so the
+    /// runtime count and [`crate::assert_handle_budget!`] cannot disagree.
+    pub fn required_handles(&self) -> u16 {
+        let shapes: Vec<_> = self
+            .characteristics
+            .iter()
+            .map(CharacteristicSpec::shape)
+            .collect();
+
+        budget::service_handles(&shapes)
+    }
+
+    /// Handle count to pass to `create_service`.
+    pub fn num_handles(&self) -> u16 {
+        self.num_handles.unwrap_or_else(|| self.required_handles())
+    }
+}
+
+impl ServerSpec {
+    /// Capabilities this spec needs from the build; check them with
+    /// [`super::preflight::check_capabilities`].
+    pub fn required_capabilities(&self) -> Vec<Capability> {
+        let mut required = vec![Capability::Bluedroid];
+        if self.security != SecurityMode::None {
+            required.push(Capability::Security);
+        }
+        required
+    }
+
+    /// Checks the whole spec against every rule in [`rules`] and returns
+    /// all problems found, before anything is handed to the stack.
+    ///
+    /// `is_known_handler` tells whether a handler binding with the given name
+    /// has been registered.
+    pub fn validate(&self, is_known_handler: impl Fn(&str) -> bool) -> Result<(), Vec<SpecError>> {
+        let mut errors = rules::duplicate_services(&self.services);
+        errors.extend(rules::device_name(&self.device_name));
+
+        for service in &self.services {
+            errors.extend(rules::handle_budget(service));
+            errors.extend(rules::duplicate_characteristics(service));
+
+            for characteristic in &service.characteristics {
+                errors.extend(rules::max_len(service.uuid, characteristic));
+                errors.extend(rules::cccd(service.uuid, characteristic));
+                errors.extend(rules::permissions(service.uuid, characteristic));
+                errors.extend(rules::user_description(service.uuid, characteristic));
+                errors.extend(rules::handler_binding(characteristic, &is_known_handler));
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+/// The individual checks of [`ServerSpec::validate`].
+pub mod rules {
+    use std::collections::HashSet;
+
+    use super::{
+        CharPerm, CharProp, CharacteristicSpec, ServiceSpec, SpecError, MAX_ATTRIBUTE_LEN,
+        MAX_DEVICE_NAME_LEN,
+    };
+    use crate::proto::Uuid;
+
+    /// The name must fit the GAP limit, counted in bytes, not characters.
+    pub fn device_name(name: &str) -> Option<SpecError> {
+        (name.is_empty() || name.len() > MAX_DEVICE_NAME_LEN)
+            .then_some(SpecError::InvalidDeviceName { len: name.len() })
+    }
+
+    /// A 0x2901 value must fit one attribute, so it is never cut in the
+    /// middle of a character.
+    pub fn user_description(
+        service: Uuid,
+        characteristic: &CharacteristicSpec,
+    ) -> Option<SpecError> {
+        let len = characteristic.description()?.len();
+
+        (len > MAX_ATTRIBUTE_LEN).then_some(SpecError::DescriptionTooLong {
+            service,
+            characteristic: characteristic.uuid,
+            len,
+        })
+    }
+
+    /// A service UUID may be declared once.
+    pub fn duplicate_services(services: &[ServiceSpec]) -> Vec<SpecError> {
+        let mut seen = HashSet::new();
+
+        services
+            .iter()
+            .filter(|service| !seen.insert(service.uuid))
+            .map(|service| SpecError::DuplicateService(service.uuid))
+            .collect()
+    }
+
+    /// A characteristic UUID may be declared once per service.
+    pub fn duplicate_characteristics(service: &ServiceSpec) -> Vec<SpecError> {
+        let mut seen = HashSet::new();
+
+        service
+            .characteristics
+            .iter()
+            .filter(|characteristic| !seen.insert(characteristic.uuid))
+            .map(|characteristic| SpecError::DuplicateCharacteristic {
+                service: service.uuid,
+                characteristic: characteristic.uuid,
+            })
+            .collect()
+    }
+
+    /// The reserved handles must cover the declared attributes.
+    pub fn handle_budget(service: &ServiceSpec) -> Option<SpecError> {
+        let required = service.required_handles();
+        let available = service.num_handles();
+
+        (available < required).then_some(SpecError::OverBudget {
+            service: service.uuid,
+            required,
+            available,
+        })
+    }
+
+    pub fn max_len(service: Uuid, characteristic: &CharacteristicSpec) -> Option<SpecError> {
+        (characteristic.max_len == 0).then_some(SpecError::ZeroMaxLen {
+            service,
+            characteristic: characteristic.uuid,
+        })
+    }
+
+    /// Notify and Indicate need a CCCD, and a CCCD needs one of them.
+    pub fn cccd(service: Uuid, characteristic: &CharacteristicSpec) -> Option<SpecError> {
+        let pushes = characteristic
+            .props
+            .iter()
+            .any(|prop| matches!(prop, CharProp::Notify | CharProp::Indicate));
+
+        match (pushes, characteristic.cccd) {
+            (true, false) => Some(SpecError::MissingCccd {
+                service,
+                characteristic: characteristic.uuid,
+            }),
+            (false, true) => Some(SpecError::UnusedCccd {
+                service,
+                characteristic: characteristic.uuid,
+            }),
+            _ => None,
+        }
+    }
+
+    /// Read needs a read permission and Write/WriteNoResponse a write
+    /// permission; each permission needs a property using it.
+    pub fn permissions(service: Uuid, characteristic: &CharacteristicSpec) -> Vec<SpecError> {
+        let has_prop = |props: &[CharProp]| characteristic.props.iter().any(|p| props.contains(p));
+        let has_perm = |perms: &[CharPerm]| characteristic.perms.iter().any(|p| perms.contains(p));
+        let read_perms = [CharPerm::Read, CharPerm::ReadEncrypted];
+        let write_perms = [CharPerm::Write, CharPerm::WriteEncrypted];
+        let write_props = [CharProp::Write, CharProp::WriteNoResponse];
+
+        let mut errors = Vec::new();
+        for &prop in &characteristic.props {
+            let allowed = match prop {
+                CharProp::Read => has_perm(&read_perms),
+                CharProp::Write | CharProp::WriteNoResponse => has_perm(&write_perms),
+                CharProp::Notify | CharProp::Indicate => true,
+            };
+            if !allowed {
+                errors.push(SpecError::MissingPermission {
+                    service,
+                    characteristic: characteristic.uuid,
+                    prop,
+                });
+            }
+        }
+        for &perm in &characteristic.perms {
+            let used = match perm {
+                CharPerm::Read | CharPerm::ReadEncrypted => has_prop(&[CharProp::Read]),
+                CharPerm::Write | CharPerm::WriteEncrypted => has_prop(&write_props),
+            };
+            if !used {
+                errors.push(SpecError::UnusedPermission {
+                    service,
+                    characteristic: characteristic.uuid,
+                    perm,
+                });
+            }
+        }
+
+        errors
+    }
+
+    /// A named handler binding must be registered.
+    pub fn handler_binding(
+        characteristic: &CharacteristicSpec,
+        is_known_handler: impl Fn(&str) -> bool,
+    ) -> Option<SpecError> {
+        let name = characteristic.handler.as_ref()?;
+
+        (!is_known_handler(name)).then(|| SpecError::UnknownHandler {
+            characteristic: characteristic.uuid,
+            name: name.clone(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SERVICE: Uuid = Uuid::Uuid16(0x180F);
+    const LEVEL: Uuid = Uuid::Uuid16(0x2A19);
+    const COMMAND: Uuid = Uuid::Uuid128(0x0000_1524_1212_efde_1523_785f_eabc_d123);
+
+    fn characteristic(uuid: Uuid) -> CharacteristicSpec {
+        CharacteristicSpec {
+            uuid,
+            props: props![Read, Notify],
+            perms: perms![Read],
+            max_len: 1,
+            cccd: true,
+            user_description: Some("Battery level".into()),
+            doc: "",
+            encoding: Some("u8 percent".into()),
+            handler: Some("battery".into()),
+            max_subscribers: Some(2),
+            send_security: None,
+        }
+    }
+
+    fn spec() -> ServerSpec {
+        let mut command = characteristic(COMMAND);
+        command.props = props![Write];
+        command.perms = perms![WriteEncrypted];
+        command.cccd = false;
+        command.user_description = None;
+        command.handler = Some("command".into());
+
+        ServerSpec {
+            device_name: "sensor".into(),
+            advertising: AdvertisingSpec {
+                include_service_uuids: true,
+                appearance: Some(0x0540),
+                interval_ms: Some(100),
+            },
+            security: SecurityMode::Bonded,
+            services: vec![ServiceSpec {
+                uuid: SERVICE,
+                num_handles: None,
+                characteristics: vec![characteristic(LEVEL), command],
+                doc: "",
+                protocol_version: None,
+            }],
+        }
+    }
+
+    fn known(name: &str) -> bool {
+        matches!(name, "battery" | "command")
+    }
+
+    #[test]
+    fn valid_spec_passes() {
+        assert_eq!(spec().validate(known), Ok(()));
+    }
+
+    #[test]
+    fn reports_every_problem() {
+        let mut spec = spec();
+        spec.device_name.clear();
+        spec.services.push(spec.services[0].clone());
+
+        let service = &mut spec.services[0];
+        service.num_handles = Some(3);
+        service.characteristics[0].cccd = false;
+        service.characteristics[0].max_len = 0;
+        service.characteristics[1].handler = Some("missing".into());
+        service.characteristics.push(characteristic(LEVEL));
+
+        let errors = spec.validate(known).unwrap_err();
+        for expected in [
+            SpecError::DuplicateService(SERVICE),
+            SpecError::InvalidDeviceName { len: 0 },
+            SpecError::OverBudget {
+                service: SERVICE,
+                required: 10,
+                available: 3,
+            },
+            SpecError::DuplicateCharacteristic {
+                service: SERVICE,
+                characteristic: LEVEL,
+            },
+            SpecError::ZeroMaxLen {
+                service: SERVICE,
+                characteristic: LEVEL,
+            },
+            SpecError::MissingCccd {
+                service: SERVICE,
+                characteristic: LEVEL,
+            },
+            SpecError::UnknownHandler {
+                characteristic: COMMAND,
+                name: "missing".into(),
+            },
+        ] {
+            assert!(errors.contains(&expected), "{expected:?} not in {errors:?}");
+        }
+    }
+
+    #[test]
+    fn required_handles_match_const_budget() {
+        const SHAPES: [CharShape; 2] = [CharShape::new(true, true), CharShape::PLAIN];
+        const REQUIRED: u16 = budget::service_handles(&SHAPES);
+        crate::assert_handle_budget!(SHAPES, 7);
+
+        let service = &spec().services[0];
+        assert_eq!(service.required_handles(), REQUIRED);
+        assert_eq!(service.num_handles(), REQUIRED);
+        assert_eq!(rules::handle_budget(service), None);
+    }
+
+    #[test]
+    fn permissions_must_match_properties() {
+        let mut level = characteristic(LEVEL);
+        level.perms = perms![Write];
+
+        assert_eq!(
+            rules::permissions(SERVICE, &level),
+            vec![
+                SpecError::MissingPermission {
+                    service: SERVICE,
+                    characteristic: LEVEL,
+                    prop: CharProp::Read,
+                },
+                SpecError::UnusedPermission {
+                    service: SERVICE,
+                    characteristic: LEVEL,
+                    perm: CharPerm::Write,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn encrypted_read_is_enforced() {
+        let mut level = characteristic(LEVEL);
+        level.perms = perms![ReadEncrypted];
+
+        assert_eq!(
+            level.check_access(false, SecurityLevel::None),
+            Err(ATT_ERR_INSUFFICIENT_ENCRYPTION)
+        );
+        assert_eq!(level.check_access(false, SecurityLevel::Encrypted), Ok(()));
+        assert_eq!(
+            level.check_access(true, SecurityLevel::Encrypted),
+            Err(ATT_ERR_WRITE_NOT_PERMITTED)
+        );
+        assert_eq!(
+            level.send_security().map(|security| security.min),
+            Some(SecurityLevel::Encrypted)
+        );
+    }
+
+    fn name_errors(name: String) -> Vec<SpecError> {
+        let mut spec = spec();
+        spec.device_name = name;
+        spec.validate(known)
+            .err()
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|error| matches!(error, SpecError::InvalidDeviceName { .. }))
+            .collect()
+    }
+
+    #[test]
+    fn device_name_limit_counts_bytes() {
+        // Two bytes per "é", four per "🌡".
+        assert_eq!(name_errors("é".repeat(124)), []);
+        assert_eq!(
+            name_errors("é".repeat(124) + "a"),
+            [SpecError::InvalidDeviceName { len: 249 }]
+        );
+        assert_eq!(name_errors("🌡".repeat(62)), []);
+        assert_eq!(
+            name_errors("🌡".repeat(63)),
+            [SpecError::InvalidDeviceName { len: 252 }]
+        );
+        // Under the limit in characters, over it in bytes.
+        assert_eq!(
+            name_errors("🌡".repeat(100)),
+            [SpecError::InvalidDeviceName { len: 400 }]
+        );
+    }
+
+    #[test]
+    fn user_description_limit_counts_bytes() {
+        let described = |description: String| {
+            let mut characteristic = characteristic(LEVEL);
+            characteristic.user_description = Some(description);
+            rules::user_description(SERVICE, &characteristic)
+        };
+
+        assert_eq!(described("🌡".repeat(128)), None);
+        assert_eq!(described("🌡".repeat(127) + "éé"), None);
+        assert_eq!(
+            described("🌡".repeat(128) + "é"),
+            Some(SpecError::DescriptionTooLong {
+                service: SERVICE,
+                characteristic: LEVEL,
+                len: 514,
+            })
+        );
+    }
+
+    /// Only referenced here, so the marker is in the test binary only if
+    /// `doc_str!` keeps it.
+    const SIZE_MARKER: &str = crate::doc_str!("rich-docs size marker 7f3a");
+
+    #[test]
+    fn doc_strings_are_compiled_out_without_rich_docs() {
+        let service = spec().services.remove(0);
+        let service = service.with_protocol_version(ProtocolVersion::new(1, 0, 0));
+        let version = service.characteristics.last().unwrap();
+        assert_eq!(version.doc.is_empty(), !cfg!(feature = "rich-docs"));
+
+        // Assembled at run time so that the needle itself is not a literal
+        // in the binary.
+        let marker = ["rich-docs", "size", "marker", "7f3a"].join(" ");
+        let binary = std::fs::read(std::env::current_exe().unwrap()).unwrap();
+        let in_binary = binary
+            .windows(marker.len())
+            .any(|window| window == marker.as_bytes());
+
+        assert_eq!(std::hint::black_box(SIZE_MARKER).is_empty(), !in_binary);
+        assert_eq!(in_binary, cfg!(feature = "rich-docs"));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn json_round_trip() {
+        let spec = spec();
+        let json = serde_json::to_string(&spec).unwrap();
+        assert_eq!(serde_json::from_str::<ServerSpec>(&json).unwrap(), spec);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn json_defaults_optional_fields() {
+        let json = r#"{
+            "device_name": "sensor",
+            "services": [{
+                "uuid": { "Uuid16": 6159 },
+                "characteristics": [{
+                    "uuid": { "Uuid16": 10777 },
+                    "props": ["Read"],
+                    "perms": ["Read"],
+                    "max_len": 1
+                }]
+            }]
+        }"#;
+
+        let spec: ServerSpec = serde_json::from_str(json).unwrap();
+        assert_eq!(spec.security, SecurityMode::None);
+        assert_eq!(spec.advertising, AdvertisingSpec::default());
+        assert_eq!(spec.services[0].characteristics[0].handler, None);
+        assert_eq!(spec.validate(|_| false), Ok(()));
+    }
+}
@@ -0,0 +1,331 @@
+//! Protection against back-to-back reconnection storms.
+//!
+//! A central that connects, fails its handshake and reconnects several
+//! times a second costs a session, log lines and an advertising restart
+//! per cycle. [`StormGuard`] counts connection attempts per address over a
+//! sliding window; past the threshold the address is penalized and its
+//! connections are dropped immediately, without a session, for a penalty
+//! that doubles with every repeat offence. Only the start of a penalty is
+//! logged and raised as a [`HealthAlert`]. The penalty level decays by one
+//! per quiet `decay` period.
+//!
+//! While any penalty is active, [`StormGuard::keep_advertising`] tells the
+//! caller to leave advertising running across the rejected connections
+//! rather than stopping and restarting it for each one.
+
+use core::time::Duration;
+use std::collections::VecDeque;
+use std::time::Instant;
+
+use super::health::HealthAlert;
+use super::PeerAddr;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct StormPolicy {
+    pub window: Duration,
+    /// Attempts allowed within `window`; one more starts a penalty.
+    pub max_attempts: u32,
+    pub base_penalty: Duration,
+    pub max_penalty: Duration,
+    /// Quiet time after a penalty that lowers the level by one.
+    pub decay: Duration,
+    pub exempt_bonded: bool,
+    /// Addresses tracked at once; the least recently seen is forgotten.
+    pub max_peers: usize,
+}
+
+impl Default for StormPolicy {
+    fn default() -> Self {
+        Self {
+            window: Duration::from_secs(10),
+            max_attempts: 5,
+            base_penalty: Duration::from_secs(5),
+            max_penalty: Duration::from_secs(300),
+            decay: Duration::from_secs(60),
+            exempt_bonded: true,
+            max_peers: 16,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum StormDecision {
+    Admit,
+    /// A penalty starts now; log it and raise the alert.
+    Penalized {
+        until: Instant,
+        alert: HealthAlert,
+    },
+    /// Still penalized; disconnect quietly.
+    Ignored {
+        until: Instant,
+    },
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct StormStats {
+    pub penalties: u64,
+    pub ignored: u64,
+}
+
+#[derive(Debug)]
+struct Peer {
+    addr: PeerAddr,
+    attempts: VecDeque<Instant>,
+    level: u32,
+    penalty_until: Option<Instant>,
+    /// When the level last changed, for decay.
+    level_at: Instant,
+}
+
+#[derive(Debug)]
+pub struct StormGuard {
+    policy: StormPolicy,
+    /// Most recently seen last.
+    peers: VecDeque<Peer>,
+    stats: StormStats,
+}
+
+impl StormGuard {
+    pub fn new(policy: StormPolicy) -> Self {
+        Self {
+            policy,
+            peers: VecDeque::new(),
+            stats: StormStats::default(),
+        }
+    }
+
+    pub fn stats(&self) -> StormStats {
+        self.stats
+    }
+
+    /// Checks a new connection before any session is created for it.
+    pub fn on_connect(&mut self, addr: PeerAddr, bonded: bool, now: Instant) -> StormDecision {
+        if bonded && self.policy.exempt_bonded {
+            return StormDecision::Admit;
+        }
+
+        let mut peer = match self.peers.iter().position(|peer| peer.addr == addr) {
+            Some(index) => self.peers.remove(index).expect("index in range"),
+            None => Peer {
+                addr,
+                attempts: VecDeque::new(),
+                level: 0,
+                penalty_until: None,
+                level_at: now,
+            },
+        };
+        let decision = self.check(&mut peer, now);
+
+        if self.peers.len() >= self.policy.max_peers {
+            self.peers.pop_front();
+        }
+        self.peers.push_back(peer);
+        decision
+    }
+
+    fn check(&mut self, peer: &mut Peer, now: Instant) -> StormDecision {
+        if let Some(until) = peer.penalty_until.filter(|&until| now < until) {
+            self.stats.ignored += 1;
+            return StormDecision::Ignored { until };
+        }
+
+        let quiet_since = peer
+            .penalty_until
+            .map_or(peer.level_at, |until| until.max(peer.level_at));
+        if peer.level > 0 && !self.policy.decay.is_zero() {
+            let quiet = now.saturating_duration_since(quiet_since);
+            let periods = quiet.as_millis() / self.policy.decay.as_millis();
+            if periods > 0 {
+                peer.level = peer.level.saturating_sub(periods as u32);
+                peer.level_at = now;
+            }
+        }
+
+        peer.attempts.push_back(now);
+        while peer
+            .attempts
+            .front()
+            .is_some_and(|&at| now.saturating_duration_since(at) >= self.policy.window)
+        {
+            peer.attempts.pop_front();
+        }
+
+        let attempts = peer.attempts.len() as u32;
+        if attempts <= self.policy.max_attempts {
+            return StormDecision::Admit;
+        }
+
+        let penalty = self
+            .policy
+            .base_penalty
+            .saturating_mul(1 << peer.level.min(16))
+            .min(self.policy.max_penalty);
+        let until = now + penalty;
+
+        peer.level += 1;
+        peer.level_at = now;
+        peer.penalty_until = Some(until);
+        peer.attempts.clear();
+        self.stats.penalties += 1;
+
+        StormDecision::Penalized {
+            until,
+            alert: HealthAlert::ReconnectStorm {
+                peer: peer.addr,
+                attempts,
+                penalty,
+            },
+        }
+    }
+
+    /// Whether some address is penalized now.
+    pub fn keep_advertising(&self, now: Instant) -> bool {
+        self.peers
+            .iter()
+            .any(|peer| peer.penalty_until.is_some_and(|until| now < until))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PEER: PeerAddr = [1, 2, 3, 4, 5, 6];
+
+    fn policy() -> StormPolicy {
+        StormPolicy {
+            window: Duration::from_secs(10),
+            max_attempts: 3,
+            base_penalty: Duration::from_secs(5),
+            max_penalty: Duration::from_secs(15),
+            decay: Duration::from_secs(60),
+            exempt_bonded: true,
+            max_peers: 4,
+        }
+    }
+
+    fn secs(start: Instant, secs: u64) -> Instant {
+        start + Duration::from_secs(secs)
+    }
+
+    /// Connects at each of `times`, expecting all to be admitted.
+    fn admitted(guard: &mut StormGuard, start: Instant, times: &[u64]) {
+        for &at in times {
+            assert_eq!(
+                guard.on_connect(PEER, false, secs(start, at)),
+                StormDecision::Admit,
+                "attempt at {at}s"
+            );
+        }
+    }
+
+    fn penalty(decision: StormDecision) -> Duration {
+        match decision {
+            StormDecision::Penalized {
+                alert: HealthAlert::ReconnectStorm { penalty, .. },
+                ..
+            } => penalty,
+            other => panic!("expected a penalty, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn attempts_older_than_the_window_slide_out() {
+        let start = Instant::now();
+        let mut guard = StormGuard::new(policy());
+
+        admitted(&mut guard, start, &[0, 4, 8]);
+        // The attempt at 0 s is out of the window at 10 s.
+        admitted(&mut guard, start, &[10, 14]);
+
+        let decision = guard.on_connect(PEER, false, secs(start, 16));
+        assert_eq!(
+            decision,
+            StormDecision::Penalized {
+                until: secs(start, 21),
+                alert: HealthAlert::ReconnectStorm {
+                    peer: PEER,
+                    attempts: 4,
+                    penalty: Duration::from_secs(5),
+                },
+            }
+        );
+    }
+
+    #[test]
+    fn penalized_peer_is_ignored_until_the_penalty_ends() {
+        let start = Instant::now();
+        let mut guard = StormGuard::new(policy());
+        admitted(&mut guard, start, &[0, 0, 0]);
+        guard.on_connect(PEER, false, secs(start, 1));
+
+        assert!(guard.keep_advertising(secs(start, 5)));
+        assert_eq!(
+            guard.on_connect(PEER, false, secs(start, 5)),
+            StormDecision::Ignored {
+                until: secs(start, 6)
+            }
+        );
+        assert_eq!(
+            guard.stats(),
+            StormStats {
+                penalties: 1,
+                ignored: 1
+            }
+        );
+
+        assert!(!guard.keep_advertising(secs(start, 6)));
+        admitted(&mut guard, start, &[6]);
+    }
+
+    /// Connects four times at `at`: three admitted, then a penalty.
+    fn storm(guard: &mut StormGuard, start: Instant, at: u64) -> Duration {
+        admitted(guard, start, &[at, at, at]);
+        penalty(guard.on_connect(PEER, false, secs(start, at)))
+    }
+
+    #[test]
+    fn repeat_offences_escalate_up_to_the_cap() {
+        let start = Instant::now();
+        let mut guard = StormGuard::new(policy());
+
+        assert_eq!(storm(&mut guard, start, 0), Duration::from_secs(5));
+        assert_eq!(storm(&mut guard, start, 5), Duration::from_secs(10));
+        assert_eq!(storm(&mut guard, start, 15), Duration::from_secs(15));
+        assert_eq!(storm(&mut guard, start, 30), Duration::from_secs(15));
+        assert_eq!(guard.stats().penalties, 4);
+    }
+
+    #[test]
+    fn penalty_level_decays_when_quiet() {
+        let start = Instant::now();
+
+        let mut guard = StormGuard::new(policy());
+        storm(&mut guard, start, 0);
+        assert_eq!(storm(&mut guard, start, 20), Duration::from_secs(10));
+
+        // A full decay period after the first penalty ended at 5 s.
+        let mut guard = StormGuard::new(policy());
+        storm(&mut guard, start, 0);
+        assert_eq!(storm(&mut guard, start, 65), Duration::from_secs(5));
+    }
+
+    #[test]
+    fn bonded_peers_can_be_exempt() {
+        let start = Instant::now();
+        let mut guard = StormGuard::new(policy());
+        for _ in 0..10 {
+            assert_eq!(guard.on_connect(PEER, true, start), StormDecision::Admit);
+        }
+
+        let mut guard = StormGuard::new(StormPolicy {
+            exempt_bonded: false,
+            ..policy()
+        });
+        for _ in 0..3 {
+            guard.on_connect(PEER, true, start);
+        }
+        penalty(guard.on_connect(PEER, true, start));
+    }
+}
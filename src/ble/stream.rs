@@ -0,0 +1,176 @@
+//! Resumable application-level streams.
+//!
+//! Streams of bonded peers keep a small backlog of recent records keyed by
+//! the peer identity address and a stream id. When the same peer reconnects
+//! and resubscribes, the stream service asks for the records after the last
+//! one the client acknowledged and replays them before sending new data.
+//! Unbonded peers are never registered.
+
+use std::collections::{HashMap, VecDeque};
+
+use crate::proto::seq;
+
+use super::PeerAddr;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct StreamKey {
+    /// Identity address of the bonded peer.
+    pub peer: PeerAddr,
+    pub stream_id: u16,
+}
+
+impl StreamKey {
+    pub const fn new(peer: PeerAddr, stream_id: u16) -> Self {
+        Self { peer, stream_id }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct BacklogLimits {
+    pub max_records_per_stream: usize,
+    pub max_bytes_per_stream: usize,
+    /// Bound over all streams; the least recently used streams are evicted.
+    pub max_total_bytes: usize,
+}
+
+impl Default for BacklogLimits {
+    fn default() -> Self {
+        Self {
+            max_records_per_stream: 64,
+            max_bytes_per_stream: 4096,
+            max_total_bytes: 16384,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Backlog {
+    records: VecDeque<(u32, Vec<u8>)>,
+    bytes: usize,
+    last_used: u64,
+}
+
+impl Backlog {
+    fn pop_front(&mut self) -> usize {
+        let len = self.records.pop_front().map_or(0, |(_, data)| data.len());
+        self.bytes -= len;
+        len
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct StreamRegistry {
+    limits: BacklogLimits,
+    streams: HashMap<StreamKey, Backlog>,
+    total_bytes: usize,
+    tick: u64,
+}
+
+impl StreamRegistry {
+    pub fn new(limits: BacklogLimits) -> Self {
+        Self {
+            limits,
+            ..Default::default()
+        }
+    }
+
+    /// Records a sent record in the stream's backlog.
+    pub fn push(&mut self, key: StreamKey, seq: u32, data: &[u8]) {
+        if data.len() > self.limits.max_bytes_per_stream {
+            return;
+        }
+
+        self.tick += 1;
+
+        let backlog = self.streams.entry(key).or_default();
+        backlog.last_used = self.tick;
+        backlog.bytes += data.len();
+        backlog.records.push_back((seq, data.to_vec()));
+        self.total_bytes += data.len();
+
+        while backlog.records.len() > self.limits.max_records_per_stream
+            || backlog.bytes > self.limits.max_bytes_per_stream
+        {
+            self.total_bytes -= backlog.pop_front();
+        }
+
+        self.evict(key);
+    }
+
+    /// Drops every record up to and including `acked_seq`.
+    pub fn ack(&mut self, key: StreamKey, acked_seq: u32) {
+        let Some(backlog) = self.streams.get_mut(&key) else {
+            return;
+        };
+
+        while backlog
+            .records
+            .front()
+            .is_some_and(|(seq, _)| !seq::is_after(*seq, acked_seq))
+        {
+            self.total_bytes -= backlog.pop_front();
+        }
+    }
+
+    /// Records to replay after `last_acked_seq`, oldest first.
+    pub fn resume(&mut self, key: StreamKey, last_acked_seq: u32) -> Vec<(u32, Vec<u8>)> {
+        self.ack(key, last_acked_seq);
+
+        self.tick += 1;
+        let tick = self.tick;
+
+        self.streams
+            .get_mut(&key)
+            .map(|backlog| {
+                backlog.last_used = tick;
+                backlog.records.iter().cloned().collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Forgets all streams of a peer, e.g. when its bond is removed.
+    pub fn remove_peer(&mut self, peer: &PeerAddr) {
+        let total_bytes = &mut self.total_bytes;
+        self.streams.retain(|key, backlog| {
+            let keep = key.peer != *peer;
+            if !keep {
+                *total_bytes -= backlog.bytes;
+            }
+            keep
+        });
+    }
+
+    pub fn total_bytes(&self) -> usize {
+        self.total_bytes
+    }
+
+    pub fn len(&self) -> usize {
+        self.streams.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.streams.is_empty()
+    }
+
+    /// Evicts least recently used streams other than `current` until the
+    /// total bound holds, falling back to trimming `current` itself.
+    fn evict(&mut self, current: StreamKey) {
+        while self.total_bytes > self.limits.max_total_bytes {
+            let oldest = self
+                .streams
+                .iter()
+                .filter(|(key, _)| **key != current)
+                .min_by_key(|(_, backlog)| backlog.last_used)
+                .map(|(key, _)| *key);
+
+            if let Some(backlog) = oldest.and_then(|oldest| self.streams.remove(&oldest)) {
+                self.total_bytes -= backlog.bytes;
+            } else if let Some(backlog) = self.streams.get_mut(&current) {
+                // Only the current stream is left, trim it instead.
+                self.total_bytes -= backlog.pop_front();
+            } else {
+                break;
+            }
+        }
+    }
+}
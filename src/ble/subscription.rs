@@ -0,0 +1,185 @@
+//! CCCD subscription state and notify/indicate selection.
+//!
+//! A characteristic may declare both Notify and Indicate; the client then
+//! decides through its CCCD which one it wants. [`Subscriptions`] keeps both
+//! bits per connection and characteristic, and [`Subscriptions::delivery`]
+//! picks how a value is sent. Only [`Delivery::Indicate`] needs confirmation
+//! tracking.
+//!
+//! A characteristic can limit how many connections subscribe at once; a CCCD
+//! write enabling a subscription beyond the limit is rejected and not
+//! recorded.
+
+use core::fmt;
+use std::collections::HashMap;
+
+use crate::proto::CccdFlags;
+
+/// ATT application error for a CCCD write beyond the subscriber limit.
+pub const ATT_ERR_TOO_MANY_SUBSCRIBERS: u8 = 0x82;
+
+/// How a send call wants the value delivered.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum SendMode {
+    /// Whichever the client enabled, preferring notifications.
+    #[default]
+    Auto,
+    Notify,
+    Indicate,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Delivery {
+    Notify,
+    Indicate,
+}
+
+impl Delivery {
+    pub fn needs_confirm(self) -> bool {
+        self == Self::Indicate
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct SubscriberLimit {
+    pub max: u8,
+    /// ATT error returned for the rejected CCCD write.
+    pub reject_code: u8,
+}
+
+impl SubscriberLimit {
+    pub const fn new(max: u8) -> Self {
+        Self {
+            max,
+            reject_code: ATT_ERR_TOO_MANY_SUBSCRIBERS,
+        }
+    }
+}
+
+/// A CCCD write refused because the characteristic is at its limit. The
+/// owning service is told through this so it can report "busy" elsewhere.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct SubscriptionRejected {
+    pub conn_id: u16,
+    pub handle: u16,
+    pub code: u8,
+}
+
+impl fmt::Display for SubscriptionRejected {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "subscription of connection {} to handle {} rejected, subscriber limit reached",
+            self.conn_id, self.handle
+        )
+    }
+}
+
+impl std::error::Error for SubscriptionRejected {}
+
+#[derive(Debug, Default)]
+pub struct Subscriptions {
+    /// CCCD value per `(conn_id, handle)`; cleared entries are removed.
+    cccds: HashMap<(u16, u16), CccdFlags>,
+    limits: HashMap<u16, SubscriberLimit>,
+    rejected: u64,
+}
+
+impl Subscriptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set_limit(&mut self, handle: u16, limit: SubscriberLimit) {
+        self.limits.insert(handle, limit);
+    }
+
+    /// Number of rejected subscriptions so far.
+    pub fn rejected(&self) -> u64 {
+        self.rejected
+    }
+
+    /// Connections currently subscribed to `handle`.
+    pub fn subscriber_count(&self, handle: u16) -> usize {
+        self.cccds.keys().filter(|(_, h)| *h == handle).count()
+    }
+
+    /// Records a CCCD write, returning the previous value.
+    pub fn on_cccd_write(
+        &mut self,
+        conn_id: u16,
+        handle: u16,
+        flags: CccdFlags,
+    ) -> Result<CccdFlags, SubscriptionRejected> {
+        if flags.is_empty() {
+            return Ok(self.cccds.remove(&(conn_id, handle)).unwrap_or_default());
+        }
+
+        let already_subscribed = self.cccds.contains_key(&(conn_id, handle));
+        if let Some(limit) = self.limits.get(&handle) {
+            if !already_subscribed && self.subscriber_count(handle) >= usize::from(limit.max) {
+                self.rejected += 1;
+                return Err(SubscriptionRejected {
+                    conn_id,
+                    handle,
+                    code: limit.reject_code,
+                });
+            }
+        }
+
+        Ok(self
+            .cccds
+            .insert((conn_id, handle), flags)
+            .unwrap_or_default())
+    }
+
+    /// Subscribed connections of `handle` with their CCCD bits, by
+    /// connection id.
+    pub fn subscribers(&self, handle: u16) -> Vec<(u16, CccdFlags)> {
+        let mut subscribers: Vec<_> = self
+            .cccds
+            .iter()
+            .filter(|((_, h), _)| *h == handle)
+            .map(|(&(conn_id, _), &flags)| (conn_id, flags))
+            .collect();
+        subscribers.sort_unstable_by_key(|(conn_id, _)| *conn_id);
+        subscribers
+    }
+
+    /// Handles `conn_id` is subscribed to, in handle order.
+    pub fn subscriptions(&self, conn_id: u16) -> Vec<u16> {
+        let mut handles: Vec<_> = self
+            .cccds
+            .keys()
+            .filter(|(conn, _)| *conn == conn_id)
+            .map(|&(_, handle)| handle)
+            .collect();
+        handles.sort_unstable();
+        handles
+    }
+
+    pub fn flags(&self, conn_id: u16, handle: u16) -> CccdFlags {
+        self.cccds
+            .get(&(conn_id, handle))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// How to send to `conn_id`, or `None` if the client has not enabled
+    /// what `mode` asks for.
+    pub fn delivery(&self, conn_id: u16, handle: u16, mode: SendMode) -> Option<Delivery> {
+        let flags = self.flags(conn_id, handle);
+
+        match mode {
+            SendMode::Auto if flags.notify() => Some(Delivery::Notify),
+            SendMode::Auto if flags.indicate() => Some(Delivery::Indicate),
+            SendMode::Notify if flags.notify() => Some(Delivery::Notify),
+            SendMode::Indicate if flags.indicate() => Some(Delivery::Indicate),
+            _ => None,
+        }
+    }
+
+    pub fn on_disconnect(&mut self, conn_id: u16) {
+        self.cccds.retain(|(conn, _), _| *conn != conn_id);
+    }
+}
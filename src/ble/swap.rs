@@ -0,0 +1,105 @@
+//! Runtime replacement of a shared handler.
+//!
+//! A [`HandlerSlot`] holds the `Arc` a service's events are dispatched to.
+//! Dispatch takes a clone with [`HandlerSlot::load`] and runs against it, so
+//! a [`HandlerSlot::replace`] in the middle of a dispatch lets that dispatch
+//! finish on the old handler while the next event goes to the new one.
+//! Handles, connections and subscriptions are not the handler's and are
+//! unaffected.
+
+use std::sync::{Arc, PoisonError, RwLock};
+
+#[derive(Debug)]
+pub struct HandlerSlot<T: ?Sized> {
+    current: RwLock<Arc<T>>,
+}
+
+impl<T: ?Sized> HandlerSlot<T> {
+    pub fn new(handler: Arc<T>) -> Self {
+        Self {
+            current: RwLock::new(handler),
+        }
+    }
+
+    /// The handler to dispatch the next event to.
+    pub fn load(&self) -> Arc<T> {
+        self.current
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    /// Installs `handler`, returning the previous one.
+    pub fn replace(&self, handler: Arc<T>) -> Arc<T> {
+        let mut current = self.current.write().unwrap_or_else(PoisonError::into_inner);
+        core::mem::replace(&mut *current, handler)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use super::*;
+
+    trait WriteHandler: Send + Sync {
+        fn on_write(&self, value: &[u8]);
+    }
+
+    #[derive(Default)]
+    struct Recording(Mutex<Vec<Vec<u8>>>);
+
+    impl Recording {
+        fn writes(&self) -> Vec<Vec<u8>> {
+            self.0.lock().unwrap().clone()
+        }
+    }
+
+    impl WriteHandler for Recording {
+        fn on_write(&self, value: &[u8]) {
+            self.0.lock().unwrap().push(value.to_vec());
+        }
+    }
+
+    fn dispatch(slot: &HandlerSlot<dyn WriteHandler>, value: &[u8]) {
+        slot.load().on_write(value);
+    }
+
+    #[test]
+    fn writes_reach_the_handler_installed_at_dispatch() {
+        let old = Arc::new(Recording::default());
+        let new = Arc::new(Recording::default());
+        let slot: HandlerSlot<dyn WriteHandler> = HandlerSlot::new(old.clone());
+
+        dispatch(&slot, b"first");
+        let previous = slot.replace(new.clone());
+        dispatch(&slot, b"second");
+
+        assert!(Arc::ptr_eq(
+            &previous,
+            &(old.clone() as Arc<dyn WriteHandler>)
+        ));
+        assert_eq!(old.writes(), [b"first".to_vec()]);
+        assert_eq!(new.writes(), [b"second".to_vec()]);
+    }
+
+    #[test]
+    fn in_flight_dispatch_finishes_on_the_old_handler() {
+        let old = Arc::new(Recording::default());
+        let new = Arc::new(Recording::default());
+        let slot: HandlerSlot<dyn WriteHandler> = HandlerSlot::new(old.clone());
+
+        let in_flight = slot.load();
+        drop(slot.replace(new.clone()));
+        // The slot no longer holds the old handler; the dispatch does.
+        assert_eq!(Arc::strong_count(&old), 2);
+
+        in_flight.on_write(b"during");
+        dispatch(&slot, b"after");
+        drop(in_flight);
+
+        assert_eq!(Arc::strong_count(&old), 1);
+        assert_eq!(old.writes(), [b"during".to_vec()]);
+        assert_eq!(new.writes(), [b"after".to_vec()]);
+    }
+}
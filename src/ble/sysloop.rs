@@ -0,0 +1,123 @@
+//! BLE lifecycle events on the esp-idf system event loop.
+//!
+//! Firmware organized around `EspSystemEventLoop` subscriptions can react
+//! to BLE connections, subscriptions and alerts without a handler
+//! callback: a [`LifecycleBridge`] posts each [`BleLifecycleEvent`] under a
+//! custom event source, and any component subscribes with
+//! `sysloop.subscribe::<BleLifecycleEvent, _>(..)`.
+//!
+//! Posting never blocks the dispatch path. When the loop queue is full the
+//! event is dropped and counted in [`LifecycleBridge::dropped`].
+
+use super::health::HealthKind;
+use super::PeerAddr;
+
+/// Fixed-size record, copied by value into the event loop queue.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[repr(C)]
+pub enum BleLifecycleEvent {
+    Connected {
+        addr: PeerAddr,
+    },
+    Disconnected {
+        addr: PeerAddr,
+        /// HCI disconnect reason.
+        reason: u8,
+    },
+    Subscribed {
+        handle: u16,
+    },
+    ProvisioningDone,
+    HealthAlert {
+        kind: HealthKind,
+    },
+}
+
+#[cfg(feature = "esp")]
+mod bridge {
+    use core::ffi::CStr;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    use esp_idf_svc::eventloop::{
+        EspEvent, EspEventDeserializer, EspEventPostData, EspEventSerializer, EspEventSource,
+        EspSystemEventLoop,
+    };
+    use esp_idf_svc::hal::delay::NON_BLOCK;
+
+    use super::BleLifecycleEvent;
+
+    unsafe impl EspEventSource for BleLifecycleEvent {
+        fn source() -> Option<&'static CStr> {
+            Some(c"BLE-LIFECYCLE")
+        }
+    }
+
+    impl EspEventSerializer for BleLifecycleEvent {
+        type Data<'a> = BleLifecycleEvent;
+
+        fn serialize<F, R>(event: &Self::Data<'_>, f: F) -> R
+        where
+            F: FnOnce(&EspEventPostData) -> R,
+        {
+            // Safety: the payload is `Copy` and owns no pointers, so a byte
+            // copy in the loop queue is a valid value.
+            f(&unsafe { EspEventPostData::new(Self::source().unwrap(), Self::event_id(), event) })
+        }
+    }
+
+    impl EspEventDeserializer for BleLifecycleEvent {
+        type Data<'a> = BleLifecycleEvent;
+
+        fn deserialize<'a>(data: &EspEvent<'a>) -> Self::Data<'a> {
+            // Safety: only `serialize` posts under this source.
+            *unsafe { data.as_payload::<BleLifecycleEvent>() }
+        }
+    }
+
+    /// Posts lifecycle events to the system event loop.
+    pub struct LifecycleBridge {
+        sysloop: EspSystemEventLoop,
+        dropped: AtomicU32,
+    }
+
+    impl core::fmt::Debug for LifecycleBridge {
+        fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+            f.debug_struct("LifecycleBridge")
+                .field("dropped", &self.dropped())
+                .finish_non_exhaustive()
+        }
+    }
+
+    impl LifecycleBridge {
+        pub fn new(sysloop: EspSystemEventLoop) -> Self {
+            Self {
+                sysloop,
+                dropped: AtomicU32::new(0),
+            }
+        }
+
+        /// Posts without waiting; returns whether the event was queued.
+        pub fn post(&self, event: BleLifecycleEvent) -> bool {
+            let posted = match self.sysloop.post::<BleLifecycleEvent>(&event, NON_BLOCK) {
+                Ok(posted) => posted,
+                Err(err) => {
+                    log::warn!("posting {event:?} failed: {err}");
+                    false
+                }
+            };
+
+            if !posted {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+            }
+            posted
+        }
+
+        /// Events lost to a full queue or a failed post.
+        pub fn dropped(&self) -> u32 {
+            self.dropped.load(Ordering::Relaxed)
+        }
+    }
+}
+
+#[cfg(feature = "esp")]
+pub use bridge::LifecycleBridge;
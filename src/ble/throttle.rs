@@ -0,0 +1,358 @@
+//! Notification deduplication and rate limiting.
+//!
+//! A [`Throttle`] sits in front of the notify path. Per connection and
+//! characteristic it can skip payloads identical to the last one sent and
+//! coalesce bursts to at most one notification per `min_interval`. A
+//! coalesced value is never lost: the latest one is sent when the interval
+//! ends, see [`Throttle::poll`]. Indications are exempt.
+//!
+//! Payloads are held as shared `Arc<[u8]>` buffers, so
+//! [`Throttle::broadcast`] to many connections keeps one copy.
+
+use core::time::Duration;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Instant;
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct SendPolicy {
+    /// Skip a payload equal to the last one sent on that connection.
+    pub dedup: bool,
+    /// Send at most one notification per interval; zero disables throttling.
+    pub min_interval: Duration,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SendDecision {
+    /// Send the payload now.
+    Send,
+    /// Held back; it will be returned by [`Throttle::poll`] once due.
+    Deferred,
+    /// Dropped as a duplicate.
+    Suppressed,
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct ThrottleStats {
+    pub dedup_suppressed: u64,
+    /// Deferred payloads overwritten by a newer value before being sent.
+    pub coalesced: u64,
+}
+
+#[derive(Debug, Default)]
+struct Slot {
+    last_sent: Option<Arc<[u8]>>,
+    last_sent_at: Option<Instant>,
+    pending: Option<Arc<[u8]>>,
+}
+
+#[derive(Debug, Default)]
+pub struct Throttle {
+    policies: HashMap<u16, SendPolicy>,
+    slots: HashMap<(u16, u16), Slot>,
+    stats: ThrottleStats,
+}
+
+impl Throttle {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set_policy(&mut self, handle: u16, policy: SendPolicy) {
+        self.policies.insert(handle, policy);
+    }
+
+    pub fn stats(&self) -> ThrottleStats {
+        self.stats
+    }
+
+    /// Decides what to do with a notification of `payload`.
+    pub fn offer(
+        &mut self,
+        conn_id: u16,
+        handle: u16,
+        payload: &[u8],
+        indicate: bool,
+        now: Instant,
+    ) -> SendDecision {
+        if indicate || !self.policies.contains_key(&handle) {
+            return SendDecision::Send;
+        }
+
+        self.offer_shared(conn_id, handle, &Arc::from(payload), indicate, now)
+    }
+
+    /// [`offer`](Self::offer) for every connection in `conn_ids`, sharing a
+    /// single copy of `payload` between them.
+    pub fn broadcast(
+        &mut self,
+        conn_ids: impl IntoIterator<Item = u16>,
+        handle: u16,
+        payload: &[u8],
+        indicate: bool,
+        now: Instant,
+    ) -> Vec<(u16, SendDecision)> {
+        let payload = Arc::from(payload);
+
+        conn_ids
+            .into_iter()
+            .map(|conn_id| {
+                let decision = self.offer_shared(conn_id, handle, &payload, indicate, now);
+                (conn_id, decision)
+            })
+            .collect()
+    }
+
+    fn offer_shared(
+        &mut self,
+        conn_id: u16,
+        handle: u16,
+        payload: &Arc<[u8]>,
+        indicate: bool,
+        now: Instant,
+    ) -> SendDecision {
+        let Some(policy) = self.policies.get(&handle).copied() else {
+            return SendDecision::Send;
+        };
+
+        if indicate {
+            return SendDecision::Send;
+        }
+
+        let slot = self.slots.entry((conn_id, handle)).or_default();
+
+        if policy.dedup && slot.last_sent.as_deref() == Some(&**payload) {
+            // The client already has this value, a pending newer one is moot.
+            if slot.pending.take().is_some() {
+                self.stats.coalesced += 1;
+            }
+            self.stats.dedup_suppressed += 1;
+            return SendDecision::Suppressed;
+        }
+
+        let due = slot
+            .last_sent_at
+            .map_or(true, |at| now >= at + policy.min_interval);
+
+        if due {
+            slot.pending = None;
+            slot.last_sent = Some(payload.clone());
+            slot.last_sent_at = Some(now);
+            SendDecision::Send
+        } else {
+            if slot.pending.replace(payload.clone()).is_some() {
+                self.stats.coalesced += 1;
+            }
+            SendDecision::Deferred
+        }
+    }
+
+    /// Returns the deferred payloads whose interval has ended, as
+    /// `(conn_id, handle, payload)`, and records them as sent.
+    pub fn poll(&mut self, now: Instant) -> Vec<(u16, u16, Arc<[u8]>)> {
+        let mut due = Vec::new();
+
+        for (&(conn_id, handle), slot) in &mut self.slots {
+            let Some(policy) = self.policies.get(&handle) else {
+                continue;
+            };
+
+            let ready = slot
+                .last_sent_at
+                .map_or(true, |at| now >= at + policy.min_interval);
+
+            if ready {
+                if let Some(payload) = slot.pending.take() {
+                    slot.last_sent = Some(payload.clone());
+                    slot.last_sent_at = Some(now);
+                    due.push((conn_id, handle, payload));
+                }
+            }
+        }
+
+        due
+    }
+
+    /// Earliest instant at which [`poll`](Self::poll) has something to send.
+    pub fn next_due(&self) -> Option<Instant> {
+        self.slots
+            .iter()
+            .filter(|(_, slot)| slot.pending.is_some())
+            .filter_map(|((_, handle), slot)| {
+                let policy = self.policies.get(handle)?;
+                slot.last_sent_at.map(|at| at + policy.min_interval)
+            })
+            .min()
+    }
+
+    pub fn on_disconnect(&mut self, conn_id: u16) {
+        self.slots.retain(|(conn, _), _| *conn != conn_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_clock::MockClock;
+
+    const HANDLE: u16 = 0x2A;
+
+    fn throttle(dedup: bool, min_interval_ms: u64) -> Throttle {
+        let mut throttle = Throttle::new();
+        throttle.set_policy(
+            HANDLE,
+            SendPolicy {
+                dedup,
+                min_interval: Duration::from_millis(min_interval_ms),
+            },
+        );
+        throttle
+    }
+
+    #[test]
+    fn unthrottled_handles_and_indications_always_send() {
+        let clock = MockClock::new();
+        let mut throttle = throttle(true, 100);
+
+        for _ in 0..3 {
+            assert_eq!(
+                throttle.offer(1, HANDLE + 1, b"a", false, clock.ms(0)),
+                SendDecision::Send
+            );
+            assert_eq!(
+                throttle.offer(1, HANDLE, b"a", true, clock.ms(0)),
+                SendDecision::Send
+            );
+        }
+    }
+
+    #[test]
+    fn burst_is_coalesced_to_latest_value() {
+        let clock = MockClock::new();
+        let mut throttle = throttle(false, 100);
+
+        assert_eq!(
+            throttle.offer(1, HANDLE, b"1", false, clock.ms(0)),
+            SendDecision::Send
+        );
+        assert_eq!(
+            throttle.offer(1, HANDLE, b"2", false, clock.ms(10)),
+            SendDecision::Deferred
+        );
+        assert_eq!(
+            throttle.offer(1, HANDLE, b"3", false, clock.ms(20)),
+            SendDecision::Deferred
+        );
+        assert_eq!(throttle.next_due(), Some(clock.ms(100)));
+
+        assert!(throttle.poll(clock.ms(99)).is_empty());
+        let due = throttle.poll(clock.ms(100));
+        assert_eq!(due, vec![(1, HANDLE, Arc::from(&b"3"[..]))]);
+        assert_eq!(throttle.next_due(), None);
+        assert_eq!(throttle.stats().coalesced, 1);
+
+        assert_eq!(
+            throttle.offer(1, HANDLE, b"4", false, clock.ms(150)),
+            SendDecision::Deferred
+        );
+        assert_eq!(throttle.next_due(), Some(clock.ms(200)));
+        assert_eq!(
+            throttle.offer(1, HANDLE, b"5", false, clock.ms(200)),
+            SendDecision::Send
+        );
+        assert!(throttle.poll(clock.ms(300)).is_empty());
+    }
+
+    #[test]
+    fn duplicates_are_suppressed_and_cancel_pending() {
+        let clock = MockClock::new();
+        let mut throttle = throttle(true, 100);
+
+        assert_eq!(
+            throttle.offer(1, HANDLE, b"a", false, clock.ms(0)),
+            SendDecision::Send
+        );
+        assert_eq!(
+            throttle.offer(1, HANDLE, b"b", false, clock.ms(10)),
+            SendDecision::Deferred
+        );
+        assert_eq!(
+            throttle.offer(1, HANDLE, b"a", false, clock.ms(20)),
+            SendDecision::Suppressed
+        );
+        assert!(throttle.poll(clock.ms(100)).is_empty());
+        assert_eq!(
+            throttle.stats(),
+            ThrottleStats {
+                dedup_suppressed: 1,
+                coalesced: 1,
+            }
+        );
+    }
+
+    #[test]
+    fn broadcast_shares_one_buffer_per_connection() {
+        let clock = MockClock::new();
+        let mut throttle = throttle(false, 100);
+        throttle.offer(2, HANDLE, b"old", false, clock.ms(0));
+
+        let decisions = throttle.broadcast([1, 2, 3], HANDLE, b"new", false, clock.ms(50));
+        assert_eq!(
+            decisions,
+            vec![
+                (1, SendDecision::Send),
+                (2, SendDecision::Deferred),
+                (3, SendDecision::Send),
+            ]
+        );
+
+        let sent = &throttle.slots[&(1, HANDLE)].last_sent;
+        let pending = &throttle.slots[&(2, HANDLE)].pending;
+        assert!(Arc::ptr_eq(
+            sent.as_ref().unwrap(),
+            pending.as_ref().unwrap()
+        ));
+    }
+
+    #[test]
+    fn broadcast_allocations_do_not_grow_with_subscribers() {
+        let clock = MockClock::new();
+        let mut throttle = throttle(false, 100);
+        let payload = [0x5A; 20];
+
+        let mut measure = |conn_ids: Vec<u16>, at| {
+            // The first offer per connection creates its slot; not measured.
+            throttle.broadcast(conn_ids.iter().copied(), HANDLE, &payload, false, at);
+            let (allocations, decisions) = crate::test_alloc::allocations(|| {
+                throttle.broadcast(conn_ids, HANDLE, &payload, false, at)
+            });
+            assert!(decisions
+                .iter()
+                .all(|(_, decision)| *decision == SendDecision::Deferred));
+            allocations
+        };
+
+        let one = measure(vec![1], clock.ms(0));
+        let ten = measure((10..20).collect(), clock.ms(0));
+        // The shared buffer and the returned decisions, however many
+        // connections get the value.
+        assert_eq!(one, 2);
+        assert_eq!(ten, one);
+    }
+
+    #[test]
+    fn disconnect_drops_pending_values() {
+        let clock = MockClock::new();
+        let mut throttle = throttle(false, 100);
+        throttle.offer(1, HANDLE, b"1", false, clock.ms(0));
+        throttle.offer(1, HANDLE, b"2", false, clock.ms(10));
+
+        throttle.on_disconnect(1);
+        assert_eq!(throttle.next_due(), None);
+        assert!(throttle.poll(clock.ms(100)).is_empty());
+        assert_eq!(
+            throttle.offer(1, HANDLE, b"3", false, clock.ms(20)),
+            SendDecision::Send
+        );
+    }
+}
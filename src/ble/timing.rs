@@ -0,0 +1,106 @@
+//! Handler execution budgets.
+//!
+//! A slow handler delays every other connection's responses, so the
+//! dispatcher times each invocation and feeds the duration to
+//! [`HandlerTiming::record`]. Beyond the soft budget a warning names the
+//! service; beyond the optional hard budget the dispatcher should answer the
+//! pending request with an application error itself. Handlers cannot be
+//! preempted, so this only bounds how long the client waits.
+
+use core::time::Duration;
+use std::collections::HashMap;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum CallbackKind {
+    Read,
+    Write,
+    Subscribe,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Budget {
+    pub soft: Duration,
+    pub hard: Option<Duration>,
+}
+
+impl Default for Budget {
+    fn default() -> Self {
+        Self {
+            soft: Duration::from_millis(50),
+            hard: None,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum BudgetOutcome {
+    Within,
+    SoftExceeded,
+    /// Respond with an application error instead of waiting for the handler.
+    HardExceeded,
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct HandlerLatency {
+    pub invocations: u64,
+    pub worst: Duration,
+    pub soft_exceeded: u64,
+    /// The handler has exceeded its hard budget at least once.
+    pub slow: bool,
+}
+
+#[derive(Debug, Default)]
+pub struct HandlerTiming {
+    budgets: HashMap<CallbackKind, Budget>,
+    latencies: HashMap<&'static str, HandlerLatency>,
+}
+
+impl HandlerTiming {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set_budget(&mut self, kind: CallbackKind, budget: Budget) {
+        self.budgets.insert(kind, budget);
+    }
+
+    pub fn budget(&self, kind: CallbackKind) -> Budget {
+        self.budgets.get(&kind).copied().unwrap_or_default()
+    }
+
+    /// Records one invocation of `service`'s `kind` callback.
+    pub fn record(
+        &mut self,
+        service: &'static str,
+        kind: CallbackKind,
+        elapsed: Duration,
+    ) -> BudgetOutcome {
+        let budget = self.budget(kind);
+        let latency = self.latencies.entry(service).or_default();
+        latency.invocations += 1;
+        latency.worst = latency.worst.max(elapsed);
+
+        if budget.hard.is_some_and(|hard| elapsed > hard) {
+            latency.slow = true;
+            log::warn!("{service} {kind:?} handler took {elapsed:?}, over the hard budget");
+            BudgetOutcome::HardExceeded
+        } else if elapsed > budget.soft {
+            latency.soft_exceeded += 1;
+            log::warn!("{service} {kind:?} handler took {elapsed:?}");
+            BudgetOutcome::SoftExceeded
+        } else {
+            BudgetOutcome::Within
+        }
+    }
+
+    pub fn latency(&self, service: &str) -> Option<HandlerLatency> {
+        self.latencies.get(service).copied()
+    }
+
+    /// Worst observed latency per service.
+    pub fn latencies(&self) -> impl Iterator<Item = (&'static str, &HandlerLatency)> {
+        self.latencies
+            .iter()
+            .map(|(&service, latency)| (service, latency))
+    }
+}
@@ -0,0 +1,193 @@
+//! Per-connection conversion of served values.
+//!
+//! A client writes its [`Preferences`] (e.g. °F instead of °C) to the
+//! preferences characteristic; [`on_preferences_write`] stores them in the
+//! connection's [`Session`]. A [`ValueTransformer`] registered for a handle
+//! then rewrites the value on every read and notification of that handle
+//! for the connection's preferences, or the defaults when none were
+//! written.
+//!
+//! Broadcasts of a handle without a transformer keep sharing one buffer
+//! between all connections; with a transformer each connection gets its
+//! own bytes, see [`Transformers::materialize`].
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use super::session::{Session, SessionRegistry};
+use crate::proto::prefs::{Preferences, PrefsError};
+
+/// Converts an outgoing value for the given preferences.
+pub type ValueTransformer = Arc<dyn Fn(&Preferences, &[u8]) -> Vec<u8> + Send + Sync>;
+
+/// Stores the decoded preferences; the previous ones stay on error.
+pub fn on_preferences_write(session: &mut Session, value: &[u8]) -> Result<(), PrefsError> {
+    session.insert(Preferences::decode(value)?);
+    Ok(())
+}
+
+pub fn preferences(session: Option<&Session>) -> Preferences {
+    session
+        .and_then(Session::get::<Preferences>)
+        .copied()
+        .unwrap_or_default()
+}
+
+#[derive(Clone, Default)]
+pub struct Transformers {
+    by_handle: HashMap<u16, ValueTransformer>,
+}
+
+impl core::fmt::Debug for Transformers {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_set().entries(self.by_handle.keys()).finish()
+    }
+}
+
+impl Transformers {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn register(&mut self, handle: u16, transformer: ValueTransformer) {
+        self.by_handle.insert(handle, transformer);
+    }
+
+    pub fn unregister(&mut self, handle: u16) -> bool {
+        self.by_handle.remove(&handle).is_some()
+    }
+
+    pub fn is_transformed(&self, handle: u16) -> bool {
+        self.by_handle.contains_key(&handle)
+    }
+
+    /// The value of `handle` as served to the connection owning `session`.
+    pub fn apply<'a>(
+        &self,
+        handle: u16,
+        session: Option<&Session>,
+        value: &'a [u8],
+    ) -> Cow<'a, [u8]> {
+        match self.by_handle.get(&handle) {
+            Some(transform) => Cow::Owned(transform(&preferences(session), value)),
+            None => Cow::Borrowed(value),
+        }
+    }
+
+    /// The bytes to send to each of `conn_ids`. Without a transformer they
+    /// all share `value`.
+    pub fn materialize(
+        &self,
+        handle: u16,
+        value: &Arc<[u8]>,
+        conn_ids: impl IntoIterator<Item = u16>,
+        sessions: &SessionRegistry,
+    ) -> Vec<(u16, Arc<[u8]>)> {
+        let Some(transform) = self.by_handle.get(&handle) else {
+            return conn_ids
+                .into_iter()
+                .map(|conn_id| (conn_id, value.clone()))
+                .collect();
+        };
+
+        conn_ids
+            .into_iter()
+            .map(|conn_id| {
+                let prefs = preferences(sessions.get(conn_id));
+                (conn_id, Arc::from(transform(&prefs, value)))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proto::prefs::TemperatureUnit;
+
+    const TEMPERATURE: u16 = 42;
+    const COUNTER: u16 = 44;
+
+    /// Temperature in 0.01 degrees, `i16` little-endian.
+    fn temperature() -> ValueTransformer {
+        Arc::new(|prefs, value| {
+            let celsius = i16::from_le_bytes([value[0], value[1]]);
+            let converted = match prefs.temperature {
+                TemperatureUnit::Celsius => celsius,
+                TemperatureUnit::Fahrenheit => celsius * 9 / 5 + 3200,
+            };
+            converted.to_le_bytes().to_vec()
+        })
+    }
+
+    fn sessions() -> SessionRegistry {
+        let fahrenheit = Preferences {
+            temperature: TemperatureUnit::Fahrenheit,
+            ..Preferences::default()
+        };
+
+        let mut sessions = SessionRegistry::new();
+        on_preferences_write(sessions.on_connect(1), &fahrenheit.encode()).unwrap();
+        sessions.on_connect(2);
+        sessions
+    }
+
+    #[test]
+    fn subscribers_get_their_own_units_from_one_value() {
+        let mut transformers = Transformers::new();
+        transformers.register(TEMPERATURE, temperature());
+        let sessions = sessions();
+
+        let value: Arc<[u8]> = Arc::from(&2500i16.to_le_bytes()[..]);
+        let sent = transformers.materialize(TEMPERATURE, &value, [1, 2], &sessions);
+
+        assert_eq!(sent[0], (1, Arc::from(&7700i16.to_le_bytes()[..])));
+        assert_eq!(sent[1], (2, value.clone()));
+        assert!(!Arc::ptr_eq(&sent[1].1, &value));
+
+        // Reads follow the same preferences.
+        assert_eq!(
+            *transformers.apply(TEMPERATURE, sessions.get(1), &value),
+            7700i16.to_le_bytes()
+        );
+        assert_eq!(
+            *transformers.apply(TEMPERATURE, None, &value),
+            2500i16.to_le_bytes()
+        );
+    }
+
+    #[test]
+    fn untransformed_handles_share_one_buffer() {
+        let mut transformers = Transformers::new();
+        transformers.register(TEMPERATURE, temperature());
+        let sessions = sessions();
+
+        let value: Arc<[u8]> = Arc::from(&[1, 2, 3][..]);
+        let sent = transformers.materialize(COUNTER, &value, [1, 2], &sessions);
+        assert!(sent.iter().all(|(_, bytes)| Arc::ptr_eq(bytes, &value)));
+        assert!(matches!(
+            transformers.apply(COUNTER, sessions.get(1), &value),
+            Cow::Borrowed(_)
+        ));
+
+        assert!(transformers.unregister(TEMPERATURE));
+        assert!(!transformers.is_transformed(TEMPERATURE));
+    }
+
+    #[test]
+    fn bad_preferences_keep_the_previous_ones() {
+        let mut sessions = sessions();
+        let session = sessions.get_mut(1).unwrap();
+
+        assert_eq!(
+            on_preferences_write(session, &[1, 7, b'e', b'n']),
+            Err(PrefsError::UnknownUnit(7))
+        );
+        assert_eq!(
+            preferences(Some(session)).temperature,
+            TemperatureUnit::Fahrenheit
+        );
+        assert_eq!(preferences(None), Preferences::default());
+    }
+}
@@ -0,0 +1,105 @@
+//! Transactional configuration across several characteristics.
+//!
+//! Writes to the participating handles are staged per connection instead of
+//! reaching their handlers. A write to the commit characteristic validates the
+//! staged set as a whole; only on success are the values handed back to the
+//! caller to be applied through the real handlers.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+/// Staged values of one connection, ordered by attribute handle.
+pub type StagedValues = BTreeMap<u16, Vec<u8>>;
+
+/// Validation failure for one staged field.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct FieldError {
+    pub handle: u16,
+    /// Application-defined error code reported to the client.
+    pub code: u8,
+}
+
+impl FieldError {
+    pub const fn new(handle: u16, code: u8) -> Self {
+        Self { handle, code }
+    }
+
+    /// Encodes a rejection report for indication to the client:
+    /// `count: u8` followed by `count` times `handle: u16 LE, code: u8`.
+    pub fn encode_report(errors: &[FieldError]) -> Vec<u8> {
+        let errors = &errors[..errors.len().min(u8::MAX as usize)];
+
+        let mut report = Vec::with_capacity(1 + errors.len() * 3);
+        report.push(errors.len() as u8);
+        for error in errors {
+            report.extend_from_slice(&error.handle.to_le_bytes());
+            report.push(error.code);
+        }
+
+        report
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct ConfigTransaction {
+    participants: HashSet<u16>,
+    staging: HashMap<u16, StagedValues>,
+}
+
+impl ConfigTransaction {
+    pub fn new(participants: impl IntoIterator<Item = u16>) -> Self {
+        Self {
+            participants: participants.into_iter().collect(),
+            staging: HashMap::new(),
+        }
+    }
+
+    pub fn is_participant(&self, handle: u16) -> bool {
+        self.participants.contains(&handle)
+    }
+
+    /// Stages a write. Returns `false` (and stages nothing) if the handle does
+    /// not take part in the transaction, in which case the write should be
+    /// dispatched normally.
+    pub fn stage(&mut self, conn_id: u16, handle: u16, value: &[u8]) -> bool {
+        if !self.is_participant(handle) {
+            return false;
+        }
+
+        self.staging
+            .entry(conn_id)
+            .or_default()
+            .insert(handle, value.to_vec());
+
+        true
+    }
+
+    pub fn staged(&self, conn_id: u16) -> Option<&StagedValues> {
+        self.staging.get(&conn_id)
+    }
+
+    /// Validates the connection's staged set with `validate`.
+    ///
+    /// On success the staging area is cleared and the values are returned to
+    /// be applied. On failure the staged values are kept, so the client can
+    /// correct the offending fields and commit again.
+    pub fn commit<F>(&mut self, conn_id: u16, validate: F) -> Result<StagedValues, Vec<FieldError>>
+    where
+        F: FnOnce(&StagedValues) -> Result<(), Vec<FieldError>>,
+    {
+        let staged = self.staging.get(&conn_id).cloned().unwrap_or_default();
+
+        validate(&staged)?;
+
+        self.staging.remove(&conn_id);
+
+        Ok(staged)
+    }
+
+    pub fn rollback(&mut self, conn_id: u16) {
+        self.staging.remove(&conn_id);
+    }
+
+    pub fn on_disconnect(&mut self, conn_id: u16) {
+        self.rollback(conn_id);
+    }
+}
@@ -0,0 +1,154 @@
+//! Shared connection and subscription state, with blocking waits.
+//!
+//! [`LinkState`] shares the [`ConnectionRegistry`] and [`Subscriptions`]
+//! between the GATTS event handling and application threads. Its queries
+//! read the same state the send paths use, under this lock rather than
+//! anything held during dispatch.
+//!
+//! Every change made through [`LinkState::update`] wakes the waiters, which
+//! re-check their condition, so spurious wakeups and conditions that already
+//! hold are both handled.
+
+use core::fmt;
+use core::time::Duration;
+use std::sync::{Condvar, Mutex, MutexGuard, PoisonError};
+
+use super::conn::{Connection, ConnectionRegistry};
+use super::subscription::Subscriptions;
+use super::PeerAddr;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct SubscriberInfo {
+    pub conn_id: u16,
+    /// Identity address when resolved, see [`Connection::identity_addr`].
+    pub addr: PeerAddr,
+    pub notify: bool,
+    pub indicate: bool,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum WaitTimeout {
+    Connection { waited: Duration },
+    Subscription { handle: u16, waited: Duration },
+}
+
+impl fmt::Display for WaitTimeout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Connection { waited } => write!(f, "no connection after {waited:?}"),
+            Self::Subscription { handle, waited } => {
+                write!(f, "no subscription to handle {handle} after {waited:?}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for WaitTimeout {}
+
+#[derive(Debug, Default)]
+struct Inner {
+    conns: ConnectionRegistry,
+    subs: Subscriptions,
+}
+
+#[derive(Debug, Default)]
+pub struct LinkState {
+    inner: Mutex<Inner>,
+    changed: Condvar,
+}
+
+impl LinkState {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Applies an event to the registry and subscriptions and wakes waiters.
+    pub fn update<R>(&self, f: impl FnOnce(&mut ConnectionRegistry, &mut Subscriptions) -> R) -> R {
+        let mut inner = self.lock();
+        let inner = &mut *inner;
+        let result = f(&mut inner.conns, &mut inner.subs);
+        self.changed.notify_all();
+        result
+    }
+
+    pub fn read<R>(&self, f: impl FnOnce(&ConnectionRegistry, &Subscriptions) -> R) -> R {
+        let inner = self.lock();
+        f(&inner.conns, &inner.subs)
+    }
+
+    pub fn subscribers(&self, handle: u16) -> Vec<SubscriberInfo> {
+        let inner = self.lock();
+
+        inner
+            .subs
+            .subscribers(handle)
+            .into_iter()
+            .filter_map(|(conn_id, flags)| {
+                let conn = inner.conns.get(conn_id)?;
+                Some(SubscriberInfo {
+                    conn_id,
+                    addr: conn.identity_addr(),
+                    notify: flags.notify(),
+                    indicate: flags.indicate(),
+                })
+            })
+            .collect()
+    }
+
+    pub fn subscriptions(&self, conn_id: u16) -> Vec<u16> {
+        self.lock().subs.subscriptions(conn_id)
+    }
+
+    pub fn subscriber_count(&self, handle: u16) -> usize {
+        self.lock().subs.subscriber_count(handle)
+    }
+
+    /// Returns a connected peer, waiting up to `timeout` for one.
+    pub fn wait_for_connection(&self, timeout: Duration) -> Result<(u16, Connection), WaitTimeout> {
+        let inner = self.wait_until(timeout, |inner| !inner.conns.is_empty());
+
+        inner
+            .conns
+            .iter()
+            .min_by_key(|(_, conn)| conn.connected_at)
+            .map(|(conn_id, conn)| (conn_id, *conn))
+            .ok_or(WaitTimeout::Connection { waited: timeout })
+    }
+
+    /// Returns a connection subscribed to `handle`, waiting up to `timeout`.
+    pub fn wait_for_subscription(
+        &self,
+        handle: u16,
+        timeout: Duration,
+    ) -> Result<u16, WaitTimeout> {
+        let subscribed = |inner: &Inner| {
+            inner
+                .conns
+                .iter()
+                .map(|(conn_id, _)| conn_id)
+                .find(|&conn_id| !inner.subs.flags(conn_id, handle).is_empty())
+        };
+
+        let inner = self.wait_until(timeout, |inner| subscribed(inner).is_some());
+        subscribed(&inner).ok_or(WaitTimeout::Subscription {
+            handle,
+            waited: timeout,
+        })
+    }
+
+    fn wait_until(
+        &self,
+        timeout: Duration,
+        done: impl Fn(&Inner) -> bool,
+    ) -> MutexGuard<'_, Inner> {
+        let (inner, _) = self
+            .changed
+            .wait_timeout_while(self.lock(), timeout, |inner| !done(inner))
+            .unwrap_or_else(PoisonError::into_inner);
+        inner
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Inner> {
+        self.inner.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
@@ -0,0 +1,59 @@
+//! Build-script side of [`BuildInfo`](super::BuildInfo).
+//!
+//! Also compiled into this crate's own `build.rs` through `#[path]`, so it
+//! must not use anything else from the crate.
+
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Exports the build description as compile-time environment variables for
+/// the crate whose build script calls it, read back by
+/// [`build_info!`](crate::build_info!). Missing git is not an error; the
+/// hash is then left unset.
+pub fn emit() {
+    if let Some(hash) = git(&["rev-parse", "--short=12", "HEAD"]) {
+        println!("cargo:rustc-env=BUILD_GIT_HASH={hash}");
+        let dirty = git(&["status", "--porcelain", "--untracked-files=no"])
+            .is_some_and(|status| !status.is_empty());
+        println!("cargo:rustc-env=BUILD_GIT_DIRTY={}", u8::from(dirty));
+        println!("cargo:rerun-if-changed=.git/HEAD");
+        println!("cargo:rerun-if-changed=.git/index");
+        // A commit on a branch moves the branch ref, not HEAD. Only existing
+        // files are watched: cargo reruns on every build for missing ones.
+        let head_ref = git(&["symbolic-ref", "-q", "HEAD"]);
+        for name in head_ref.iter().map(String::as_str).chain(["packed-refs"]) {
+            if let Some(path) = git(&["rev-parse", "--git-path", name]) {
+                if std::path::Path::new(&path).exists() {
+                    println!("cargo:rerun-if-changed={path}");
+                }
+            }
+        }
+    }
+
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs());
+    println!("cargo:rustc-env=BUILD_TIMESTAMP={timestamp}");
+
+    if let Ok(target) = std::env::var("TARGET") {
+        println!("cargo:rustc-env=BUILD_TARGET={target}");
+    }
+
+    let mut features: Vec<String> = std::env::vars()
+        .filter_map(|(name, _)| {
+            let feature = name.strip_prefix("CARGO_FEATURE_")?;
+            Some(feature.to_lowercase().replace('_', "-"))
+        })
+        .collect();
+    features.sort();
+    println!("cargo:rustc-env=BUILD_FEATURES={}", features.join(","));
+}
+
+fn git(args: &[&str]) -> Option<String> {
+    let output = Command::new("git").args(args).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+
+    Some(String::from_utf8_lossy(&output.stdout).trim().to_owned())
+}
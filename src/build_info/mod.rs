@@ -0,0 +1,106 @@
+//! What firmware is running, for support triage.
+//!
+//! A binary's build script calls [`emit`]; [`build_info!`](crate::build_info!)
+//! then collects the values in that binary into a [`BuildInfo`], whose
+//! [`BuildInfo::fields`] are served as read-only characteristics. Values can
+//! exceed the MTU, they are read with
+//! [`read_blob`](crate::ble::long_read::read_blob).
+//!
+//! ```ignore
+//! // build.rs
+//! fn main() {
+//!     esp_gatt_rs_demo::build_info::emit();
+//! }
+//! ```
+
+mod emit;
+
+pub use emit::emit;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum BuildField {
+    CrateVersion,
+    /// Git commit, with `-dirty` appended for uncommitted changes.
+    GitHash,
+    /// Seconds since the Unix epoch.
+    BuildTimestamp,
+    Target,
+    /// Enabled cargo features, comma separated.
+    Features,
+    IdfVersion,
+}
+
+impl BuildField {
+    pub const ALL: [Self; 6] = [
+        Self::CrateVersion,
+        Self::GitHash,
+        Self::BuildTimestamp,
+        Self::Target,
+        Self::Features,
+        Self::IdfVersion,
+    ];
+}
+
+/// Use [`build_info!`](crate::build_info!) to fill this in.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct BuildInfo {
+    pub crate_version: &'static str,
+    pub git_hash: Option<&'static str>,
+    pub git_dirty: bool,
+    pub build_timestamp: Option<&'static str>,
+    pub target: Option<&'static str>,
+    pub features: Option<&'static str>,
+}
+
+impl BuildInfo {
+    /// The value of `field`; unknown values read as `"unknown"`.
+    pub fn value(&self, field: BuildField) -> String {
+        let known = match field {
+            BuildField::CrateVersion => Some(self.crate_version.to_owned()),
+            BuildField::GitHash => self.git_hash.map(|hash| {
+                let dirty = if self.git_dirty { "-dirty" } else { "" };
+                format!("{hash}{dirty}")
+            }),
+            BuildField::BuildTimestamp => self.build_timestamp.map(str::to_owned),
+            BuildField::Target => self.target.map(str::to_owned),
+            BuildField::Features => self.features.map(str::to_owned),
+            BuildField::IdfVersion => idf_version(),
+        };
+
+        known.unwrap_or_else(|| "unknown".to_owned())
+    }
+
+    pub fn fields(&self) -> Vec<(BuildField, String)> {
+        BuildField::ALL
+            .iter()
+            .map(|&field| (field, self.value(field)))
+            .collect()
+    }
+}
+
+#[cfg(feature = "esp")]
+fn idf_version() -> Option<String> {
+    let version = unsafe { core::ffi::CStr::from_ptr(esp_idf_svc::sys::esp_get_idf_version()) };
+    Some(version.to_string_lossy().into_owned())
+}
+
+#[cfg(not(feature = "esp"))]
+fn idf_version() -> Option<String> {
+    None
+}
+
+/// The [`BuildInfo`] of the crate this is expanded in, as exported by
+/// [`emit`] from its build script.
+#[macro_export]
+macro_rules! build_info {
+    () => {
+        $crate::build_info::BuildInfo {
+            crate_version: env!("CARGO_PKG_VERSION"),
+            git_hash: option_env!("BUILD_GIT_HASH"),
+            git_dirty: matches!(option_env!("BUILD_GIT_DIRTY"), Some("1")),
+            build_timestamp: option_env!("BUILD_TIMESTAMP"),
+            target: option_env!("BUILD_TARGET"),
+            features: option_env!("BUILD_FEATURES"),
+        }
+    };
+}
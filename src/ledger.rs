@@ -0,0 +1,96 @@
+//! Accounting of live resources, for catching leaks across restarts.
+//!
+//! With the `resource-ledger` feature, every thread, event subscription and
+//! session the crate creates registers a [`Tracked`] guard here and leaves
+//! when it goes away; [`report`] lists what is still alive and
+//! [`debug_assert_clean`] panics in debug builds if anything is. Without the
+//! feature the guards are empty and nothing is recorded.
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum ResourceKind {
+    Thread,
+    /// A subscription on the system event loop.
+    Subscription,
+    Session,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ResourceInfo {
+    pub kind: ResourceKind,
+    pub name: &'static str,
+}
+
+#[cfg(feature = "resource-ledger")]
+mod imp {
+    use std::collections::BTreeMap;
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::sync::{Mutex, PoisonError};
+
+    use super::{ResourceInfo, ResourceKind};
+
+    static LIVE: Mutex<BTreeMap<u32, ResourceInfo>> = Mutex::new(BTreeMap::new());
+    static NEXT_ID: AtomicU32 = AtomicU32::new(0);
+
+    #[derive(Debug)]
+    pub struct Tracked(u32);
+
+    impl Drop for Tracked {
+        fn drop(&mut self) {
+            LIVE.lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .remove(&self.0);
+        }
+    }
+
+    pub fn track(kind: ResourceKind, name: &'static str) -> Tracked {
+        let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        LIVE.lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(id, ResourceInfo { kind, name });
+        Tracked(id)
+    }
+
+    pub fn report() -> Vec<ResourceInfo> {
+        LIVE.lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .values()
+            .cloned()
+            .collect()
+    }
+}
+
+#[cfg(not(feature = "resource-ledger"))]
+mod imp {
+    use super::{ResourceInfo, ResourceKind};
+
+    #[derive(Debug)]
+    pub struct Tracked;
+
+    pub fn track(_kind: ResourceKind, _name: &'static str) -> Tracked {
+        Tracked
+    }
+
+    pub fn report() -> Vec<ResourceInfo> {
+        Vec::new()
+    }
+}
+
+/// Keeps a resource listed in the ledger until dropped.
+pub use imp::Tracked;
+
+/// Registers a resource; hold the guard as long as it lives.
+pub fn track(kind: ResourceKind, name: &'static str) -> Tracked {
+    imp::track(kind, name)
+}
+
+/// Resources alive right now, oldest first.
+pub fn report() -> Vec<ResourceInfo> {
+    imp::report()
+}
+
+/// In debug builds, panics listing the survivors if any resource is still
+/// alive, e.g. after everything was stopped.
+pub fn debug_assert_clean() {
+    let live = report();
+    debug_assert!(live.is_empty(), "resources still alive: {live:?}");
+}
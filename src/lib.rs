@@ -0,0 +1,13 @@
+//! BLE GATT building blocks used by the demo firmware.
+
+pub mod ble;
+pub mod build_info;
+pub mod ledger;
+pub mod prelude;
+pub mod proto;
+pub mod store;
+
+#[cfg(test)]
+mod test_alloc;
+#[cfg(test)]
+mod test_clock;
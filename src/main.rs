@@ -1,3 +1,22 @@
+use std::sync::mpsc;
+use std::thread;
+
+use esp_gatt_rs_demo::ble::report::{CapabilityReport, RuntimeConfig};
+use esp_gatt_rs_demo::ble::spec::{AdvertisingSpec, SecurityMode, ServerSpec};
+use esp_gatt_rs_demo::ble::sysloop::BleLifecycleEvent;
+use esp_gatt_rs_demo::build_info::BuildField;
+use esp_gatt_rs_demo::ledger::{self, ResourceKind};
+use esp_idf_svc::eventloop::EspSystemEventLoop;
+use esp_idf_svc::hal::gpio::PinDriver;
+use esp_idf_svc::hal::peripherals::Peripherals;
+use esp_idf_svc::sys::EspError;
+
+const RUNTIME: RuntimeConfig = RuntimeConfig {
+    app_id: 0,
+    max_connections: 4,
+    local_mtu: 517,
+};
+
 fn main() {
     // It is necessary to call this function once. Otherwise some patches to the runtime
     // implemented by esp-idf-sys might not link properly. See https://github.com/esp-rs/esp-idf-template/issues/71
@@ -6,5 +25,74 @@ fn main() {
     // Bind the log crate to the ESP Logging facilities
     esp_idf_svc::log::EspLogger::initialize_default();
 
+    let build = esp_gatt_rs_demo::build_info!();
+    log::info!(
+        "esp-gatt-rs-demo {} ({})",
+        build.crate_version,
+        build.value(BuildField::GitHash)
+    );
+
+    let spec = server_spec();
+    let warnings = match esp_gatt_rs_demo::ble::preflight::preflight(&spec.required_capabilities())
+    {
+        Ok(warnings) => warnings,
+        Err(err) => {
+            log::error!("{err}");
+            return;
+        }
+    };
+    warnings.iter().for_each(|issue| log::warn!("{issue}"));
+    CapabilityReport::new(&spec, RUNTIME, &warnings).print_banner();
+
+    if let Err(err) = status_led() {
+        log::error!("status LED: {err}");
+    }
+
     log::info!("Hello, world!");
 }
+
+/// The GATT table of the demo: none yet, only the name it advertises.
+fn server_spec() -> ServerSpec {
+    ServerSpec {
+        device_name: "esp-gatt-rs-demo".into(),
+        advertising: AdvertisingSpec::default(),
+        security: SecurityMode::None,
+        services: Vec::new(),
+    }
+}
+
+/// Lights the on-board LED while a central is connected, driven by the BLE
+/// lifecycle events on the system event loop.
+fn status_led() -> Result<(), EspError> {
+    let sysloop = EspSystemEventLoop::take()?;
+    let mut led = PinDriver::output(Peripherals::take()?.pins.gpio2)?;
+
+    // The subscription callback runs on the event loop task; keep it short
+    // and let the LED task do the rest.
+    let (tx, rx) = mpsc::channel();
+    let subscription = sysloop.subscribe::<BleLifecycleEvent, _>(move |event| {
+        let _ = tx.send(event);
+    })?;
+    let subscription_tracked = ledger::track(ResourceKind::Subscription, "status-led");
+
+    thread::Builder::new()
+        .name("status-led".into())
+        .stack_size(2048)
+        .spawn(move || {
+            let _tracked = ledger::track(ResourceKind::Thread, "status-led");
+            let _subscription = (subscription, subscription_tracked);
+            for event in rx {
+                let result = match event {
+                    BleLifecycleEvent::Connected { .. } => led.set_high(),
+                    BleLifecycleEvent::Disconnected { .. } => led.set_low(),
+                    _ => Ok(()),
+                };
+                if let Err(err) = result {
+                    log::warn!("status LED: {err}");
+                }
+            }
+        })
+        .map_err(|_| EspError::from_infallible::<{ esp_idf_svc::sys::ESP_FAIL }>())?;
+
+    Ok(())
+}
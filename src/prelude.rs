@@ -0,0 +1,27 @@
+//! Types most services need, for `use esp_gatt_rs_demo::prelude::*`.
+//!
+//! With the `experimental` feature and Bluedroid enabled, the esp-idf GATT
+//! types used alongside them, and the status checks and errors for calls
+//! into the stack, are re-exported as well.
+
+pub use crate::ble::conn::{Connection, ConnectionRegistry};
+pub use crate::ble::session::{Session, SessionRegistry};
+pub use crate::ble::spec::{
+    AdvertisingSpec, CharPerm, CharProp, CharacteristicSpec, SecurityMode, ServerSpec, ServiceSpec,
+    SpecError,
+};
+pub use crate::ble::subscription::{Delivery, SendMode, Subscriptions};
+pub use crate::ble::PeerAddr;
+pub use crate::proto::assigned::{characteristics, descriptors, services};
+pub use crate::proto::{parse_cccd, Appearance, CccdFlags, Uuid};
+pub use crate::store::{KvStore, MemStore, StoreError};
+pub use crate::{perms, props};
+
+#[cfg(all(feature = "experimental", esp_idf_bt_bluedroid_enabled))]
+pub use crate::ble::error::{check_bt_status, check_gatt_status, BtError, OpError};
+
+#[cfg(all(feature = "experimental", esp_idf_bt_bluedroid_enabled))]
+pub use esp_idf_svc::bt::ble::gatt::{GattStatus, Handle, Permission, Property};
+#[cfg(all(feature = "experimental", esp_idf_bt_bluedroid_enabled))]
+pub use esp_idf_svc::bt::{BtStatus, BtUuid};
+
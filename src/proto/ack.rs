@@ -0,0 +1,382 @@
+//! Application-level acknowledgments for notified records.
+//!
+//! Every notified record is prefixed with a `u32` sequence number. The client
+//! writes cumulative acks (or a nack for the first missing record) to a paired
+//! ack characteristic:
+//!
+//! | kind: u8 (0 = ack, 1 = nack) | seq: u32 |
+//!
+//! [`AckWindow`] is the server side, [`AckTracker`] the client side.
+//! Sequence numbers wrap at `u32::MAX`.
+
+use core::fmt;
+use core::ops::Range;
+use std::collections::VecDeque;
+
+use super::seq;
+
+pub const RECORD_HEADER_LEN: usize = 4;
+pub const ACK_FRAME_LEN: usize = 5;
+
+const KIND_ACK: u8 = 0;
+const KIND_NACK: u8 = 1;
+
+pub fn encode_record(seq: u32, payload: &[u8]) -> Vec<u8> {
+    let mut record = Vec::with_capacity(RECORD_HEADER_LEN + payload.len());
+    record.extend_from_slice(&seq.to_le_bytes());
+    record.extend_from_slice(payload);
+    record
+}
+
+pub fn decode_record(record: &[u8]) -> Option<(u32, &[u8])> {
+    if record.len() < RECORD_HEADER_LEN {
+        return None;
+    }
+
+    let (header, payload) = record.split_at(RECORD_HEADER_LEN);
+    let seq = u32::from_le_bytes(header.try_into().ok()?);
+
+    Some((seq, payload))
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum AckFrame {
+    /// Every record up to and including `seq` was processed.
+    Ack(u32),
+    /// Record `seq` is missing; everything before it was processed.
+    Nack(u32),
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum AckFrameError {
+    BadLength(usize),
+    UnknownKind(u8),
+}
+
+impl fmt::Display for AckFrameError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::BadLength(len) => write!(f, "ack frame must be {ACK_FRAME_LEN} bytes, got {len}"),
+            Self::UnknownKind(kind) => write!(f, "unknown ack frame kind {kind}"),
+        }
+    }
+}
+
+impl std::error::Error for AckFrameError {}
+
+impl AckFrame {
+    pub fn encode(&self) -> [u8; ACK_FRAME_LEN] {
+        let (kind, seq) = match *self {
+            Self::Ack(seq) => (KIND_ACK, seq),
+            Self::Nack(seq) => (KIND_NACK, seq),
+        };
+
+        let mut frame = [0; ACK_FRAME_LEN];
+        frame[0] = kind;
+        frame[1..].copy_from_slice(&seq.to_le_bytes());
+        frame
+    }
+
+    pub fn decode(frame: &[u8]) -> Result<Self, AckFrameError> {
+        let frame: [u8; ACK_FRAME_LEN] = frame
+            .try_into()
+            .map_err(|_| AckFrameError::BadLength(frame.len()))?;
+        let seq = u32::from_le_bytes([frame[1], frame[2], frame[3], frame[4]]);
+
+        match frame[0] {
+            KIND_ACK => Ok(Self::Ack(seq)),
+            KIND_NACK => Ok(Self::Nack(seq)),
+            kind => Err(AckFrameError::UnknownKind(kind)),
+        }
+    }
+}
+
+/// Server-side window of sent but unacknowledged records.
+pub struct AckWindow {
+    /// Oldest unacknowledged sequence number.
+    base: u32,
+    next_seq: u32,
+    retained: VecDeque<(u32, Vec<u8>)>,
+    capacity: usize,
+    on_acked: Option<Box<dyn FnMut(u32) + Send>>,
+}
+
+impl fmt::Debug for AckWindow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AckWindow")
+            .field("base", &self.base)
+            .field("next_seq", &self.next_seq)
+            .field("retained", &self.retained.len())
+            .field("capacity", &self.capacity)
+            .finish_non_exhaustive()
+    }
+}
+
+impl AckWindow {
+    /// `capacity` is the number of records kept for retransmission.
+    pub fn new(first_seq: u32, capacity: usize) -> Self {
+        Self {
+            base: first_seq,
+            next_seq: first_seq,
+            retained: VecDeque::with_capacity(capacity),
+            capacity,
+            on_acked: None,
+        }
+    }
+
+    /// Called with the new high-water mark (last acknowledged sequence
+    /// number) whenever an ack advances the window.
+    pub fn set_on_acked(&mut self, callback: impl FnMut(u32) + Send + 'static) {
+        self.on_acked = Some(Box::new(callback));
+    }
+
+    /// Assigns the next sequence number to `payload` and returns the record
+    /// to notify.
+    pub fn send(&mut self, payload: &[u8]) -> Vec<u8> {
+        let seq = self.next_seq;
+        self.next_seq = seq.wrapping_add(1);
+
+        if self.retained.len() == self.capacity {
+            self.retained.pop_front();
+        }
+        if self.capacity > 0 {
+            self.retained.push_back((seq, payload.to_vec()));
+        }
+
+        encode_record(seq, payload)
+    }
+
+    /// Unacknowledged sequence numbers. On wraparound `end < start`; use
+    /// [`seq::distance`] for the count.
+    pub fn unacked(&self) -> Range<u32> {
+        self.base..self.next_seq
+    }
+
+    /// Applies a client ack frame. Returns the records to retransmit, which is
+    /// empty for acks.
+    pub fn on_frame(&mut self, frame: AckFrame) -> Vec<Vec<u8>> {
+        match frame {
+            AckFrame::Ack(seq) => {
+                self.advance(seq.wrapping_add(1));
+                Vec::new()
+            }
+            AckFrame::Nack(seq) => {
+                self.advance(seq);
+                self.retained
+                    .iter()
+                    .filter(|(retained, _)| !seq::is_after(seq, *retained))
+                    .map(|(retained, payload)| encode_record(*retained, payload))
+                    .collect()
+            }
+        }
+    }
+
+    /// Moves the window base to `new_base` if that is within the window.
+    fn advance(&mut self, new_base: u32) {
+        let in_window =
+            seq::is_after(new_base, self.base) && !seq::is_after(new_base, self.next_seq);
+        if !in_window {
+            return;
+        }
+
+        self.base = new_base;
+        while self
+            .retained
+            .front()
+            .is_some_and(|(seq, _)| seq::is_after(new_base, *seq))
+        {
+            self.retained.pop_front();
+        }
+
+        if let Some(on_acked) = &mut self.on_acked {
+            on_acked(new_base.wrapping_sub(1));
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Reception {
+    InOrder,
+    /// Records were skipped; the client should nack `expected`.
+    Gap {
+        expected: u32,
+    },
+    Duplicate,
+}
+
+/// Client-side tracking of received sequence numbers.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct AckTracker {
+    expected: u32,
+}
+
+impl AckTracker {
+    pub const fn new(first_seq: u32) -> Self {
+        Self {
+            expected: first_seq,
+        }
+    }
+
+    pub fn receive(&mut self, seq: u32) -> Reception {
+        if seq == self.expected {
+            self.expected = seq.wrapping_add(1);
+            Reception::InOrder
+        } else if seq::is_after(seq, self.expected) {
+            Reception::Gap {
+                expected: self.expected,
+            }
+        } else {
+            Reception::Duplicate
+        }
+    }
+
+    /// Cumulative ack for everything received in order so far.
+    pub fn ack(&self) -> AckFrame {
+        AckFrame::Ack(self.expected.wrapping_sub(1))
+    }
+
+    pub fn nack(&self) -> AckFrame {
+        AckFrame::Nack(self.expected)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use proptest::prelude::*;
+
+    /// Delivers `record` to the client and returns the frame it writes back.
+    fn deliver(tracker: &mut AckTracker, record: &[u8]) -> AckFrame {
+        let (seq, _) = decode_record(record).unwrap();
+        let frame = match tracker.receive(seq) {
+            Reception::InOrder | Reception::Duplicate => tracker.ack(),
+            Reception::Gap { .. } => tracker.nack(),
+        };
+        AckFrame::decode(&frame.encode()).unwrap()
+    }
+
+    #[test]
+    fn record_round_trip() {
+        let record = encode_record(0xDEAD_BEEF, b"telemetry");
+        assert_eq!(
+            decode_record(&record),
+            Some((0xDEAD_BEEF, &b"telemetry"[..]))
+        );
+        assert_eq!(decode_record(&record[..3]), None);
+    }
+
+    #[test]
+    fn frame_round_trip_and_errors() {
+        for frame in [AckFrame::Ack(0), AckFrame::Nack(u32::MAX)] {
+            assert_eq!(AckFrame::decode(&frame.encode()), Ok(frame));
+        }
+        assert_eq!(AckFrame::decode(&[0; 4]), Err(AckFrameError::BadLength(4)));
+        assert_eq!(
+            AckFrame::decode(&[7, 0, 0, 0, 0]),
+            Err(AckFrameError::UnknownKind(7))
+        );
+    }
+
+    #[test]
+    fn client_acks_advance_server_window() {
+        let acked = Arc::new(Mutex::new(Vec::new()));
+        let mut window = AckWindow::new(10, 8);
+        let sink = acked.clone();
+        window.set_on_acked(move |seq| sink.lock().unwrap().push(seq));
+        let mut tracker = AckTracker::new(10);
+
+        for payload in [b"a", b"b", b"c"] {
+            let record = window.send(payload);
+            let frame = deliver(&mut tracker, &record);
+            assert!(window.on_frame(frame).is_empty());
+        }
+
+        assert_eq!(window.unacked(), 13..13);
+        assert_eq!(*acked.lock().unwrap(), vec![10, 11, 12]);
+    }
+
+    #[test]
+    fn lost_record_is_retransmitted_after_nack() {
+        let mut window = AckWindow::new(0, 8);
+        let mut tracker = AckTracker::new(0);
+
+        let first = window.send(b"0");
+        let _lost = window.send(b"1");
+        let third = window.send(b"2");
+
+        window.on_frame(deliver(&mut tracker, &first));
+        let frame = deliver(&mut tracker, &third);
+        assert_eq!(frame, AckFrame::Nack(1));
+
+        let resent = window.on_frame(frame);
+        assert_eq!(resent, vec![encode_record(1, b"1"), encode_record(2, b"2")]);
+        for record in &resent {
+            window.on_frame(deliver(&mut tracker, record));
+        }
+        assert_eq!(window.unacked(), 3..3);
+    }
+
+    #[test]
+    fn sequence_numbers_wrap() {
+        let mut window = AckWindow::new(u32::MAX - 1, 8);
+        let mut tracker = AckTracker::new(u32::MAX - 1);
+
+        let records: Vec<_> = (0..4).map(|_| window.send(b"x")).collect();
+        let unacked = window.unacked();
+        assert_eq!((unacked.start, unacked.end), (u32::MAX - 1, 2));
+        assert_eq!(seq::distance(unacked.start, unacked.end), 4);
+
+        for record in &records {
+            window.on_frame(deliver(&mut tracker, record));
+        }
+        assert_eq!(window.unacked(), 2..2);
+        assert_eq!(tracker.ack(), AckFrame::Ack(1));
+    }
+
+    #[test]
+    fn stale_and_future_acks_are_ignored() {
+        let mut window = AckWindow::new(5, 8);
+        window.send(b"a");
+        window.send(b"b");
+
+        window.on_frame(AckFrame::Ack(100));
+        assert_eq!(window.unacked(), 5..7);
+        window.on_frame(AckFrame::Ack(5));
+        window.on_frame(AckFrame::Ack(3));
+        assert_eq!(window.unacked(), 6..7);
+
+        let mut tracker = AckTracker::new(5);
+        tracker.receive(5);
+        assert_eq!(tracker.receive(5), Reception::Duplicate);
+        assert_eq!(tracker.receive(9), Reception::Gap { expected: 6 });
+    }
+
+    proptest! {
+        #[test]
+        fn decode_never_panics(bytes in prop::collection::vec(any::<u8>(), 0..16)) {
+            if let Ok(frame) = AckFrame::decode(&bytes) {
+                prop_assert_eq!(&frame.encode()[..], &bytes[..]);
+            }
+            if let Some((seq, payload)) = decode_record(&bytes) {
+                prop_assert_eq!(encode_record(seq, payload), bytes.clone());
+            }
+        }
+
+        #[test]
+        fn frames_round_trip(nack in any::<bool>(), seq in any::<u32>()) {
+            let frame = if nack { AckFrame::Nack(seq) } else { AckFrame::Ack(seq) };
+            prop_assert_eq!(AckFrame::decode(&frame.encode()), Ok(frame));
+        }
+
+        #[test]
+        fn records_round_trip(
+            seq in any::<u32>(),
+            payload in prop::collection::vec(any::<u8>(), 0..32),
+        ) {
+            let record = encode_record(seq, &payload);
+            prop_assert_eq!(decode_record(&record), Some((seq, &payload[..])));
+        }
+    }
+}
@@ -0,0 +1,479 @@
+//! Raw advertising and scan-response payload assembly.
+//!
+//! `AdvConfiguration` only takes a single service UUID. This builder accepts
+//! any mix of 16/32/128-bit service UUIDs and places them into AD structures
+//! across the advertising data and the scan response, so both can be applied
+//! with the raw configuration calls (see `ble::adv`).
+
+use core::fmt;
+
+use super::Appearance;
+
+/// Maximum size of a legacy advertising or scan-response payload.
+pub const MAX_PAYLOAD_LEN: usize = 31;
+/// Maximum data carried by one AD structure, after its length and type.
+pub const MAX_AD_DATA_LEN: usize = MAX_PAYLOAD_LEN - 2;
+
+/// AD structure type codes used by the builder.
+pub mod ad_type {
+    pub const FLAGS: u8 = 0x01;
+    pub const INCOMPLETE_UUID16: u8 = 0x02;
+    pub const COMPLETE_UUID16: u8 = 0x03;
+    pub const INCOMPLETE_UUID32: u8 = 0x04;
+    pub const COMPLETE_UUID32: u8 = 0x05;
+    pub const INCOMPLETE_UUID128: u8 = 0x06;
+    pub const COMPLETE_UUID128: u8 = 0x07;
+    pub const COMPLETE_LOCAL_NAME: u8 = 0x09;
+    pub const SERVICE_DATA_UUID16: u8 = 0x16;
+    pub const APPEARANCE: u8 = 0x19;
+    pub const SERVICE_DATA_UUID32: u8 = 0x20;
+    pub const SERVICE_DATA_UUID128: u8 = 0x21;
+    pub const MANUFACTURER_DATA: u8 = 0xFF;
+}
+
+/// LE General Discoverable, BR/EDR not supported.
+pub const FLAGS_GENERAL_DISC_BREDR_NOT_SUPPORTED: u8 = 0x06;
+
+/// A Bluetooth UUID, of a service, characteristic or descriptor alike.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Uuid {
+    Uuid16(u16),
+    Uuid32(u32),
+    Uuid128(u128),
+}
+
+impl Uuid {
+    /// Encoded size of the UUID in bytes.
+    pub const fn width(&self) -> usize {
+        match self {
+            Self::Uuid16(_) => 2,
+            Self::Uuid32(_) => 4,
+            Self::Uuid128(_) => 16,
+        }
+    }
+
+    pub(crate) fn write_le(&self, buf: &mut Vec<u8>) {
+        match self {
+            Self::Uuid16(uuid) => buf.extend_from_slice(&uuid.to_le_bytes()),
+            Self::Uuid32(uuid) => buf.extend_from_slice(&uuid.to_le_bytes()),
+            Self::Uuid128(uuid) => buf.extend_from_slice(&uuid.to_le_bytes()),
+        }
+    }
+}
+
+/// Lowercase hex; 128-bit UUIDs in the usual dashed form.
+impl fmt::Display for Uuid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Self::Uuid16(uuid) => write!(f, "{uuid:04x}"),
+            Self::Uuid32(uuid) => write!(f, "{uuid:08x}"),
+            Self::Uuid128(uuid) => write!(
+                f,
+                "{:08x}-{:04x}-{:04x}-{:04x}-{:012x}",
+                uuid >> 96,
+                (uuid >> 80) & 0xFFFF,
+                (uuid >> 64) & 0xFFFF,
+                (uuid >> 48) & 0xFFFF,
+                uuid & 0xFFFF_FFFF_FFFF
+            ),
+        }
+    }
+}
+
+/// One AD structure contributed on top of the builder's payload.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum AdFragment {
+    ServiceData { uuid: Uuid, bytes: Vec<u8> },
+    ManufacturerData { company_id: u16, bytes: Vec<u8> },
+    Raw { ad_type: u8, data: Vec<u8> },
+}
+
+impl AdFragment {
+    /// Encoded size including the length and type bytes.
+    pub fn encoded_len(&self) -> usize {
+        2 + match self {
+            Self::ServiceData { uuid, bytes } => uuid.width() + bytes.len(),
+            Self::ManufacturerData { bytes, .. } => 2 + bytes.len(),
+            Self::Raw { data, .. } => data.len(),
+        }
+    }
+
+    /// Appends the AD structure; fails if it is longer than
+    /// [`MAX_AD_DATA_LEN`].
+    pub fn push_to(&self, buf: &mut Vec<u8>) -> Result<(), AdvError> {
+        match self {
+            Self::ServiceData { uuid, bytes } => {
+                let ty = match uuid {
+                    Uuid::Uuid16(_) => ad_type::SERVICE_DATA_UUID16,
+                    Uuid::Uuid32(_) => ad_type::SERVICE_DATA_UUID32,
+                    Uuid::Uuid128(_) => ad_type::SERVICE_DATA_UUID128,
+                };
+                let mut data = Vec::with_capacity(uuid.width() + bytes.len());
+                uuid.write_le(&mut data);
+                data.extend_from_slice(bytes);
+                push_ad(buf, ty, &data)
+            }
+            Self::ManufacturerData { company_id, bytes } => {
+                let mut data = company_id.to_le_bytes().to_vec();
+                data.extend_from_slice(bytes);
+                push_ad(buf, ad_type::MANUFACTURER_DATA, &data)
+            }
+            Self::Raw { ad_type, data } => push_ad(buf, *ad_type, data),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum AdvError {
+    /// The service UUIDs of the given width cannot all be placed.
+    ServiceUuidsDoNotFit { width: usize },
+    /// The device name fits neither the advertising data nor the scan response.
+    NameDoesNotFit,
+    /// An AD structure with more than [`MAX_AD_DATA_LEN`] data bytes.
+    AdTooLong { len: usize },
+}
+
+impl fmt::Display for AdvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::ServiceUuidsDoNotFit { width } => write!(
+                f,
+                "{}-bit service UUIDs do not fit in advertising + scan response",
+                width * 8
+            ),
+            Self::NameDoesNotFit => {
+                write!(f, "device name does not fit in advertising + scan response")
+            }
+            Self::AdTooLong { len } => write!(
+                f,
+                "AD structure carries {len} bytes, at most {MAX_AD_DATA_LEN} fit"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for AdvError {}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct AdvPayload {
+    pub adv_data: Vec<u8>,
+    pub scan_rsp: Vec<u8>,
+}
+
+/// Builds the advertising and scan-response payloads.
+///
+/// Placement is deterministic: flags and appearance go first into the
+/// advertising data, then service UUID lists from widest to narrowest, then
+/// the device name. Each UUID list goes whole into the advertising data if it
+/// fits, else whole into the scan response, else it is split across both as
+/// two "incomplete" lists.
+#[derive(Clone, Debug, Default)]
+pub struct AdvPayloadBuilder {
+    flags: Option<u8>,
+    appearance: Option<Appearance>,
+    name: Option<String>,
+    service_uuids: Vec<Uuid>,
+}
+
+impl AdvPayloadBuilder {
+    pub fn new() -> Self {
+        Self {
+            flags: Some(FLAGS_GENERAL_DISC_BREDR_NOT_SUPPORTED),
+            ..Default::default()
+        }
+    }
+
+    pub fn flags(mut self, flags: Option<u8>) -> Self {
+        self.flags = flags;
+        self
+    }
+
+    pub fn appearance(mut self, appearance: Appearance) -> Self {
+        self.appearance = Some(appearance);
+        self
+    }
+
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+
+    /// Adds a service UUID; duplicates are ignored.
+    pub fn service_uuid(mut self, uuid: Uuid) -> Self {
+        if !self.service_uuids.contains(&uuid) {
+            self.service_uuids.push(uuid);
+        }
+        self
+    }
+
+    pub fn service_uuids(self, uuids: impl IntoIterator<Item = Uuid>) -> Self {
+        uuids
+            .into_iter()
+            .fold(self, |builder, uuid| builder.service_uuid(uuid))
+    }
+
+    pub fn build(&self) -> Result<AdvPayload, AdvError> {
+        let mut payload = AdvPayload::default();
+
+        if let Some(flags) = self.flags {
+            push_ad(&mut payload.adv_data, ad_type::FLAGS, &[flags])?;
+        }
+
+        if let Some(appearance) = self.appearance {
+            push_ad(
+                &mut payload.adv_data,
+                ad_type::APPEARANCE,
+                &appearance.raw().to_le_bytes(),
+            )?;
+        }
+
+        for (width, complete, incomplete) in [
+            (16, ad_type::COMPLETE_UUID128, ad_type::INCOMPLETE_UUID128),
+            (4, ad_type::COMPLETE_UUID32, ad_type::INCOMPLETE_UUID32),
+            (2, ad_type::COMPLETE_UUID16, ad_type::INCOMPLETE_UUID16),
+        ] {
+            let uuids: Vec<_> = self
+                .service_uuids
+                .iter()
+                .filter(|uuid| uuid.width() == width)
+                .collect();
+
+            place_uuid_list(&mut payload, &uuids, width, complete, incomplete)?;
+        }
+
+        if let Some(name) = &self.name {
+            let target = if fits(&payload.adv_data, name.len()) {
+                &mut payload.adv_data
+            } else if fits(&payload.scan_rsp, name.len()) {
+                &mut payload.scan_rsp
+            } else {
+                return Err(AdvError::NameDoesNotFit);
+            };
+
+            push_ad(target, ad_type::COMPLETE_LOCAL_NAME, name.as_bytes())?;
+        }
+
+        Ok(payload)
+    }
+}
+
+fn place_uuid_list(
+    payload: &mut AdvPayload,
+    uuids: &[&Uuid],
+    width: usize,
+    complete: u8,
+    incomplete: u8,
+) -> Result<(), AdvError> {
+    if uuids.is_empty() {
+        return Ok(());
+    }
+
+    let data_len = uuids.len() * width;
+
+    for target in [&mut payload.adv_data, &mut payload.scan_rsp] {
+        if fits(target, data_len) {
+            return push_uuids(target, complete, uuids);
+        }
+    }
+
+    let in_adv = capacity(&payload.adv_data) / width;
+    let (head, tail) = uuids.split_at(in_adv.min(uuids.len()));
+
+    if !fits(&payload.scan_rsp, tail.len() * width) {
+        return Err(AdvError::ServiceUuidsDoNotFit { width });
+    }
+
+    if !head.is_empty() {
+        push_uuids(&mut payload.adv_data, incomplete, head)?;
+    }
+    push_uuids(&mut payload.scan_rsp, incomplete, tail)
+}
+
+fn push_uuids(buf: &mut Vec<u8>, ty: u8, uuids: &[&Uuid]) -> Result<(), AdvError> {
+    let mut data = Vec::with_capacity(uuids.len() * 16);
+    for uuid in uuids {
+        uuid.write_le(&mut data);
+    }
+
+    push_ad(buf, ty, &data)
+}
+
+/// Appends one AD structure (length, type, data). The data must fit one
+/// legacy payload, see [`MAX_AD_DATA_LEN`].
+pub fn push_ad(buf: &mut Vec<u8>, ty: u8, data: &[u8]) -> Result<(), AdvError> {
+    if data.len() > MAX_AD_DATA_LEN {
+        return Err(AdvError::AdTooLong { len: data.len() });
+    }
+
+    buf.push(data.len() as u8 + 1);
+    buf.push(ty);
+    buf.extend_from_slice(data);
+    Ok(())
+}
+
+/// Number of data bytes a new AD structure can still carry in `buf`.
+fn capacity(buf: &[u8]) -> usize {
+    MAX_PAYLOAD_LEN.saturating_sub(buf.len() + 2)
+}
+
+fn fits(buf: &[u8], data_len: usize) -> bool {
+    buf.len() + 2 + data_len <= MAX_PAYLOAD_LEN
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const UUID16: Uuid = Uuid::Uuid16(0x180F);
+    const UUID32: Uuid = Uuid::Uuid32(0x1234_5678);
+    const UUID128: Uuid = Uuid::Uuid128(0x0000_1523_1212_efde_1523_785f_eabc_d123);
+    const UUID128_B: Uuid = Uuid::Uuid128(0x0000_1524_1212_efde_1523_785f_eabc_d123);
+
+    /// Splits a payload into (type, data) AD structures.
+    fn structures(buf: &[u8]) -> Vec<(u8, Vec<u8>)> {
+        let mut out = Vec::new();
+        let mut rest = buf;
+        while let [len, ty, tail @ ..] = rest {
+            let data_len = usize::from(*len) - 1;
+            out.push((*ty, tail[..data_len].to_vec()));
+            rest = &tail[data_len..];
+        }
+        out
+    }
+
+    fn le(uuids: &[Uuid]) -> Vec<u8> {
+        let mut buf = Vec::new();
+        for uuid in uuids {
+            uuid.write_le(&mut buf);
+        }
+        buf
+    }
+
+    #[test]
+    fn mixed_widths_fill_advertising_data_widest_first() {
+        let payload = AdvPayloadBuilder::new()
+            .service_uuids([UUID16, UUID32, UUID128])
+            .build()
+            .unwrap();
+
+        assert_eq!(payload.adv_data.len(), MAX_PAYLOAD_LEN);
+        assert!(payload.scan_rsp.is_empty());
+        assert_eq!(
+            structures(&payload.adv_data),
+            vec![
+                (ad_type::FLAGS, vec![FLAGS_GENERAL_DISC_BREDR_NOT_SUPPORTED]),
+                (ad_type::COMPLETE_UUID128, le(&[UUID128])),
+                (ad_type::COMPLETE_UUID32, le(&[UUID32])),
+                (ad_type::COMPLETE_UUID16, le(&[UUID16])),
+            ]
+        );
+    }
+
+    #[test]
+    fn list_that_fits_nowhere_whole_is_split_as_incomplete() {
+        let payload = AdvPayloadBuilder::new()
+            .service_uuids([UUID128, UUID128_B, UUID16])
+            .build()
+            .unwrap();
+
+        assert_eq!(
+            structures(&payload.adv_data),
+            vec![
+                (ad_type::FLAGS, vec![FLAGS_GENERAL_DISC_BREDR_NOT_SUPPORTED]),
+                (ad_type::INCOMPLETE_UUID128, le(&[UUID128])),
+                (ad_type::COMPLETE_UUID16, le(&[UUID16])),
+            ]
+        );
+        assert_eq!(
+            structures(&payload.scan_rsp),
+            vec![(ad_type::INCOMPLETE_UUID128, le(&[UUID128_B]))]
+        );
+    }
+
+    #[test]
+    fn narrower_lists_move_to_scan_response_when_advertising_is_full() {
+        let payload = AdvPayloadBuilder::new()
+            .service_uuids([UUID128, UUID32, Uuid::Uuid32(7), Uuid::Uuid32(8), UUID16])
+            .name("sensor")
+            .build()
+            .unwrap();
+
+        assert_eq!(
+            structures(&payload.adv_data),
+            vec![
+                (ad_type::FLAGS, vec![FLAGS_GENERAL_DISC_BREDR_NOT_SUPPORTED]),
+                (ad_type::COMPLETE_UUID128, le(&[UUID128])),
+                (ad_type::COMPLETE_UUID16, le(&[UUID16])),
+            ]
+        );
+        assert_eq!(
+            structures(&payload.scan_rsp),
+            vec![
+                (
+                    ad_type::COMPLETE_UUID32,
+                    le(&[UUID32, Uuid::Uuid32(7), Uuid::Uuid32(8)])
+                ),
+                (ad_type::COMPLETE_LOCAL_NAME, b"sensor".to_vec()),
+            ]
+        );
+    }
+
+    #[test]
+    fn too_many_uuids_are_reported_by_width() {
+        let uuids = (0..4).map(Uuid::Uuid128);
+        assert_eq!(
+            AdvPayloadBuilder::new().service_uuids(uuids).build(),
+            Err(AdvError::ServiceUuidsDoNotFit { width: 16 })
+        );
+    }
+
+    #[test]
+    fn overlong_structures_are_rejected_not_truncated() {
+        let mut buf = Vec::new();
+        assert_eq!(
+            push_ad(&mut buf, ad_type::MANUFACTURER_DATA, &[0; 300]),
+            Err(AdvError::AdTooLong { len: 300 })
+        );
+        assert!(buf.is_empty());
+
+        let fragment = AdFragment::ServiceData {
+            uuid: UUID16,
+            bytes: vec![0; MAX_AD_DATA_LEN - 1],
+        };
+        assert_eq!(
+            fragment.push_to(&mut buf),
+            Err(AdvError::AdTooLong {
+                len: MAX_AD_DATA_LEN + 1
+            })
+        );
+
+        let fragment = AdFragment::Raw {
+            ad_type: 0x2A,
+            data: vec![1; MAX_AD_DATA_LEN],
+        };
+        assert_eq!(fragment.push_to(&mut buf), Ok(()));
+        assert_eq!(buf.len(), fragment.encoded_len());
+        assert_eq!(buf.len(), MAX_PAYLOAD_LEN);
+    }
+
+    #[test]
+    fn name_budget_counts_bytes() {
+        let name_in = |name: String| {
+            let payload = AdvPayloadBuilder::new().name(name.clone()).build()?;
+            let entry = (ad_type::COMPLETE_LOCAL_NAME, name.into_bytes());
+            Ok((
+                structures(&payload.adv_data).contains(&entry),
+                structures(&payload.scan_rsp).contains(&entry),
+            ))
+        };
+
+        // After the flags, 26 bytes of name fit in the advertising data:
+        // 13 two-byte characters, but not 13 and an ASCII one.
+        assert_eq!(name_in("é".repeat(13)), Ok((true, false)));
+        assert_eq!(name_in("é".repeat(13) + "a"), Ok((false, true)));
+        // 29 bytes fit in the scan response, 30 nowhere.
+        assert_eq!(name_in("🌡".repeat(7) + "a"), Ok((false, true)));
+        assert_eq!(name_in("🌡".repeat(7) + "é"), Err(AdvError::NameDoesNotFit));
+    }
+}
@@ -0,0 +1,228 @@
+//! Device alerts: wire format and retention.
+//!
+//! Frame layout (little endian):
+//!
+//! | severity: u8 | code: u16 | message: utf8 ... |
+//!
+//! The message is optional and cut at a character boundary to fit the
+//! space available. An empty value on the read characteristic means no
+//! alert is active.
+//!
+//! [`AlertBoard`] keeps the active alerts, one per code. Alerts raised while
+//! nobody is subscribed are retained and handed out on the next
+//! subscription.
+
+use core::cmp::Reverse;
+use std::collections::{BTreeMap, BTreeSet};
+
+use super::utf8::truncate_utf8;
+
+pub const ALERT_HEADER_LEN: usize = 3;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Alert {
+    /// Higher is more severe.
+    pub severity: u8,
+    pub code: u16,
+    pub message: String,
+}
+
+impl Alert {
+    pub fn new(severity: u8, code: u16) -> Self {
+        Self {
+            severity,
+            code,
+            message: String::new(),
+        }
+    }
+
+    pub fn with_message(mut self, message: impl Into<String>) -> Self {
+        self.message = message.into();
+        self
+    }
+
+    /// Encodes the alert into at most `max_len` bytes (at least the header),
+    /// truncating the message as needed.
+    pub fn encode(&self, max_len: usize) -> Vec<u8> {
+        let message = truncate_utf8(&self.message, max_len.saturating_sub(ALERT_HEADER_LEN));
+
+        let mut frame = Vec::with_capacity(ALERT_HEADER_LEN + message.len());
+        frame.push(self.severity);
+        frame.extend_from_slice(&self.code.to_le_bytes());
+        frame.extend_from_slice(message.as_bytes());
+        frame
+    }
+
+    /// Decodes a frame; `None` if it is too short or the message is not
+    /// UTF-8.
+    pub fn decode(frame: &[u8]) -> Option<Self> {
+        if frame.len() < ALERT_HEADER_LEN {
+            return None;
+        }
+
+        let (header, message) = frame.split_at(ALERT_HEADER_LEN);
+        Some(Self {
+            severity: header[0],
+            code: u16::from_le_bytes([header[1], header[2]]),
+            message: core::str::from_utf8(message).ok()?.to_owned(),
+        })
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct AlertBoard {
+    active: BTreeMap<u16, Alert>,
+    /// Codes raised while nobody was subscribed, not yet delivered.
+    retained: BTreeSet<u16>,
+}
+
+impl AlertBoard {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records `alert`, replacing an active one with the same code. Returns
+    /// the alert to notify now, or `None` if it was retained because nobody
+    /// is `subscribed`.
+    pub fn raise(&mut self, alert: Alert, subscribed: bool) -> Option<&Alert> {
+        let code = alert.code;
+        self.active.insert(code, alert);
+
+        if subscribed {
+            self.retained.remove(&code);
+            self.active.get(&code)
+        } else {
+            self.retained.insert(code);
+            None
+        }
+    }
+
+    /// Clears the alert with `code`, returning it if it was active.
+    pub fn clear(&mut self, code: u16) -> Option<Alert> {
+        self.retained.remove(&code);
+        self.active.remove(&code)
+    }
+
+    /// Active alerts, most severe first.
+    pub fn active_alerts(&self) -> Vec<&Alert> {
+        let mut alerts: Vec<_> = self.active.values().collect();
+        alerts.sort_by_key(|alert| Reverse(alert.severity));
+        alerts
+    }
+
+    /// The most severe active alert; among equals, the lowest code.
+    pub fn highest(&self) -> Option<&Alert> {
+        self.active_alerts().first().copied()
+    }
+
+    /// Value of the read characteristic.
+    pub fn read_value(&self, max_len: usize) -> Vec<u8> {
+        self.highest()
+            .map(|alert| alert.encode(max_len))
+            .unwrap_or_default()
+    }
+
+    /// Retained alerts to notify to a new subscriber, most severe first.
+    /// They count as delivered afterwards.
+    pub fn on_subscribe(&mut self) -> Vec<&Alert> {
+        let retained = core::mem::take(&mut self.retained);
+        let mut alerts: Vec<_> = retained
+            .iter()
+            .filter_map(|code| self.active.get(code))
+            .collect();
+        alerts.sort_by_key(|alert| Reverse(alert.severity));
+        alerts
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::*;
+
+    const OVERHEAT: u16 = 0x0101;
+    const LOW_BATTERY: u16 = 0x0202;
+
+    #[test]
+    fn one_alert_retained_per_code() {
+        let mut board = AlertBoard::new();
+        assert_eq!(board.raise(Alert::new(1, LOW_BATTERY), false), None);
+        assert_eq!(board.raise(Alert::new(2, LOW_BATTERY), false), None);
+        assert_eq!(board.raise(Alert::new(3, OVERHEAT), false), None);
+
+        assert_eq!(board.active_alerts().len(), 2);
+        assert_eq!(
+            board.on_subscribe(),
+            [&Alert::new(3, OVERHEAT), &Alert::new(2, LOW_BATTERY)]
+        );
+    }
+
+    #[test]
+    fn raising_a_code_again_replaces_it() {
+        let mut board = AlertBoard::new();
+        board.raise(Alert::new(1, OVERHEAT).with_message("41 C"), true);
+
+        let raised = board.raise(Alert::new(4, OVERHEAT).with_message("60 C"), true);
+        assert_eq!(raised, Some(&Alert::new(4, OVERHEAT).with_message("60 C")));
+        assert_eq!(board.active_alerts().len(), 1);
+        assert_eq!(
+            board.read_value(20),
+            [4, 0x01, 0x01, b'6', b'0', b' ', b'C']
+        );
+        assert_eq!(
+            board.read_value(ALERT_HEADER_LEN + 2),
+            [4, 0x01, 0x01, b'6', b'0']
+        );
+    }
+
+    #[test]
+    fn retained_alerts_are_delivered_once_on_subscribe() {
+        let mut board = AlertBoard::new();
+        board.raise(Alert::new(1, LOW_BATTERY), false);
+        board.raise(Alert::new(2, OVERHEAT), false);
+        board.clear(OVERHEAT);
+
+        assert_eq!(board.on_subscribe(), [&Alert::new(1, LOW_BATTERY)]);
+        // Delivered: the next subscriber only reads the active alert.
+        assert!(board.on_subscribe().is_empty());
+        assert_eq!(board.highest(), Some(&Alert::new(1, LOW_BATTERY)));
+
+        // Raised while subscribed: notified now, nothing retained.
+        assert!(board.raise(Alert::new(5, OVERHEAT), true).is_some());
+        assert!(board.on_subscribe().is_empty());
+
+        assert_eq!(board.clear(LOW_BATTERY), Some(Alert::new(1, LOW_BATTERY)));
+        assert_eq!(board.clear(OVERHEAT), Some(Alert::new(5, OVERHEAT)));
+        assert!(board.read_value(20).is_empty());
+    }
+
+    proptest! {
+        #[test]
+        fn decode_never_panics(frame in prop::collection::vec(any::<u8>(), 0..32)) {
+            if let Some(alert) = Alert::decode(&frame) {
+                prop_assert_eq!(alert.encode(usize::MAX), frame.clone());
+            }
+        }
+
+        #[test]
+        fn encode_decode_round_trips(
+            severity in any::<u8>(),
+            code in any::<u16>(),
+            message in ".{0,16}",
+        ) {
+            let alert = Alert::new(severity, code).with_message(message);
+            prop_assert_eq!(Alert::decode(&alert.encode(usize::MAX)), Some(alert));
+        }
+
+        #[test]
+        fn truncated_encoding_still_decodes(message in ".{0,16}", max_len in 0usize..24) {
+            let alert = Alert::new(2, 7).with_message(message);
+            let frame = alert.encode(max_len);
+            prop_assert!(frame.len() <= max_len.max(ALERT_HEADER_LEN));
+
+            let decoded = Alert::decode(&frame).unwrap();
+            prop_assert!(alert.message.starts_with(&decoded.message));
+        }
+    }
+}
@@ -0,0 +1,93 @@
+//! GAP appearance values.
+//!
+//! The Bluetooth SIG encodes appearance as a 16-bit value: the upper 10 bits
+//! are the category and the lower 6 bits the subcategory. The coarse
+//! `AppearanceCategory` enum from esp-idf-svc only covers the category part, so
+//! this type carries the full value and is used both for the advertising
+//! payload and for the GAP appearance characteristic.
+
+const SUBCATEGORY_BITS: u16 = 6;
+const SUBCATEGORY_MASK: u16 = (1 << SUBCATEGORY_BITS) - 1;
+
+/// Full 16-bit GAP appearance (category + subcategory).
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Appearance(u16);
+
+impl Appearance {
+    pub const UNKNOWN: Self = Self::new(0x000, 0);
+    pub const GENERIC_PHONE: Self = Self::new(0x001, 0);
+    pub const GENERIC_COMPUTER: Self = Self::new(0x002, 0);
+    pub const GENERIC_REMOTE_CONTROL: Self = Self::new(0x006, 0);
+    pub const GENERIC_TAG: Self = Self::new(0x008, 0);
+    pub const GENERIC_HID: Self = Self::new(0x00F, 0);
+    pub const HID_KEYBOARD: Self = Self::new(0x00F, 1);
+    pub const GENERIC_NETWORK_DEVICE: Self = Self::new(0x014, 0);
+    pub const NETWORK_ACCESS_POINT: Self = Self::new(0x014, 1);
+    pub const GENERIC_SENSOR: Self = Self::new(0x015, 0);
+    pub const GENERIC_PERSONAL_MOBILITY_DEVICE: Self = Self::new(0x033, 0);
+
+    /// Builds an appearance from a 10-bit category and a 6-bit subcategory.
+    /// Out-of-range bits are masked off.
+    pub const fn new(category: u16, subcategory: u8) -> Self {
+        Self((category << SUBCATEGORY_BITS) | (subcategory as u16 & SUBCATEGORY_MASK))
+    }
+
+    /// Escape hatch for values not covered by the named constants.
+    pub const fn from_raw(raw: u16) -> Self {
+        Self(raw)
+    }
+
+    pub const fn raw(self) -> u16 {
+        self.0
+    }
+
+    pub const fn category(self) -> u16 {
+        self.0 >> SUBCATEGORY_BITS
+    }
+
+    pub const fn subcategory(self) -> u8 {
+        (self.0 & SUBCATEGORY_MASK) as u8
+    }
+}
+
+impl From<u16> for Appearance {
+    fn from(raw: u16) -> Self {
+        Self::from_raw(raw)
+    }
+}
+
+impl From<Appearance> for u16 {
+    fn from(appearance: Appearance) -> Self {
+        appearance.raw()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_category_and_subcategory() {
+        assert_eq!(Appearance::HID_KEYBOARD.raw(), 0x03C1);
+        assert_eq!(Appearance::HID_KEYBOARD.category(), 0x00F);
+        assert_eq!(Appearance::HID_KEYBOARD.subcategory(), 1);
+        assert_eq!(Appearance::GENERIC_SENSOR.raw(), 0x0540);
+    }
+
+    #[test]
+    fn out_of_range_subcategory_is_masked() {
+        let appearance = Appearance::new(0x015, 0xC2);
+        assert_eq!(appearance.category(), 0x015);
+        assert_eq!(appearance.subcategory(), 0x02);
+    }
+
+    #[test]
+    fn raw_conversions_round_trip() {
+        let appearance = Appearance::from(0xBEEF);
+        assert_eq!(u16::from(appearance), 0xBEEF);
+        assert_eq!(
+            Appearance::new(appearance.category(), appearance.subcategory()),
+            appearance
+        );
+    }
+}
@@ -0,0 +1,43 @@
+//! Bluetooth SIG assigned numbers used by the services in this crate.
+//!
+//! Use these instead of raw 16-bit literals; a mistyped UUID is accepted by
+//! the stack and only shows up as a client that cannot find the attribute.
+
+use super::Uuid;
+
+pub mod services {
+    use super::Uuid;
+
+    pub const GENERIC_ACCESS: Uuid = Uuid::Uuid16(0x1800);
+    pub const GENERIC_ATTRIBUTE: Uuid = Uuid::Uuid16(0x1801);
+    pub const DEVICE_INFORMATION: Uuid = Uuid::Uuid16(0x180A);
+    pub const BATTERY: Uuid = Uuid::Uuid16(0x180F);
+    pub const ENVIRONMENTAL_SENSING: Uuid = Uuid::Uuid16(0x181A);
+}
+
+pub mod characteristics {
+    use super::Uuid;
+
+    pub const DEVICE_NAME: Uuid = Uuid::Uuid16(0x2A00);
+    pub const APPEARANCE: Uuid = Uuid::Uuid16(0x2A01);
+    pub const SERVICE_CHANGED: Uuid = Uuid::Uuid16(0x2A05);
+    pub const BATTERY_LEVEL: Uuid = Uuid::Uuid16(0x2A19);
+    pub const MODEL_NUMBER: Uuid = Uuid::Uuid16(0x2A24);
+    pub const SERIAL_NUMBER: Uuid = Uuid::Uuid16(0x2A25);
+    pub const FIRMWARE_REVISION: Uuid = Uuid::Uuid16(0x2A26);
+    pub const HARDWARE_REVISION: Uuid = Uuid::Uuid16(0x2A27);
+    pub const SOFTWARE_REVISION: Uuid = Uuid::Uuid16(0x2A28);
+    pub const MANUFACTURER_NAME: Uuid = Uuid::Uuid16(0x2A29);
+    pub const TEMPERATURE: Uuid = Uuid::Uuid16(0x2A6E);
+    pub const HUMIDITY: Uuid = Uuid::Uuid16(0x2A6F);
+}
+
+pub mod descriptors {
+    use super::Uuid;
+
+    pub const CHAR_EXTENDED_PROPERTIES: Uuid = Uuid::Uuid16(0x2900);
+    pub const CHAR_USER_DESCRIPTION: Uuid = Uuid::Uuid16(0x2901);
+    pub const CLIENT_CHAR_CONFIG: Uuid = Uuid::Uuid16(0x2902);
+    pub const SERVER_CHAR_CONFIG: Uuid = Uuid::Uuid16(0x2903);
+    pub const CHAR_PRESENTATION_FORMAT: Uuid = Uuid::Uuid16(0x2904);
+}
@@ -0,0 +1,313 @@
+//! Binary format for captured GATTS event sessions.
+//!
+//! A capture is a header followed by records, all integers little-endian:
+//!
+//! | magic: "GCAP" | version: u8 |
+//!
+//! | kind: u8 | flags: u8 | conn_id: u16 | handle: u16 | status: u8 |
+//! | delta_ms: u32 | len: u16 |
+//!
+//! and, if flag 0x01 is set, | captured: u16 | payload: [u8; captured] |
+//!
+//! `delta_ms` is the time since the previous record (since the start of the
+//! capture for the first one). `len` is always the original payload length,
+//! whether or not the payload bytes follow; `captured` may be shorter when
+//! the payload was truncated. Readers reject other versions.
+
+use core::fmt;
+use std::io::{self, Read, Write};
+
+pub const MAGIC: [u8; 4] = *b"GCAP";
+pub const VERSION: u8 = 2;
+
+const HEADER_LEN: usize = 5;
+const RECORD_LEN: usize = 13;
+
+const FLAG_PAYLOAD: u8 = 0x01;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CaptureRecord {
+    /// Raw event kind; the capture format does not interpret it.
+    pub kind: u8,
+    pub conn_id: u16,
+    pub handle: u16,
+    pub status: u8,
+    pub delta_ms: u32,
+    pub len: u16,
+    /// Present only if payloads were captured for this record; at most
+    /// `len` bytes, fewer if truncated.
+    pub payload: Option<Vec<u8>>,
+}
+
+#[derive(Debug)]
+pub enum CaptureError {
+    Io(io::Error),
+    BadMagic([u8; 4]),
+    UnsupportedVersion(u8),
+    /// The stream ended inside a record.
+    Truncated,
+    /// A record's payload is longer than its `len` field.
+    PayloadLength {
+        len: u16,
+        payload: usize,
+    },
+}
+
+impl fmt::Display for CaptureError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "capture i/o error: {err}"),
+            Self::BadMagic(magic) => write!(f, "not a capture, magic {magic:02x?}"),
+            Self::UnsupportedVersion(version) => {
+                write!(
+                    f,
+                    "unsupported capture version {version}, expected {VERSION}"
+                )
+            }
+            Self::Truncated => write!(f, "capture ends inside a record"),
+            Self::PayloadLength { len, payload } => {
+                write!(f, "record length {len} but {payload} payload bytes")
+            }
+        }
+    }
+}
+
+impl std::error::Error for CaptureError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for CaptureError {
+    fn from(err: io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+/// Writes the header on creation, then one record per [`write`](Self::write).
+#[derive(Debug)]
+pub struct CaptureWriter<W> {
+    sink: W,
+}
+
+impl<W: Write> CaptureWriter<W> {
+    pub fn new(mut sink: W) -> io::Result<Self> {
+        let mut header = [0; HEADER_LEN];
+        header[..4].copy_from_slice(&MAGIC);
+        header[4] = VERSION;
+        sink.write_all(&header)?;
+
+        Ok(Self { sink })
+    }
+
+    pub fn write(&mut self, record: &CaptureRecord) -> Result<(), CaptureError> {
+        if let Some(payload) = &record.payload {
+            if payload.len() > usize::from(record.len) {
+                return Err(CaptureError::PayloadLength {
+                    len: record.len,
+                    payload: payload.len(),
+                });
+            }
+        }
+
+        let mut header = [0; RECORD_LEN];
+        header[0] = record.kind;
+        header[1] = if record.payload.is_some() {
+            FLAG_PAYLOAD
+        } else {
+            0
+        };
+        header[2..4].copy_from_slice(&record.conn_id.to_le_bytes());
+        header[4..6].copy_from_slice(&record.handle.to_le_bytes());
+        header[6] = record.status;
+        header[7..11].copy_from_slice(&record.delta_ms.to_le_bytes());
+        header[11..13].copy_from_slice(&record.len.to_le_bytes());
+
+        self.sink.write_all(&header)?;
+        if let Some(payload) = &record.payload {
+            // Not longer than `len`, checked above.
+            self.sink.write_all(&(payload.len() as u16).to_le_bytes())?;
+            self.sink.write_all(payload)?;
+        }
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.sink.flush()
+    }
+
+    pub fn into_inner(self) -> W {
+        self.sink
+    }
+}
+
+#[derive(Debug)]
+pub struct CaptureReader<R> {
+    source: R,
+}
+
+impl<R: Read> CaptureReader<R> {
+    /// Reads and checks the header.
+    pub fn new(mut source: R) -> Result<Self, CaptureError> {
+        let mut header = [0; HEADER_LEN];
+        if !read_full(&mut source, &mut header)? {
+            return Err(CaptureError::Truncated);
+        }
+
+        let magic = [header[0], header[1], header[2], header[3]];
+        if magic != MAGIC {
+            return Err(CaptureError::BadMagic(magic));
+        }
+        if header[4] != VERSION {
+            return Err(CaptureError::UnsupportedVersion(header[4]));
+        }
+
+        Ok(Self { source })
+    }
+
+    /// The next record, `None` at a clean end of stream.
+    pub fn next_record(&mut self) -> Result<Option<CaptureRecord>, CaptureError> {
+        let mut header = [0; RECORD_LEN];
+        if !read_full(&mut self.source, &mut header)? {
+            return Ok(None);
+        }
+
+        let len = u16::from_le_bytes([header[11], header[12]]);
+        let payload = if header[1] & FLAG_PAYLOAD != 0 {
+            let mut captured = [0; 2];
+            if !read_full(&mut self.source, &mut captured)? {
+                return Err(CaptureError::Truncated);
+            }
+            let captured = u16::from_le_bytes(captured);
+            if captured > len {
+                return Err(CaptureError::PayloadLength {
+                    len,
+                    payload: captured.into(),
+                });
+            }
+
+            let mut payload = vec![0; usize::from(captured)];
+            if !read_full(&mut self.source, &mut payload)? {
+                return Err(CaptureError::Truncated);
+            }
+            Some(payload)
+        } else {
+            None
+        };
+
+        Ok(Some(CaptureRecord {
+            kind: header[0],
+            conn_id: u16::from_le_bytes([header[2], header[3]]),
+            handle: u16::from_le_bytes([header[4], header[5]]),
+            status: header[6],
+            delta_ms: u32::from_le_bytes([header[7], header[8], header[9], header[10]]),
+            len,
+            payload,
+        }))
+    }
+}
+
+impl<R: Read> Iterator for CaptureReader<R> {
+    type Item = Result<CaptureRecord, CaptureError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_record().transpose()
+    }
+}
+
+/// Fills `buf`; `Ok(false)` if the stream was already at its end,
+/// [`CaptureError::Truncated`] if it ended part way.
+fn read_full(source: &mut impl Read, buf: &mut [u8]) -> Result<bool, CaptureError> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match source.read(&mut buf[filled..]) {
+            Ok(0) if filled == 0 => return Ok(false),
+            Ok(0) => return Err(CaptureError::Truncated),
+            Ok(n) => filled += n,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(err) => return Err(err.into()),
+        }
+    }
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(len: u16, payload: Option<&[u8]>) -> CaptureRecord {
+        CaptureRecord {
+            kind: 5,
+            conn_id: 1,
+            handle: 42,
+            status: 0,
+            delta_ms: 250,
+            len,
+            payload: payload.map(<[u8]>::to_vec),
+        }
+    }
+
+    fn round_trip(records: &[CaptureRecord]) -> Vec<CaptureRecord> {
+        let mut writer = CaptureWriter::new(Vec::new()).unwrap();
+        for record in records {
+            writer.write(record).unwrap();
+        }
+        let bytes = writer.into_inner();
+
+        CaptureReader::new(bytes.as_slice())
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap()
+    }
+
+    #[test]
+    fn records_round_trip() {
+        let records = [
+            record(3, Some(b"abc")),
+            record(20, None),
+            // Truncated: the original length is kept.
+            record(512, Some(b"head")),
+            record(0, Some(b"")),
+        ];
+        assert_eq!(round_trip(&records), records);
+    }
+
+    #[test]
+    fn payload_longer_than_len_is_refused() {
+        let mut writer = CaptureWriter::new(Vec::new()).unwrap();
+        assert!(matches!(
+            writer.write(&record(2, Some(b"abc"))),
+            Err(CaptureError::PayloadLength { len: 2, payload: 3 })
+        ));
+    }
+
+    #[test]
+    fn bad_header_is_refused() {
+        assert!(matches!(
+            CaptureReader::new(&b"PCAP\x02"[..]),
+            Err(CaptureError::BadMagic(magic)) if magic == *b"PCAP"
+        ));
+        assert!(matches!(
+            CaptureReader::new(&b"GCAP\x01"[..]),
+            Err(CaptureError::UnsupportedVersion(1))
+        ));
+        assert!(matches!(
+            CaptureReader::new(&b"GCA"[..]),
+            Err(CaptureError::Truncated)
+        ));
+    }
+
+    #[test]
+    fn stream_ending_inside_a_record_is_truncated() {
+        let mut writer = CaptureWriter::new(Vec::new()).unwrap();
+        writer.write(&record(3, Some(b"abc"))).unwrap();
+        let mut bytes = writer.into_inner();
+        bytes.pop();
+
+        let mut reader = CaptureReader::new(bytes.as_slice()).unwrap();
+        assert!(matches!(reader.next_record(), Err(CaptureError::Truncated)));
+    }
+}
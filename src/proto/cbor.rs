@@ -0,0 +1,54 @@
+//! Minimal CBOR (RFC 8949) writer.
+//!
+//! Only what the descriptors served by the device need: definite-length
+//! maps and arrays, unsigned integers, text strings and booleans.
+
+const MAJOR_UINT: u8 = 0;
+const MAJOR_TEXT: u8 = 3;
+const MAJOR_ARRAY: u8 = 4;
+const MAJOR_MAP: u8 = 5;
+const FALSE: u8 = 0xF4;
+const TRUE: u8 = 0xF5;
+
+fn head(buf: &mut Vec<u8>, major: u8, n: u64) {
+    let major = major << 5;
+    match n {
+        0..=23 => buf.push(major | n as u8),
+        24..=0xFF => buf.extend_from_slice(&[major | 24, n as u8]),
+        0x100..=0xFFFF => {
+            buf.push(major | 25);
+            buf.extend_from_slice(&(n as u16).to_be_bytes());
+        }
+        0x1_0000..=0xFFFF_FFFF => {
+            buf.push(major | 26);
+            buf.extend_from_slice(&(n as u32).to_be_bytes());
+        }
+        _ => {
+            buf.push(major | 27);
+            buf.extend_from_slice(&n.to_be_bytes());
+        }
+    }
+}
+
+pub fn uint(buf: &mut Vec<u8>, value: u64) {
+    head(buf, MAJOR_UINT, value);
+}
+
+pub fn text(buf: &mut Vec<u8>, value: &str) {
+    head(buf, MAJOR_TEXT, value.len() as u64);
+    buf.extend_from_slice(value.as_bytes());
+}
+
+pub fn bool(buf: &mut Vec<u8>, value: bool) {
+    buf.push(if value { TRUE } else { FALSE });
+}
+
+/// Starts an array; `len` items must follow.
+pub fn array(buf: &mut Vec<u8>, len: usize) {
+    head(buf, MAJOR_ARRAY, len as u64);
+}
+
+/// Starts a map; `len` key-value pairs must follow.
+pub fn map(buf: &mut Vec<u8>, len: usize) {
+    head(buf, MAJOR_MAP, len as u64);
+}
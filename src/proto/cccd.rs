@@ -0,0 +1,132 @@
+//! Client Characteristic Configuration Descriptor (0x2902) values.
+
+use core::ops::BitOr;
+
+/// Subscription bits of a CCCD value.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct CccdFlags(u16);
+
+impl CccdFlags {
+    pub const NONE: Self = Self(0);
+    pub const NOTIFY: Self = Self(0x0001);
+    pub const INDICATE: Self = Self(0x0002);
+
+    const DEFINED: u16 = Self::NOTIFY.0 | Self::INDICATE.0;
+
+    pub const fn bits(self) -> u16 {
+        self.0
+    }
+
+    pub const fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub const fn notify(self) -> bool {
+        self.contains(Self::NOTIFY)
+    }
+
+    pub const fn indicate(self) -> bool {
+        self.contains(Self::INDICATE)
+    }
+
+    pub const fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    /// The descriptor value to store or send; reserved bits are never set.
+    pub const fn encode(self) -> [u8; 2] {
+        (self.0 & Self::DEFINED).to_le_bytes()
+    }
+
+    /// Same as [`parse_cccd`].
+    pub fn decode(value: &[u8]) -> Option<Self> {
+        parse_cccd(value)
+    }
+}
+
+impl BitOr for CccdFlags {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+
+/// Parses a CCCD write. The value must be exactly two bytes (little endian);
+/// reserved bits are ignored.
+pub fn parse_cccd(value: &[u8]) -> Option<CccdFlags> {
+    let value: [u8; 2] = value.try_into().ok()?;
+    let bits = u16::from_le_bytes(value);
+
+    Some(CccdFlags(bits & CccdFlags::DEFINED))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    #[test]
+    fn parses_defined_bits() {
+        assert_eq!(parse_cccd(&[0x00, 0x00]), Some(CccdFlags::NONE));
+        assert_eq!(parse_cccd(&[0x01, 0x00]), Some(CccdFlags::NOTIFY));
+        assert_eq!(parse_cccd(&[0x02, 0x00]), Some(CccdFlags::INDICATE));
+
+        let both = parse_cccd(&[0x03, 0x00]).unwrap();
+        assert!(both.notify() && both.indicate());
+        assert_eq!(both, CccdFlags::NOTIFY | CccdFlags::INDICATE);
+    }
+
+    #[test]
+    fn reserved_bits_are_ignored() {
+        assert_eq!(parse_cccd(&[0xFD, 0xFF]), Some(CccdFlags::NOTIFY));
+        assert_eq!(parse_cccd(&[0x00, 0x80]), Some(CccdFlags::NONE));
+    }
+
+    #[test]
+    fn rejects_wrong_length() {
+        assert_eq!(parse_cccd(&[]), None);
+        assert_eq!(parse_cccd(&[0x01]), None);
+        assert_eq!(parse_cccd(&[0x01, 0x00, 0x00]), None);
+    }
+
+    #[test]
+    fn encode_round_trips() {
+        for flags in [
+            CccdFlags::NONE,
+            CccdFlags::NOTIFY,
+            CccdFlags::INDICATE,
+            CccdFlags::NOTIFY | CccdFlags::INDICATE,
+        ] {
+            assert_eq!(CccdFlags::decode(&flags.encode()), Some(flags));
+        }
+        assert_eq!(CccdFlags::NOTIFY.encode(), [0x01, 0x00]);
+    }
+
+    #[test]
+    fn encode_never_sets_reserved_bits() {
+        assert_eq!(CccdFlags(0xFFFF).encode(), [0x03, 0x00]);
+        assert_eq!(CccdFlags(0x8004).encode(), [0x00, 0x00]);
+        assert_eq!(
+            CccdFlags::decode(&[0xFE, 0xFF]).unwrap().encode(),
+            CccdFlags::INDICATE.encode()
+        );
+    }
+
+    proptest! {
+        #[test]
+        fn decode_never_panics(value in prop::collection::vec(any::<u8>(), 0..8)) {
+            if let Some(flags) = CccdFlags::decode(&value) {
+                prop_assert_eq!(value.len(), 2);
+                prop_assert_eq!(CccdFlags::decode(&flags.encode()), Some(flags));
+            }
+        }
+
+        #[test]
+        fn two_bytes_always_decode(bits in any::<u16>()) {
+            let flags = CccdFlags::decode(&bits.to_le_bytes()).unwrap();
+            prop_assert_eq!(flags.bits(), bits & 0x0003);
+            prop_assert_eq!(flags.encode(), flags.bits().to_le_bytes());
+        }
+    }
+}
@@ -0,0 +1,231 @@
+//! CRC-protected command envelope.
+//!
+//! Frame layout (all little endian):
+//!
+//! | version: u8 | seq: u8 | payload ... | crc16: u16 |
+//!
+//! The CRC (CRC-16/CCITT-FALSE) covers version, seq and payload. The sequence
+//! number lets the server recognise ATT-level retries of the same command.
+
+use core::fmt;
+use std::collections::HashMap;
+
+pub const ENVELOPE_VERSION: u8 = 1;
+
+/// Version + sequence number + CRC.
+pub const ENVELOPE_OVERHEAD: usize = 4;
+
+/// ATT application error returned for a frame that fails CRC or length checks.
+pub const ATT_ERR_CORRUPT_FRAME: u8 = 0x80;
+/// ATT application error returned for an unknown envelope version.
+pub const ATT_ERR_UNSUPPORTED_VERSION: u8 = 0x81;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Envelope<'a> {
+    pub seq: u8,
+    pub payload: &'a [u8],
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum EnvelopeError {
+    TooShort(usize),
+    BadCrc { expected: u16, actual: u16 },
+    UnsupportedVersion(u8),
+}
+
+impl EnvelopeError {
+    /// ATT status to answer the offending write with.
+    pub const fn att_status(&self) -> u8 {
+        match self {
+            Self::TooShort(_) | Self::BadCrc { .. } => ATT_ERR_CORRUPT_FRAME,
+            Self::UnsupportedVersion(_) => ATT_ERR_UNSUPPORTED_VERSION,
+        }
+    }
+}
+
+impl fmt::Display for EnvelopeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::TooShort(len) => write!(f, "envelope too short: {len} bytes"),
+            Self::BadCrc { expected, actual } => {
+                write!(f, "envelope CRC mismatch: {expected:#06x} != {actual:#06x}")
+            }
+            Self::UnsupportedVersion(version) => {
+                write!(f, "unsupported envelope version {version}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for EnvelopeError {}
+
+impl<'a> Envelope<'a> {
+    pub const fn new(seq: u8, payload: &'a [u8]) -> Self {
+        Self { seq, payload }
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut frame = Vec::with_capacity(self.payload.len() + ENVELOPE_OVERHEAD);
+        frame.push(ENVELOPE_VERSION);
+        frame.push(self.seq);
+        frame.extend_from_slice(self.payload);
+
+        let crc = crc16(&frame);
+        frame.extend_from_slice(&crc.to_le_bytes());
+
+        frame
+    }
+
+    pub fn decode(frame: &'a [u8]) -> Result<Self, EnvelopeError> {
+        if frame.len() < ENVELOPE_OVERHEAD {
+            return Err(EnvelopeError::TooShort(frame.len()));
+        }
+
+        let (body, crc) = frame.split_at(frame.len() - 2);
+        let expected = u16::from_le_bytes([crc[0], crc[1]]);
+        let actual = crc16(body);
+        if expected != actual {
+            return Err(EnvelopeError::BadCrc { expected, actual });
+        }
+
+        if body[0] != ENVELOPE_VERSION {
+            return Err(EnvelopeError::UnsupportedVersion(body[0]));
+        }
+
+        Ok(Self {
+            seq: body[1],
+            payload: &body[2..],
+        })
+    }
+}
+
+/// CRC-16/CCITT-FALSE (poly 0x1021, init 0xFFFF, no reflection).
+pub fn crc16(data: &[u8]) -> u16 {
+    data.iter().fold(0xFFFF, |crc, byte| {
+        (0..8).fold(crc ^ ((*byte as u16) << 8), |crc, _| {
+            if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            }
+        })
+    })
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Freshness {
+    /// Deliver to the handler.
+    Fresh,
+    /// Same sequence number as the previous command: acknowledge, don't deliver.
+    Duplicate,
+}
+
+/// Remembers the last sequence number per (connection, attribute handle).
+#[derive(Debug, Default)]
+pub struct DuplicateFilter {
+    last_seq: HashMap<(u16, u16), u8>,
+}
+
+impl DuplicateFilter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn check(&mut self, conn_id: u16, handle: u16, seq: u8) -> Freshness {
+        match self.last_seq.insert((conn_id, handle), seq) {
+            Some(last) if last == seq => Freshness::Duplicate,
+            _ => Freshness::Fresh,
+        }
+    }
+
+    /// Sequence state does not survive a reconnection.
+    pub fn on_disconnect(&mut self, conn_id: u16) {
+        self.last_seq.retain(|(conn, _), _| *conn != conn_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    #[test]
+    fn crc_matches_check_value() {
+        assert_eq!(crc16(b"123456789"), 0x29B1);
+    }
+
+    #[test]
+    fn encode_decode_round_trip() {
+        let frame = Envelope::new(42, b"toggle").encode();
+        assert_eq!(frame.len(), 6 + ENVELOPE_OVERHEAD);
+        assert_eq!(&frame[..2], &[ENVELOPE_VERSION, 42]);
+        assert_eq!(Envelope::decode(&frame), Ok(Envelope::new(42, b"toggle")));
+
+        let empty = Envelope::new(0, &[]).encode();
+        assert_eq!(Envelope::decode(&empty), Ok(Envelope::new(0, &[])));
+    }
+
+    #[test]
+    fn corrupt_frames_are_rejected() {
+        let mut frame = Envelope::new(1, b"on").encode();
+        frame[2] ^= 0x01;
+        let err = Envelope::decode(&frame).unwrap_err();
+        assert!(matches!(err, EnvelopeError::BadCrc { .. }));
+        assert_eq!(err.att_status(), ATT_ERR_CORRUPT_FRAME);
+
+        let err = Envelope::decode(&[ENVELOPE_VERSION, 1, 0]).unwrap_err();
+        assert_eq!(err, EnvelopeError::TooShort(3));
+        assert_eq!(err.att_status(), ATT_ERR_CORRUPT_FRAME);
+    }
+
+    #[test]
+    fn unknown_version_is_checked_after_crc() {
+        let mut frame = vec![ENVELOPE_VERSION + 1, 7, 0xAA];
+        frame.extend_from_slice(&crc16(&frame).to_le_bytes());
+
+        let err = Envelope::decode(&frame).unwrap_err();
+        assert_eq!(err, EnvelopeError::UnsupportedVersion(ENVELOPE_VERSION + 1));
+        assert_eq!(err.att_status(), ATT_ERR_UNSUPPORTED_VERSION);
+    }
+
+    #[test]
+    fn duplicates_are_per_connection_and_handle() {
+        let mut filter = DuplicateFilter::new();
+        assert_eq!(filter.check(1, 0x2A, 5), Freshness::Fresh);
+        assert_eq!(filter.check(1, 0x2A, 5), Freshness::Duplicate);
+        assert_eq!(filter.check(1, 0x2B, 5), Freshness::Fresh);
+        assert_eq!(filter.check(2, 0x2A, 5), Freshness::Fresh);
+        assert_eq!(filter.check(1, 0x2A, 6), Freshness::Fresh);
+        assert_eq!(filter.check(1, 0x2A, 5), Freshness::Fresh);
+    }
+
+    #[test]
+    fn disconnect_resets_sequence_state() {
+        let mut filter = DuplicateFilter::new();
+        filter.check(1, 0x2A, 9);
+        filter.check(2, 0x2A, 9);
+
+        filter.on_disconnect(1);
+        assert_eq!(filter.check(1, 0x2A, 9), Freshness::Fresh);
+        assert_eq!(filter.check(2, 0x2A, 9), Freshness::Duplicate);
+    }
+
+    proptest! {
+        #[test]
+        fn decode_never_panics(frame in prop::collection::vec(any::<u8>(), 0..64)) {
+            if let Ok(envelope) = Envelope::decode(&frame) {
+                prop_assert_eq!(envelope.encode(), frame.clone());
+            }
+        }
+
+        #[test]
+        fn encode_decode_round_trips(
+            seq in any::<u8>(),
+            payload in prop::collection::vec(any::<u8>(), 0..64),
+        ) {
+            let envelope = Envelope::new(seq, &payload);
+            let frame = envelope.encode();
+            prop_assert_eq!(Envelope::decode(&frame), Ok(envelope));
+        }
+    }
+}
@@ -0,0 +1,30 @@
+//! Byte-level protocol logic.
+//!
+//! Nothing in here depends on esp-idf, so the module builds and can be
+//! exercised on the host with `--no-default-features` (see `docs/README.md`).
+
+pub mod ack;
+pub mod adv;
+pub mod alert;
+pub mod appearance;
+pub mod assigned;
+pub mod capture;
+pub mod cbor;
+pub mod cccd;
+pub mod envelope;
+pub mod payload;
+pub mod prefs;
+pub mod seq;
+pub mod tlv;
+pub mod utf8;
+pub mod version;
+
+pub use adv::{AdFragment, AdvError, AdvPayload, AdvPayloadBuilder, Uuid};
+pub use alert::{Alert, AlertBoard};
+pub use appearance::Appearance;
+pub use cccd::{parse_cccd, CccdFlags};
+pub use envelope::{DuplicateFilter, Envelope, EnvelopeError, Freshness};
+pub use payload::SmallPayload;
+pub use prefs::{Preferences, TemperatureUnit};
+pub use utf8::truncate_utf8;
+pub use version::{Negotiated, ProtocolVersion};
@@ -0,0 +1,179 @@
+//! Byte buffer stored inline when small.
+//!
+//! Most writes and notifications are a few bytes; [`SmallPayload`] keeps
+//! up to [`INLINE_CAPACITY`] bytes without allocating and falls back to a
+//! `Vec` beyond. It derefs to `[u8]`, so both cases hand a contiguous slice
+//! to esp-idf without copying.
+
+use core::fmt;
+use core::ops::Deref;
+
+pub const INLINE_CAPACITY: usize = 32;
+
+#[derive(Clone)]
+pub struct SmallPayload(Repr);
+
+#[derive(Clone)]
+enum Repr {
+    Inline { len: u8, buf: [u8; INLINE_CAPACITY] },
+    Heap(Vec<u8>),
+}
+
+impl SmallPayload {
+    pub const fn new() -> Self {
+        Self(Repr::Inline {
+            len: 0,
+            buf: [0; INLINE_CAPACITY],
+        })
+    }
+
+    pub fn is_inline(&self) -> bool {
+        matches!(self.0, Repr::Inline { .. })
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        match &self.0 {
+            Repr::Inline { len, buf } => &buf[..usize::from(*len)],
+            Repr::Heap(vec) => vec,
+        }
+    }
+
+    pub fn into_vec(self) -> Vec<u8> {
+        match self.0 {
+            Repr::Inline { .. } => self.as_slice().to_vec(),
+            Repr::Heap(vec) => vec,
+        }
+    }
+}
+
+impl Default for SmallPayload {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Deref for SmallPayload {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        self.as_slice()
+    }
+}
+
+impl AsRef<[u8]> for SmallPayload {
+    fn as_ref(&self) -> &[u8] {
+        self.as_slice()
+    }
+}
+
+impl From<&[u8]> for SmallPayload {
+    fn from(value: &[u8]) -> Self {
+        if value.len() > INLINE_CAPACITY {
+            return Self(Repr::Heap(value.to_vec()));
+        }
+
+        let mut buf = [0; INLINE_CAPACITY];
+        buf[..value.len()].copy_from_slice(value);
+        Self(Repr::Inline {
+            len: value.len() as u8,
+            buf,
+        })
+    }
+}
+
+impl<const N: usize> From<&[u8; N]> for SmallPayload {
+    fn from(value: &[u8; N]) -> Self {
+        Self::from(&value[..])
+    }
+}
+
+/// Keeps the allocation of a long `value`; a short one is moved inline.
+impl From<Vec<u8>> for SmallPayload {
+    fn from(value: Vec<u8>) -> Self {
+        if value.len() > INLINE_CAPACITY {
+            Self(Repr::Heap(value))
+        } else {
+            Self::from(value.as_slice())
+        }
+    }
+}
+
+impl PartialEq for SmallPayload {
+    fn eq(&self, other: &Self) -> bool {
+        self.as_slice() == other.as_slice()
+    }
+}
+
+impl Eq for SmallPayload {}
+
+impl PartialEq<[u8]> for SmallPayload {
+    fn eq(&self, other: &[u8]) -> bool {
+        self.as_slice() == other
+    }
+}
+
+impl<const N: usize> PartialEq<&[u8; N]> for SmallPayload {
+    fn eq(&self, other: &&[u8; N]) -> bool {
+        self.as_slice() == other.as_slice()
+    }
+}
+
+impl fmt::Debug for SmallPayload {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self.as_slice(), f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_alloc::allocations;
+
+    #[test]
+    fn small_payloads_do_not_allocate() {
+        let (count, payload) = allocations(|| SmallPayload::from(&[7; 20]));
+        assert_eq!(count, 0);
+        assert!(payload.is_inline());
+
+        let (count, copy) = allocations(|| payload.clone());
+        assert_eq!(count, 0);
+        assert_eq!(copy, payload);
+
+        let (count, full) = allocations(|| SmallPayload::from(&[7; INLINE_CAPACITY]));
+        assert_eq!(count, 0);
+        assert!(full.is_inline());
+    }
+
+    #[test]
+    fn large_payloads_allocate_once() {
+        let (count, payload) = allocations(|| SmallPayload::from(&[7; INLINE_CAPACITY + 1]));
+        assert_eq!(count, 1);
+        assert!(!payload.is_inline());
+
+        // A long Vec keeps its allocation.
+        let vec = vec![7; 100];
+        let ptr = vec.as_ptr();
+        let (count, payload) = allocations(|| SmallPayload::from(vec));
+        assert_eq!(count, 0);
+        assert_eq!(payload.as_ptr(), ptr);
+        let vec = payload.into_vec();
+        assert_eq!(vec.as_ptr(), ptr);
+    }
+
+    #[test]
+    fn short_vec_moves_inline() {
+        let payload = SmallPayload::from(b"abc".to_vec());
+        assert!(payload.is_inline());
+        assert_eq!(payload, b"abc");
+        assert_eq!(&*payload, b"abc");
+        assert_eq!(payload.into_vec(), b"abc");
+    }
+
+    #[test]
+    fn empty() {
+        let payload = SmallPayload::new();
+        assert!(payload.is_inline());
+        assert!(payload.is_empty());
+        assert_eq!(payload, SmallPayload::default());
+    }
+}
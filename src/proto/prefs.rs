@@ -0,0 +1,125 @@
+//! Per-connection display preferences written by the client.
+//!
+//! Wire format, 4 bytes:
+//!
+//! | byte | field                                          |
+//! |------|------------------------------------------------|
+//! | 0    | format version, currently 1                    |
+//! | 1    | temperature unit: 0 = Celsius, 1 = Fahrenheit  |
+//! | 2..4 | ISO 639-1 language code, ASCII, e.g. `b"en"`   |
+//!
+//! Longer values are accepted and the extra bytes ignored, so later
+//! versions can append fields.
+
+use core::fmt;
+
+pub const PREFS_VERSION: u8 = 1;
+pub const PREFS_LEN: usize = 4;
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub enum TemperatureUnit {
+    #[default]
+    Celsius,
+    Fahrenheit,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Preferences {
+    pub temperature: TemperatureUnit,
+    pub language: [u8; 2],
+}
+
+impl Default for Preferences {
+    fn default() -> Self {
+        Self {
+            temperature: TemperatureUnit::Celsius,
+            language: *b"en",
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PrefsError {
+    TooShort { len: usize },
+    UnknownVersion(u8),
+    UnknownUnit(u8),
+    InvalidLanguage,
+}
+
+impl fmt::Display for PrefsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::TooShort { len } => {
+                write!(f, "preferences are {len} bytes, expected {PREFS_LEN}")
+            }
+            Self::UnknownVersion(version) => write!(f, "unknown preferences version {version}"),
+            Self::UnknownUnit(unit) => write!(f, "unknown temperature unit {unit}"),
+            Self::InvalidLanguage => f.write_str("language code is not two ASCII letters"),
+        }
+    }
+}
+
+impl std::error::Error for PrefsError {}
+
+impl Preferences {
+    pub fn encode(&self) -> [u8; PREFS_LEN] {
+        let unit = match self.temperature {
+            TemperatureUnit::Celsius => 0,
+            TemperatureUnit::Fahrenheit => 1,
+        };
+        [PREFS_VERSION, unit, self.language[0], self.language[1]]
+    }
+
+    pub fn decode(value: &[u8]) -> Result<Self, PrefsError> {
+        let Some(&[version, unit, lang0, lang1]) = value.get(..PREFS_LEN) else {
+            return Err(PrefsError::TooShort { len: value.len() });
+        };
+
+        if version != PREFS_VERSION {
+            return Err(PrefsError::UnknownVersion(version));
+        }
+        let temperature = match unit {
+            0 => TemperatureUnit::Celsius,
+            1 => TemperatureUnit::Fahrenheit,
+            other => return Err(PrefsError::UnknownUnit(other)),
+        };
+        let language = [lang0, lang1];
+        if !language.iter().all(u8::is_ascii_alphabetic) {
+            return Err(PrefsError::InvalidLanguage);
+        }
+
+        Ok(Self {
+            temperature,
+            language: language.map(|c| c.to_ascii_lowercase()),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::*;
+
+    proptest! {
+        #[test]
+        fn decode_never_panics(value in prop::collection::vec(any::<u8>(), 0..8)) {
+            if let Ok(prefs) = Preferences::decode(&value) {
+                prop_assert_eq!(Preferences::decode(&prefs.encode()), Ok(prefs));
+            }
+        }
+
+        #[test]
+        fn encode_decode_round_trips(fahrenheit in any::<bool>(), language in "[a-z]{2}") {
+            let prefs = Preferences {
+                temperature: if fahrenheit {
+                    TemperatureUnit::Fahrenheit
+                } else {
+                    TemperatureUnit::Celsius
+                },
+                language: language.as_bytes().try_into().unwrap(),
+            };
+            prop_assert_eq!(Preferences::decode(&prefs.encode()), Ok(prefs));
+        }
+    }
+}
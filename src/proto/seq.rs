@@ -0,0 +1,33 @@
+//! Wrapping `u32` sequence numbers.
+
+/// Serial number comparison (RFC 1982): `true` if `a` comes after `b`,
+/// treating sequence numbers less than 2^31 apart as ordered across the wrap.
+pub const fn is_after(a: u32, b: u32) -> bool {
+    a != b && a.wrapping_sub(b) < 1 << 31
+}
+
+/// Number of sequence numbers from `from` (inclusive) to `to` (exclusive).
+pub const fn distance(from: u32, to: u32) -> u32 {
+    to.wrapping_sub(from)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn orders_across_the_wrap() {
+        assert!(is_after(1, 0));
+        assert!(is_after(0, u32::MAX));
+        assert!(is_after(5, u32::MAX - 5));
+        assert!(!is_after(u32::MAX, 0));
+        assert!(!is_after(7, 7));
+    }
+
+    #[test]
+    fn distance_wraps() {
+        assert_eq!(distance(10, 15), 5);
+        assert_eq!(distance(u32::MAX - 1, 2), 4);
+        assert_eq!(distance(3, 3), 0);
+    }
+}
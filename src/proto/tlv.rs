@@ -0,0 +1,129 @@
+//! Tag-length-value records.
+//!
+//! | tag: u8 | len: u8 | value: len bytes | ...
+//!
+//! Tag [`ERROR_TAG`] is reserved: its value is the tag that could not be
+//! produced followed by an ATT status, so one failing entry does not void
+//! the rest of a record set.
+
+pub const ERROR_TAG: u8 = 0xFF;
+pub const MAX_VALUE_LEN: usize = u8::MAX as usize;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Tlv<'a> {
+    Value { tag: u8, value: &'a [u8] },
+    Error { tag: u8, status: u8 },
+}
+
+/// Appends a record; `None` if `value` is longer than [`MAX_VALUE_LEN`] or
+/// `tag` is [`ERROR_TAG`].
+pub fn push(buf: &mut Vec<u8>, tag: u8, value: &[u8]) -> Option<()> {
+    if tag == ERROR_TAG {
+        return None;
+    }
+    let len = u8::try_from(value.len()).ok()?;
+
+    buf.push(tag);
+    buf.push(len);
+    buf.extend_from_slice(value);
+    Some(())
+}
+
+/// Appends an error record for `tag`.
+pub fn push_error(buf: &mut Vec<u8>, tag: u8, status: u8) {
+    buf.extend_from_slice(&[ERROR_TAG, 2, tag, status]);
+}
+
+/// Iterates the records of `buf`. Yields `Err(offset)` and stops at a
+/// record running past the end.
+pub fn records(buf: &[u8]) -> Records<'_> {
+    Records { buf, offset: 0 }
+}
+
+#[derive(Clone, Debug)]
+pub struct Records<'a> {
+    buf: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Iterator for Records<'a> {
+    type Item = Result<Tlv<'a>, usize>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let rest = &self.buf[self.offset..];
+        if rest.is_empty() {
+            return None;
+        }
+
+        let start = self.offset;
+        let record = match rest {
+            [tag, len, tail @ ..] if tail.len() >= usize::from(*len) => {
+                let value = &tail[..usize::from(*len)];
+                match (*tag, value) {
+                    (ERROR_TAG, &[tag, status]) => Ok(Tlv::Error { tag, status }),
+                    (ERROR_TAG, _) => Err(start),
+                    (tag, value) => Ok(Tlv::Value { tag, value }),
+                }
+            }
+            _ => Err(start),
+        };
+
+        match record {
+            Ok(_) => self.offset += 2 + usize::from(rest[1]),
+            Err(_) => self.offset = self.buf.len(),
+        }
+        Some(record)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::*;
+    use crate::test_alloc::allocations;
+
+    proptest! {
+        #[test]
+        fn decode_never_panics_or_allocates(buf in prop::collection::vec(any::<u8>(), 0..128)) {
+            let (allocated, records) = allocations(|| records(&buf).count());
+            prop_assert_eq!(allocated, 0);
+            // Every record takes at least two bytes.
+            prop_assert!(records <= buf.len() / 2 + 1);
+        }
+
+        #[test]
+        fn decode_reencodes_the_valid_prefix(buf in prop::collection::vec(any::<u8>(), 0..128)) {
+            let mut encoded = Vec::new();
+            let mut valid_len = buf.len();
+            for record in records(&buf) {
+                match record {
+                    Ok(Tlv::Value { tag, value }) => push(&mut encoded, tag, value).unwrap(),
+                    Ok(Tlv::Error { tag, status }) => push_error(&mut encoded, tag, status),
+                    Err(offset) => valid_len = offset,
+                }
+            }
+            prop_assert_eq!(&encoded[..], &buf[..valid_len]);
+        }
+
+        #[test]
+        fn encode_decode_round_trips(
+            entries in prop::collection::vec(
+                (0u8..ERROR_TAG, prop::collection::vec(any::<u8>(), 0..=MAX_VALUE_LEN)),
+                0..8,
+            ),
+        ) {
+            let mut buf = Vec::new();
+            for (tag, value) in &entries {
+                push(&mut buf, *tag, value).unwrap();
+            }
+
+            let decoded: Vec<_> = records(&buf).collect();
+            let expected: Vec<_> = entries
+                .iter()
+                .map(|(tag, value)| Ok(Tlv::Value { tag: *tag, value }))
+                .collect();
+            prop_assert_eq!(decoded, expected);
+        }
+    }
+}
@@ -0,0 +1,41 @@
+//! Byte-based string limits.
+//!
+//! Radio-side limits (31-byte advertising payloads, the MTU, attribute
+//! sizes) count bytes, not characters. Cutting a string at an arbitrary
+//! byte offset can split a multi-byte character and produce invalid UTF-8,
+//! which some clients refuse to display or choke on.
+
+/// Longest prefix of `s` that is at most `max_bytes` long and ends on a
+/// character boundary.
+pub fn truncate_utf8(s: &str, max_bytes: usize) -> &str {
+    if s.len() <= max_bytes {
+        return s;
+    }
+
+    let mut end = max_bytes;
+    while !s.is_char_boundary(end) {
+        end -= 1;
+    }
+    &s[..end]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn short_strings_are_untouched() {
+        assert_eq!(truncate_utf8("sensor", 6), "sensor");
+        assert_eq!(truncate_utf8("sensor", 31), "sensor");
+    }
+
+    #[test]
+    fn never_splits_a_character() {
+        // "é" and "ü" are two bytes each, "🌡" is four.
+        assert_eq!(truncate_utf8("café", 4), "caf");
+        assert_eq!(truncate_utf8("café", 5), "café");
+        assert_eq!(truncate_utf8("über", 1), "");
+        assert_eq!(truncate_utf8("a🌡", 4), "a");
+        assert_eq!(truncate_utf8("a🌡", 0), "");
+    }
+}
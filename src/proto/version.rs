@@ -0,0 +1,223 @@
+//! Protocol version and capability negotiation.
+//!
+//! A service declares its version on a read characteristic; the client
+//! writes its own to a paired characteristic. Both use the same 6-byte
+//! value (little endian):
+//!
+//! | major: u8 | minor: u8 | features: u32 |
+//!
+//! Peers with the same major version interoperate at the lower minor
+//! version and the features both claim.
+
+use core::fmt;
+
+use super::Uuid;
+
+pub const VERSION_LEN: usize = 6;
+
+/// Read characteristic carrying the service's [`ProtocolVersion`].
+pub const SERVER_VERSION_UUID: Uuid = Uuid::Uuid128(0x5e1f_0001_7a3c_4b6e_9d2a_61c8_f04b_7e93);
+/// Write characteristic the client puts its [`ProtocolVersion`] into.
+pub const CLIENT_VERSION_UUID: Uuid = Uuid::Uuid128(0x5e1f_0002_7a3c_4b6e_9d2a_61c8_f04b_7e93);
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ProtocolVersion {
+    pub major: u8,
+    pub minor: u8,
+    pub features: u32,
+}
+
+impl ProtocolVersion {
+    pub const fn new(major: u8, minor: u8, features: u32) -> Self {
+        Self {
+            major,
+            minor,
+            features,
+        }
+    }
+
+    pub fn encode(&self) -> [u8; VERSION_LEN] {
+        let mut value = [0; VERSION_LEN];
+        value[0] = self.major;
+        value[1] = self.minor;
+        value[2..].copy_from_slice(&self.features.to_le_bytes());
+        value
+    }
+
+    /// Decodes a value; longer values are accepted so later revisions can
+    /// append fields.
+    pub fn decode(value: &[u8]) -> Option<Self> {
+        if value.len() < VERSION_LEN {
+            return None;
+        }
+
+        Some(Self {
+            major: value[0],
+            minor: value[1],
+            features: u32::from_le_bytes([value[2], value[3], value[4], value[5]]),
+        })
+    }
+
+    /// What `self` (the server) and `peer` can use together.
+    pub fn negotiate(&self, peer: &Self) -> Result<Negotiated, VersionMismatch> {
+        if self.major != peer.major {
+            return Err(VersionMismatch {
+                local: self.major,
+                peer: peer.major,
+            });
+        }
+
+        Ok(Negotiated {
+            major: self.major,
+            minor: self.minor.min(peer.minor),
+            features: self.features & peer.features,
+        })
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Negotiated {
+    pub major: u8,
+    pub minor: u8,
+    pub features: u32,
+}
+
+impl Negotiated {
+    /// Whether both sides claimed all of `features`.
+    pub const fn supports(&self, features: u32) -> bool {
+        self.features & features == features
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct VersionMismatch {
+    pub local: u8,
+    pub peer: u8,
+}
+
+impl fmt::Display for VersionMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "peer speaks major version {}, this service {}",
+            self.peer, self.local
+        )
+    }
+}
+
+impl std::error::Error for VersionMismatch {}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::*;
+
+    const COMPRESSION: u32 = 0x01;
+    const BATCHING: u32 = 0x02;
+    const SIGNED: u32 = 0x04;
+
+    #[test]
+    fn encode_layout() {
+        let version = ProtocolVersion::new(2, 7, 0x0403_0201);
+        assert_eq!(version.encode(), [2, 7, 1, 2, 3, 4]);
+        assert_eq!(
+            ProtocolVersion::decode(&[2, 7, 1, 2, 3, 4, 0xEE]),
+            Some(version)
+        );
+        assert_eq!(ProtocolVersion::decode(&[2, 7, 1, 2, 3]), None);
+    }
+
+    #[test]
+    fn negotiation_matrix() {
+        let local = ProtocolVersion::new(1, 3, COMPRESSION | BATCHING);
+
+        for (peer, expected) in [
+            // Same version, same features.
+            (
+                ProtocolVersion::new(1, 3, COMPRESSION | BATCHING),
+                Ok(Negotiated {
+                    major: 1,
+                    minor: 3,
+                    features: COMPRESSION | BATCHING,
+                }),
+            ),
+            // Older peer: its minor, the common features.
+            (
+                ProtocolVersion::new(1, 1, COMPRESSION),
+                Ok(Negotiated {
+                    major: 1,
+                    minor: 1,
+                    features: COMPRESSION,
+                }),
+            ),
+            // Newer peer: our minor; features only it knows are dropped.
+            (
+                ProtocolVersion::new(1, 9, BATCHING | SIGNED),
+                Ok(Negotiated {
+                    major: 1,
+                    minor: 3,
+                    features: BATCHING,
+                }),
+            ),
+            (
+                ProtocolVersion::new(1, 0, 0),
+                Ok(Negotiated {
+                    major: 1,
+                    minor: 0,
+                    features: 0,
+                }),
+            ),
+            (
+                ProtocolVersion::new(2, 3, COMPRESSION | BATCHING),
+                Err(VersionMismatch { local: 1, peer: 2 }),
+            ),
+            (
+                ProtocolVersion::new(0, 3, COMPRESSION),
+                Err(VersionMismatch { local: 1, peer: 0 }),
+            ),
+        ] {
+            assert_eq!(local.negotiate(&peer), expected, "peer {peer:?}");
+            // Symmetric apart from which side reports as local.
+            assert_eq!(
+                peer.negotiate(&local).map_err(|err| err.local),
+                expected.map_err(|err| err.peer),
+                "reversed {peer:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn supports_needs_every_feature() {
+        let negotiated = Negotiated {
+            major: 1,
+            minor: 0,
+            features: COMPRESSION | BATCHING,
+        };
+        assert!(negotiated.supports(0));
+        assert!(negotiated.supports(COMPRESSION));
+        assert!(negotiated.supports(COMPRESSION | BATCHING));
+        assert!(!negotiated.supports(SIGNED));
+        assert!(!negotiated.supports(COMPRESSION | SIGNED));
+    }
+
+    proptest! {
+        #[test]
+        fn decode_never_panics(value in prop::collection::vec(any::<u8>(), 0..16)) {
+            if let Some(version) = ProtocolVersion::decode(&value) {
+                prop_assert_eq!(&version.encode()[..], &value[..VERSION_LEN]);
+            }
+        }
+
+        #[test]
+        fn encode_decode_round_trips(
+            major in any::<u8>(),
+            minor in any::<u8>(),
+            features in any::<u32>(),
+        ) {
+            let version = ProtocolVersion::new(major, minor, features);
+            prop_assert_eq!(ProtocolVersion::decode(&version.encode()), Some(version));
+        }
+    }
+}
@@ -0,0 +1,450 @@
+//! Write-behind wrapper keeping flash writes off the dispatch thread.
+//!
+//! A flash erase stalls the writing thread for tens of milliseconds. When that
+//! thread is the BLE dispatch worker, all traffic freezes with it.
+//! [`DeferredStore`] queues writes to a background thread instead, keeping
+//! only the latest value per key, and answers reads from the queue before
+//! going to the backend.
+//!
+//! Durability trade-off: a reboot before the queue drains loses the queued
+//! writes, typically the last few seconds of CCCD state. Keys that must not
+//! be lost can be made synchronous with [`DeferredStore::set_sync`];
+//! [`DeferredStore::flush`] drains the queue, e.g. at shutdown.
+//!
+//! If the writer thread dies (a backend panicking, say), writes go straight
+//! to the backend from then on and [`DeferredStore::flush`] applies what is
+//! left in the queue itself instead of waiting for it.
+
+use core::fmt;
+use core::time::Duration;
+use std::collections::{BTreeMap, HashSet};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError};
+use std::thread::{self, JoinHandle};
+use std::time::Instant;
+
+use super::{KvStore, StoreError};
+use crate::ledger::{self, ResourceKind};
+
+type Key = (String, String);
+
+#[derive(Clone, Debug)]
+enum Op {
+    Set(Vec<u8>),
+    Remove,
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct DeferredStats {
+    pub written: u64,
+    /// Queued writes replaced by a newer write to the same key.
+    pub coalesced: u64,
+    pub failed: u64,
+    pub last_flush: Option<Duration>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    pending: BTreeMap<Key, Op>,
+    in_flight: Option<(Key, Op)>,
+    sync_keys: HashSet<Key>,
+    stats: DeferredStats,
+    shutdown: bool,
+    writer_alive: bool,
+}
+
+#[derive(Debug, Default)]
+struct Shared {
+    state: Mutex<State>,
+    changed: Condvar,
+}
+
+impl Shared {
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn wait<'a>(&self, guard: MutexGuard<'a, State>) -> MutexGuard<'a, State> {
+        self.changed
+            .wait(guard)
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+pub struct DeferredStore {
+    inner: Arc<dyn KvStore>,
+    shared: Arc<Shared>,
+    worker: Option<JoinHandle<()>>,
+}
+
+impl fmt::Debug for DeferredStore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DeferredStore")
+            .field("queue_depth", &self.queue_depth())
+            .field("stats", &self.stats())
+            .finish_non_exhaustive()
+    }
+}
+
+impl DeferredStore {
+    /// Starts the writer thread. `stack_size` is in bytes.
+    pub fn new(inner: Arc<dyn KvStore>, stack_size: usize) -> std::io::Result<Self> {
+        let shared = Arc::new(Shared::default());
+        shared.state().writer_alive = true;
+
+        let worker = thread::Builder::new()
+            .name("kv-deferred".into())
+            .stack_size(stack_size)
+            .spawn({
+                let inner = inner.clone();
+                let shared = shared.clone();
+                move || {
+                    let _tracked = ledger::track(ResourceKind::Thread, "kv-deferred");
+                    let _alive = WriterAlive(&shared);
+                    write_behind(inner.as_ref(), &shared)
+                }
+            })?;
+
+        Ok(Self {
+            inner,
+            shared,
+            worker: Some(worker),
+        })
+    }
+
+    /// Writes to this key bypass the queue from now on. A write still queued
+    /// for it is applied first, so it cannot land after a synchronous one.
+    pub fn set_sync(&self, namespace: &str, key: &str) -> Result<(), StoreError> {
+        let key = (namespace.to_owned(), key.to_owned());
+
+        let mut state = self.shared.state();
+        while state.writer_alive && state.in_flight.as_ref().is_some_and(|(k, _)| *k == key) {
+            state = self.shared.wait(state);
+        }
+
+        let queued = state.pending.remove(&key);
+        state.sync_keys.insert(key.clone());
+
+        // Still under the lock, so a synchronous write to the key cannot
+        // overtake the queued one.
+        match queued {
+            Some(op) => apply(self.inner.as_ref(), &mut state.stats, &key, op),
+            None => Ok(()),
+        }
+    }
+
+    pub fn queue_depth(&self) -> usize {
+        let state = self.shared.state();
+        state.pending.len() + usize::from(state.in_flight.is_some())
+    }
+
+    pub fn stats(&self) -> DeferredStats {
+        self.shared.state().stats
+    }
+
+    /// Blocks until every queued write has reached the backend. If the
+    /// writer thread is gone, applies the queue on this thread and returns
+    /// the first error.
+    pub fn flush(&self) -> Result<(), StoreError> {
+        let started = Instant::now();
+        let mut result = Ok(());
+
+        let mut state = self.shared.state();
+        loop {
+            if !state.writer_alive {
+                while let Some((key, op)) = state.pending.pop_first() {
+                    let applied = apply(self.inner.as_ref(), &mut state.stats, &key, op);
+                    result = result.and(applied);
+                }
+            }
+            if state.pending.is_empty() && state.in_flight.is_none() {
+                break;
+            }
+            state = self.shared.wait(state);
+        }
+        state.stats.last_flush = Some(started.elapsed());
+
+        result
+    }
+
+    /// The queued operation for a key, if any; the newest one wins.
+    fn queued(&self, key: &Key) -> Option<Op> {
+        let state = self.shared.state();
+
+        state.pending.get(key).cloned().or_else(|| {
+            state
+                .in_flight
+                .as_ref()
+                .filter(|(in_flight, _)| in_flight == key)
+                .map(|(_, op)| op.clone())
+        })
+    }
+
+    /// Queues `op`, or returns it if the key is synchronous or nobody is
+    /// left to write the queue.
+    fn enqueue(&self, key: Key, op: Op) -> Option<Op> {
+        let mut state = self.shared.state();
+        if state.sync_keys.contains(&key) || !state.writer_alive {
+            return Some(op);
+        }
+
+        if state.pending.insert(key, op).is_some() {
+            state.stats.coalesced += 1;
+        }
+        self.shared.changed.notify_all();
+        None
+    }
+}
+
+impl KvStore for DeferredStore {
+    fn get(&self, namespace: &str, key: &str) -> Result<Option<Vec<u8>>, StoreError> {
+        match self.queued(&(namespace.to_owned(), key.to_owned())) {
+            Some(Op::Set(value)) => Ok(Some(value)),
+            Some(Op::Remove) => Ok(None),
+            None => self.inner.get(namespace, key),
+        }
+    }
+
+    fn set(&self, namespace: &str, key: &str, value: &[u8]) -> Result<(), StoreError> {
+        let key_owned = (namespace.to_owned(), key.to_owned());
+        match self.enqueue(key_owned, Op::Set(value.to_vec())) {
+            Some(_) => self.inner.set(namespace, key, value),
+            None => Ok(()),
+        }
+    }
+
+    fn remove(&self, namespace: &str, key: &str) -> Result<bool, StoreError> {
+        let existed = self.get(namespace, key)?.is_some();
+
+        match self.enqueue((namespace.to_owned(), key.to_owned()), Op::Remove) {
+            Some(_) => self.inner.remove(namespace, key),
+            None => Ok(existed),
+        }
+    }
+
+    fn keys(&self, namespace: &str) -> Result<Vec<String>, StoreError> {
+        let mut keys: HashSet<String> = self.inner.keys(namespace)?.into_iter().collect();
+
+        let state = self.shared.state();
+        let in_flight = state.in_flight.iter().map(|(key, op)| (key, op));
+        let queued = in_flight.chain(&state.pending);
+        for ((_, key), op) in queued.filter(|((ns, _), _)| ns == namespace) {
+            match op {
+                Op::Set(_) => keys.insert(key.clone()),
+                Op::Remove => keys.remove(key),
+            };
+        }
+
+        Ok(keys.into_iter().collect())
+    }
+}
+
+impl Drop for DeferredStore {
+    fn drop(&mut self) {
+        self.shared.state().shutdown = true;
+        self.shared.changed.notify_all();
+
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
+
+/// Marks the writer gone however its thread ends, so nothing waits on it.
+struct WriterAlive<'a>(&'a Shared);
+
+impl Drop for WriterAlive<'_> {
+    fn drop(&mut self) {
+        let mut state = self.0.state();
+        state.writer_alive = false;
+        if let Some(((namespace, key), _)) = state.in_flight.take() {
+            state.stats.failed += 1;
+            log::error!("deferred writer died writing {namespace}/{key}");
+        }
+        self.0.changed.notify_all();
+    }
+}
+
+/// Applies one operation to the backend, counting it in `stats`.
+fn apply(
+    inner: &dyn KvStore,
+    stats: &mut DeferredStats,
+    key: &Key,
+    op: Op,
+) -> Result<(), StoreError> {
+    let result = write(inner, key, op);
+    record(stats, key, &result);
+    result
+}
+
+fn write(inner: &dyn KvStore, (namespace, key): &Key, op: Op) -> Result<(), StoreError> {
+    match op {
+        Op::Set(value) => inner.set(namespace, key, &value),
+        Op::Remove => inner.remove(namespace, key).map(drop),
+    }
+}
+
+fn record(stats: &mut DeferredStats, (namespace, key): &Key, result: &Result<(), StoreError>) {
+    match result {
+        Ok(()) => stats.written += 1,
+        Err(err) => {
+            stats.failed += 1;
+            log::warn!("deferred write of {namespace}/{key} failed: {err}");
+        }
+    }
+}
+
+/// Writer thread: applies queued operations until shut down and drained.
+fn write_behind(inner: &dyn KvStore, shared: &Shared) {
+    let mut state = shared.state();
+
+    loop {
+        let Some((key, op)) = state.pending.pop_first() else {
+            if state.shutdown {
+                return;
+            }
+            state = shared.wait(state);
+            continue;
+        };
+
+        state.in_flight = Some((key.clone(), op.clone()));
+        drop(state);
+
+        let result = write(inner, &key, op);
+
+        state = shared.state();
+        state.in_flight = None;
+        record(&mut state.stats, &key, &result);
+        shared.changed.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc::{self, Receiver};
+
+    use super::*;
+    use crate::store::MemStore;
+
+    const STACK: usize = 64 * 1024;
+
+    /// Delegates to a [`MemStore`]; a write to `"block"` waits for a message
+    /// and one to `"panic"` panics.
+    struct TestBackend {
+        mem: MemStore,
+        release: Mutex<Receiver<()>>,
+    }
+
+    impl KvStore for TestBackend {
+        fn get(&self, namespace: &str, key: &str) -> Result<Option<Vec<u8>>, StoreError> {
+            self.mem.get(namespace, key)
+        }
+
+        fn set(&self, namespace: &str, key: &str, value: &[u8]) -> Result<(), StoreError> {
+            match key {
+                "block" => self.release.lock().unwrap().recv().unwrap(),
+                "panic" => panic!("backend panicked"),
+                _ => {}
+            }
+            self.mem.set(namespace, key, value)
+        }
+
+        fn remove(&self, namespace: &str, key: &str) -> Result<bool, StoreError> {
+            self.mem.remove(namespace, key)
+        }
+
+        fn keys(&self, namespace: &str) -> Result<Vec<String>, StoreError> {
+            self.mem.keys(namespace)
+        }
+    }
+
+    fn backend() -> (Arc<TestBackend>, mpsc::Sender<()>) {
+        let (release, rx) = mpsc::channel();
+        let backend = TestBackend {
+            mem: MemStore::new(),
+            release: Mutex::new(rx),
+        };
+        (Arc::new(backend), release)
+    }
+
+    /// Waits until the writer holds the `"block"` write.
+    fn wait_blocked(store: &DeferredStore) {
+        while store.shared.state().in_flight.is_none() {
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn queued_writes_are_read_back_and_flushed() {
+        let (backend, release) = backend();
+        let store = DeferredStore::new(backend.clone(), STACK).unwrap();
+
+        store.set("ns", "block", b"0").unwrap();
+        wait_blocked(&store);
+        store.set("ns", "a", b"1").unwrap();
+        store.set("ns", "a", b"2").unwrap();
+        store.set("ns", "b", b"3").unwrap();
+        store.remove("ns", "b").unwrap();
+
+        assert_eq!(store.get("ns", "a").unwrap(), Some(b"2".to_vec()));
+        assert_eq!(store.get("ns", "b").unwrap(), None);
+        assert_eq!(backend.mem.get("ns", "a").unwrap(), None);
+        assert_eq!(store.queue_depth(), 3);
+
+        release.send(()).unwrap();
+        store.flush().unwrap();
+        assert_eq!(backend.mem.get("ns", "a").unwrap(), Some(b"2".to_vec()));
+        assert_eq!(store.queue_depth(), 0);
+        assert_eq!(store.stats().coalesced, 2);
+        assert_eq!(store.stats().written, 3);
+    }
+
+    #[test]
+    fn set_sync_writes_the_queued_value_first() {
+        let (backend, release) = backend();
+        let store = DeferredStore::new(backend.clone(), STACK).unwrap();
+
+        store.set("ns", "block", b"0").unwrap();
+        wait_blocked(&store);
+        store.set("ns", "cccd", b"old").unwrap();
+
+        store.set_sync("ns", "cccd").unwrap();
+        assert_eq!(
+            backend.mem.get("ns", "cccd").unwrap(),
+            Some(b"old".to_vec())
+        );
+
+        store.set("ns", "cccd", b"new").unwrap();
+        assert_eq!(
+            backend.mem.get("ns", "cccd").unwrap(),
+            Some(b"new".to_vec())
+        );
+
+        release.send(()).unwrap();
+        store.flush().unwrap();
+        assert_eq!(
+            backend.mem.get("ns", "cccd").unwrap(),
+            Some(b"new".to_vec())
+        );
+    }
+
+    #[test]
+    fn flush_does_not_hang_after_the_writer_died() {
+        let (backend, release) = backend();
+        let store = DeferredStore::new(backend.clone(), STACK).unwrap();
+
+        store.set("ns", "block", b"0").unwrap();
+        wait_blocked(&store);
+        store.set("ns", "panic", b"1").unwrap();
+        store.set("ns", "a", b"2").unwrap();
+        release.send(()).unwrap();
+
+        store.flush().unwrap();
+        assert_eq!(backend.mem.get("ns", "a").unwrap(), Some(b"2".to_vec()));
+        assert!(!store.shared.state().writer_alive);
+
+        // With the writer gone, writes go straight to the backend.
+        store.set("ns", "b", b"3").unwrap();
+        assert_eq!(backend.mem.get("ns", "b").unwrap(), Some(b"3".to_vec()));
+        assert_eq!(store.stats().failed, 1);
+    }
+}
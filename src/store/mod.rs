@@ -0,0 +1,111 @@
+//! Key-value persistence.
+//!
+//! Persistence features take an `Arc<dyn KvStore>` instead of assuming NVS,
+//! so boards keeping settings elsewhere (e.g. external FRAM) can plug in their
+//! own backend. [`NvsStore`] is the esp-idf implementation and [`MemStore`]
+//! an in-memory one for host use.
+
+use core::fmt;
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+mod deferred;
+#[cfg(feature = "esp")]
+mod nvs;
+mod token;
+
+pub use deferred::{DeferredStats, DeferredStore};
+#[cfg(feature = "esp")]
+pub use nvs::NvsStore;
+pub use token::{Capabilities, TokenStore};
+
+#[derive(Debug)]
+pub enum StoreError {
+    /// Namespace or key rejected by the backend (e.g. too long for NVS).
+    InvalidKey(String),
+    /// The backend failed; unlike a missing key this is not a reason to
+    /// silently fall back to defaults.
+    Backend(Box<dyn std::error::Error + Send + Sync>),
+}
+
+impl fmt::Display for StoreError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidKey(key) => write!(f, "invalid store key {key:?}"),
+            Self::Backend(err) => write!(f, "store backend error: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for StoreError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::InvalidKey(_) => None,
+            Self::Backend(err) => Some(err.as_ref()),
+        }
+    }
+}
+
+/// Namespaced byte-value store.
+///
+/// A missing key is `Ok(None)`; `Err` always means the backend failed.
+pub trait KvStore: Send + Sync {
+    fn get(&self, namespace: &str, key: &str) -> Result<Option<Vec<u8>>, StoreError>;
+
+    fn set(&self, namespace: &str, key: &str, value: &[u8]) -> Result<(), StoreError>;
+
+    /// Returns whether the key existed.
+    fn remove(&self, namespace: &str, key: &str) -> Result<bool, StoreError>;
+
+    fn keys(&self, namespace: &str) -> Result<Vec<String>, StoreError>;
+}
+
+/// In-memory [`KvStore`].
+#[derive(Debug, Default)]
+pub struct MemStore {
+    entries: Mutex<BTreeMap<(String, String), Vec<u8>>>,
+}
+
+impl MemStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn entries(&self) -> std::sync::MutexGuard<'_, BTreeMap<(String, String), Vec<u8>>> {
+        self.entries
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+}
+
+impl KvStore for MemStore {
+    fn get(&self, namespace: &str, key: &str) -> Result<Option<Vec<u8>>, StoreError> {
+        Ok(self
+            .entries()
+            .get(&(namespace.to_owned(), key.to_owned()))
+            .cloned())
+    }
+
+    fn set(&self, namespace: &str, key: &str, value: &[u8]) -> Result<(), StoreError> {
+        self.entries()
+            .insert((namespace.to_owned(), key.to_owned()), value.to_vec());
+
+        Ok(())
+    }
+
+    fn remove(&self, namespace: &str, key: &str) -> Result<bool, StoreError> {
+        Ok(self
+            .entries()
+            .remove(&(namespace.to_owned(), key.to_owned()))
+            .is_some())
+    }
+
+    fn keys(&self, namespace: &str) -> Result<Vec<String>, StoreError> {
+        Ok(self
+            .entries()
+            .keys()
+            .filter(|(ns, _)| ns == namespace)
+            .map(|(_, key)| key.clone())
+            .collect())
+    }
+}
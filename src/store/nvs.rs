@@ -0,0 +1,137 @@
+//! [`KvStore`] on top of the default NVS partition.
+
+use core::ffi::CStr;
+use std::collections::HashMap;
+use std::ffi::CString;
+use std::sync::{Mutex, PoisonError};
+
+use esp_idf_svc::nvs::{EspDefaultNvsPartition, EspNvs, NvsDefault};
+use esp_idf_svc::sys::{
+    esp, nvs_entry_find, nvs_entry_info, nvs_entry_info_t, nvs_entry_next, nvs_iterator_t,
+    nvs_release_iterator, nvs_type_t_NVS_TYPE_ANY, EspError, ESP_ERR_NVS_NOT_FOUND,
+};
+
+use super::{KvStore, StoreError};
+
+/// NVS limits namespaces and keys to 15 bytes.
+const MAX_NAME_LEN: usize = 15;
+
+const PARTITION_NAME: &CStr = c"nvs";
+
+pub struct NvsStore {
+    partition: EspDefaultNvsPartition,
+    namespaces: Mutex<HashMap<String, EspNvs<NvsDefault>>>,
+}
+
+impl NvsStore {
+    pub fn new(partition: EspDefaultNvsPartition) -> Self {
+        Self {
+            partition,
+            namespaces: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Runs `f` on the (lazily opened) handle of `namespace`.
+    fn with_namespace<R>(
+        &self,
+        namespace: &str,
+        key: &str,
+        f: impl FnOnce(&mut EspNvs<NvsDefault>) -> Result<R, EspError>,
+    ) -> Result<R, StoreError> {
+        check_name(namespace)?;
+        check_name(key)?;
+
+        let mut namespaces = self
+            .namespaces
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+
+        if !namespaces.contains_key(namespace) {
+            let nvs = EspNvs::new(self.partition.clone(), namespace, true).map_err(backend)?;
+            namespaces.insert(namespace.to_owned(), nvs);
+        }
+
+        let nvs = namespaces
+            .get_mut(namespace)
+            .expect("namespace just opened");
+
+        f(nvs).map_err(backend)
+    }
+}
+
+impl KvStore for NvsStore {
+    fn get(&self, namespace: &str, key: &str) -> Result<Option<Vec<u8>>, StoreError> {
+        self.with_namespace(namespace, key, |nvs| {
+            let Some(len) = nvs.blob_len(key)? else {
+                return Ok(None);
+            };
+
+            let mut buf = vec![0; len];
+            let value = nvs.get_blob(key, &mut buf)?.map(<[u8]>::to_vec);
+
+            Ok(value)
+        })
+    }
+
+    fn set(&self, namespace: &str, key: &str, value: &[u8]) -> Result<(), StoreError> {
+        self.with_namespace(namespace, key, |nvs| nvs.set_blob(key, value))
+    }
+
+    fn remove(&self, namespace: &str, key: &str) -> Result<bool, StoreError> {
+        self.with_namespace(namespace, key, |nvs| nvs.remove(key))
+    }
+
+    fn keys(&self, namespace: &str) -> Result<Vec<String>, StoreError> {
+        check_name(namespace)?;
+
+        let namespace = CString::new(namespace).map_err(|_| invalid(namespace))?;
+        let mut keys = Vec::new();
+        let mut iter: nvs_iterator_t = core::ptr::null_mut();
+
+        let mut err = unsafe {
+            nvs_entry_find(
+                PARTITION_NAME.as_ptr(),
+                namespace.as_ptr(),
+                nvs_type_t_NVS_TYPE_ANY,
+                &mut iter,
+            )
+        };
+
+        while err == 0 {
+            let mut info: nvs_entry_info_t = unsafe { core::mem::zeroed() };
+            if let Err(e) = esp!(unsafe { nvs_entry_info(iter, &mut info) }) {
+                unsafe { nvs_release_iterator(iter) };
+                return Err(backend(e));
+            }
+
+            let key = unsafe { CStr::from_ptr(info.key.as_ptr()) };
+            keys.push(key.to_string_lossy().into_owned());
+
+            err = unsafe { nvs_entry_next(&mut iter) };
+        }
+
+        // The iterator is released (and set to NULL) once exhausted.
+        unsafe { nvs_release_iterator(iter) };
+
+        match esp!(err) {
+            Err(e) if e.code() != ESP_ERR_NVS_NOT_FOUND as i32 => Err(backend(e)),
+            _ => Ok(keys),
+        }
+    }
+}
+
+fn check_name(name: &str) -> Result<(), StoreError> {
+    if name.is_empty() || name.len() > MAX_NAME_LEN {
+        Err(invalid(name))
+    } else {
+        Ok(())
+    }
+}
+
+fn invalid(name: &str) -> StoreError {
+    StoreError::InvalidKey(name.to_owned())
+}
+
+fn backend(err: EspError) -> StoreError {
+    StoreError::Backend(Box::new(err))
+}
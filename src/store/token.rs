@@ -0,0 +1,196 @@
+//! Per-capability access tokens.
+//!
+//! Tokens are provisioned into the store as SHA-256 hashes with the
+//! capabilities they grant; the token itself is never stored. An entry's key
+//! is the hex of the first 7 hash bytes (NVS keys are limited to 15
+//! characters), its value the full hash followed by the capability bits
+//! (`u32`, little endian). Verification compares the full hash in constant
+//! time.
+
+use core::fmt;
+use core::ops::BitOr;
+use std::sync::Arc;
+
+use super::{KvStore, StoreError};
+
+const KEY_HASH_BYTES: usize = 7;
+
+#[derive(Copy, Clone, Default, PartialEq, Eq, Hash)]
+pub struct Capabilities(u32);
+
+impl Capabilities {
+    pub const NONE: Self = Self(0);
+    pub const READ_DIAGNOSTICS: Self = Self(1 << 0);
+    pub const REBOOT: Self = Self(1 << 1);
+    pub const OTA: Self = Self(1 << 2);
+    /// Adding and revoking tokens.
+    pub const MANAGE_TOKENS: Self = Self(1 << 3);
+
+    pub const fn from_bits(bits: u32) -> Self {
+        Self(bits)
+    }
+
+    pub const fn bits(self) -> u32 {
+        self.0
+    }
+
+    pub const fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl BitOr for Capabilities {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+
+impl fmt::Debug for Capabilities {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Capabilities({:#x})", self.0)
+    }
+}
+
+pub struct TokenStore {
+    store: Arc<dyn KvStore>,
+    namespace: String,
+}
+
+impl fmt::Debug for TokenStore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TokenStore")
+            .field("namespace", &self.namespace)
+            .finish_non_exhaustive()
+    }
+}
+
+impl TokenStore {
+    pub fn new(store: Arc<dyn KvStore>, namespace: impl Into<String>) -> Self {
+        Self {
+            store,
+            namespace: namespace.into(),
+        }
+    }
+
+    /// Adds `token`, or replaces the capabilities of an existing one.
+    pub fn add(&self, token: &[u8], capabilities: Capabilities) -> Result<(), StoreError> {
+        let hash = sha256(token);
+
+        let mut value = hash.to_vec();
+        value.extend_from_slice(&capabilities.bits().to_le_bytes());
+        self.store.set(&self.namespace, &key_of(&hash), &value)
+    }
+
+    /// Returns whether the token existed.
+    pub fn revoke(&self, token: &[u8]) -> Result<bool, StoreError> {
+        let hash = sha256(token);
+        if self.lookup(&hash)?.is_none() {
+            return Ok(false);
+        }
+
+        self.store.remove(&self.namespace, &key_of(&hash))
+    }
+
+    /// Capabilities granted by `token`, `None` if it is unknown.
+    pub fn verify(&self, token: &[u8]) -> Result<Option<Capabilities>, StoreError> {
+        self.lookup(&sha256(token))
+    }
+
+    fn lookup(&self, hash: &[u8; 32]) -> Result<Option<Capabilities>, StoreError> {
+        let Some(value) = self.store.get(&self.namespace, &key_of(hash))? else {
+            return Ok(None);
+        };
+        if value.len() != 36 || !constant_time_eq(&value[..32], hash) {
+            return Ok(None);
+        }
+
+        let bits = u32::from_le_bytes([value[32], value[33], value[34], value[35]]);
+        Ok(Some(Capabilities::from_bits(bits)))
+    }
+}
+
+fn key_of(hash: &[u8; 32]) -> String {
+    hash[..KEY_HASH_BYTES]
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+/// SHA-256 through mbedtls, which uses the SHA accelerator where the chip
+/// has one.
+#[cfg(feature = "esp")]
+fn sha256(data: &[u8]) -> [u8; 32] {
+    let mut digest = [0u8; 32];
+    // Only fails for bad arguments or SHA-224 output, neither possible here.
+    let ret = unsafe {
+        esp_idf_svc::sys::mbedtls_sha256(data.as_ptr(), data.len(), digest.as_mut_ptr(), 0)
+    };
+    assert_eq!(ret, 0, "mbedtls_sha256 failed: {ret}");
+    digest
+}
+
+#[cfg(not(feature = "esp"))]
+fn sha256(data: &[u8]) -> [u8; 32] {
+    use sha2::Digest;
+
+    sha2::Sha256::digest(data).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::MemStore;
+
+    fn tokens() -> TokenStore {
+        TokenStore::new(Arc::new(MemStore::new()), "tokens")
+    }
+
+    #[test]
+    fn sha256_known_answers() {
+        // The key is the hex of the first 7 hash bytes.
+        assert_eq!(key_of(&sha256(b"")), "e3b0c44298fc1c");
+        assert_eq!(key_of(&sha256(b"abc")), "ba7816bf8f01cf");
+    }
+
+    #[test]
+    fn add_verify_revoke() {
+        let tokens = tokens();
+        tokens.add(b"reboot-token", Capabilities::REBOOT).unwrap();
+
+        assert_eq!(
+            tokens.verify(b"reboot-token").unwrap(),
+            Some(Capabilities::REBOOT)
+        );
+        assert_eq!(tokens.verify(b"other").unwrap(), None);
+
+        tokens
+            .add(b"reboot-token", Capabilities::REBOOT | Capabilities::OTA)
+            .unwrap();
+        assert_eq!(
+            tokens.verify(b"reboot-token").unwrap(),
+            Some(Capabilities::REBOOT | Capabilities::OTA)
+        );
+
+        assert!(tokens.revoke(b"reboot-token").unwrap());
+        assert!(!tokens.revoke(b"reboot-token").unwrap());
+        assert_eq!(tokens.verify(b"reboot-token").unwrap(), None);
+    }
+
+    #[test]
+    fn token_itself_is_not_stored() {
+        let store = Arc::new(MemStore::new());
+        let tokens = TokenStore::new(store.clone(), "tokens");
+        tokens.add(b"secret", Capabilities::OTA).unwrap();
+
+        let keys = store.keys("tokens").unwrap();
+        assert_eq!(keys.len(), 1);
+        let value = store.get("tokens", &keys[0]).unwrap().unwrap();
+        assert!(!value.windows(6).any(|window| window == b"secret"));
+    }
+}
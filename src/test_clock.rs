@@ -0,0 +1,22 @@
+//! Hand-stepped time for tests of components that take `now` as an argument.
+
+use core::time::Duration;
+use std::time::Instant;
+
+/// Instants relative to a fixed origin.
+#[derive(Copy, Clone, Debug)]
+pub struct MockClock(Instant);
+
+impl MockClock {
+    pub fn new() -> Self {
+        Self(Instant::now())
+    }
+
+    pub fn ms(&self, ms: u64) -> Instant {
+        self.0 + Duration::from_millis(ms)
+    }
+
+    pub fn secs(&self, secs: u64) -> Instant {
+        self.0 + Duration::from_secs(secs)
+    }
+}
@@ -0,0 +1,54 @@
+//! Repeated start/stop cycles leave nothing behind.
+//!
+//! The ledger is process-wide, so this lives in its own test binary where
+//! no other test creates resources concurrently.
+
+#![cfg(feature = "resource-ledger")]
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use esp_gatt_rs_demo::ble::session::SessionRegistry;
+use esp_gatt_rs_demo::ledger::{self, ResourceKind};
+use esp_gatt_rs_demo::store::{DeferredStore, KvStore, MemStore};
+
+#[test]
+fn fifty_start_stop_cycles_leave_the_ledger_empty() {
+    let backend = Arc::new(MemStore::new());
+
+    for cycle in 0..50u32 {
+        let store = DeferredStore::new(backend.clone(), 4096).unwrap();
+        let mut sessions = SessionRegistry::new();
+        for conn_id in 0..3 {
+            let session = sessions.on_connect(conn_id);
+            session.insert(cycle);
+            session.insert_with_ttl(String::from("challenge"), Duration::ZERO, Instant::now());
+        }
+        store.set("app", "cycle", &cycle.to_le_bytes()).unwrap();
+        // Once the write went through, the writer thread is registered.
+        store.flush().unwrap();
+
+        let kinds: Vec<_> = ledger::report().iter().map(|info| info.kind).collect();
+        assert_eq!(kinds.len(), 4, "cycle {cycle}: {kinds:?}");
+        assert!(kinds.contains(&ResourceKind::Thread));
+
+        // Stop: disconnect everyone and shut the writer down.
+        sessions.sweep(Instant::now());
+        for conn_id in 0..3 {
+            sessions.on_disconnect(conn_id);
+        }
+        drop(store);
+
+        assert!(
+            ledger::report().is_empty(),
+            "cycle {cycle}: {:?}",
+            ledger::report()
+        );
+        ledger::debug_assert_clean();
+    }
+
+    assert_eq!(
+        backend.get("app", "cycle").unwrap(),
+        Some(49u32.to_le_bytes().to_vec())
+    );
+}